@@ -0,0 +1,118 @@
+// Throughput of the hot paths: CPU instructions, PPU dots, rendering a frame
+// and running whole frames, the PPU and whole frames once with the default
+// accuracy and once with FAST. Run with `cargo bench`; criterion compares
+// each run with the last one and flags changes beyond the noise threshold
+// below as regressions.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rust_nes_emu::accuracy::AccuracyConfig;
+use rust_nes_emu::bus::BUS;
+use rust_nes_emu::cpu::CPU;
+use rust_nes_emu::nes::Nes;
+use rust_nes_emu::render::{self, frame::Frame};
+use rust_nes_emu::rom::ROM;
+
+use std::time::Duration;
+
+// NROM that turns rendering on, then loops over RAM forever:
+//   LDA #$1E; STA $2001
+//   loop: LDX #0
+//   inner: LDA $00,X; ADC #1; STA $0200,X; INX; BNE inner
+//   JMP loop
+fn synthetic_rom() -> Vec<u8> {
+    let mut rom = vec![
+        0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut prg = vec![0xea; 0x8000];
+    prg[..20].copy_from_slice(&[
+        0xa9, 0x1e, 0x8d, 0x01, 0x20, 0xa2, 0x00, 0xb5, 0x00, 0x69, 0x01, 0x9d, 0x00, 0x02, 0xe8,
+        0xd0, 0xf6, 0x4c, 0x05, 0x80,
+    ]);
+    // NMI and IRQ: RTI
+    prg[0x100] = 0x40;
+    prg[0x7ffa..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x81]);
+    rom.extend(prg);
+    // every tile has some of each colour
+    rom.extend((0..0x2000).map(|i| (i * 7) as u8));
+    rom
+}
+
+fn cpu_instructions(c: &mut Criterion) {
+    const INSTRUCTIONS: u64 = 10_000;
+    let mut cpu = CPU::new(BUS::new(ROM::from_bytes(&synthetic_rom()).unwrap()).unwrap());
+    cpu.reset();
+
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("instructions", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                black_box(cpu.step());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn ppu_dots(c: &mut Criterion) {
+    const DOTS: u64 = 341 * 262;
+    let mut group = c.benchmark_group("ppu");
+    group.throughput(Throughput::Elements(DOTS));
+    let profiles = [
+        ("dots", AccuracyConfig::default()),
+        ("dots fast", AccuracyConfig::FAST),
+    ];
+    for (name, accuracy) in profiles {
+        let mut nes = Nes::new(&synthetic_rom()).unwrap();
+        nes.set_accuracy(accuracy);
+        nes.run_frame();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let ppu = &mut nes.bus_mut().ppu;
+                for _ in 0..DOTS / 3 {
+                    black_box(ppu.tick(3));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn rendering(c: &mut Criterion) {
+    let mut nes = Nes::new(&synthetic_rom()).unwrap();
+    nes.run_frame();
+    let mut frame = Frame::new();
+
+    c.bench_function("render frame", |b| {
+        b.iter(|| render::render(black_box(&nes.bus().ppu), &mut frame))
+    });
+}
+
+fn whole_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nes");
+    group.throughput(Throughput::Elements(1));
+    let profiles = [
+        ("run frame", AccuracyConfig::default()),
+        ("run frame fast", AccuracyConfig::FAST),
+    ];
+    for (name, accuracy) in profiles {
+        let mut nes = Nes::new(&synthetic_rom()).unwrap();
+        nes.set_accuracy(accuracy);
+        group.bench_function(name, |b| b.iter(|| nes.run_frame()));
+    }
+    group.finish();
+}
+
+// changes under 5% are noise, anything past it is reported
+fn config() -> Criterion {
+    Criterion::default()
+        .noise_threshold(0.05)
+        .significance_level(0.01)
+        .measurement_time(Duration::from_secs(5))
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = cpu_instructions, ppu_dots, rendering, whole_frames
+}
+criterion_main!(benches);
@@ -0,0 +1,2 @@
+// everything is in src/ffi.rs, this only links it into a library C can load
+pub use rust_nes_emu::ffi::*;
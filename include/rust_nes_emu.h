@@ -0,0 +1,123 @@
+#ifndef RUST_NES_EMU_H
+#define RUST_NES_EMU_H
+
+/* Made by cbindgen from src/ffi.rs, don't edit it by hand */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define NES_WIDTH 256
+
+#define NES_HEIGHT 240
+
+#define NES_OK 0
+
+#define NES_ERROR_NULL_POINTER -1
+
+#define NES_ERROR_NO_ROM -2
+
+#define NES_ERROR_INVALID_ROM -3
+
+#define NES_ERROR_UNSUPPORTED_MAPPER -4
+
+#define NES_ERROR_INVALID_STATE -5
+
+#define NES_ERROR_PANIC -6
+
+#define NES_BUTTON_A 1
+
+#define NES_BUTTON_B 2
+
+#define NES_BUTTON_SELECT 4
+
+#define NES_BUTTON_START 8
+
+#define NES_BUTTON_UP 16
+
+#define NES_BUTTON_DOWN 32
+
+#define NES_BUTTON_LEFT 64
+
+#define NES_BUTTON_RIGHT 128
+
+typedef struct NesHandle NesHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Makes a console with no cartridge in, putting out sound at
+// `sample_rate`, or the default 44100Hz for 0. Free it with nes_destroy.
+struct NesHandle *nes_create(uint32_t sample_rate);
+
+// # Safety
+// `nes` is null or came from nes_create and hasn't been destroyed yet.
+void nes_destroy(struct NesHandle *nes);
+
+// Loads an iNES or NES 2.0 file, replacing any cartridge already in.
+//
+// # Safety
+// `nes` is null or a live handle, `data` points to `len` readable bytes.
+int32_t nes_load_rom(struct NesHandle *nes, const uint8_t *data, size_t len);
+
+// # Safety
+// `nes` is null or a live handle.
+int32_t nes_run_frame(struct NesHandle *nes);
+
+// The last frame as NES_WIDTH x NES_HEIGHT RGB pixels, 3 bytes each, row
+// by row; null without a cartridge. Valid until the next call with `nes`.
+//
+// # Safety
+// `nes` is null or a live handle.
+const uint8_t *nes_framebuffer_ptr(const struct NesHandle *nes);
+
+// Presses or releases NES_BUTTON_* buttons on controller `player` (1-4).
+// Other players are ignored.
+//
+// # Safety
+// `nes` is null or a live handle.
+void nes_set_button(struct NesHandle *nes, uint32_t player, uint8_t button, bool pressed);
+
+// Copies the sound made since the last call into `out`, up to `capacity`
+// samples, and returns how many it copied. What doesn't fit is kept for
+// the next call.
+//
+// # Safety
+// `nes` is null or a live handle, `out` points to room for `capacity`
+// floats.
+size_t nes_audio_samples(struct NesHandle *nes, float *out, size_t capacity);
+
+// Writes a save state into `out` if it fits in `capacity` bytes, and
+// returns its size either way, so a null `out` asks how big it is. 0
+// without a cartridge.
+//
+// # Safety
+// `nes` is null or a live handle, `out` is null or points to room for
+// `capacity` bytes.
+size_t nes_save_state(const struct NesHandle *nes, uint8_t *out, size_t capacity);
+
+// Restores a state from nes_save_state, made with the same cartridge. On
+// error the console carries on as it was.
+//
+// # Safety
+// `nes` is null or a live handle, `data` points to `len` readable bytes.
+int32_t nes_load_state(struct NesHandle *nes, const uint8_t *data, size_t len);
+
+// Presses the console's reset button.
+//
+// # Safety
+// `nes` is null or a live handle.
+void nes_reset(struct NesHandle *nes);
+
+// Frames a second for the cartridge's region, 60 without one.
+//
+// # Safety
+// `nes` is null or a live handle.
+double nes_frame_rate(const struct NesHandle *nes);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUST_NES_EMU_H */
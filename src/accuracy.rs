@@ -0,0 +1,65 @@
+use crate::ppu::raster::RasterTiming;
+
+// How closely the console follows the hardware, where that costs speed or
+// only matters to a few games and test ROMs. FAST for slow machines or
+// running many consoles at once, ACCURATE otherwise; the fields can also be
+// set one by one. The default is ACCURATE with scanline raster timing, so a
+// console nobody configured draws mid-line writes from the next line on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccuracyConfig {
+    // when mid-line PPUCTRL and PPUMASK writes show up, see ppu/raster.rs
+    pub raster_timing: RasterTiming,
+    // reads of undriven bits and addresses return the last value on the
+    // bus, otherwise 0
+    pub open_bus: bool,
+    // OAM DMA and DMC sample fetches halt the CPU, see bus.rs
+    pub dma_stalls: bool,
+    // the sprite overflow flag comes from the PPU's buggy sprite
+    // evaluation, otherwise from an honest count of the sprites on the line
+    pub sprite_overflow_bug: bool,
+    // OAM forgets what's in it when rendering is off for long, see ppu/mod.rs
+    pub oam_decay: bool,
+    // sprite 0 hit is looked for at every dot of a line, otherwise once at
+    // the start of each line and set there, up to a line early, which is
+    // much less work, see ppu/mod.rs
+    pub sprite_zero_hit_dot: bool,
+}
+
+impl AccuracyConfig {
+    pub const FAST: AccuracyConfig = AccuracyConfig {
+        raster_timing: RasterTiming::Scanline,
+        open_bus: false,
+        dma_stalls: false,
+        sprite_overflow_bug: false,
+        oam_decay: false,
+        sprite_zero_hit_dot: false,
+    };
+
+    pub const ACCURATE: AccuracyConfig = AccuracyConfig {
+        raster_timing: RasterTiming::Dot,
+        open_bus: true,
+        dma_stalls: true,
+        sprite_overflow_bug: true,
+        oam_decay: true,
+        sprite_zero_hit_dot: true,
+    };
+
+    // "accurate" or "fast", as frontends take them
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "accurate" => Some(AccuracyConfig::ACCURATE),
+            "fast" => Some(AccuracyConfig::FAST),
+            _ => None,
+        }
+    }
+}
+
+impl Default for AccuracyConfig {
+    fn default() -> Self {
+        AccuracyConfig {
+            raster_timing: RasterTiming::Scanline,
+            ..AccuracyConfig::ACCURATE
+        }
+    }
+}
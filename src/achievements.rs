@@ -0,0 +1,122 @@
+use crate::md5::md5;
+use crate::nes::Nes;
+use alloc::format;
+use alloc::string::String;
+
+// What an achievement runtime like rcheevos needs from the emulator: the
+// console's memory at the addresses RetroAchievements uses for the NES, the
+// ROM hash it identifies games by, and a call once per frame to evaluate
+// its conditions in (Nes::set_frame_callback).
+// from: https://github.com/RetroAchievements/rcheevos
+//
+// The NES memory map is the CPU's own, read with Nes::peek so evaluating
+// achievements never disturbs the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryKind {
+    SystemRam,
+    // the same bytes as other addresses
+    Mirror,
+    Registers,
+    // battery backed or work RAM on the cartridge, $6000-$7FFF
+    SaveRam,
+    ReadOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub end: u32,
+    pub kind: MemoryKind,
+    pub description: &'static str,
+}
+
+const fn region(start: u32, end: u32, kind: MemoryKind, description: &'static str) -> MemoryRegion {
+    MemoryRegion {
+        start,
+        end,
+        kind,
+        description,
+    }
+}
+
+pub const MEMORY_REGIONS: &[MemoryRegion] = &[
+    region(0x0000, 0x07ff, MemoryKind::SystemRam, "System RAM"),
+    region(0x0800, 0x1fff, MemoryKind::Mirror, "Mirror RAM"),
+    region(0x2000, 0x2007, MemoryKind::Registers, "PPU Register"),
+    region(0x2008, 0x3fff, MemoryKind::Mirror, "Mirrored PPU Register"),
+    region(
+        0x4000,
+        0x401f,
+        MemoryKind::Registers,
+        "APU and I/O register",
+    ),
+    region(0x4020, 0x5fff, MemoryKind::Registers, "Cartridge data"),
+    region(0x6000, 0x7fff, MemoryKind::SaveRam, "Cartridge RAM"),
+    region(0x8000, 0xffff, MemoryKind::ReadOnly, "Cartridge ROM"),
+];
+
+pub const MEMORY_SIZE: u32 = 0x10000;
+
+// Fills `buffer` from `address` on, like rcheevos' read memory callback,
+// and returns how many bytes there were to read
+pub fn read_memory(nes: &Nes, address: u32, buffer: &mut [u8]) -> usize {
+    let available = MEMORY_SIZE.saturating_sub(address) as usize;
+    let len = buffer.len().min(available);
+    for (offset, byte) in buffer[..len].iter_mut().enumerate() {
+        *byte = nes.peek((address as usize + offset) as u16);
+    }
+    len
+}
+
+// The hash RetroAchievements identifies NES games by: the MD5 of the file
+// without its iNES or fwNES (FDS) header, in lower case hex
+pub fn rom_hash(rom_bytes: &[u8]) -> String {
+    let data = match rom_bytes.get(..4) {
+        Some(b"NES\x1a") | Some(b"FDS\x1a") => &rom_bytes[16.min(rom_bytes.len())..],
+        _ => rom_bytes,
+    };
+    md5(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use crate::rom::test::looping_rom;
+
+    #[test]
+    fn test_read_memory() {
+        let mut nes = Nes::new(&looping_rom()).unwrap();
+        nes.bus_mut().mem_write(0x07ff, 0x12);
+        nes.bus_mut().mem_write(0x6000, 0x34);
+
+        let mut buffer = [0; 2];
+        assert_eq!(read_memory(&nes, 0x0fff, &mut buffer), 2);
+        assert_eq!(buffer, [0x12, 0x00]);
+        assert_eq!(read_memory(&nes, 0x6000, &mut buffer[..1]), 1);
+        assert_eq!(buffer[0], 0x34);
+        // the reset vector, and nothing past the end
+        let mut buffer = [0; 4];
+        assert_eq!(read_memory(&nes, 0xfffc, &mut buffer), 4);
+        assert_eq!(buffer[..2], [0x00, 0x80]);
+        assert_eq!(read_memory(&nes, 0xfffe, &mut buffer), 2);
+        assert_eq!(read_memory(&nes, MEMORY_SIZE, &mut buffer), 0);
+
+        assert!(MEMORY_REGIONS
+            .windows(2)
+            .all(|pair| pair[0].end + 1 == pair[1].start));
+        assert_eq!(MEMORY_REGIONS.last().unwrap().end + 1, MEMORY_SIZE);
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        let rom = looping_rom();
+        assert_eq!(rom_hash(&rom), rom_hash(&rom[16..]));
+        assert_ne!(rom_hash(&rom), rom_hash(&rom[..rom.len() - 1]));
+        // MD5 of nothing
+        assert_eq!(rom_hash(b"NES\x1a"), "d41d8cd98f00b204e9800998ecf8427e");
+    }
+}
@@ -0,0 +1,268 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Delta modulation channel at $4010-$4013
+// from: https://www.nesdev.org/wiki/APU_DMC
+//
+// | Register | Bits      | Contents                                         |
+// |----------|-----------|--------------------------------------------------|
+// | $4010    | IL-- RRRR | IRQ enable, loop, rate index                     |
+// | $4011    | -DDD DDDD | Output level, loaded directly                    |
+// | $4012    | AAAA AAAA | Sample address: $C000 + A * 64                   |
+// | $4013    | LLLL LLLL | Sample length: L * 16 + 1 bytes                  |
+//
+// Sample bytes are fetched from the CPU bus by DMA. Each bit moves the 7-bit
+// output level up or down by 2. When a sample ends without looping the DMC
+// can raise an IRQ.
+
+// NTSC rates, in CPU cycles per bit
+const RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    timer: u16,
+    timer_period: u16,
+    pub pal: bool,
+    pub level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    pub irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            timer: 0,
+            timer_period: RATES[0],
+            pal: false,
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+}
+
+impl Dmc {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                self.looping = value & 0b0100_0000 != 0;
+                let rates = if self.pal { &PAL_RATES } else { &RATES };
+                self.timer_period = rates[(value & 0b1111) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = value & 0x7f,
+            2 => self.sample_address = 0xc000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) | 1,
+        }
+    }
+
+    // $4015 bit 4
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // address the DMA unit wants to read next, if the buffer is empty
+    pub fn pending_read(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_sample_byte(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.current_address = if self.current_address == 0xffff {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn bytes_remaining(&self) -> u16 {
+        self.bytes_remaining
+    }
+
+    // in CPU cycles per bit played
+    pub fn period(&self) -> u16 {
+        self.timer_period
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.looping);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_u8(self.level);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_option(self.buffer);
+        state.write_u8(self.shift);
+        state.write_u8(self.bits_remaining);
+        state.write_bool(self.silence);
+        state.write_bool(self.irq);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.level = state.read_u8()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.current_address = state.read_u16()?;
+        self.bytes_remaining = state.read_u16()?;
+        self.buffer = state.read_option()?;
+        self.shift = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        self.silence = state.read_bool()?;
+        self.irq = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_playback_and_irq() {
+        let mut dmc = Dmc::default();
+        // IRQ, fastest rate, sample at $C040, 1 byte
+        dmc.write(0, 0b1000_1111);
+        dmc.write(2, 0x01);
+        dmc.write(3, 0x00);
+        dmc.write(1, 0x40);
+        dmc.set_enabled(true);
+
+        assert_eq!(dmc.pending_read(), Some(0xc040));
+        dmc.load_sample_byte(0b0000_1111);
+        assert!(dmc.irq);
+        assert!(!dmc.is_active());
+        assert_eq!(dmc.pending_read(), None);
+
+        // the first 8 bits play out of the empty shift register in silence
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x40);
+
+        // then four 1 bits and four 0 bits
+        for _ in 0..4 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x48);
+        for _ in 0..4 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x40);
+    }
+
+    #[test]
+    fn test_looping_sample_wraps_to_start() {
+        let mut dmc = Dmc::default();
+        dmc.write(0, 0b0100_0000);
+        dmc.write(2, 0xff);
+        dmc.write(3, 0x00);
+        dmc.set_enabled(true);
+
+        assert_eq!(dmc.pending_read(), Some(0xffc0));
+        dmc.load_sample_byte(0);
+        assert!(!dmc.irq);
+        assert!(dmc.is_active());
+        dmc.buffer = None;
+        assert_eq!(dmc.pending_read(), Some(0xffc0));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Frame sequencer: divides the CPU clock into quarter and half frames that
+// drive the envelopes, length counters, linear counter and sweeps.
+// from: https://www.nesdev.org/wiki/APU_Frame_Counter
+//
+// $4017: MI-- ----  M: 5-step mode, I: inhibit the frame IRQ
+//
+// In CPU cycles since the sequence started:
+// | Cycle | 4-step mode                | 5-step mode                |
+// |-------|----------------------------|----------------------------|
+// | 7457  | quarter                    | quarter                    |
+// | 14913 | quarter, half              | quarter, half              |
+// | 22371 | quarter                    | quarter                    |
+// | 29828 | IRQ                        |                            |
+// | 29829 | quarter, half, IRQ         |                            |
+// | 29830 | IRQ, restart               |                            |
+// | 37281 |                            | quarter, half              |
+// | 37282 |                            | restart                    |
+//
+// A $4017 write restarts the sequence 3 or 4 CPU cycles later, depending on
+// where in the APU's 2-cycle clock it landed. Restarting in 5-step mode also
+// clocks a quarter and half frame straight away.
+//
+// The PAL APU steps at 8313, 16627, 24939, 33253 and 41565 instead.
+const NTSC_STEPS: [usize; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_STEPS: [usize; 5] = [8313, 16627, 24939, 33253, 41565];
+#[cfg(test)]
+const FOUR_STEP_LENGTH: usize = 29830;
+#[cfg(test)]
+const FIVE_STEP_LENGTH: usize = 37282;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameClock {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+const NONE: FrameClock = FrameClock {
+    quarter: false,
+    half: false,
+};
+const QUARTER: FrameClock = FrameClock {
+    quarter: true,
+    half: false,
+};
+const HALF: FrameClock = FrameClock {
+    quarter: true,
+    half: true,
+};
+
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCounter {
+    cycle: usize,
+    five_step: bool,
+    irq_inhibit: bool,
+    reset_delay: Option<u8>,
+    pub irq: bool,
+    pub pal: bool,
+}
+
+impl FrameCounter {
+    // `odd_cycle` is true when the write lands on the second half of an
+    // APU cycle, which delays the restart by one more CPU cycle
+    pub fn write(&mut self, value: u8, odd_cycle: bool) {
+        self.five_step = value & 0b1000_0000 != 0;
+        self.irq_inhibit = value & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.reset_delay = Some(if odd_cycle { 4 } else { 3 });
+    }
+
+    // advances one CPU cycle
+    pub fn tick(&mut self) -> FrameClock {
+        if let Some(delay) = self.reset_delay {
+            if delay <= 1 {
+                self.reset_delay = None;
+                self.cycle = 0;
+                return if self.five_step { HALF } else { NONE };
+            }
+            self.reset_delay = Some(delay - 1);
+        }
+
+        let steps = if self.pal { &PAL_STEPS } else { &NTSC_STEPS };
+        self.cycle += 1;
+        let four_step_end = steps[3] - 1..=steps[3] + 1;
+        if !self.five_step && four_step_end.contains(&self.cycle) && !self.irq_inhibit {
+            self.irq = true;
+        }
+
+        let clock = if self.cycle == steps[0] || self.cycle == steps[2] {
+            QUARTER
+        } else if self.cycle == steps[1]
+            || (self.cycle == steps[3] && !self.five_step)
+            || (self.cycle == steps[4] && self.five_step)
+        {
+            HALF
+        } else {
+            NONE
+        };
+
+        let length = if self.five_step { steps[4] } else { steps[3] } + 1;
+        if self.cycle >= length {
+            self.cycle = 0;
+        }
+        clock
+    }
+}
+
+impl Snapshot for FrameCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.cycle as u32);
+        state.write_bool(self.five_step);
+        state.write_bool(self.irq_inhibit);
+        state.write_option(self.reset_delay);
+        state.write_bool(self.irq);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = state.read_u32()? as usize;
+        self.five_step = state.read_bool()?;
+        self.irq_inhibit = state.read_bool()?;
+        self.reset_delay = state.read_option()?;
+        self.irq = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    // cycles (counting from 1) at which quarter and half frames happen
+    fn run(counter: &mut FrameCounter, cycles: usize) -> (Vec<usize>, Vec<usize>) {
+        let mut quarters = vec![];
+        let mut halves = vec![];
+        for cycle in 1..=cycles {
+            let clock = counter.tick();
+            if clock.quarter {
+                quarters.push(cycle);
+            }
+            if clock.half {
+                halves.push(cycle);
+            }
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut counter = FrameCounter::default();
+        let (quarters, halves) = run(&mut counter, 2 * FOUR_STEP_LENGTH);
+        assert_eq!(quarters[..4], [7457, 14913, 22371, 29829]);
+        assert_eq!(quarters[4], FOUR_STEP_LENGTH + 7457);
+        assert_eq!(halves, vec![14913, 29829, 29830 + 14913, 29830 + 29829]);
+    }
+
+    #[test]
+    fn test_four_step_irq() {
+        let mut counter = FrameCounter::default();
+        run(&mut counter, 29827);
+        assert!(!counter.irq);
+        counter.tick();
+        assert!(counter.irq);
+
+        // inhibiting clears the flag and stops it being set again
+        counter.write(0b0100_0000, false);
+        assert!(!counter.irq);
+        run(&mut counter, 2 * FOUR_STEP_LENGTH);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut counter = FrameCounter::default();
+        counter.write(0b1000_0000, false);
+
+        // the restart lands on the third cycle and clocks everything
+        let (quarters, halves) = run(&mut counter, 3 + FIVE_STEP_LENGTH);
+        assert_eq!(quarters, vec![3, 3 + 7457, 3 + 14913, 3 + 22371, 3 + 37281]);
+        assert_eq!(halves, vec![3, 3 + 14913, 3 + 37281]);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn test_pal_sequence() {
+        let mut counter = FrameCounter {
+            pal: true,
+            ..Default::default()
+        };
+        let (quarters, halves) = run(&mut counter, 33254 + 8313);
+        assert_eq!(quarters, vec![8313, 16627, 24939, 33253, 33254 + 8313]);
+        assert_eq!(halves, vec![16627, 33253]);
+        assert!(counter.irq);
+    }
+
+    #[test]
+    fn test_reset_delay() {
+        let mut counter = FrameCounter::default();
+        counter.write(0, true);
+        let (quarters, _) = run(&mut counter, 4 + 7457);
+        assert_eq!(quarters, vec![4 + 7457]);
+    }
+}
@@ -0,0 +1,498 @@
+pub mod dmc;
+pub mod frame_counter;
+pub mod noise;
+pub mod pulse;
+pub mod scope;
+pub mod stereo;
+pub mod triangle;
+pub mod units;
+
+use alloc::boxed::Box;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use dmc::Dmc;
+use frame_counter::FrameCounter;
+use noise::Noise;
+use pulse::Pulse;
+use scope::ChannelScope;
+use triangle::Triangle;
+
+// Audio processing unit of the 2A03
+// from: https://www.nesdev.org/wiki/APU
+//
+// | Address     | Channel                                           |
+// |-------------|---------------------------------------------------|
+// | $4000-$4003 | Pulse 1                                           |
+// | $4004-$4007 | Pulse 2                                           |
+// | $4008-$400B | Triangle                                          |
+// | $400C-$400F | Noise                                             |
+// | $4010-$4013 | DMC                                               |
+// | $4015       | Channel enable (write), length status (read)      |
+// | $4017       | Frame counter                                     |
+
+// The five sound channels of the 2A03, in register order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+}
+
+// What an audio debugger shows of a channel, see APU::channel_state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelState {
+    pub channel: Channel,
+    // false when muted with APU::set_channel_enabled
+    pub enabled: bool,
+    // timer period: APU cycles for the pulses, CPU cycles for the rest
+    pub period: u16,
+    // envelope or constant volume, 0-15; the triangle and DMC have none
+    pub volume: Option<u8>,
+    // half frames left of the note, 0 when it's over; bytes left of the
+    // sample for the DMC
+    pub length: u16,
+    // current output level, as in Levels
+    pub output: u8,
+}
+
+impl ChannelState {
+    // pitch of a pulse or triangle note given the CPU clock rate; the noise
+    // and DMC don't have one
+    pub fn frequency(&self, cpu_clock: f64) -> Option<f64> {
+        let steps = match self.channel {
+            Channel::Pulse1 | Channel::Pulse2 => 16.0,
+            Channel::Triangle => 32.0,
+            Channel::Noise | Channel::Dmc => return None,
+        };
+        Some(cpu_clock / (steps * (self.period as f64 + 1.0)))
+    }
+}
+
+// Raw output level of every channel, indexed by Channel. Pulses, triangle
+// and noise go from 0 to 15, the DMC from 0 to 127.
+pub type Levels = [u8; 5];
+
+// gets the 2A03's levels and the cartridge's sound, already mixed
+type SampleCallback = dyn FnMut(Levels, f32);
+
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    frame_counter: FrameCounter,
+    // the pulse timers run at half the CPU clock
+    even_cycle: bool,
+    sample_callback: Option<Box<SampleCallback>>,
+    // channels muted by the frontend, indexed by Channel
+    muted: [bool; 5],
+    // the cartridge's own sound, see Mapper::audio_output
+    expansion: f32,
+    // each channel's recent output, for a frontend's visualizer
+    pub scope: Option<ChannelScope>,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame_counter: FrameCounter::default(),
+            even_cycle: false,
+            sample_callback: None,
+            muted: [false; 5],
+            expansion: 0.0,
+            scope: None,
+        }
+    }
+
+    // called once per CPU cycle with the channels' output levels and the
+    // cartridge's sound, which audio::mix adds to theirs
+    pub fn set_sample_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(Levels, f32) + 'static,
+    {
+        self.sample_callback = Some(Box::new(callback));
+    }
+
+    // Sound from the cartridge for the next cycles, on the scale of
+    // audio::mix; the BUS sets it from Mapper::audio_output
+    pub fn set_expansion_output(&mut self, output: f32) {
+        self.expansion = output;
+    }
+
+    // reset silences every channel, as if $4015 was written with 0
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_counter.irq = false;
+    }
+
+    // power on puts the channels and the frame counter back as they were
+    // made, keeping the region and what the frontend set up
+    pub fn power_on(&mut self) {
+        let pal = self.frame_counter.pal;
+        self.pulse1 = Pulse::new(true);
+        self.pulse2 = Pulse::new(false);
+        self.triangle = Triangle::default();
+        self.noise = Noise::default();
+        self.dmc = Dmc::default();
+        self.frame_counter = FrameCounter::default();
+        self.even_cycle = false;
+        self.expansion = 0.0;
+        self.set_pal(pal);
+    }
+
+    // the PAL APU counts its frames and noise and DMC periods differently
+    pub fn set_pal(&mut self, pal: bool) {
+        self.frame_counter.pal = pal;
+        self.noise.pal = pal;
+        self.dmc.pal = pal;
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, value),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, value),
+            0x4008..=0x400b => self.triangle.write(addr - 0x4008, value),
+            0x400c..=0x400f => self.noise.write(addr - 0x400c, value),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, value),
+            0x4015 => {
+                self.pulse1.length.set_enabled(value & 0b0_0001 != 0);
+                self.pulse2.length.set_enabled(value & 0b0_0010 != 0);
+                self.triangle.length.set_enabled(value & 0b0_0100 != 0);
+                self.noise.length.set_enabled(value & 0b0_1000 != 0);
+                self.dmc.set_enabled(value & 0b1_0000 != 0);
+            }
+            0x4017 => self.frame_counter.write(value, self.even_cycle),
+            _ => {}
+        }
+    }
+
+    // IF-D NT21: DMC IRQ, frame IRQ, DMC active, length counters
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_counter.irq = false;
+        status
+    }
+
+    // $4015 without acknowledging the frame IRQ
+    pub fn peek_status(&self) -> u8 {
+        (self.pulse1.length.is_active() as u8)
+            | (self.pulse2.length.is_active() as u8) << 1
+            | (self.triangle.length.is_active() as u8) << 2
+            | (self.noise.length.is_active() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (self.frame_counter.irq as u8) << 6
+            | (self.dmc.irq as u8) << 7
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.dmc.irq || self.frame_counter.irq
+    }
+
+    // muted channels are at 0
+    pub fn levels(&self) -> Levels {
+        let mut levels = [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ];
+        for (level, &muted) in levels.iter_mut().zip(self.muted.iter()) {
+            if muted {
+                *level = 0;
+            }
+        }
+        levels
+    }
+
+    // Mutes or unmutes a channel in the output only: it keeps running, so
+    // the game can't tell, and unmuting picks up wherever it is
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.muted[channel as usize] = !enabled;
+    }
+
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        let (period, volume, length, output) = match channel {
+            Channel::Pulse1 => (
+                self.pulse1.period(),
+                Some(self.pulse1.envelope.volume()),
+                self.pulse1.length.remaining() as u16,
+                self.pulse1.output(),
+            ),
+            Channel::Pulse2 => (
+                self.pulse2.period(),
+                Some(self.pulse2.envelope.volume()),
+                self.pulse2.length.remaining() as u16,
+                self.pulse2.output(),
+            ),
+            Channel::Triangle => (
+                self.triangle.period(),
+                None,
+                self.triangle.length.remaining() as u16,
+                self.triangle.output(),
+            ),
+            Channel::Noise => (
+                self.noise.period(),
+                Some(self.noise.envelope.volume()),
+                self.noise.length.remaining() as u16,
+                self.noise.output(),
+            ),
+            Channel::Dmc => (
+                self.dmc.period(),
+                None,
+                self.dmc.bytes_remaining(),
+                self.dmc.output(),
+            ),
+        };
+        ChannelState {
+            channel,
+            enabled: !self.muted[channel as usize],
+            period,
+            volume,
+            length,
+            output,
+        }
+    }
+
+    // `read` fetches DMC sample bytes from the CPU bus
+    pub fn tick<F>(&mut self, cycles: u8, mut read: F)
+    where
+        F: FnMut(u16) -> u8,
+    {
+        for _ in 0..cycles {
+            let clock = self.frame_counter.tick();
+            if clock.quarter {
+                self.pulse1.clock_quarter_frame();
+                self.pulse2.clock_quarter_frame();
+                self.triangle.clock_quarter_frame();
+                self.noise.clock_quarter_frame();
+            }
+            if clock.half {
+                self.pulse1.clock_half_frame();
+                self.pulse2.clock_half_frame();
+                self.triangle.clock_half_frame();
+                self.noise.clock_half_frame();
+            }
+
+            if self.even_cycle {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+            }
+            self.even_cycle = !self.even_cycle;
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+
+            if let Some(addr) = self.dmc.pending_read() {
+                let value = read(addr);
+                self.dmc.load_sample_byte(value);
+            }
+
+            if self.sample_callback.is_some() || self.scope.is_some() {
+                let levels = self.levels();
+                if let Some(callback) = self.sample_callback.as_mut() {
+                    callback(levels, self.expansion);
+                }
+                if let Some(scope) = self.scope.as_mut() {
+                    scope.push(&levels);
+                }
+            }
+        }
+    }
+}
+
+// the APU's part of a MachineState
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ApuState {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    pub even_cycle: bool,
+}
+
+#[cfg(feature = "serde")]
+impl APU {
+    pub fn state(&self) -> ApuState {
+        ApuState {
+            pulse1: self.pulse1.clone(),
+            pulse2: self.pulse2.clone(),
+            triangle: self.triangle.clone(),
+            noise: self.noise.clone(),
+            dmc: self.dmc.clone(),
+            frame_counter: self.frame_counter.clone(),
+            even_cycle: self.even_cycle,
+        }
+    }
+
+    // the region's timings stay as they are, like the sample callback
+    pub fn set_state(&mut self, state: &ApuState) {
+        let (noise_pal, dmc_pal, frame_counter_pal) =
+            (self.noise.pal, self.dmc.pal, self.frame_counter.pal);
+        self.pulse1 = state.pulse1.clone();
+        self.pulse2 = state.pulse2.clone();
+        self.triangle = state.triangle.clone();
+        self.noise = state.noise.clone();
+        self.dmc = state.dmc.clone();
+        self.frame_counter = state.frame_counter.clone();
+        self.even_cycle = state.even_cycle;
+        self.noise.pal = noise_pal;
+        self.dmc.pal = dmc_pal;
+        self.frame_counter.pal = frame_counter_pal;
+    }
+}
+
+// the sample callback belongs to the frontend and stays as it is
+impl Snapshot for APU {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        self.frame_counter.save_state(state);
+        state.write_bool(self.even_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter.load_state(state)?;
+        self.even_cycle = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_status_reflects_length_counters() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b11);
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b01);
+        apu.write_register(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b11);
+
+        apu.write_register(0x4015, 0b10);
+        assert_eq!(apu.read_status(), 0b10);
+    }
+
+    #[test]
+    fn test_sample_callback() {
+        let samples = Rc::new(RefCell::new(vec![]));
+        let mut apu = APU::new();
+        let sink = samples.clone();
+        apu.set_sample_callback(move |levels, _| sink.borrow_mut().push(levels));
+
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b1101_1111);
+        apu.write_register(0x4002, 0x40);
+        apu.write_register(0x4003, 0x00);
+        apu.tick(200, |_| 0);
+
+        let samples = samples.borrow();
+        assert_eq!(samples.len(), 200);
+        assert!(samples.iter().any(|levels| levels[0] == 15));
+        assert!(samples.iter().any(|levels| levels[0] == 0));
+        assert!(samples.iter().all(|levels| levels[1] == 0));
+    }
+
+    #[test]
+    fn test_channel_state_and_muting() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b1101_1111);
+        apu.write_register(0x4002, 0xfd);
+        apu.write_register(0x4003, 0x00);
+
+        let state = apu.channel_state(Channel::Pulse1);
+        assert_eq!(state.period, 0xfd);
+        assert_eq!(state.volume, Some(15));
+        assert_eq!(state.length, 10);
+        // A440 at the NTSC CPU clock
+        let frequency = state.frequency(1_789_773.0).unwrap();
+        assert!((frequency - 440.0).abs() < 1.0);
+
+        apu.set_channel_enabled(Channel::Pulse1, false);
+        assert!(!apu.channel_state(Channel::Pulse1).enabled);
+        for _ in 0..16 {
+            apu.tick(1, |_| 0);
+            assert_eq!(apu.levels()[0], 0);
+        }
+        apu.set_channel_enabled(Channel::Pulse1, true);
+        assert!(apu.channel_state(Channel::Pulse1).enabled);
+    }
+
+    #[test]
+    fn test_dmc_reads_through_the_bus() {
+        let mut apu = APU::new();
+        apu.write_register(0x4012, 0x02);
+        apu.write_register(0x4013, 0x01);
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!(apu.read_status() & 0b1_0000, 0b1_0000);
+
+        let mut reads = vec![];
+        apu.tick(1, |addr| {
+            reads.push(addr);
+            0
+        });
+        assert_eq!(reads, vec![0xc080]);
+
+        // 17 bytes, one per 8 bits played
+        apu.write_register(0x4017, 0b0100_0000);
+        for _ in 0..16 * 8 * 428 {
+            apu.tick(1, |_| 0);
+        }
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status(), 0b1000_0000);
+    }
+
+    #[test]
+    fn test_reading_status_acknowledges_frame_irq() {
+        let mut apu = APU::new();
+        apu.tick(255, |_| 0);
+        for _ in 0..200 {
+            apu.tick(150, |_| 0);
+        }
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status(), 0b0100_0000);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read_status(), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use super::units::{Envelope, LengthCounter};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Noise channel at $400C-$400F
+// from: https://www.nesdev.org/wiki/APU_Noise
+//
+// | Register | Bits      | Contents                                         |
+// |----------|-----------|--------------------------------------------------|
+// | $400C    | --LC VVVV | Length halt / envelope loop, constant volume,    |
+// |          |           | volume or envelope period                        |
+// | $400E    | M--- PPPP | Mode, timer period index                         |
+// | $400F    | LLLL L--- | Length counter load                              |
+//
+// A 15-bit LFSR shifts on every timer expiry, its feedback taken from bits
+// 0 and 1, or bits 0 and 6 in mode 1 which gives a short metallic loop.
+
+// NTSC timer periods, in CPU cycles
+const PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    short_mode: bool,
+    shift: u16,
+    timer: u16,
+    timer_period: u16,
+    pub pal: bool,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            short_mode: false,
+            shift: 1,
+            timer: 0,
+            timer_period: PERIODS[0],
+            pal: false,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+}
+
+impl Noise {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length.halt = value & 0b0010_0000 != 0;
+                self.envelope.write(value);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = value & 0b1000_0000 != 0;
+                let periods = if self.pal { &PAL_PERIODS } else { &PERIODS };
+                self.timer_period = periods[(value & 0b1111) as usize];
+            }
+            _ => {
+                self.length.load(value >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // in CPU cycles
+    pub fn period(&self) -> u16 {
+        self.timer_period
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift & 1 != 0 || !self.length.is_active() {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+impl Snapshot for Noise {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.short_mode);
+        state.write_u16(self.shift);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.short_mode = state.read_bool()?;
+        self.shift = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn period_of(short_mode: bool) -> usize {
+        let mut noise = Noise {
+            short_mode,
+            ..Noise::default()
+        };
+        let start = noise.shift;
+        (1..=32767)
+            .find(|_| {
+                noise.timer = 0;
+                noise.clock_timer();
+                noise.shift == start
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lfsr_periods() {
+        assert_eq!(period_of(false), 32767);
+        assert_eq!(period_of(true), 93);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut noise = Noise::default();
+        noise.length.set_enabled(true);
+        noise.write(0, 0b0001_0101);
+        noise.write(3, 0b0000_1000);
+
+        // bit 0 of the shift register silences the channel
+        assert_eq!(noise.output(), 0);
+        noise.shift = 0b10;
+        assert_eq!(noise.output(), 5);
+    }
+}
@@ -0,0 +1,233 @@
+use super::units::{Envelope, LengthCounter};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Square wave channels at $4000-$4003 and $4004-$4007
+// from: https://www.nesdev.org/wiki/APU_Pulse
+//
+// | Register    | Bits      | Contents                                      |
+// |-------------|-----------|-----------------------------------------------|
+// | $4000/$4004 | DDLC VVVV | Duty, length halt / envelope loop, constant   |
+// |             |           | volume, volume or envelope period             |
+// | $4001/$4005 | EPPP NSSS | Sweep enable, period, negate, shift           |
+// | $4002/$4006 | TTTT TTTT | Timer low                                     |
+// | $4003/$4007 | LLLL LTTT | Length counter load, timer high               |
+#[rustfmt::skip]
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    // pulse 1 negates its sweep with ones' complement, pulse 2 with two's
+    ones_complement: bool,
+    duty: u8,
+    sequence_step: u8,
+    timer: u16,
+    timer_period: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence_step: 0,
+            timer: 0,
+            timer_period: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    // `register` is the offset from the channel's first register, 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.halt = value & 0b0010_0000 != 0;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0b1000_0000 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0b1000 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | (((value & 0b111) as u16) << 8);
+                self.length.load(value >> 3);
+                self.sequence_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // clocked every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.timer_period + change
+        } else if self.ones_complement {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    // the sweep unit silences notes that are too high or would overflow,
+    // even when it isn't enabled
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7ff
+    }
+
+    // in APU cycles, two CPU cycles each
+    pub fn period(&self) -> u16 {
+        self.timer_period
+    }
+
+    pub fn output(&self) -> u8 {
+        let high = DUTY_SEQUENCES[self.duty as usize][self.sequence_step as usize] != 0;
+        if !high || !self.length.is_active() || self.is_muted() {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.duty);
+        state.write_u8(self.sequence_step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_bool(self.sweep_enabled);
+        state.write_u8(self.sweep_period);
+        state.write_bool(self.sweep_negate);
+        state.write_u8(self.sweep_shift);
+        state.write_u8(self.sweep_divider);
+        state.write_bool(self.sweep_reload);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.duty = state.read_u8()?;
+        self.sequence_step = state.read_u8()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.sweep_enabled = state.read_bool()?;
+        self.sweep_period = state.read_u8()?;
+        self.sweep_negate = state.read_bool()?;
+        self.sweep_shift = state.read_u8()?;
+        self.sweep_divider = state.read_u8()?;
+        self.sweep_reload = state.read_bool()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    fn playing_pulse(duty: u8, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(true);
+        pulse.length.set_enabled(true);
+        pulse.write(0, (duty << 6) | 0b0001_1010);
+        pulse.write(2, period as u8);
+        pulse.write(3, (period >> 8) as u8);
+        pulse
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let mut pulse = playing_pulse(2, 8);
+        let mut waveform = vec![];
+        for _ in 0..8 {
+            waveform.push(pulse.output());
+            for _ in 0..9 {
+                pulse.clock_timer();
+            }
+        }
+        assert_eq!(waveform, vec![0, 10, 10, 10, 10, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut pulse = playing_pulse(2, 0x100);
+        // enabled, period 0, add period >> 1
+        pulse.write(1, 0b1000_0001);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x180);
+
+        // pulse 1 subtracts one more than pulse 2
+        pulse.write(1, 0b1000_1001);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x180 - 0xc0 - 1);
+    }
+
+    #[test]
+    fn test_muting() {
+        let mut pulse = playing_pulse(3, 7);
+        assert_eq!(pulse.output(), 0, "period below 8");
+
+        pulse = playing_pulse(3, 0x600);
+        pulse.write(1, 0b0000_1000);
+        assert_eq!(pulse.output(), 10);
+        // a sweep target past $7FF mutes the channel even when disabled
+        pulse.write(1, 0b0000_0001);
+        assert_eq!(pulse.output(), 0);
+    }
+}
@@ -0,0 +1,196 @@
+use super::{Channel, Levels};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// The recent output of each channel on its own, for frontends that draw an
+// oscilloscope per channel. Levels are averaged down to about the audio
+// sample rate and scaled to 0.0-1.0, the DMC from its 0-127 and the rest
+// from 0-15, so every channel fills the same height. Muted channels stay
+// at 0 like in APU::levels.
+pub struct ChannelScope {
+    buffers: [Vec<f32>; 5],
+    // where the next sample goes in every buffer
+    write: usize,
+    len: usize,
+    // CPU cycles per sample
+    step: f64,
+    position: f64,
+    sums: [u32; 5],
+    count: u32,
+}
+
+impl ChannelScope {
+    // the last `capacity` samples of each channel at `sample_rate`, from
+    // levels at `clock_rate` a second
+    pub fn new(capacity: usize, sample_rate: u32, clock_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        ChannelScope {
+            buffers: core::array::from_fn(|_| vec![0.0; capacity]),
+            write: 0,
+            len: 0,
+            step: clock_rate / sample_rate as f64,
+            position: 0.0,
+            sums: [0; 5],
+            count: 0,
+        }
+    }
+
+    // for a region with another CPU clock
+    pub fn set_rates(&mut self, sample_rate: u32, clock_rate: f64) {
+        self.step = clock_rate / sample_rate as f64;
+    }
+
+    // one CPU cycle's levels
+    pub fn push(&mut self, levels: &Levels) {
+        for (sum, &level) in self.sums.iter_mut().zip(levels.iter()) {
+            *sum += level as u32;
+        }
+        self.count += 1;
+        self.position += 1.0;
+        if self.position < self.step {
+            return;
+        }
+        self.position -= self.step;
+
+        for (i, buffer) in self.buffers.iter_mut().enumerate() {
+            let range = if i == Channel::Dmc as usize {
+                127.0
+            } else {
+                15.0
+            };
+            buffer[self.write] = self.sums[i] as f32 / self.count as f32 / range;
+        }
+        self.sums = [0; 5];
+        self.count = 0;
+        self.write = (self.write + 1) % self.capacity();
+        self.len = (self.len + 1).min(self.capacity());
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffers[0].len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // oldest first, up to `capacity` samples
+    pub fn waveform(&self, channel: Channel) -> Vec<f32> {
+        let buffer = &self.buffers[channel as usize];
+        let start = (self.write + self.capacity() - self.len) % self.capacity();
+        (0..self.len)
+            .map(|i| buffer[(start + i) % self.capacity()])
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.write = 0;
+        self.len = 0;
+        self.sums = [0; 5];
+        self.count = 0;
+        self.position = 0.0;
+    }
+}
+
+// Magnitudes of the frequencies in `samples`, for a spectrum display: bin i
+// is i * sample_rate / n Hz, for the first n / 2 bins where n is the
+// largest power of two that fits in `samples`. A Hann window keeps a tone
+// between two bins from smearing over the rest.
+// from: https://en.wikipedia.org/wiki/Cooley%E2%80%93Tukey_FFT_algorithm
+#[cfg(feature = "fft")]
+pub fn spectrum(samples: &[f32]) -> Vec<f32> {
+    use core::f32::consts::PI;
+
+    if samples.len() < 2 {
+        return Vec::new();
+    }
+    let n = 1 << (usize::BITS - 1 - samples.len().leading_zeros());
+    // the offset of a scope waveform would all go to bin 0
+    let mean = samples[..n].iter().sum::<f32>() / n as f32;
+    let mut re: Vec<f32> = samples[..n]
+        .iter()
+        .enumerate()
+        .map(|(i, &sample)| {
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos();
+            (sample - mean) * window
+        })
+        .collect();
+    let mut im = vec![0.0f32; n];
+
+    // bit reversed order, then butterflies of doubling size
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            re.swap(i, j);
+        }
+    }
+    let mut size = 2;
+    while size <= n {
+        let angle = -2.0 * PI / size as f32;
+        for start in (0..n).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + size / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        size *= 2;
+    }
+
+    (0..n / 2)
+        .map(|i| (re[i] * re[i] + im[i] * im[i]).sqrt() * 2.0 / n as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scope_averages_and_wraps() {
+        // 4 cycles a sample, 3 samples kept
+        let mut scope = ChannelScope::new(3, 1, 4.0);
+        for i in 0..20u8 {
+            scope.push(&[15, (i % 2) * 15, 0, 3, 127]);
+        }
+        assert_eq!(scope.len(), 3);
+        assert_eq!(scope.waveform(Channel::Pulse1), [1.0, 1.0, 1.0]);
+        assert_eq!(scope.waveform(Channel::Pulse2), [0.5, 0.5, 0.5]);
+        assert_eq!(scope.waveform(Channel::Triangle), [0.0, 0.0, 0.0]);
+        assert_eq!(scope.waveform(Channel::Noise), [0.2, 0.2, 0.2]);
+        assert_eq!(scope.waveform(Channel::Dmc), [1.0, 1.0, 1.0]);
+        scope.clear();
+        assert!(scope.is_empty());
+        assert!(scope.waveform(Channel::Pulse1).is_empty());
+    }
+
+    #[cfg(feature = "fft")]
+    #[test]
+    fn test_spectrum() {
+        use core::f32::consts::PI;
+
+        // a 1kHz tone at 16kHz lands in bin 1000 / (16000 / 256) = 16
+        let tone: Vec<f32> = (0..300)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let bins = spectrum(&tone);
+        assert_eq!(bins.len(), 128);
+        let peak = (0..bins.len())
+            .max_by(|&a, &b| bins[a].total_cmp(&bins[b]))
+            .unwrap();
+        assert_eq!(peak, 16);
+        assert!((bins[16] - 0.5).abs() < 0.05, "{}", bins[16]);
+        assert!(bins[40] < 0.01);
+        assert!(spectrum(&[1.0]).is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use super::Channel;
+
+// Optional stereo spread applied after the console's mono mix. The NES only
+// has one output, so this is purely an enhancement: with a width of 0 (the
+// default) the mono sample goes to both sides unchanged.
+//
+// Each channel gets a pan position from -1.0 (left) to 1.0 (right), scaled
+// by the width. The mono sample is split between the channels in proportion
+// to their raw levels, which keeps the non-linear mix and overall loudness
+// of the real mixer, and each share is then panned:
+//   left  = min(1, 1 - pan * width)
+//   right = min(1, 1 + pan * width)
+// so a centred channel is as loud on both sides as it is in mono.
+pub struct StereoMixer {
+    pans: [f32; 5],
+    width: f32,
+}
+
+impl Default for StereoMixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StereoMixer {
+    pub fn new() -> Self {
+        StereoMixer {
+            // pulses either side, bass in the middle
+            pans: [-0.5, 0.5, 0.0, 0.25, -0.25],
+            width: 0.0,
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0.0, 1.0);
+    }
+
+    pub fn pan(&self, channel: Channel) -> f32 {
+        self.pans[channel as usize]
+    }
+
+    pub fn set_pan(&mut self, channel: Channel, pan: f32) {
+        self.pans[channel as usize] = pan.clamp(-1.0, 1.0);
+    }
+
+    // `levels` are the channels' raw outputs that went into `mono`
+    pub fn mix(&self, mono: f32, levels: &[f32; 5]) -> (f32, f32) {
+        let total: f32 = levels.iter().sum();
+        if self.width == 0.0 || total <= 0.0 {
+            return (mono, mono);
+        }
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (level, pan) in levels.iter().zip(self.pans.iter()) {
+            let share = mono * level / total;
+            let pan = pan * self.width;
+            left += share * (1.0 - pan).min(1.0);
+            right += share * (1.0 + pan).min(1.0);
+        }
+        (left, right)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_is_mono() {
+        let mixer = StereoMixer::new();
+        assert_eq!(mixer.mix(0.3, &[15.0, 4.0, 9.0, 2.0, 60.0]), (0.3, 0.3));
+    }
+
+    #[test]
+    fn test_spread() {
+        let mut mixer = StereoMixer::new();
+        mixer.set_width(1.0);
+        mixer.set_pan(Channel::Pulse1, -1.0);
+
+        // only pulse 1 playing: hard left
+        assert_eq!(mixer.mix(0.5, &[15.0, 0.0, 0.0, 0.0, 0.0]), (0.5, 0.0));
+        // centred channels are unchanged
+        assert_eq!(mixer.mix(0.5, &[0.0, 0.0, 15.0, 0.0, 0.0]), (0.5, 0.5));
+
+        // half the width halves how far a channel moves
+        mixer.set_width(0.5);
+        assert_eq!(mixer.mix(0.5, &[15.0, 0.0, 0.0, 0.0, 0.0]), (0.5, 0.25));
+    }
+}
@@ -0,0 +1,140 @@
+use super::units::LengthCounter;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Triangle channel at $4008-$400B
+// from: https://www.nesdev.org/wiki/APU_Triangle
+//
+// | Register | Bits      | Contents                                         |
+// |----------|-----------|--------------------------------------------------|
+// | $4008    | CRRR RRRR | Length halt / linear control, linear reload value |
+// | $400A    | TTTT TTTT | Timer low                                        |
+// | $400B    | LLLL LTTT | Length counter load, timer high                  |
+//
+// No volume control: it steps through a 32-step triangle whenever both the
+// linear and the length counter are non-zero, and holds its level otherwise.
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Triangle {
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer: u16,
+    timer_period: u16,
+    sequence_step: u8,
+    pub length: LengthCounter,
+}
+
+impl Triangle {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0b1000_0000 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = value & 0x7f;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | (((value & 0b111) as u16) << 8);
+                self.length.load(value >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear_counter > 0 && self.length.is_active() {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // in CPU cycles, one of the 32 steps each
+    pub fn period(&self) -> u16 {
+        self.timer_period
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.control);
+        state.write_u8(self.linear_reload_value);
+        state.write_u8(self.linear_counter);
+        state.write_bool(self.linear_reload);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_u8(self.sequence_step);
+        self.length.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.control = state.read_bool()?;
+        self.linear_reload_value = state.read_u8()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_reload = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.sequence_step = state.read_u8()?;
+        self.length.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_steps_only_while_both_counters_run() {
+        let mut triangle = Triangle::default();
+        triangle.length.set_enabled(true);
+        triangle.write(0, 0x02);
+        triangle.write(2, 0);
+        triangle.write(3, 0b0000_1000);
+
+        // the linear counter hasn't been reloaded yet
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+
+        // two more quarter frames empty the linear counter
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Building blocks shared by several APU channels
+// from: https://www.nesdev.org/wiki/APU_Envelope
+//       https://www.nesdev.org/wiki/APU_Length_Counter
+
+// Note lengths picked by the top five bits of $4003/$4007/$400B/$400F
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel once its note has played for long enough. Clocked by
+// the frame counter's half frames unless halted.
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1f) as usize];
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn remaining(&self) -> u8 {
+        self.counter
+    }
+}
+
+// Either a constant volume or a sawtooth decaying from 15 to 0, optionally
+// looping. Clocked by the frame counter's quarter frames.
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool,
+    pub constant: bool,
+    // constant volume, or the decay divider's period
+    pub period: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // the --LC VVVV bits of $4000/$4004/$400C
+    pub fn write(&mut self, value: u8) {
+        self.looping = value & 0b0010_0000 != 0;
+        self.constant = value & 0b0001_0000 != 0;
+        self.period = value & 0b1111;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+        } else if self.divider == 0 {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        if self.constant {
+            self.period
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.halt);
+        state.write_u8(self.counter);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.halt = state.read_bool()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start);
+        state.write_bool(self.looping);
+        state.write_bool(self.constant);
+        state.write_u8(self.period);
+        state.write_u8(self.divider);
+        state.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.constant = state.read_bool()?;
+        self.period = state.read_u8()?;
+        self.divider = state.read_u8()?;
+        self.decay = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter() {
+        let mut length = LengthCounter::default();
+        length.load(1);
+        assert!(!length.is_active(), "disabled channels don't load");
+
+        length.set_enabled(true);
+        length.load(3);
+        assert!(length.is_active());
+        length.clock();
+        assert!(length.is_active());
+        length.clock();
+        assert!(!length.is_active());
+
+        length.load(0);
+        length.halt = true;
+        for _ in 0..20 {
+            length.clock();
+        }
+        assert!(length.is_active());
+        length.set_enabled(false);
+        assert!(!length.is_active());
+    }
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0000_0001);
+        envelope.start = true;
+
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        // the divider counts 1, 0 before each step down
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 14);
+        for _ in 0..28 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 0, "no looping");
+
+        envelope.write(0b0010_0001);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+
+        envelope.write(0b0001_0111);
+        assert_eq!(envelope.volume(), 7);
+    }
+}
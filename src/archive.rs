@@ -0,0 +1,234 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// ROM files as they're usually kept: in .zip or .7z archives, one game to
+// an archive. The first .nes file in one is what gets loaded; anything that
+// isn't an archive is taken to be the ROM itself. Archives are recognized by
+// their signature rather than the file's extension, and opening them needs
+// the `zip` or `sevenz` feature.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveError {
+    Io(io::ErrorKind),
+    // an archive without the feature to open it, by its format
+    Unsupported(&'static str),
+    // an archive with no .nes file in it
+    NoRom,
+    // what the decompressor made of a damaged archive
+    Corrupt(String),
+    // a .nes file bigger than any game, most likely a decompression bomb
+    TooLarge,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Io(kind) => write!(f, "could not read the file: {}", kind),
+            ArchiveError::Unsupported(format) => write!(
+                f,
+                "{} archives need the emulator built with the {} feature",
+                format,
+                match *format {
+                    "7z" => "sevenz",
+                    _ => "zip",
+                }
+            ),
+            ArchiveError::NoRom => write!(f, "the archive has no .nes file in it"),
+            ArchiveError::Corrupt(err) => write!(f, "the archive is damaged: {}", err),
+            ArchiveError::TooLarge => write!(f, "the .nes file in the archive is too big"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        ArchiveError::Io(err.kind())
+    }
+}
+
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+const SEVENZ_SIGNATURE: &[u8] = b"7z\xbc\xaf\x27\x1c";
+
+// well past the biggest NES 2.0 ROMs there are
+#[cfg(any(feature = "zip", feature = "sevenz", test))]
+const MAX_ROM_SIZE: u64 = 16 << 20;
+
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+fn is_rom(name: &str) -> bool {
+    name.to_ascii_lowercase().ends_with(".nes")
+}
+
+// An entry's data, `size` long by the archive, which isn't trusted any
+// further than MAX_ROM_SIZE
+#[cfg(any(feature = "zip", feature = "sevenz", test))]
+fn read_entry<R: io::Read>(reader: R, size: u64) -> Result<Vec<u8>, ArchiveError> {
+    use std::io::Read;
+
+    if size > MAX_ROM_SIZE {
+        return Err(ArchiveError::TooLarge);
+    }
+    let mut data = Vec::with_capacity(size as usize);
+    reader.take(MAX_ROM_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_ROM_SIZE {
+        return Err(ArchiveError::TooLarge);
+    }
+    Ok(data)
+}
+
+// The ROM in the file at `path`, out of its archive if it's in one
+pub fn read_rom<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, ArchiveError> {
+    extract_rom(std::fs::read(path)?)
+}
+
+// The same for a file already read
+pub fn extract_rom(bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+    if bytes.starts_with(ZIP_SIGNATURE) {
+        extract_zip(bytes)
+    } else if bytes.starts_with(SEVENZ_SIGNATURE) {
+        extract_7z(bytes)
+    } else {
+        Ok(bytes)
+    }
+}
+
+#[cfg(feature = "zip")]
+fn extract_zip(bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+    let corrupt = |err: zip::result::ZipError| ArchiveError::Corrupt(err.to_string());
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes)).map_err(corrupt)?;
+    for index in 0..archive.len() {
+        let file = archive.by_index(index).map_err(corrupt)?;
+        if file.is_file() && is_rom(file.name()) {
+            let size = file.size();
+            return read_entry(file, size);
+        }
+    }
+    Err(ArchiveError::NoRom)
+}
+
+#[cfg(not(feature = "zip"))]
+fn extract_zip(_bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+    Err(ArchiveError::Unsupported("zip"))
+}
+
+#[cfg(feature = "sevenz")]
+fn extract_7z(bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+    use sevenz_rust::{Password, SevenZReader};
+
+    let corrupt = |err: sevenz_rust::Error| ArchiveError::Corrupt(err.to_string());
+    let len = bytes.len() as u64;
+    let mut archive =
+        SevenZReader::new(io::Cursor::new(bytes), len, Password::empty()).map_err(corrupt)?;
+    let mut rom = None;
+    archive
+        .for_each_entries(|entry, reader| {
+            if entry.is_directory() || !is_rom(entry.name()) {
+                // the entries of a solid block have to be read through in turn
+                io::copy(reader, &mut io::sink())?;
+                return Ok(true);
+            }
+            rom = Some(read_entry(reader, entry.size()));
+            Ok(false)
+        })
+        .map_err(corrupt)?;
+    rom.unwrap_or(Err(ArchiveError::NoRom))
+}
+
+#[cfg(not(feature = "sevenz"))]
+fn extract_7z(_bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+    Err(ArchiveError::Unsupported("7z"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plain_rom() {
+        let rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 2, 3];
+        assert_eq!(extract_rom(rom.clone()), Ok(rom));
+    }
+
+    #[cfg(not(feature = "zip"))]
+    #[test]
+    fn test_zip_unsupported() {
+        assert_eq!(
+            extract_rom(b"PK\x03\x04 and so on".to_vec()),
+            Err(ArchiveError::Unsupported("zip"))
+        );
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_zip() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 2, 3];
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("readme.txt", options).unwrap();
+        zip.write_all(b"not a ROM").unwrap();
+        zip.start_file("Game.NES", options).unwrap();
+        zip.write_all(&rom).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        assert_eq!(extract_rom(bytes), Ok(rom));
+
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        zip.start_file("readme.txt", options).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        assert_eq!(extract_rom(bytes), Err(ArchiveError::NoRom));
+    }
+
+    #[test]
+    fn test_entries_past_the_limit() {
+        assert_eq!(read_entry(&[1, 2, 3][..], 3), Ok(vec![1, 2, 3]));
+        assert_eq!(
+            read_entry(io::repeat(0), MAX_ROM_SIZE + 1),
+            Err(ArchiveError::TooLarge)
+        );
+        // an archive that says it's smaller than it is
+        assert_eq!(read_entry(io::repeat(0), 16), Err(ArchiveError::TooLarge));
+    }
+
+    #[cfg(not(feature = "sevenz"))]
+    #[test]
+    fn test_7z_unsupported() {
+        assert_eq!(
+            extract_rom(b"7z\xbc\xaf\x27\x1c and so on".to_vec()),
+            Err(ArchiveError::Unsupported("7z"))
+        );
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn test_7z() {
+        use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+
+        let entry = |name: &str| {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            entry.has_stream = true;
+            entry
+        };
+        let rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 2, 3];
+        let mut archive = SevenZWriter::new(io::Cursor::new(Vec::new())).unwrap();
+        archive
+            .push_archive_entry(entry("readme.txt"), Some(&b"not a ROM"[..]))
+            .unwrap();
+        archive
+            .push_archive_entry(entry("Game.NES"), Some(&rom[..]))
+            .unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+        assert_eq!(extract_rom(bytes), Ok(rom));
+
+        let mut archive = SevenZWriter::new(io::Cursor::new(Vec::new())).unwrap();
+        archive
+            .push_archive_entry(entry("readme.txt"), Some(&b"not a ROM"[..]))
+            .unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+        assert_eq!(extract_rom(bytes), Err(ArchiveError::NoRom));
+    }
+}
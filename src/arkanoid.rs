@@ -0,0 +1,132 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Arkanoid "Vaus" paddle controller, NES version
+// from: https://www.nesdev.org/wiki/Arkanoid_controller
+//
+// | Bit | Read from $4016/$4017                           |
+// |-----|-------------------------------------------------|
+// | 3   | Fire button: 1 while pressed                    |
+// | 4   | Knob position, one bit per read, MSB first and  |
+// |     | inverted                                        |
+//
+// The strobe latches the knob's potentiometer, like a standard controller
+// latches its buttons; once its 8 bits are read the line reads 1.
+pub const KNOB_MIN: u8 = 0x62;
+pub const KNOB_MAX: u8 = 0xf2;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Arkanoid {
+    knob: u8,
+    fire: bool,
+    strobe: bool,
+    // the bits of the latched knob not read yet, from bit 7
+    shift: u8,
+}
+
+impl Default for Arkanoid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arkanoid {
+    pub fn new() -> Self {
+        Arkanoid {
+            knob: KNOB_MIN,
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    // the potentiometer's reading, KNOB_MIN at the left to KNOB_MAX at
+    // the right
+    pub fn set_knob(&mut self, knob: u8) {
+        self.knob = knob.clamp(KNOB_MIN, KNOB_MAX);
+    }
+
+    pub fn knob(&self) -> u8 {
+        self.knob
+    }
+
+    // for a frontend steering with the mouse: `x` across a screen `width`
+    // wide turns the knob from one end to the other
+    pub fn set_position(&mut self, x: u32, width: u32) {
+        let x = x.min(width.saturating_sub(1)) as u64;
+        let range = (KNOB_MAX - KNOB_MIN) as u64;
+        let knob = KNOB_MIN as u64 + x * range / width.saturating_sub(1).max(1) as u64;
+        self.set_knob(knob as u8);
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift = self.knob;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.shift = self.knob;
+        }
+        let bit = (!self.shift >> 7) & 1;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        (bit << 4) | ((self.fire as u8) << 3)
+    }
+}
+
+impl Snapshot for Arkanoid {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.knob);
+        state.write_bool(self.fire);
+        state.write_bool(self.strobe);
+        state.write_u8(self.shift);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.knob = state.read_u8()?;
+        self.fire = state.read_bool()?;
+        self.strobe = state.read_bool()?;
+        self.shift = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_knob_is_read_msb_first_inverted() {
+        let mut paddle = Arkanoid::new();
+        paddle.set_knob(0xa5);
+        paddle.set_fire(true);
+        paddle.write(1);
+        paddle.write(0);
+        let mut knob = 0;
+        for _ in 0..8 {
+            let read = paddle.read();
+            assert_eq!(read & 0x08, 0x08);
+            knob = (knob << 1) | ((!read >> 4) & 1);
+        }
+        assert_eq!(knob, 0xa5);
+        // then the line reads 1, a knob of 0
+        assert_eq!(paddle.read(), 0b1_1000);
+
+        paddle.set_knob(0);
+        assert_eq!(paddle.knob(), KNOB_MIN);
+        paddle.set_position(0, 256);
+        assert_eq!(paddle.knob(), KNOB_MIN);
+        paddle.set_position(255, 256);
+        assert_eq!(paddle.knob(), KNOB_MAX);
+        paddle.set_position(1000, 256);
+        assert_eq!(paddle.knob(), KNOB_MAX);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::apu::Levels;
+#[cfg(feature = "std")]
+use crate::region::Region;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+// Turns the APU's per-cycle channel levels into a stream a sound card can
+// play: mixed with the console's non-linear DAC formula, averaged down from
+// the CPU clock to the output rate, then filtered like the NES's own output
+// stage (two high-passes at 90Hz and 440Hz, a low-pass at 14kHz).
+// from: https://www.nesdev.org/wiki/APU_Mixer
+pub const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+
+// 0.0 to ~1.0, plus whatever expansion sound the cartridge adds
+pub fn mix(levels: &Levels, expansion: f32) -> f32 {
+    let pulse = (levels[0] + levels[1]) as f32;
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+
+    let tnd = levels[2] as f32 / 8227.0 + levels[3] as f32 / 12241.0 + levels[4] as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+
+    pulse_out + tnd_out + expansion
+}
+
+// first order IIR filter
+struct Filter {
+    alpha: f32,
+    high_pass: bool,
+    previous_in: f32,
+    previous_out: f32,
+}
+
+impl Filter {
+    fn new(sample_rate: f32, cutoff: f32, high_pass: bool) -> Self {
+        let rc = 1.0 / (2.0 * core::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = if high_pass {
+            rc / (rc + dt)
+        } else {
+            dt / (rc + dt)
+        };
+        Filter {
+            alpha,
+            high_pass,
+            previous_in: 0.0,
+            previous_out: 0.0,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = if self.high_pass {
+            self.alpha * (self.previous_out + sample - self.previous_in)
+        } else {
+            self.previous_out + self.alpha * (sample - self.previous_out)
+        };
+        self.previous_in = sample;
+        self.previous_out = out;
+        out
+    }
+}
+
+pub struct Resampler {
+    // input samples per output sample
+    step: f64,
+    position: f64,
+    sum: f32,
+    count: u32,
+    filters: [Filter; 3],
+}
+
+impl Resampler {
+    pub fn new(output_rate: u32) -> Self {
+        Resampler::with_clock_rate(output_rate, CPU_CLOCK_RATE)
+    }
+
+    // for input at another region's CPU clock
+    pub fn with_clock_rate(output_rate: u32, clock_rate: f64) -> Self {
+        let rate = output_rate as f32;
+        Resampler {
+            step: clock_rate / output_rate as f64,
+            position: 0.0,
+            sum: 0.0,
+            count: 0,
+            filters: [
+                Filter::new(rate, 90.0, true),
+                Filter::new(rate, 440.0, true),
+                Filter::new(rate, 14_000.0, false),
+            ],
+        }
+    }
+
+    // feed one CPU cycle's worth of output, get a sample back whenever an
+    // output period is complete
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        self.sum += sample;
+        self.count += 1;
+        self.position += 1.0;
+        if self.position < self.step {
+            return None;
+        }
+        self.position -= self.step;
+
+        let mut out = self.sum / self.count as f32;
+        self.sum = 0.0;
+        self.count = 0;
+        for filter in self.filters.iter_mut() {
+            out = filter.process(out);
+        }
+        Some(out)
+    }
+}
+
+// Fixed size buffer between the emulation and the audio device's callback.
+// When the device runs ahead it gets the last sample repeated, which is much
+// less audible than silence; when the emulation runs ahead the oldest
+// samples are dropped.
+pub struct RingBuffer {
+    samples: Vec<f32>,
+    read: usize,
+    len: usize,
+    last: f32,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            samples: vec![0.0; capacity],
+            read: 0,
+            len: 0,
+            last: 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let capacity = self.samples.len();
+        let write = (self.read + self.len) % capacity;
+        self.samples[write] = sample;
+        if self.len == capacity {
+            self.read = (self.read + 1) % capacity;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            if self.len > 0 {
+                self.last = self.samples[self.read];
+                self.read = (self.read + 1) % self.samples.len();
+                self.len -= 1;
+            }
+            *sample = self.last;
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub type SharedRingBuffer = Arc<Mutex<RingBuffer>>;
+
+// Sample callback for the APU of a console in `region`: mixes, resamples
+// from its CPU clock and queues into `ring`
+#[cfg(feature = "std")]
+pub fn sample_sink(
+    output_rate: u32,
+    region: Region,
+    ring: SharedRingBuffer,
+) -> impl FnMut(Levels, f32) {
+    let mut resampler = Resampler::with_clock_rate(output_rate, region.cpu_clock_rate());
+    let mut batch = Vec::with_capacity(64);
+    move |levels, expansion| {
+        if let Some(sample) = resampler.push(mix(&levels, expansion)) {
+            batch.push(sample);
+            if batch.len() == batch.capacity() {
+                let mut ring = ring.lock().unwrap();
+                for sample in batch.drain(..) {
+                    ring.push(sample);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(mix(&[0, 0, 0, 0, 0], 0.0), 0.0);
+        assert!((mix(&[15, 15, 0, 0, 0], 0.0) - 0.2585).abs() < 0.0001);
+        assert!((mix(&[0, 0, 15, 15, 127], 0.0) - 0.7415).abs() < 0.0001);
+        assert!(mix(&[15, 15, 15, 15, 127], 0.0) < 1.0);
+    }
+
+    #[test]
+    fn test_resampled_rate() {
+        let mut resampler = Resampler::new(44_100);
+        let produced = (0..CPU_CLOCK_RATE as usize)
+            .filter_map(|_| resampler.push(0.5))
+            .count();
+        assert!((44_099..=44_100).contains(&produced));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sample_sink_follows_the_region() {
+        let ring = Arc::new(Mutex::new(RingBuffer::new(48_000)));
+        let mut sink = sample_sink(44_100, Region::Pal, ring.clone());
+        for _ in 0..Region::Pal.cpu_clock_rate() as usize {
+            sink([0; 5], 0.0);
+        }
+        // less the last batch, still waiting to be queued
+        let queued = ring.lock().unwrap().len();
+        assert!((44_100 - 64..=44_100).contains(&queued));
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut ring = RingBuffer::new(4);
+        let mut out = [0.0; 3];
+        ring.push(0.1);
+        ring.push(0.2);
+        ring.fill(&mut out);
+        assert_eq!(out, [0.1, 0.2, 0.2], "underruns repeat the last sample");
+
+        for i in 0..6 {
+            ring.push(i as f32);
+        }
+        assert_eq!(ring.len(), 4);
+        ring.fill(&mut out);
+        assert_eq!(out, [2.0, 3.0, 4.0], "overruns drop the oldest samples");
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::EmulatorError;
+use crate::md5::md5;
+use crate::nes::Nes;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+// Determinism audits: an MD5 of the console's whole state (the save state,
+// so CPU, RAM, PPU, APU and cartridge alike) after every frame. Two runs of
+// the same ROM with the same input have to give the same hashes, frame for
+// frame, or netplay desyncs, movies stop playing back and RL experiments
+// can't be reproduced; the first frame they differ at is where to start
+// looking.
+//
+// Hash streams are plain text, one hash in hex per frame, so they can be
+// saved from one build and compared against another.
+
+#[derive(Debug, PartialEq)]
+pub enum AuditError {
+    // a line that isn't 32 hex digits
+    InvalidLine(usize),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::InvalidLine(line) => write!(f, "line {} isn't an MD5 hash", line),
+        }
+    }
+}
+
+impl core::error::Error for AuditError {}
+
+pub fn state_hash(nes: &Nes) -> [u8; 16] {
+    md5(&nes.save_state())
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Audit {
+    hashes: Vec<[u8; 16]>,
+}
+
+impl Audit {
+    pub fn new() -> Self {
+        Audit::default()
+    }
+
+    pub fn record(&mut self, nes: &Nes) {
+        self.hashes.push(state_hash(nes));
+    }
+
+    // drops the hashes of the last `frames` frames, which are about to be
+    // run again
+    pub fn forget(&mut self, frames: usize) {
+        let length = self.hashes.len().saturating_sub(frames);
+        self.hashes.truncate(length);
+    }
+
+    // one per frame since the audit started
+    pub fn hashes(&self) -> &[[u8; 16]] {
+        &self.hashes
+    }
+
+    // The first frame, counting from 0, where the two runs' states differ,
+    // or where one of them ended early
+    pub fn first_divergence(&self, other: &Audit) -> Option<usize> {
+        let common = self.hashes.len().min(other.hashes.len());
+        (0..common)
+            .find(|&frame| self.hashes[frame] != other.hashes[frame])
+            .or((self.hashes.len() != other.hashes.len()).then_some(common))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for hash in &self.hashes {
+            for byte in hash {
+                let _ = write!(text, "{:02x}", byte);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Audit, AuditError> {
+        let mut hashes = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || AuditError::InvalidLine(number + 1);
+            if line.len() != 32 || !line.is_ascii() {
+                return Err(invalid());
+            }
+            let mut hash = [0; 16];
+            for (i, byte) in hash.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&line[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+            }
+            hashes.push(hash);
+        }
+        Ok(Audit { hashes })
+    }
+}
+
+// Runs two consoles from `build` side by side for `frames` frames, `input`
+// setting each one's controllers before every frame, and returns the first
+// frame their states differ at
+pub fn compare_runs<B, I>(
+    mut build: B,
+    frames: usize,
+    mut input: I,
+) -> Result<Option<usize>, EmulatorError>
+where
+    B: FnMut() -> Result<Nes, EmulatorError>,
+    I: FnMut(usize, &mut Nes),
+{
+    let mut first = build()?;
+    let mut second = build()?;
+    for frame in 0..frames {
+        input(frame, &mut first);
+        input(frame, &mut second);
+        first.run_frame();
+        second.run_frame();
+        if state_hash(&first) != state_hash(&second) {
+            return Ok(Some(frame));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use crate::joypad::Button;
+    use crate::rom::test::looping_rom;
+    use alloc::format;
+
+    #[test]
+    fn test_audit() {
+        let mut nes = Nes::new(&looping_rom()).unwrap();
+        nes.enable_audit();
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let audit = nes.disable_audit().unwrap();
+        assert_eq!(audit.hashes().len(), 3);
+        assert_eq!(audit.hashes()[2], state_hash(&nes));
+        assert_ne!(audit.hashes()[1], audit.hashes()[2]);
+
+        let text = audit.to_text();
+        assert_eq!(text.lines().count(), 3);
+        assert_eq!(Audit::from_text(&text), Ok(audit.clone()));
+        assert_eq!(audit.first_divergence(&audit), None);
+
+        let mut other = audit.clone();
+        other.hashes[1][0] ^= 1;
+        assert_eq!(audit.first_divergence(&other), Some(1));
+        other.hashes.truncate(1);
+        assert_eq!(audit.first_divergence(&other), Some(1));
+
+        assert_eq!(Audit::from_text("0123\n"), Err(AuditError::InvalidLine(1)));
+        assert_eq!(
+            Audit::from_text(&format!(
+                "{}\n{}",
+                text.lines().next().unwrap(),
+                "g".repeat(32)
+            )),
+            Err(AuditError::InvalidLine(2))
+        );
+    }
+
+    #[test]
+    fn test_compare_runs() {
+        let build = || Nes::new(&looping_rom());
+        let same = compare_runs(build, 5, |frame, nes| {
+            nes.set_input(1, Button::from_bits_truncate(frame as u8))
+        });
+        assert_eq!(same, Ok(None));
+
+        // the second console's RAM changes under it before frame 2
+        let mut runs = 0;
+        let differs = compare_runs(build, 5, |frame, nes| {
+            runs += 1;
+            if frame == 2 && runs % 2 == 0 {
+                nes.bus_mut().mem_write(0x0300, 1);
+            }
+        });
+        assert_eq!(differs, Ok(Some(2)));
+    }
+}
@@ -0,0 +1,83 @@
+// Runs every .nes file under a directory for a while and reports how each
+// one did, see compat.rs:
+//
+//   compat-runner [--frames <n>] [--threads <n>] [--json <report.json>]
+//                 [--html <report.html>] <roms directory>
+//
+// --frames is how long each ROM runs, 10 emulated seconds by default, and
+// --threads how many run at once, one per core by default. Without --json
+// or --html only the summary is printed.
+use rust_nes_emu::compat::{self, Outcome};
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+const DEFAULT_FRAMES: usize = 600;
+
+fn main() {
+    let mut frames = DEFAULT_FRAMES;
+    let mut threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let mut json_path = None;
+    let mut html_path = None;
+    let mut dir = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = parse_count(args.next(), "--frames"),
+            "--threads" => threads = parse_count(args.next(), "--threads"),
+            "--json" => json_path = args.next(),
+            "--html" => html_path = args.next(),
+            _ => dir = Some(PathBuf::from(arg)),
+        }
+    }
+    let dir = dir.unwrap_or_else(|| {
+        eprintln!(
+            "usage: compat-runner [--frames <n>] [--threads <n>] [--json <report.json>] \
+             [--html <report.html>] <roms directory>"
+        );
+        std::process::exit(1);
+    });
+
+    // panics are reported with their ROM, not on stderr as they happen
+    std::panic::set_hook(Box::new(|_| {}));
+    let started = Instant::now();
+    let results = compat::run_all(&dir, frames, threads, |result| match &result.outcome {
+        Outcome::LoadError(message) | Outcome::Panicked(message) => {
+            println!("{}: {} ({})", result.name, result.outcome.name(), message)
+        }
+        outcome => println!("{}: {}", result.name, outcome.name()),
+    });
+    let _ = std::panic::take_hook();
+
+    let counts: Vec<String> = compat::summary(&results)
+        .iter()
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+    println!(
+        "{} ROMs in {:.1}s: {}",
+        results.len(),
+        started.elapsed().as_secs_f64(),
+        counts.join(", ")
+    );
+    for (path, report) in [
+        (json_path, compat::to_json(&results)),
+        (html_path, compat::to_html(&results)),
+    ] {
+        if let Some(path) = path {
+            if let Err(err) = std::fs::write(&path, report) {
+                eprintln!("could not write {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn parse_count(arg: Option<String>, flag: &str) -> usize {
+    match arg.and_then(|arg| arg.parse().ok()) {
+        Some(count) if count > 0 => count,
+        _ => {
+            eprintln!("{} needs a number above 0", flag);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,5 +1,33 @@
-use crate::rom::ROM;
+use crate::accuracy::AccuracyConfig;
+use crate::apu::APU;
+use crate::rom::{RomError, ROM};
 use crate::cpu::Mem;
+use crate::cheats::Cheats;
+use crate::debugger::{Access, PpuBreakpoints, Watchpoints};
+use crate::error::BusFault;
+use crate::events::{Event, EventBus, Events, SubscriptionId};
+use crate::heatmap::AccessHeatmap;
+use crate::hooks::{HookId, MemoryHooks};
+use crate::mappers::{self, MapperRef};
+use crate::peripheral::Peripheral;
+use crate::ppu::{PPUInterface, PPU};
+use crate::ppu_log::PpuWriteLog;
+use crate::protect::{Protection, WriteProtection};
+use crate::region::Region;
+use crate::rng::Rng;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+#[cfg(feature = "serde")]
+use crate::state::copy_memory;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
 //  _______________ $10000  _______________
 // | PRG-ROM       |       |               |
@@ -33,66 +61,812 @@ const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+
 pub struct BUS {
     cpu_vram: [u8; 2048],
-    rom: ROM,
+    mapper: MapperRef,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub port1: Peripheral,
+    pub port2: Peripheral,
+    // the Famicom's second controller has a microphone, heard at bit 2 of
+    // $4016 while something is loud enough
+    pub microphone: bool,
+    cycles: usize,
+    // CPU cycles DMA has taken from the instruction being run
+    dma_stall: u16,
+    oam_dma: bool,
+    frame_complete: bool,
+    // the last value on the CPU data bus, which unmapped reads return
+    open_bus: u8,
+    region: Region,
+    // PAL PPU dots owed to the PPU, in fifths
+    dot_remainder: u32,
+    pub ram_heatmap: AccessHeatmap,
+    pub write_protection: WriteProtection,
+    pub watchpoints: Watchpoints,
+    pub ppu_breakpoints: PpuBreakpoints,
+    pub ppu_write_log: Option<PpuWriteLog>,
+    hooks: MemoryHooks,
+    pub(crate) events: EventBus,
+    pub cheats: Cheats,
+    // report accesses with no defined effect instead of just dropping them
+    pub strict: bool,
+    accuracy: AccuracyConfig,
+    fault: Option<BusFault>,
+    seed: u64,
+    // the cartridge as it came up, a save state with just its CART section
+    mapper_power_on: Vec<u8>,
+    battery: bool,
+    #[cfg(feature = "std")]
+    sram_path: Option<PathBuf>,
 }
 
 impl BUS {
-    pub fn new(rom: ROM) -> Self {
-        BUS {
-            cpu_vram: [0; 2048],
-            rom,
+    pub fn new(rom: ROM) -> Result<Self, RomError> {
+        BUS::with_seed(rom, 0)
+    }
+
+    // Power-on RAM contents are derived from `seed`; 0 gives zeroed RAM
+    pub fn with_seed(rom: ROM, seed: u64) -> Result<Self, RomError> {
+        let battery = rom.battery;
+        let region = Region::from_timing(rom.timing);
+        let mut bus = BUS::with_mapper(mappers::create(rom)?, seed);
+        bus.battery = battery;
+        bus.set_region(region);
+        Ok(bus)
+    }
+
+    pub fn with_mapper(mapper: MapperRef, seed: u64) -> Self {
+        let mut cpu_vram = [0; 2048];
+        Rng::new(seed).fill(&mut cpu_vram);
+        let mut mapper_power_on = StateWriter::new();
+        mapper_power_on.section(b"CART", |state| mapper.borrow().save_state(state));
+
+        let mut bus = BUS {
+            cpu_vram,
+            mapper: mapper.clone(),
+            ppu: PPU::new(mapper),
+            apu: APU::new(),
+            port1: Peripheral::default(),
+            port2: Peripheral::default(),
+            microphone: false,
+            cycles: 0,
+            dma_stall: 0,
+            oam_dma: false,
+            frame_complete: false,
+            open_bus: 0,
+            region: Region::Ntsc,
+            dot_remainder: 0,
+            ram_heatmap: AccessHeatmap::new(2048),
+            write_protection: WriteProtection::new(),
+            watchpoints: Watchpoints::new(),
+            ppu_breakpoints: PpuBreakpoints::new(),
+            ppu_write_log: None,
+            hooks: MemoryHooks::new(),
+            events: EventBus::new(),
+            cheats: Cheats::new(),
+            strict: false,
+            accuracy: AccuracyConfig::default(),
+            fault: None,
+            seed,
+            mapper_power_on: mapper_power_on.finish(),
+            battery: false,
+            #[cfg(feature = "std")]
+            sram_path: None,
+        };
+        bus.set_accuracy(AccuracyConfig::default());
+        bus
+    }
+
+    pub fn accuracy(&self) -> AccuracyConfig {
+        self.accuracy
+    }
+
+    pub fn set_accuracy(&mut self, accuracy: AccuracyConfig) {
+        self.accuracy = accuracy;
+        self.ppu.set_raster_timing(accuracy.raster_timing);
+        self.ppu.sprite_overflow_bug = accuracy.sprite_overflow_bug;
+        self.ppu.oam_decay = accuracy.oam_decay;
+        self.ppu.sprite_zero_hit_dot = accuracy.sprite_zero_hit_dot;
+    }
+
+    // the last values on the CPU and PPU data buses, or 0 when open bus
+    // isn't emulated
+    fn cpu_open_bus(&self) -> u8 {
+        if self.accuracy.open_bus { self.open_bus } else { 0 }
+    }
+
+    fn ppu_open_bus(&self) -> u8 {
+        if self.accuracy.open_bus { self.ppu.open_bus() } else { 0 }
+    }
+
+    // The reset button: RAM, VRAM and the cartridge keep their contents
+    pub fn soft_reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+    }
+
+    // Switching the console off and on: RAM is refilled from the power-on
+    // seed, and the cartridge's registers and RAM, the PPU and the APU go
+    // back to how they came up. Battery-backed PRG RAM keeps its contents;
+    // the controllers, the region, the accuracy settings and the debugging
+    // state are the frontend's, and stay as they are.
+    pub fn power_on(&mut self) {
+        Rng::new(self.seed).fill(&mut self.cpu_vram);
+        {
+            let mut mapper = self.mapper.borrow_mut();
+            let battery_ram = self.battery.then(|| mapper.prg_ram().to_vec());
+            StateReader::new(&self.mapper_power_on)
+                .and_then(|state| mapper.load_state(&mut state.section(b"CART")?))
+                .expect("the cartridge's own state should load");
+            if let Some(ram) = battery_ram {
+                mapper.prg_ram().copy_from_slice(&ram);
+            }
+        }
+        self.ppu.power_on();
+        self.apu.power_on();
+        self.dma_stall = 0;
+        self.oam_dma = false;
+        self.frame_complete = false;
+        self.open_bus = 0;
+        self.dot_remainder = 0;
+    }
+
+    // true when the cartridge's PRG RAM survives power off
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    // Writes battery-backed PRG RAM to `path`. Does nothing for cartridges
+    // without a battery.
+    #[cfg(feature = "std")]
+    pub fn save_sram<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        fs::write(path, self.mapper.borrow_mut().prg_ram())
+    }
+
+    // Restores battery-backed PRG RAM from `path`, which is also where it is
+    // saved again when the bus is dropped. A missing file is a fresh save.
+    #[cfg(feature = "std")]
+    pub fn load_sram<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        let path = path.as_ref();
+        self.sram_path = Some(path.to_path_buf());
+        let saved = match fs::read(path) {
+            Ok(saved) => saved,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let mut mapper = self.mapper.borrow_mut();
+        let prg_ram = mapper.prg_ram();
+        let len = saved.len().min(prg_ram.len());
+        prg_ram[..len].copy_from_slice(&saved[..len]);
+        Ok(())
+    }
+
+    // Writes battery-backed PRG RAM to where load_sram read it from, as
+    // dropping the bus would. For handing the save over to a console built
+    // from the same cartridge before this one goes away.
+    #[cfg(feature = "std")]
+    pub fn flush_sram(&mut self) -> io::Result<()> {
+        match self.sram_path.clone() {
+            Some(path) => self.save_sram(path),
+            None => Ok(()),
+        }
+    }
+
+    // picked from the ROM header, this overrides it
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.dot_remainder = 0;
+        self.ppu.set_region(region);
+        self.apu.set_pal(region.pal_apu());
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn mapper(&self) -> &MapperRef {
+        &self.mapper
+    }
+
+    // Runs the rest of the console for the cycles an instruction took, then
+    // for as long as DMA halted the CPU after it:
+    // - OAM DMA takes 513 cycles, 514 when it starts on an odd cycle
+    // - every DMC sample fetch takes 4
+    // from: https://www.nesdev.org/wiki/DMA
+    pub fn tick(&mut self, cycles: u8) {
+        self.run(cycles);
+        if self.oam_dma {
+            self.oam_dma = false;
+            self.dma_stall += 513 + (self.cycles % 2) as u16;
+        }
+        if !self.accuracy.dma_stalls {
+            self.dma_stall = 0;
+        }
+        // more DMC fetches can come due while the CPU is halted
+        while self.dma_stall > 0 {
+            let stall = self.dma_stall.min(u8::MAX as u16);
+            self.dma_stall -= stall;
+            self.run(stall as u8);
+        }
+    }
+
+    fn run(&mut self, cycles: u8) {
+        self.cycles += cycles as usize;
+        self.ppu.decay_open_bus(cycles as usize);
+        self.ppu.decay_oam(cycles as usize);
+        // boards with their own timers or sound run alongside the APU
+        let mapper = &self.mapper;
+        let mut fetches = 0;
+        for _ in 0..cycles {
+            let expansion = {
+                let mut mapper = mapper.borrow_mut();
+                mapper.clock();
+                mapper.audio_output()
+            };
+            self.apu.set_expansion_output(expansion);
+            self.apu.tick(1, |addr| {
+                fetches += 1;
+                mapper.borrow_mut().cpu_read(addr)
+            });
+        }
+        self.dma_stall += fetches * 4;
+
+        // the PPU runs 3 dots per CPU cycle, 3.2 on PAL consoles
+        let (dots, per_cycle) = self.region.ppu_dots_per_cycle();
+        let dots = cycles as u32 * dots + self.dot_remainder;
+        self.dot_remainder = dots % per_cycle;
+        let scanline = self.ppu.scanline;
+        let dot = self.ppu.dot;
+        if self.ppu.tick((dots / per_cycle) as u16) {
+            self.frame_complete = true;
+            self.ram_heatmap.end_frame();
+            self.ppu.vram_heatmap.end_frame();
+            if let Some(log) = self.ppu_write_log.as_mut() {
+                log.end_frame();
+            }
+            self.events.emit(Event::VblankStart);
+        }
+        if self.events.wants(Events::SCANLINE_START) {
+            let mut line = scanline;
+            while line != self.ppu.scanline {
+                line = (line + 1) % self.region.scanlines();
+                self.events.emit(Event::ScanlineStart(line));
+            }
+        }
+        if !self.ppu_breakpoints.is_empty() {
+            let now = (self.ppu.scanline, self.ppu.dot);
+            self.ppu_breakpoints
+                .check((scanline, dot), now, self.region.scanlines());
+        }
+    }
+
+    // the 2KB of CPU RAM, without its mirrors
+    pub fn ram(&self) -> &[u8] {
+        &self.cpu_vram
+    }
+
+    // CPU cycles since power on
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn poll_nmi_status(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    // The IRQ line is shared: it stays asserted while the cartridge or the
+    // APU hold it, until the CPU acknowledges them through their registers
+    pub fn irq_line(&self) -> bool {
+        self.mapper.borrow().irq_pending() || self.apu.irq_pending()
+    }
+
+    fn write_ppu_register(&mut self, addr: u16, data: u8) {
+        self.log_ppu_write(addr, data);
+        match addr {
+            PPU_REGISTERS => self.ppu.write_to_control(data),
+            0x2001 => self.ppu.write_to_mask(data),
+            0x2002 => {
+                // read-only status register
+            }
+            0x2003 => self.ppu.write_to_oam_addr(data),
+            0x2004 => self.ppu.write_to_oam_data(data),
+            0x2005 => self.ppu.write_to_scroll(data),
+            0x2006 => self.ppu.write_to_address(data),
+            0x2007 => {
+                self.check_ppu_data_fault(Access::Write);
+                self.ppu.write_to_data(data)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn log_ppu_write(&mut self, addr: u16, data: u8) {
+        if let Some(log) = self.ppu_write_log.as_mut() {
+            log.record(addr, data, (self.ppu.scanline, self.ppu.dot), self.cycles);
+        }
+    }
+
+    // What a read of `addr` would return, without its side effects: the
+    // PPU's read buffer, vblank flag and write toggle, the APU's frame IRQ
+    // and the controllers' shift registers stay as they are. For tracing
+    // and debuggers.
+    pub fn peek(&self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07ff) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0x2007 {
+                0x2002 => (self.ppu.peek_status() & 0xe0) | (self.ppu_open_bus() & 0x1f),
+                0x2004 => self.ppu.oam_data[self.ppu.oam_addr as usize],
+                0x2007 => self.ppu.peek_data(),
+                _ => self.ppu_open_bus(),
+            },
+            0x4015 => self.apu.peek_status() | (self.cpu_open_bus() & 0x20),
+            0x4020..=0xFFFF => self.mapper.borrow().cpu_peek(addr),
+            _ => self.cpu_open_bus(),
+        };
+        self.cheats.apply(addr, data)
+    }
+
+    // Calls `callback` with the address and value of every CPU read in
+    // `range`, see MemoryHooks
+    pub fn on_read<F: FnMut(u16, u8) + 'static>(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: F,
+    ) -> HookId {
+        self.hooks.add(Access::Read, range, Box::new(callback))
+    }
+
+    pub fn on_write<F: FnMut(u16, u8) + 'static>(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: F,
+    ) -> HookId {
+        self.hooks.add(Access::Write, range, Box::new(callback))
+    }
+
+    // false if there was no such hook
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.hooks.remove(id)
+    }
+
+    // Calls `callback` with every event in `events`, see EventBus
+    pub fn subscribe<F: FnMut(&Event) + 'static>(
+        &mut self,
+        events: Events,
+        callback: F,
+    ) -> SubscriptionId {
+        self.events.subscribe(events, Box::new(callback))
+    }
+
+    // false if there was no such subscription
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        self.events.emit(event);
+    }
+
+    // which 8KB of PRG ROM each of $8000-$FFFF's four windows shows
+    fn prg_banks(&self) -> [Option<usize>; 4] {
+        let mapper = self.mapper.borrow();
+        [0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mapper.prg_offset(addr))
+    }
+
+    // where in PRG ROM `addr` is mapped to, for cartridge ROM addresses
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.borrow().prg_offset(addr)
+    }
+
+    fn record_fault(&mut self, addr: u16, access: Access) {
+        if self.strict && self.fault.is_none() {
+            self.fault = Some(BusFault { addr, access });
         }
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            //mirror if needed
-            addr = addr % 0x4000;
+    // the first fault since the last call, in strict mode
+    pub fn take_fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+
+    // $3000-$3EFF through $2007
+    fn check_ppu_data_fault(&mut self, access: Access) {
+        let addr = self.ppu.address.get();
+        if (0x3000..=0x3eff).contains(&addr) {
+            self.record_fault(addr, access);
         }
-        self.rom.prg_rom[addr as usize]
+    }
+
+    // true once per finished frame
+    pub fn poll_frame_complete(&mut self) -> bool {
+        let complete = self.frame_complete;
+        self.frame_complete = false;
+        complete
     }
 }
 
 impl Mem for BUS {
-    fn mem_read(&self, addr: u16) -> u8 {
-        match addr {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
+                self.ram_heatmap.record_read(mirror_down_addr as usize);
                 self.cpu_vram[mirror_down_addr as usize]
             }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0b00100000_00000111;
-                todo!("PPU is not supported yet")
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => {
+                // write-only PPU registers read back the PPU's data bus
+                self.ppu_open_bus()
+            }
+            0x2002 => {
+                // only the top 3 bits are driven, the rest is open bus
+                let data = (self.ppu.read_from_status() & 0xe0) | (self.ppu_open_bus() & 0x1f);
+                self.ppu.refresh_open_bus(data);
+                data
+            }
+            0x2004 => {
+                let data = self.ppu.read_from_oam_data();
+                self.ppu.refresh_open_bus(data);
+                data
+            }
+            0x2007 => {
+                self.check_ppu_data_fault(Access::Read);
+                let data = self.ppu.read_from_data();
+                self.ppu.refresh_open_bus(data);
+                data
+            }
+
+            0x2008..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.mem_read(mirror_down_addr)
+            }
+            // bit 5 isn't driven
+            0x4015 => self.apu.read_status() | (self.cpu_open_bus() & 0x20),
+            // the controllers only drive the low bits
+            0x4016 => {
+                let microphone = (self.microphone as u8) << 2;
+                self.port1.read(&self.ppu) | microphone | (self.cpu_open_bus() & 0xe0)
             }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
+            0x4017 => self.port2.read(&self.ppu) | (self.cpu_open_bus() & 0xe0),
+            0x4020..=0xFFFF => self.mapper.borrow_mut().cpu_read(addr),
 
+            // write-only APU registers and the unused test registers
             _ => {
-                println!("Ignoring mem access at {}", addr);
-                0
+                self.record_fault(addr, Access::Read);
+                self.cpu_open_bus()
             }
-        }
+        };
+        let data = self.cheats.apply(addr, data);
+        self.open_bus = data;
+        self.watchpoints.check(addr, Access::Read, data);
+        self.hooks.check(addr, Access::Read, data);
+        data
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        self.watchpoints.check(addr, Access::Write, data);
+        self.hooks.check(addr, Access::Write, data);
+        if let Some(protection) = self.write_protection.check(addr) {
+            let previous = match addr {
+                RAM..=RAM_MIRRORS_END => Some(self.cpu_vram[(addr & 0x07ff) as usize]),
+                _ => None,
+            };
+            self.write_protection.record(addr, data, previous, protection);
+            if protection == Protection::ReadOnly {
+                return;
+            }
+        }
+
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b11111111111;
+                self.ram_heatmap.record_write(mirror_down_addr as usize);
                 self.cpu_vram[mirror_down_addr as usize] = data;
             }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0b00100000_00000111;
-                todo!("PPU is not supported yet");
+            PPU_REGISTERS..=0x2007 => {
+                self.ppu.refresh_open_bus(data);
+                self.write_ppu_register(addr, data);
+            }
+
+            0x2008..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00100000_00000111;
+                self.mem_write(mirror_down_addr, data);
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartridge ROM space")
+
+
+            // OAM DMA: copy a whole page of CPU memory into OAM
+            0x4014 => {
+                let mut buffer = [0; 0x100];
+                let page = (data as u16) << 8;
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.mem_read(page + i as u16);
+                }
+                self.log_ppu_write(addr, data);
+                self.ppu.write_to_oam_dma(&buffer);
+                self.oam_dma = true;
             }
 
-            _ => {
-                println!("Ignoring mem write-access at {}", addr);
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+
+            // the strobe line is shared by both controller ports
+            0x4016 => {
+                self.port1.write(data);
+                self.port2.write(data);
             }
+            0x4020..=0xFFFF if self.events.wants(Events::MAPPER_BANK_SWITCHED) => {
+                let banks = self.prg_banks();
+                self.mapper.borrow_mut().cpu_write(addr, data);
+                if self.prg_banks() != banks {
+                    self.events.emit(Event::MapperBankSwitched { addr, value: data });
+                }
+            }
+            0x4020..=0xFFFF => self.mapper.borrow_mut().cpu_write(addr, data),
+
+            // $4018-$401F: the CPU test mode registers, off on retail consoles
+            _ => self.record_fault(addr, Access::Write),
+        }
+    }
+}
+
+// the bus's part of a MachineState: RAM, timing and the controller ports
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct BusState {
+    pub ram: Vec<u8>,
+    pub cycles: u64,
+    pub frame_complete: bool,
+    pub open_bus: u8,
+    pub dot_remainder: u32,
+    pub port1: Peripheral,
+    pub port2: Peripheral,
+}
+
+#[cfg(feature = "serde")]
+impl BUS {
+    pub fn state(&self) -> BusState {
+        BusState {
+            ram: self.cpu_vram.to_vec(),
+            cycles: self.cycles as u64,
+            frame_complete: self.frame_complete,
+            open_bus: self.open_bus,
+            dot_remainder: self.dot_remainder,
+            port1: self.port1.clone(),
+            port2: self.port2.clone(),
         }
     }
+
+    // like save states, the ports need the same devices plugged in
+    pub fn set_state(&mut self, state: &BusState) -> Result<(), StateError> {
+        let same_device = |port: &Peripheral, saved: &Peripheral| {
+            core::mem::discriminant(port) == core::mem::discriminant(saved)
+        };
+        if !same_device(&self.port1, &state.port1) || !same_device(&self.port2, &state.port2) {
+            return Err(StateError::Mismatch);
+        }
+        copy_memory(&mut self.cpu_vram, &state.ram)?;
+        self.cycles = state.cycles as usize;
+        self.frame_complete = state.frame_complete;
+        self.open_bus = state.open_bus;
+        self.dot_remainder = state.dot_remainder;
+        self.port1 = state.port1.clone();
+        self.port2 = state.port2.clone();
+        Ok(())
+    }
+}
+
+// RAM and controllers; the PPU, APU and cartridge have sections of their own
+impl Snapshot for BUS {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.cpu_vram);
+        state.write_u64(self.cycles as u64);
+        state.write_bool(self.frame_complete);
+        self.port1.save_state(state);
+        self.port2.save_state(state);
+        state.write_u8(self.open_bus);
+        state.write_u8(self.dot_remainder as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.cpu_vram)?;
+        self.cycles = state.read_u64()? as usize;
+        self.frame_complete = state.read_bool()?;
+        self.port1.load_state(state)?;
+        self.port2.load_state(state)?;
+        // version 1 states don't have the CPU open bus, version 3 and older
+        // the PAL dot remainder
+        self.open_bus = if state.version() >= 2 {
+            state.read_u8()?
+        } else {
+            0
+        };
+        self.dot_remainder = if state.version() >= 4 {
+            state.read_u8()? as u32
+        } else {
+            0
+        };
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for BUS {
+    fn drop(&mut self) {
+        if let Some(path) = self.sram_path.take() {
+            // nowhere to report a failure to, the save is kept as it was
+            let _ = self.save_sram(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    #[cfg(feature = "std")]
+    fn battery_bus() -> BUS {
+        let mut rom = test::test_rom();
+        rom.battery = true;
+        BUS::new(rom).unwrap()
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sram_survives_reload() {
+        let path = std::env::temp_dir().join(format!("sram-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut bus = battery_bus();
+        bus.load_sram(&path).unwrap();
+        bus.mem_write(0x6010, 0x42);
+        drop(bus);
+
+        let mut bus = battery_bus();
+        bus.load_sram(&path).unwrap();
+        assert_eq!(bus.mem_read(0x6010), 0x42);
+        drop(bus);
+
+        // without a battery nothing is written
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        bus.save_sram(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bus.save_sram(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sram_flushed_before_a_reload() {
+        let path = std::env::temp_dir().join(format!("sram-flush-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut old = battery_bus();
+        old.load_sram(&path).unwrap();
+        old.mem_write(0x6000, 0x5a);
+        // the new console is loaded while the old one is still around
+        old.flush_sram().unwrap();
+        let mut new = battery_bus();
+        new.load_sram(&path).unwrap();
+        drop(old);
+        assert_eq!(new.mem_read(0x6000), 0x5a);
+        drop(new);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x5a);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_protected_ram_through_a_mirror() {
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        bus.mem_write(0x0010, 0x55);
+        bus.write_protection.protect(0x0010..=0x0010, Protection::ReadOnly);
+        bus.write_protection.protect(0x2001..=0x2001, Protection::ReadOnly);
+
+        bus.mem_write(0x0810, 0x99);
+        bus.mem_write(0x3ff9, 0x1e);
+        assert_eq!(bus.mem_read(0x0010), 0x55);
+        assert_eq!(bus.ppu.mask.bits(), 0);
+        let violations = bus.write_protection.take_violations();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].addr, 0x0810);
+        assert_eq!(violations[0].previous, Some(0x55));
+        assert_eq!(violations[1].addr, 0x3ff9);
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_the_cpu() {
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        // a write landing on an even cycle, then one on an odd cycle
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(4);
+        assert_eq!(bus.cycles(), 4 + 513);
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(4);
+        assert_eq!(bus.cycles(), 4 + 513 + 4 + 514);
+        // and the PPU ran 3 dots for every one of them
+        let dots = bus.cycles() * 3;
+        assert_eq!(bus.ppu.scanline as usize, dots / 341);
+        assert_eq!(bus.ppu.dot as usize, dots % 341);
+    }
+
+    #[test]
+    fn test_fast_accuracy() {
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        bus.set_accuracy(AccuracyConfig::FAST);
+        bus.mem_write(0x4014, 0x02);
+        bus.tick(4);
+        assert_eq!(bus.cycles(), 4);
+        bus.mem_write(0x0010, 0x5a);
+        assert_eq!(bus.mem_read(0x4000), 0);
+        assert_eq!(bus.mem_read(0x2000), 0);
+    }
+
+    #[test]
+    fn test_dmc_fetches_stall_the_cpu() {
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        // a one byte sample at $C000
+        bus.mem_write(0x4012, 0x00);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0x10);
+        bus.tick(2);
+        assert_eq!(bus.cycles(), 2 + 4);
+        bus.tick(2);
+        assert_eq!(bus.cycles(), 2 + 4 + 2);
+    }
+
+    #[test]
+    fn test_unmapped_reads_return_open_bus() {
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        bus.mem_write(0x0010, 0x5a);
+        assert_eq!(bus.mem_read(0x0010), 0x5a);
+        assert_eq!(bus.mem_read(0x4000), 0x5a);
+        assert_eq!(bus.mem_read(0x401f), 0x5a);
+        assert_eq!(bus.peek(0x4014), 0x5a);
+
+        // only the undriven bits come from the bus
+        bus.mem_write(0x0010, 0x40);
+        bus.mem_read(0x0010);
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        bus.mem_write(0x0010, 0xff);
+        bus.mem_read(0x0010);
+        assert_eq!(bus.mem_read(0x4015), 0x20);
+    }
+
+    #[test]
+    fn test_memory_hooks() {
+        use core::cell::RefCell;
+        use alloc::rc::Rc;
+
+        let mut bus = BUS::new(test::test_rom()).unwrap();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let id = bus.on_write(0x0300..=0x03ff, move |addr, value| {
+            log.borrow_mut().push((addr, value))
+        });
+        let log = seen.clone();
+        bus.on_read(0x0300..=0x0300, move |addr, value| {
+            log.borrow_mut().push((addr, value + 1))
+        });
+
+        // through a mirror, and outside the range
+        bus.mem_write(0x0b00, 7);
+        bus.mem_write(0x0400, 8);
+        bus.mem_read(0x0300);
+        assert_eq!(*seen.borrow(), [(0x0300, 7), (0x0300, 8)]);
+
+        assert!(bus.remove_hook(id));
+        bus.mem_write(0x0300, 9);
+        assert_eq!(seen.borrow().len(), 2);
+    }
 }
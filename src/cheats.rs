@@ -0,0 +1,227 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Game Genie and Pro Action Replay codes
+// from: https://www.nesdev.org/wiki/Game_Genie
+//
+// Game Genie codes are 6 or 8 letters, each one 4 bits:
+//   A P Z L G I T Y E O X U K S V N
+//   0 1 2 3 4 5 6 7 8 9 A B C D E F
+// The bits are scrambled into a PRG address ($8000-$FFFF), a value, and for
+// 8 letter codes a compare byte: the value only replaces what the cartridge
+// returns when that is the compare byte, so the patch follows one bank.
+//
+// Raw codes are hex, "AAAA:VV" or "AAAA?CC:VV" with a compare byte, and
+// can target any address, so they also freeze RAM.
+//
+// Either way the cheat replaces what CPU reads of its address return.
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    InvalidLength(usize),
+    InvalidCharacter(char),
+    InvalidFormat,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidLength(length) => {
+                write!(f, "Game Genie codes have 6 or 8 letters, not {}", length)
+            }
+            CheatError::InvalidCharacter(c) => write!(f, "'{}' can't be in a cheat code", c),
+            CheatError::InvalidFormat => {
+                write!(f, "Raw codes look like AAAA:VV or AAAA?CC:VV")
+            }
+        }
+    }
+}
+
+impl core::error::Error for CheatError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub code: String,
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // Game Genie letters, or a raw hex code
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim().to_uppercase();
+        let (address, value, compare) = if code.contains(':') {
+            parse_raw(&code)?
+        } else {
+            decode_game_genie(&code)?
+        };
+        Ok(Cheat {
+            code,
+            address,
+            value,
+            compare,
+            enabled: true,
+        })
+    }
+}
+
+fn decode_game_genie(code: &str) -> Result<(u16, u8, Option<u8>), CheatError> {
+    let n = code
+        .chars()
+        .map(|c| {
+            LETTERS
+                .iter()
+                .position(|&letter| letter as char == c)
+                .map(|value| value as u16)
+                .ok_or(CheatError::InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<u16>, CheatError>>()?;
+    if n.len() != 6 && n.len() != 8 {
+        return Err(CheatError::InvalidLength(n.len()));
+    }
+
+    let address = 0x8000
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    if n.len() == 6 {
+        Ok((address, (value | (n[5] & 8)) as u8, None))
+    } else {
+        let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+        Ok((address, (value | (n[7] & 8)) as u8, Some(compare as u8)))
+    }
+}
+
+fn parse_raw(code: &str) -> Result<(u16, u8, Option<u8>), CheatError> {
+    let hex = |digits: &str, length: usize| {
+        if digits.len() != length {
+            return Err(CheatError::InvalidFormat);
+        }
+        u16::from_str_radix(digits, 16).map_err(|_| CheatError::InvalidFormat)
+    };
+    let (target, value) = code.split_once(':').ok_or(CheatError::InvalidFormat)?;
+    let (address, compare) = match target.split_once('?') {
+        Some((address, compare)) => (address, Some(hex(compare, 2)? as u8)),
+        None => (target, None),
+    };
+    Ok((hex(address, 4)?, hex(value, 2)? as u8, compare))
+}
+
+// The active codes, consulted by the BUS on every CPU read
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats::default()
+    }
+
+    // returns the index to enable, disable or remove it by
+    pub fn add(&mut self, code: &str) -> Result<usize, CheatError> {
+        self.cheats.push(Cheat::parse(code)?);
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.cheats.len() {
+            Some(self.cheats.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    // what a read of `addr` returns with the cheats applied to `data`
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled && cheat.address == addr)
+            .find(|cheat| cheat.compare.is_none_or(|compare| compare == data))
+            .map_or(data, |cheat| cheat.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_game_genie() {
+        let cheat = Cheat::parse("gossip").unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0xd1dd, 0x14, None)
+        );
+
+        // Super Mario Bros. infinite lives
+        let cheat = Cheat::parse("SXIOPO").unwrap();
+        assert_eq!((cheat.address, cheat.value), (0x91d9, 0xad));
+
+        let cheat = Cheat::parse("ZEXPYGLA").unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x94a7, 0x02, Some(0x03))
+        );
+
+        assert_eq!(Cheat::parse("GOSSI"), Err(CheatError::InvalidLength(5)));
+        assert_eq!(
+            Cheat::parse("GOSSIB"),
+            Err(CheatError::InvalidCharacter('B'))
+        );
+    }
+
+    #[test]
+    fn test_raw_codes() {
+        let cheat = Cheat::parse("075a:09").unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x075a, 0x09, None)
+        );
+        let cheat = Cheat::parse("C010?A9:EA").unwrap();
+        assert_eq!(cheat.compare, Some(0xa9));
+        assert_eq!(Cheat::parse("75a:09"), Err(CheatError::InvalidFormat));
+        assert_eq!(Cheat::parse("075a:0x"), Err(CheatError::InvalidFormat));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut cheats = Cheats::new();
+        let freeze = cheats.add("0010:63").unwrap();
+        cheats.add("8000?A9:EA").unwrap();
+
+        assert_eq!(cheats.apply(0x0010, 0x01), 0x63);
+        assert_eq!(cheats.apply(0x0011, 0x01), 0x01);
+        assert_eq!(cheats.apply(0x8000, 0xa9), 0xea);
+        // another bank is mapped in
+        assert_eq!(cheats.apply(0x8000, 0x4c), 0x4c);
+
+        cheats.set_enabled(freeze, false);
+        assert_eq!(cheats.apply(0x0010, 0x01), 0x01);
+        assert!(cheats.remove(freeze).is_some());
+        assert_eq!(cheats.iter().count(), 1);
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+// Paces a frontend's loop: every host frame, at the console's frame rate,
+// `tick` says how many frames to emulate.
+//
+// At 1x that's one. Faster speeds run several and only the last one needs to
+// be shown (frame skip); slower speeds run none on some ticks so the picture
+// holds for a few host frames. Sound only plays at 1x: at any other speed it
+// would come too fast or too slow for the audio device, so it's muted.
+pub struct Clock {
+    frame_rate: f64,
+    speed: f32,
+    // frames owed, less than one
+    credit: f32,
+    next_frame: Option<Instant>,
+}
+
+impl Clock {
+    pub const MIN_SPEED: f32 = 0.125;
+    pub const MAX_SPEED: f32 = 8.0;
+
+    pub fn new(frame_rate: f64) -> Self {
+        Clock {
+            frame_rate,
+            speed: 1.0,
+            credit: 0.0,
+            next_frame: None,
+        }
+    }
+
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        self.frame_rate = frame_rate;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    // 2.0 is twice as fast, 0.5 half speed
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(Clock::MIN_SPEED, Clock::MAX_SPEED);
+    }
+
+    pub fn audio_muted(&self) -> bool {
+        self.speed != 1.0
+    }
+
+    // frames to emulate this host frame
+    pub fn tick(&mut self) -> usize {
+        self.credit += self.speed;
+        let frames = self.credit.floor();
+        self.credit -= frames;
+        frames as usize
+    }
+
+    // sleeps until the next host frame; a loop that fell behind carries on
+    // from now instead of catching up
+    pub fn wait(&mut self) {
+        let frame_duration = Duration::from_secs_f64(1.0 / self.frame_rate);
+        let now = Instant::now();
+        match self.next_frame {
+            Some(next_frame) if now < next_frame => {
+                std::thread::sleep(next_frame - now);
+                self.next_frame = Some(next_frame + frame_duration);
+            }
+            _ => self.next_frame = Some(now + frame_duration),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames_per_tick() {
+        let mut clock = Clock::new(60.0);
+        assert_eq!(clock.tick(), 1);
+        assert!(!clock.audio_muted());
+
+        clock.set_speed(4.0);
+        assert_eq!(clock.tick(), 4);
+        assert!(clock.audio_muted());
+
+        clock.set_speed(0.5);
+        let ticks: Vec<usize> = (0..4).map(|_| clock.tick()).collect();
+        assert_eq!(ticks, vec![0, 1, 0, 1]);
+
+        clock.set_speed(100.0);
+        assert_eq!(clock.speed(), Clock::MAX_SPEED);
+    }
+}
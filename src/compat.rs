@@ -0,0 +1,334 @@
+use crate::md5::md5;
+use crate::nes::Nes;
+use crate::rom::ROM;
+
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Compatibility runs over a whole ROM collection, see src/bin/compat_runner.rs:
+// every ROM runs headlessly for a number of frames on a pool of threads, and
+// what came of it goes in a JSON or HTML report. Comparing reports from
+// before and after a change shows which games it broke or fixed; the frame
+// hash tells whether the picture they end on changed at all.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    // ran all the frames, with something on screen at the end
+    Ran,
+    // ran all the frames, but the last one is one colour all over
+    BlankScreen,
+    // the ROM wouldn't load: a bad header, an unsupported mapper
+    LoadError(String),
+    // the emulator panicked, with the panic's message
+    Panicked(String),
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Ran => "ran",
+            Outcome::BlankScreen => "blank-screen",
+            Outcome::LoadError(_) => "load-error",
+            Outcome::Panicked(_) => "panicked",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Outcome::LoadError(message) | Outcome::Panicked(message) => message,
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatResult {
+    // the ROM's path relative to the directory the run started from
+    pub name: String,
+    pub mapper: Option<u16>,
+    pub outcome: Outcome,
+    // frames run before the outcome was known
+    pub frames: usize,
+    // MD5 of the last frame's pixels
+    pub frame_hash: Option<[u8; 16]>,
+}
+
+pub fn run_rom(name: &str, rom_bytes: &[u8], frames: usize) -> CompatResult {
+    let mut result = CompatResult {
+        name: name.to_string(),
+        mapper: None,
+        outcome: Outcome::Ran,
+        frames: 0,
+        frame_hash: None,
+    };
+    let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+        let rom = ROM::from_bytes(rom_bytes).map_err(|err| err.to_string())?;
+        result.mapper = Some(rom.mapper);
+        let mut nes = Nes::from_rom(rom, Nes::SAMPLE_RATE).map_err(|err| err.to_string())?;
+        for _ in 0..frames {
+            nes.run_frame();
+            nes.audio_samples();
+            result.frames += 1;
+        }
+        let pixels = &nes.frame_buffer().data;
+        result.frame_hash = Some(md5(pixels));
+        Ok::<bool, String>(pixels.chunks(3).all(|pixel| pixel == &pixels[..3]))
+    }));
+    result.outcome = match ran {
+        Ok(Ok(false)) => Outcome::Ran,
+        Ok(Ok(true)) => Outcome::BlankScreen,
+        Ok(Err(err)) => Outcome::LoadError(err),
+        Err(panic) => Outcome::Panicked(panic_message(panic.as_ref())),
+    };
+    result
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// every .nes file under `dir`, sorted
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = vec![];
+    let mut entries: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => return roms,
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            roms.extend(find_roms(&path));
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("nes"))
+        {
+            roms.push(path);
+        }
+    }
+    roms
+}
+
+// Runs every ROM under `dir` on `threads` threads, returning the results in
+// the order of find_roms. `progress` sees each result as it comes in.
+pub fn run_all<F>(dir: &Path, frames: usize, threads: usize, progress: F) -> Vec<CompatResult>
+where
+    F: Fn(&CompatResult) + Sync,
+{
+    let roms = find_roms(dir);
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; roms.len()]);
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = roms.get(index) else {
+                    break;
+                };
+                let name = path.strip_prefix(dir).unwrap_or(path).display().to_string();
+                let result = match std::fs::read(path) {
+                    Ok(bytes) => run_rom(&name, &bytes, frames),
+                    Err(err) => CompatResult {
+                        name,
+                        mapper: None,
+                        outcome: Outcome::LoadError(err.to_string()),
+                        frames: 0,
+                        frame_hash: None,
+                    },
+                };
+                progress(&result);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// how many results had each outcome, in the order Outcome lists them
+pub fn summary(results: &[CompatResult]) -> Vec<(&'static str, usize)> {
+    let names = ["ran", "blank-screen", "load-error", "panicked"];
+    names
+        .iter()
+        .map(|&name| {
+            let count = results
+                .iter()
+                .filter(|result| result.outcome.name() == name)
+                .count();
+            (name, count)
+        })
+        .collect()
+}
+
+pub fn to_json(results: &[CompatResult]) -> String {
+    let mut json = String::from("{\n  \"summary\": {");
+    let counts: Vec<String> = summary(results)
+        .iter()
+        .map(|(name, count)| format!("\"{}\": {}", name, count))
+        .collect();
+    json.push_str(&counts.join(", "));
+    json.push_str("},\n  \"roms\": [");
+    for (i, result) in results.iter().enumerate() {
+        json.push_str(if i == 0 { "\n" } else { ",\n" });
+        let _ = write!(
+            json,
+            "    {{\"name\": {}, \"mapper\": {}, \"outcome\": \"{}\", \"message\": {}, \
+             \"frames\": {}, \"frame_hash\": {}}}",
+            json_string(&result.name),
+            result
+                .mapper
+                .map_or("null".to_string(), |mapper| mapper.to_string()),
+            result.outcome.name(),
+            json_string(result.outcome.message()),
+            result.frames,
+            result
+                .frame_hash
+                .map_or("null".to_string(), |hash| format!("\"{}\"", hex(&hash))),
+        );
+    }
+    json.push_str("\n  ]\n}\n");
+    json
+}
+
+pub fn to_html(results: &[CompatResult]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Compatibility report</title>\n<style>\n\
+         table { border-collapse: collapse; font-family: monospace; }\n\
+         td, th { border: 1px solid #ccc; padding: 2px 6px; text-align: left; }\n\
+         .ran { background: #dfd; }\n.blank-screen { background: #ffd; }\n\
+         .load-error { background: #eee; }\n.panicked { background: #fdd; }\n\
+         </style>\n</head>\n<body>\n<h1>Compatibility report</h1>\n<p>",
+    );
+    let counts: Vec<String> = summary(results)
+        .iter()
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect();
+    html.push_str(&counts.join(", "));
+    html.push_str(
+        "</p>\n<table>\n<tr><th>ROM</th><th>Mapper</th><th>Outcome</th>\
+         <th>Frames</th><th>Frame hash</th><th>Message</th></tr>\n",
+    );
+    for result in results {
+        let _ = writeln!(
+            html,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            result.outcome.name(),
+            html_escape(&result.name),
+            result.mapper.map_or(String::new(), |mapper| mapper.to_string()),
+            result.outcome.name(),
+            result.frames,
+            result.frame_hash.map_or(String::new(), |hash| hex(&hash)),
+            html_escape(result.outcome.message()),
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::looping_rom;
+
+    #[test]
+    fn test_run_rom() {
+        let result = run_rom("loop.nes", &looping_rom(), 3);
+        assert_eq!(result.outcome, Outcome::BlankScreen);
+        assert_eq!(result.mapper, Some(0));
+        assert_eq!(result.frames, 3);
+        assert!(result.frame_hash.is_some());
+
+        let mut unsupported = looping_rom();
+        // mapper 255
+        unsupported[6] |= 0xf0;
+        unsupported[7] |= 0xf0;
+        let result = run_rom("bad.nes", &unsupported, 3);
+        assert!(matches!(result.outcome, Outcome::LoadError(_)));
+        assert_eq!(result.mapper, Some(255));
+        assert_eq!(result.frames, 0);
+
+        let result = run_rom("short.nes", &[0; 4], 3);
+        assert!(matches!(result.outcome, Outcome::LoadError(_)));
+        assert_eq!(result.mapper, None);
+    }
+
+    #[test]
+    fn test_run_all() {
+        let dir = std::env::temp_dir().join(format!("compat-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("b.nes"), looping_rom()).unwrap();
+        std::fs::write(dir.join("sub/a.NES"), [0; 4]).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let seen = AtomicUsize::new(0);
+        let results = run_all(&dir, 2, 4, |_| {
+            seen.fetch_add(1, Ordering::Relaxed);
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(seen.into_inner(), 2);
+        let names: Vec<&str> = results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["b.nes", &format!("sub{}a.NES", std::path::MAIN_SEPARATOR)]
+        );
+
+        let json = to_json(&results);
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["roms"][1]["mapper"], serde_json::Value::Null);
+        assert!(json.contains("\"summary\": {\"ran\": 0, \"blank-screen\": 1, \"load-error\": 1"));
+        assert!(json.contains("\"name\": \"b.nes\", \"mapper\": 0, \"outcome\": \"blank-screen\""));
+        let html = to_html(&results);
+        assert!(html.contains("<tr class=\"load-error\"><td>sub"));
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+        assert_eq!(html_escape("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+}
@@ -0,0 +1,333 @@
+use crate::accuracy::AccuracyConfig;
+use crate::input_map::{InputMap, InputMapError};
+use crate::region::Region;
+use crate::render::filter::VideoFilter;
+use crate::render::scaler::Scaler;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// The frontend's settings, kept in a TOML file:
+//
+//   [video]
+//   filter = "crt"
+//   scaler = "scale2x"
+//   palette = "palettes/smooth.pal"
+//
+//   [emulation]
+//   region = "pal"
+//   accuracy = "fast"
+//
+//   [audio]
+//   latency_ms = 100
+//
+//   [paths]
+//   saves = "saves"
+//   captures = "captures"
+//
+//   [input]
+//   mapping = ["port2 = zapper", "1 a = key:S", "1 b = key:D"]
+//
+// Anything left out keeps its default: no filter, scaler or palette, the
+// region from the ROM header, the accurate profile, 100ms of sound queued,
+// battery saves next to the ROM and captures in the working directory.
+// The input mapping is a list of the lines of an input_map.rs file, and
+// replaces the default bindings as a whole. Options on the command line
+// go over what the file says.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    // the file isn't TOML, or has settings of the wrong type
+    Syntax(String),
+    // a name that isn't one of the choices, like a filter that isn't there
+    InvalidSetting { key: &'static str, value: String },
+    InvalidInput(InputMapError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Syntax(message) => write!(f, "{}", message.trim_end()),
+            ConfigError::InvalidSetting { key, value } => {
+                write!(f, "invalid {} {:?}", key, value)
+            }
+            ConfigError::InvalidInput(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub video: VideoConfig,
+    pub emulation: EmulationConfig,
+    pub audio: AudioConfig,
+    pub paths: PathsConfig,
+    pub input: InputConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoConfig {
+    // see VideoFilter::from_name
+    pub filter: String,
+    // see Scaler::from_name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scaler: Option<String>,
+    // a .pal file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<PathBuf>,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            filter: "none".to_string(),
+            scaler: None,
+            palette: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmulationConfig {
+    // see Region::from_name; the ROM header's when there's none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    // see AccuracyConfig::from_name
+    pub accuracy: String,
+}
+
+impl Default for EmulationConfig {
+    fn default() -> Self {
+        EmulationConfig {
+            region: None,
+            accuracy: "accurate".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    // sound queued between the emulation and the sound card; less is more
+    // responsive, more is safer from crackling
+    pub latency_ms: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig { latency_ms: 100 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    // where battery saves go, next to the ROM if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saves: Option<PathBuf>,
+    // where screenshots and sound recordings go, the working directory if
+    // not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captures: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    pub mapping: Vec<String>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let text = InputMap::default().to_text();
+        InputConfig {
+            mapping: text.lines().map(String::from).collect(),
+        }
+    }
+}
+
+fn invalid(key: &'static str, value: &str) -> ConfigError {
+    ConfigError::InvalidSetting {
+        key,
+        value: value.to_string(),
+    }
+}
+
+impl Config {
+    // Reads the settings in `text`; names that aren't one of the choices
+    // are errors here rather than when they're used
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Config =
+            toml::from_str(text).map_err(|err| ConfigError::Syntax(err.to_string()))?;
+        config.filter()?;
+        config.scaler(1)?;
+        config.region()?;
+        config.accuracy()?;
+        config.input_map()?;
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Config::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        Ok(std::fs::write(path, self.to_toml())?)
+    }
+
+    // The settings in `path`, or the defaults written there for editing
+    // when there's no such file yet
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Config::from_toml(&text),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let config = Config::default();
+                config.save(path)?;
+                Ok(config)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn filter(&self) -> Result<VideoFilter, ConfigError> {
+        let name = &self.video.filter;
+        VideoFilter::from_name(name).ok_or_else(|| invalid("filter", name))
+    }
+
+    // a nearest neighbour scaler scales by `factor`
+    pub fn scaler(&self, factor: usize) -> Result<Option<Scaler>, ConfigError> {
+        match &self.video.scaler {
+            Some(name) => Scaler::from_name(name, factor)
+                .map(Some)
+                .ok_or_else(|| invalid("scaler", name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn region(&self) -> Result<Option<Region>, ConfigError> {
+        match &self.emulation.region {
+            Some(name) => Region::from_name(name)
+                .map(Some)
+                .ok_or_else(|| invalid("region", name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn accuracy(&self) -> Result<AccuracyConfig, ConfigError> {
+        let name = &self.emulation.accuracy;
+        AccuracyConfig::from_name(name).ok_or_else(|| invalid("accuracy", name))
+    }
+
+    pub fn input_map(&self) -> Result<InputMap, ConfigError> {
+        InputMap::parse(&self.input.mapping.join("\n")).map_err(ConfigError::InvalidInput)
+    }
+
+    // how many samples at `sample_rate` make the audio latency
+    pub fn audio_buffer_samples(&self, sample_rate: u32) -> usize {
+        (sample_rate as u64 * self.audio.latency_ms as u64 / 1000).max(1) as usize
+    }
+
+    // where the battery save of the ROM at `rom_path` goes
+    pub fn save_file(&self, rom_path: &Path) -> PathBuf {
+        let save = rom_path.with_extension("sav");
+        match (&self.paths.saves, save.file_name()) {
+            (Some(saves), Some(name)) => saves.join(name),
+            _ => save,
+        }
+    }
+
+    // where a capture called `name` goes
+    pub fn capture_file(&self, name: &str) -> PathBuf {
+        match &self.paths.captures {
+            Some(captures) => captures.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::Button;
+
+    #[test]
+    fn test_defaults_and_overrides() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.filter().unwrap(), VideoFilter::None);
+        assert_eq!(config.region().unwrap(), None);
+        assert_eq!(config.accuracy().unwrap(), AccuracyConfig::ACCURATE);
+        assert_eq!(config.audio_buffer_samples(44_100), 4410);
+        assert_eq!(
+            config.save_file(Path::new("roms/game.nes")),
+            Path::new("roms/game.sav")
+        );
+        assert_eq!(
+            config.input_map().unwrap().bindings(),
+            InputMap::default().bindings()
+        );
+
+        let config = Config::from_toml(
+            r#"
+            [video]
+            filter = "crt"
+            [emulation]
+            region = "pal"
+            accuracy = "fast"
+            [audio]
+            latency_ms = 50
+            [paths]
+            saves = "saves"
+            [input]
+            mapping = ["port2 = zapper", "1 a = key:D"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.filter().unwrap(), VideoFilter::Crt);
+        assert_eq!(config.region().unwrap(), Some(Region::Pal));
+        assert_eq!(config.accuracy().unwrap(), AccuracyConfig::FAST);
+        assert_eq!(config.audio_buffer_samples(48_000), 2400);
+        assert_eq!(
+            config.save_file(Path::new("roms/game.nes")),
+            Path::new("saves/game.sav")
+        );
+        let input = config.input_map().unwrap();
+        assert_eq!(input.bindings().len(), 1);
+        assert_eq!(input.bindings()[0].button, Button::A);
+        // and back again
+        assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            Config::from_toml("[video]\nfilter = \"vhs\""),
+            Err(ConfigError::InvalidSetting { key: "filter", .. })
+        ));
+        assert!(matches!(
+            Config::from_toml("[audio]\nlatency_ms = \"soon\""),
+            Err(ConfigError::Syntax(_))
+        ));
+        assert!(matches!(
+            Config::from_toml("[input]\nmapping = [\"1 jump = key:Z\"]"),
+            Err(ConfigError::InvalidInput(InputMapError::InvalidLine(1)))
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+
+// Co-op input sharing: a remote client owns one controller port while the
+// local user owns the other.
+//
+// Both sides run `delay` frames behind the inputs they produce, which gives
+// the remote input time to arrive without any rollback. The session doesn't
+// care how inputs travel; a transport pushes the remote's button bytes in as
+// they are received and emulation only advances when both ports have an
+// input for the next frame.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Port {
+    One,
+    Two,
+}
+
+impl Port {
+    pub fn other(&self) -> Port {
+        match self {
+            Port::One => Port::Two,
+            Port::Two => Port::One,
+        }
+    }
+}
+
+pub struct CoopSession {
+    remote_port: Port,
+    delay: usize,
+    local_inputs: VecDeque<u8>,
+    remote_inputs: VecDeque<u8>,
+    frame: u64,
+}
+
+impl CoopSession {
+    pub fn new(remote_port: Port, delay: usize) -> Self {
+        // the first `delay` frames run with nothing pressed on either port
+        CoopSession {
+            remote_port,
+            delay,
+            local_inputs: VecDeque::from(vec![0; delay]),
+            remote_inputs: VecDeque::from(vec![0; delay]),
+            frame: 0,
+        }
+    }
+
+    pub fn remote_port(&self) -> Port {
+        self.remote_port
+    }
+
+    pub fn local_port(&self) -> Port {
+        self.remote_port.other()
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // queue the local user's buttons; they take effect `delay` frames later
+    pub fn push_local(&mut self, buttons: u8) {
+        self.local_inputs.push_back(buttons);
+    }
+
+    // queue buttons received from the remote client, in the order it sent them
+    pub fn push_remote(&mut self, buttons: u8) {
+        self.remote_inputs.push_back(buttons);
+    }
+
+    // true when the remote is too far behind and the emulation has to wait
+    pub fn is_stalled(&self) -> bool {
+        self.local_inputs.is_empty() || self.remote_inputs.is_empty()
+    }
+
+    // buttons for (port 1, port 2) on the next frame, or None when stalled
+    pub fn next_frame(&mut self) -> Option<(u8, u8)> {
+        if self.is_stalled() {
+            return None;
+        }
+        let local = self.local_inputs.pop_front().unwrap();
+        let remote = self.remote_inputs.pop_front().unwrap();
+        self.frame += 1;
+
+        match self.remote_port {
+            Port::One => Some((remote, local)),
+            Port::Two => Some((local, remote)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inputs_are_delayed() {
+        let mut session = CoopSession::new(Port::Two, 2);
+        session.push_local(0x01);
+        session.push_remote(0x80);
+
+        assert_eq!(session.next_frame(), Some((0, 0)));
+        assert_eq!(session.next_frame(), Some((0, 0)));
+        assert_eq!(session.next_frame(), Some((0x01, 0x80)));
+        assert_eq!(session.frame(), 3);
+    }
+
+    #[test]
+    fn test_stalls_until_remote_input_arrives() {
+        let mut session = CoopSession::new(Port::One, 0);
+        session.push_local(0x08);
+        assert!(session.is_stalled());
+        assert_eq!(session.next_frame(), None);
+
+        session.push_remote(0x04);
+        assert_eq!(session.next_frame(), Some((0x04, 0x08)));
+        assert_eq!(session.local_port(), Port::Two);
+    }
+}
@@ -1,6 +1,15 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use crate::bus::BUS;
+use crate::debugger::{CallFrame, CallKind, CallStack};
+use crate::events::Event;
 use crate::opcodes;
-use std::collections::HashMap;
+use crate::profiler::{Location, Profiler};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+#[cfg(feature = "serde")]
+use crate::state::MachineState;
+use crate::symbols::Symbols;
+use crate::trace::{self, Tracer};
 
 // CPU memory map:
 // from: https://www.nesdev.org/wiki/CPU_memory_map
@@ -29,6 +38,7 @@ bitflags! {
     ///  | +--------------- Overflow Flag
     ///  +----------------- Negative Flag
     ///
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct CpuFlags: u8 {
         const CARRY             = 0b00000001;
         const ZERO              = 0b00000010;
@@ -52,9 +62,29 @@ pub struct CPU {
     pub program_counter: u16,
     pub stack_pointer: u8,
     pub bus: BUS,
+    // BRK stops the run loop instead of interrupting, which is how the test
+    // programs end. Cartridges want the real interrupt.
+    pub halt_on_brk: bool,
+    // what the program hasn't returned from, for the debugger
+    pub call_stack: CallStack,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    symbols: Symbols,
 }
 
-#[derive(Debug)]
+// the CPU's registers in a MachineState
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CpuState {
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status_register: CpuFlags,
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -70,14 +100,14 @@ pub enum AddressingMode {
 }
 
 pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+    fn mem_read(&mut self, addr: u16) -> u8;
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
         let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+        (hi << 8) | lo
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
@@ -89,14 +119,14 @@ pub trait Mem {
 }
 
 impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
+    fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
         self.bus.mem_write(addr, data)
     }
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         self.bus.mem_read_u16(pos)
     }
 
@@ -114,11 +144,16 @@ impl CPU {
             stack_pointer: STACK_RESET,
             program_counter: 0,
             status_register: CpuFlags::from_bits_truncate(0b100100),
-            bus: bus,
+            bus,
+            halt_on_brk: true,
+            call_stack: CallStack::new(),
+            tracer: None,
+            profiler: None,
+            symbols: Symbols::new(),
         }
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
         match mode {
             AddressingMode::Immediate => self.program_counter,
 
@@ -128,30 +163,26 @@ impl CPU {
 
             AddressingMode::ZeroPage_X => {
                 let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+                pos.wrapping_add(self.register_x) as u16
             }
             AddressingMode::ZeroPage_Y => {
                 let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
+                pos.wrapping_add(self.register_y) as u16
             }
 
             AddressingMode::Absolute_X => {
                 let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                addr
+                base.wrapping_add(self.register_x as u16)
             }
             AddressingMode::Absolute_Y => {
                 let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                addr
+                base.wrapping_add(self.register_y as u16)
             }
 
             AddressingMode::Indirect_X => {
                 let base = self.mem_read(self.program_counter);
 
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
+                let ptr: u8 = base.wrapping_add(self.register_x);
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
                 (hi as u16) << 8 | (lo as u16)
@@ -160,10 +191,9 @@ impl CPU {
                 let base = self.mem_read(self.program_counter);
 
                 let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
+                deref_base.wrapping_add(self.register_y as u16)
             }
 
             AddressingMode::NoneAddressing => {
@@ -187,7 +217,7 @@ impl CPU {
     }
 
     fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         self.set_register_a(value);
     }
@@ -257,17 +287,20 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_y);
     }
 
+    // Runs `program` from $0600 with the registers as they are, so a test
+    // can set them up first. It's loaded into RAM rather than at $8600 with
+    // the reset vector pointed at it: cartridge space is the ROM's, and
+    // writes there go to the mapper
     pub fn load_and_run(&mut self, program: Vec<u8>) {
         self.load(program);
-        self.reset();
+        self.program_counter = 0x0600;
         self.run()
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
         for i in 0..(program.len() as u16) {
-            self.mem_write(0x8600 + i, program[i as usize]);
+            self.mem_write(0x0600 + i, program[i as usize]);
         }
-        self.mem_write_u16(0xFFFC, 0x8600);
     }
 
     pub fn reset(&mut self) {
@@ -276,9 +309,229 @@ impl CPU {
         self.register_y = 0;
         self.stack_pointer = STACK_RESET;
         self.status_register = CpuFlags::from_bits_truncate(0b100100);
+        self.call_stack.clear();
         // self.memory = [0; 0xFFFF];
 
         self.program_counter = self.mem_read_u16(0xFFFC);
+        // like an interrupt, the reset sequence takes 7 cycles
+        self.bus.tick(7);
+    }
+
+    // The reset button: registers keep their values apart from the stack
+    // pointer, which moves down 3 as if an interrupt was pushed without the
+    // writes, and INTERRUPT_DISABLE
+    pub fn soft_reset(&mut self) {
+        self.bus.soft_reset();
+        self.call_stack.clear();
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status_register.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.bus.tick(7);
+    }
+
+    // Calls `tracer` with a nestest.log style line before each instruction,
+    // see trace.rs
+    pub fn set_tracer<F: FnMut(&str) + 'static>(&mut self, tracer: F) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = None;
+    }
+
+    fn enter(&mut self, kind: CallKind, from: u16, stack_pointer: u8) {
+        self.call_stack.push(CallFrame {
+            kind,
+            from,
+            to: self.program_counter,
+            stack_pointer,
+        });
+    }
+
+    // one instruction run again for reverse stepping, which the tracer and
+    // the profiler already saw the first time
+    pub(crate) fn replay_step(&mut self) -> bool {
+        let tracer = self.tracer.take();
+        let profiler = self.profiler.take();
+        let running = self.step();
+        self.tracer = tracer;
+        self.profiler = profiler;
+        running
+    }
+
+    // labels for the trace and the debugger, see symbols.rs
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn take_symbols(&mut self) -> Symbols {
+        core::mem::take(&mut self.symbols)
+    }
+
+    // the label for `addr` with the banks mapped right now
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.symbols.label(addr, self.bus.prg_offset(addr))
+    }
+
+    // Counts instructions and cycles per location from now on, see
+    // profiler.rs
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    // stops profiling and hands over what was counted
+    pub fn disable_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // The whole machine as a save state, see state.rs for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.section(b"CPU ", |state| {
+            state.write_u8(self.register_a);
+            state.write_u8(self.register_x);
+            state.write_u8(self.register_y);
+            state.write_u8(self.status_register.bits());
+            state.write_u16(self.program_counter);
+            state.write_u8(self.stack_pointer);
+        });
+        state.section(b"BUS ", |state| self.bus.save_state(state));
+        state.section(b"PPU ", |state| self.bus.ppu.save_state(state));
+        state.section(b"APU ", |state| self.bus.apu.save_state(state));
+        state.section(b"CART", |state| {
+            self.bus.mapper().borrow().save_state(state)
+        });
+        state.finish()
+    }
+
+    // Restores a state from save_state, made with the same cartridge. On
+    // error the machine is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = self.restore_state(data);
+        if result.is_err() {
+            self.restore_state(&backup)
+                .expect("the machine's own state should load");
+        }
+        result
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let state = StateReader::new(data)?;
+        // it's from a different point in the program
+        self.call_stack.clear();
+
+        let mut cpu = state.section(b"CPU ")?;
+        self.register_a = cpu.read_u8()?;
+        self.register_x = cpu.read_u8()?;
+        self.register_y = cpu.read_u8()?;
+        self.status_register = CpuFlags::from_bits_truncate(cpu.read_u8()?);
+        self.program_counter = cpu.read_u16()?;
+        self.stack_pointer = cpu.read_u8()?;
+
+        self.bus.load_state(&mut state.section(b"BUS ")?)?;
+        self.bus.ppu.load_state(&mut state.section(b"PPU ")?)?;
+        self.bus.apu.load_state(&mut state.section(b"APU ")?)?;
+        self.bus
+            .mapper()
+            .borrow_mut()
+            .load_state(&mut state.section(b"CART")?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn machine_state(&self) -> MachineState {
+        let mut cartridge = StateWriter::new();
+        cartridge.section(b"CART", |state| {
+            self.bus.mapper().borrow().save_state(state)
+        });
+        MachineState {
+            cpu: CpuState {
+                register_a: self.register_a,
+                register_x: self.register_x,
+                register_y: self.register_y,
+                status_register: self.status_register,
+                program_counter: self.program_counter,
+                stack_pointer: self.stack_pointer,
+            },
+            bus: self.bus.state(),
+            ppu: self.bus.ppu.state(),
+            apu: self.bus.apu.state(),
+            cartridge: cartridge.finish(),
+        }
+    }
+
+    // Restores a state from machine_state; on error the machine is left
+    // as it was, like load_state
+    #[cfg(feature = "serde")]
+    pub fn load_machine_state(&mut self, state: &MachineState) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = self.restore_machine_state(state);
+        if result.is_err() {
+            self.restore_state(&backup)
+                .expect("the machine's own state should load");
+        }
+        result
+    }
+
+    #[cfg(feature = "serde")]
+    fn restore_machine_state(&mut self, state: &MachineState) -> Result<(), StateError> {
+        self.call_stack.clear();
+        self.register_a = state.cpu.register_a;
+        self.register_x = state.cpu.register_x;
+        self.register_y = state.cpu.register_y;
+        self.status_register = state.cpu.status_register;
+        self.program_counter = state.cpu.program_counter;
+        self.stack_pointer = state.cpu.stack_pointer;
+
+        self.bus.set_state(&state.bus)?;
+        self.bus.ppu.set_state(&state.ppu)?;
+        self.bus.apu.set_state(&state.apu);
+        let cartridge = StateReader::new(&state.cartridge)?;
+        self.bus
+            .mapper()
+            .borrow_mut()
+            .load_state(&mut cartridge.section(b"CART")?)
+    }
+
+    // push the return address and status, then jump through the vector.
+    // Only BRK pushes the status with the B flag set.
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        self.stack_push_u16(self.program_counter);
+        let mut flag = self.status_register;
+        flag.set(CpuFlags::BREAK, brk);
+        flag.insert(CpuFlags::BREAK2);
+        self.stack_push(flag.bits);
+        self.status_register.insert(CpuFlags::INTERRUPT_DISABLE);
+
+        self.bus.tick(7);
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
+    fn interrupt_nmi(&mut self) {
+        self.interrupt(0xFFFA, false);
+    }
+
+    // the IRQ line is only seen with INTERRUPT_DISABLE clear
+    fn irq_requested(&self) -> bool {
+        !self.status_register.contains(CpuFlags::INTERRUPT_DISABLE) && self.bus.irq_line()
+    }
+
+    fn interrupt_irq(&mut self) {
+        self.interrupt(0xFFFE, false);
+    }
+
+    // BRK skips the padding byte after it
+    fn brk(&mut self) {
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.interrupt(0xFFFE, true);
     }
 
     fn set_carry_flag(&mut self) {
@@ -320,7 +573,7 @@ impl CPU {
     }
 
     fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let data = self.mem_read(addr);
         self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
     }
@@ -333,11 +586,11 @@ impl CPU {
 
     fn stack_pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.mem_read((STACK as u16) + self.stack_pointer as u16)
+        self.mem_read(STACK + self.stack_pointer as u16)
     }
 
     fn stack_push(&mut self, data: u8) {
-        self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
+        self.mem_write(STACK + self.stack_pointer as u16, data);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1)
     }
 
@@ -362,7 +615,7 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         self.set_register_a(data)
     }
 
@@ -374,7 +627,7 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
         data
@@ -387,7 +640,7 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1;
+        data >>= 1;
         self.set_register_a(data)
     }
 
@@ -399,7 +652,7 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1;
+        data >>= 1;
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
         data
@@ -415,9 +668,9 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         if old_carry {
-            data = data | 1;
+            data |= 1;
         }
         self.mem_write(addr, data);
         self.update_negative_flags(data);
@@ -433,9 +686,9 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         if old_carry {
-            data = data | 1;
+            data |= 1;
         }
         self.set_register_a(data);
     }
@@ -450,9 +703,9 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1;
+        data >>= 1;
         if old_carry {
-            data = data | 0b10000000;
+            data |= 0b10000000;
         }
         self.mem_write(addr, data);
         self.update_negative_flags(data);
@@ -468,9 +721,9 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1;
+        data >>= 1;
         if old_carry {
-            data = data | 0b10000000;
+            data |= 0b10000000;
         }
         self.set_register_a(data);
     }
@@ -516,7 +769,7 @@ impl CPU {
 
     fn php(&mut self) {
         //http://wiki.nesdev.com/w/index.php/CPU_status_flag_behavior
-        let mut flags = self.status_register.clone();
+        let mut flags = self.status_register;
         flags.insert(CpuFlags::BREAK);
         flags.insert(CpuFlags::BREAK2);
         self.stack_push(flags.bits());
@@ -584,556 +837,723 @@ impl CPU {
     where
         F: FnMut(&mut CPU),
     {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
+        while self.step() {
+            callback(self);
+        }
+    }
 
-        loop {
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
+    // Executes one instruction, servicing a pending NMI or IRQ first. Returns
+    // false on BRK when `halt_on_brk` is set, which stops the run loop.
+    pub fn step(&mut self) -> bool {
+        let start_cycles = self.bus.cycles();
+
+        let (from, stack_pointer) = (self.program_counter, self.stack_pointer);
+        if self.bus.poll_nmi_status() {
+            self.interrupt_nmi();
+            self.enter(CallKind::Nmi, from, stack_pointer);
+            self.bus.emit(Event::NmiFired);
+        } else if self.irq_requested() {
+            self.interrupt_irq();
+            self.enter(CallKind::Irq, from, stack_pointer);
+            self.bus.emit(Event::IrqFired);
+        }
 
-            let opcode = opcodes.get(&code).expect(&format!("OpCode {:x} is not recognized", code));
+        if self.tracer.is_some() {
+            let line = trace::trace(self);
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer(&line);
+            }
+        }
 
-            match code {
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                    self.lda(&opcode.mode);
-                }
+        let location = self.profiler.as_ref().map(|_| Location {
+            pc: self.program_counter,
+            prg_offset: self.bus.prg_offset(self.program_counter),
+        });
+        let code = self.mem_read(self.program_counter);
+        self.program_counter += 1;
+        let program_counter_state = self.program_counter;
 
-                0xAA => self.tax(),
-                0xe8 => self.inx(),
-                0x00 => return,
+        let opcode = &opcodes::OPCODES[code as usize];
 
-                // CLD  
-                0xd8 => self.status_register.remove(CpuFlags::DECIMAL),
+        match code {
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
+                self.lda(&opcode.mode);
+            }
 
-                // CLI  
-                0x58 => self.status_register.remove(CpuFlags::INTERRUPT_DISABLE),
+            0xAA => self.tax(),
+            0xe8 => self.inx(),
+            0x00 if self.halt_on_brk => return false,
+            0x00 => {
+                let stack_pointer = self.stack_pointer;
+                self.brk();
+                self.enter(CallKind::Brk, program_counter_state - 1, stack_pointer);
+            }
 
-                // CLV  
-                0xb8 => self.status_register.remove(CpuFlags::OVERFLOW),
+            // CLD  
+            0xd8 => self.status_register.remove(CpuFlags::DECIMAL),
 
-                // CLC  
-                0x18 => self.clear_carry_flag(),
+            // CLI  
+            0x58 => self.status_register.remove(CpuFlags::INTERRUPT_DISABLE),
 
-                // SEC  
-                0x38 => self.set_carry_flag(),
+            // CLV  
+            0xb8 => self.status_register.remove(CpuFlags::OVERFLOW),
 
-                // SEI  
-                0x78 => self.status_register.insert(CpuFlags::INTERRUPT_DISABLE),
+            // CLC  
+            0x18 => self.clear_carry_flag(),
 
-                // SED  
-                0xf8 => self.status_register.insert(CpuFlags::DECIMAL),
+            // SEC  
+            0x38 => self.set_carry_flag(),
 
-                // PHA  
-                0x48 => self.stack_push(self.register_a),
+            // SEI  
+            0x78 => self.status_register.insert(CpuFlags::INTERRUPT_DISABLE),
 
-                // PLA 
-                0x68 => {
-                    self.pla();
-                }
+            // SED  
+            0xf8 => self.status_register.insert(CpuFlags::DECIMAL),
 
-                // PHP 
-                0x08 => {
-                    self.php();
-                }
+            // PHA  
+            0x48 => self.stack_push(self.register_a),
 
-                // PLP 
-                0x28 => {
-                    self.plp();
-                }
+            // PLA 
+            0x68 => {
+                self.pla();
+            }
 
-                // ADC 
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
+            // PHP 
+            0x08 => {
+                self.php();
+            }
 
-                // SBC 
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-                    self.sbc(&opcode.mode);
-                }
+            // PLP 
+            0x28 => {
+                self.plp();
+            }
 
-                // AND 
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
+            // ADC 
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
+                self.adc(&opcode.mode);
+            }
 
-                // EOR 
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.mode);
-                }
+            // SBC 
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
+                self.sbc(&opcode.mode);
+            }
 
-                // ORA 
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.mode);
-                }
+            // AND 
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+                self.and(&opcode.mode);
+            }
 
-                // LSR  
-                0x4a => self.lsr_accumulator(),
+            // EOR 
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+                self.eor(&opcode.mode);
+            }
 
-                // LSR 
-                0x46 | 0x56 | 0x4e | 0x5e => {
-                    self.lsr(&opcode.mode);
-                }
+            // ORA 
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+                self.ora(&opcode.mode);
+            }
 
-                //ASL 
-                0x0a => self.asl_accumulator(),
+            // LSR  
+            0x4a => self.lsr_accumulator(),
 
-                // ASL 
-                0x06 | 0x16 | 0x0e | 0x1e => {
-                    self.asl(&opcode.mode);
-                }
+            // LSR 
+            0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&opcode.mode);
+            }
 
-                //ROL 
-                0x2a => self.rol_accumulator(),
+            //ASL 
+            0x0a => self.asl_accumulator(),
 
-                // ROL 
-                0x26 | 0x36 | 0x2e | 0x3e => {
-                    self.rol(&opcode.mode);
-                }
+            // ASL 
+            0x06 | 0x16 | 0x0e | 0x1e => {
+                self.asl(&opcode.mode);
+            }
 
-                // ROR  
-                0x6a => self.ror_accumulator(),
+            //ROL 
+            0x2a => self.rol_accumulator(),
 
-                // ROR 
-                0x66 | 0x76 | 0x6e | 0x7e => {
-                    self.ror(&opcode.mode);
-                }
+            // ROL 
+            0x26 | 0x36 | 0x2e | 0x3e => {
+                self.rol(&opcode.mode);
+            }
 
-                // INC 
-                0xe6 | 0xf6 | 0xee | 0xfe => {
-                    self.inc(&opcode.mode);
-                }
+            // ROR  
+            0x6a => self.ror_accumulator(),
 
-                // INY 
-                0xc8 => self.iny(),
+            // ROR 
+            0x66 | 0x76 | 0x6e | 0x7e => {
+                self.ror(&opcode.mode);
+            }
 
-                // DEC 
-                0xc6 | 0xd6 | 0xce | 0xde => {
-                    self.dec(&opcode.mode);
-                }
+            // INC 
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&opcode.mode);
+            }
 
-                // DEX 
-                0xca => {
-                    self.dex();
-                }
+            // INY 
+            0xc8 => self.iny(),
 
-                // DEY 
-                0x88 => {
-                    self.dey();
-                }
+            // DEC 
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(&opcode.mode);
+            }
 
-                // CMP 
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.compare(&opcode.mode, self.register_a);
-                }
+            // DEX 
+            0xca => {
+                self.dex();
+            }
 
-                // CPY 
-                0xc0 | 0xc4 | 0xcc => {
-                    self.compare(&opcode.mode, self.register_y);
-                }
+            // DEY 
+            0x88 => {
+                self.dey();
+            }
 
-                // CPX 
-                0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
+            // CMP 
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.compare(&opcode.mode, self.register_a);
+            }
 
-                // JMP Absolute 
-                0x4c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = mem_address;
-                }
+            // CPY 
+            0xc0 | 0xc4 | 0xcc => {
+                self.compare(&opcode.mode, self.register_y);
+            }
 
-                // JMP Indirect 
-                0x6c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-                    // let indirect_ref = self.mem_read_u16(mem_address);
-                    //6502 bug mode with with page boundary:
-                    //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
-                    // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
-                    // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
-
-                    let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                        let lo = self.mem_read(mem_address);
-                        let hi = self.mem_read(mem_address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        self.mem_read_u16(mem_address)
-                    };
-
-                    self.program_counter = indirect_ref;
-                }
+            // CPX 
+            0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
 
-                // JSR 
-                0x20 => {
-                    self.stack_push_u16(self.program_counter + 2 - 1);
-                    let target_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = target_address
-                }
+            // JMP Absolute 
+            0x4c => {
+                let mem_address = self.mem_read_u16(self.program_counter);
+                self.program_counter = mem_address;
+            }
 
-                // RTS 
-                0x60 => {
-                    self.program_counter = self.stack_pop_u16() + 1;
-                }
+            // JMP Indirect 
+            0x6c => {
+                let mem_address = self.mem_read_u16(self.program_counter);
+                // let indirect_ref = self.mem_read_u16(mem_address);
+                //6502 bug mode with with page boundary:
+                //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
+                // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
+                // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
+
+                let indirect_ref = if mem_address & 0x00FF == 0x00FF {
+                    let lo = self.mem_read(mem_address);
+                    let hi = self.mem_read(mem_address & 0xFF00);
+                    (hi as u16) << 8 | (lo as u16)
+                } else {
+                    self.mem_read_u16(mem_address)
+                };
+
+                self.program_counter = indirect_ref;
+            }
 
-                // RTI 
-                0x40 => {
-                    self.status_register.bits = self.stack_pop();
-                    self.status_register.remove(CpuFlags::BREAK);
-                    self.status_register.insert(CpuFlags::BREAK2);
+            // JSR 
+            0x20 => {
+                let stack_pointer = self.stack_pointer;
+                self.stack_push_u16(self.program_counter + 2 - 1);
+                let target_address = self.mem_read_u16(self.program_counter);
+                self.program_counter = target_address;
+                self.enter(CallKind::Subroutine, program_counter_state - 1, stack_pointer);
+            }
 
-                    self.program_counter = self.stack_pop_u16();
-                }
+            // RTS 
+            0x60 => {
+                self.program_counter = self.stack_pop_u16() + 1;
+            }
 
-                // BNE 
-                0xd0 => {
-                    self.branch(!self.status_register.contains(CpuFlags::ZERO));
-                }
+            // RTI 
+            0x40 => {
+                self.status_register.bits = self.stack_pop();
+                self.status_register.remove(CpuFlags::BREAK);
+                self.status_register.insert(CpuFlags::BREAK2);
 
-                // BVS 
-                0x70 => {
-                    self.branch(self.status_register.contains(CpuFlags::OVERFLOW));
-                }
+                self.program_counter = self.stack_pop_u16();
+            }
 
-                // BVC 
-                0x50 => {
-                    self.branch(!self.status_register.contains(CpuFlags::OVERFLOW));
-                }
+            // BNE 
+            0xd0 => {
+                self.branch(!self.status_register.contains(CpuFlags::ZERO));
+            }
 
-                // BPL 
-                0x10 => {
-                    self.branch(!self.status_register.contains(CpuFlags::NEGATIVE));
-                }
+            // BVS 
+            0x70 => {
+                self.branch(self.status_register.contains(CpuFlags::OVERFLOW));
+            }
 
-                // BMI 
-                0x30 => {
-                    self.branch(self.status_register.contains(CpuFlags::NEGATIVE));
-                }
+            // BVC 
+            0x50 => {
+                self.branch(!self.status_register.contains(CpuFlags::OVERFLOW));
+            }
 
-                // BEQ 
-                0xf0 => {
-                    self.branch(self.status_register.contains(CpuFlags::ZERO));
-                }
+            // BPL 
+            0x10 => {
+                self.branch(!self.status_register.contains(CpuFlags::NEGATIVE));
+            }
 
-                // BCS 
-                0xb0 => {
-                    self.branch(self.status_register.contains(CpuFlags::CARRY));
-                }
+            // BMI 
+            0x30 => {
+                self.branch(self.status_register.contains(CpuFlags::NEGATIVE));
+            }
 
-                // BCC 
-                0x90 => {
-                    self.branch(!self.status_register.contains(CpuFlags::CARRY));
-                }
+            // BEQ 
+            0xf0 => {
+                self.branch(self.status_register.contains(CpuFlags::ZERO));
+            }
 
-                // BIT 
-                0x24 | 0x2c => {
-                    self.bit(&opcode.mode);
-                }
+            // BCS 
+            0xb0 => {
+                self.branch(self.status_register.contains(CpuFlags::CARRY));
+            }
 
-                // STA 
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
+            // BCC 
+            0x90 => {
+                self.branch(!self.status_register.contains(CpuFlags::CARRY));
+            }
 
-                // STX 
-                0x86 | 0x96 | 0x8e => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.register_x);
-                }
+            // BIT 
+            0x24 | 0x2c => {
+                self.bit(&opcode.mode);
+            }
 
-                // STY 
-                0x84 | 0x94 | 0x8c => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.register_y);
-                }
+            // STA 
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
+                self.sta(&opcode.mode);
+            }
 
-                // LDX 
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                    self.ldx(&opcode.mode);
-                }
+            // STX 
+            0x86 | 0x96 | 0x8e => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.register_x);
+            }
 
-                // LDY 
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                    self.ldy(&opcode.mode);
-                }
+            // STY 
+            0x84 | 0x94 | 0x8c => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.register_y);
+            }
 
-                // NOP 
-                0xea => {
-                    //do nothing
-                }
+            // LDX 
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+                self.ldx(&opcode.mode);
+            }
 
-                // TAY 
-                0xa8 => {
-                    self.register_y = self.register_a;
-                    self.update_zero_and_negative_flags(self.register_y);
-                }
+            // LDY 
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+                self.ldy(&opcode.mode);
+            }
 
-                // TSX 
-                0xba => {
-                    self.register_x = self.stack_pointer;
-                    self.update_zero_and_negative_flags(self.register_x);
-                }
+            // NOP 
+            0xea => {
+                //do nothing
+            }
 
-                // TXA 
-                0x8a => {
-                    self.register_a = self.register_x;
-                    self.update_zero_and_negative_flags(self.register_a);
-                }
+            // TAY 
+            0xa8 => {
+                self.register_y = self.register_a;
+                self.update_zero_and_negative_flags(self.register_y);
+            }
 
-                // TXS 
-                0x9a => {
-                    self.stack_pointer = self.register_x;
-                }
+            // TSX 
+            0xba => {
+                self.register_x = self.stack_pointer;
+                self.update_zero_and_negative_flags(self.register_x);
+            }
 
-                // TYA 
-                0x98 => {
-                    self.register_a = self.register_y;
-                    self.update_zero_and_negative_flags(self.register_a);
-                }
+            // TXA 
+            0x8a => {
+                self.register_a = self.register_x;
+                self.update_zero_and_negative_flags(self.register_a);
+            }
 
-                // Unofficial opcodes
+            // TXS 
+            0x9a => {
+                self.stack_pointer = self.register_x;
+            }
 
-                // DCP
-                0xc7 | 0xd7 | 0xCF | 0xdF | 0xdb | 0xd3 | 0xc3 => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let mut data = self.mem_read(addr);
-                    data = data.wrapping_sub(1);
-                    self.mem_write(addr, data);
-                    // self._update_zero_and_negative_flags(data);
-                    if data <= self.register_a {
-                        self.status_register.insert(CpuFlags::CARRY);
-                    }
+            // TYA 
+            0x98 => {
+                self.register_a = self.register_y;
+                self.update_zero_and_negative_flags(self.register_a);
+            }
 
-                    self.update_zero_and_negative_flags(self.register_a.wrapping_sub(data));
-                }
+            // Unofficial opcodes
 
-                // RLA 
-                0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
-                    let data = self.rol(&opcode.mode);
-                    self.and_with_register_a(data);
+            // DCP
+            0xc7 | 0xd7 | 0xCF | 0xdf | 0xdb | 0xd3 | 0xc3 => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let mut data = self.mem_read(addr);
+                data = data.wrapping_sub(1);
+                self.mem_write(addr, data);
+                // self._update_zero_and_negative_flags(data);
+                if data <= self.register_a {
+                    self.status_register.insert(CpuFlags::CARRY);
                 }
 
-                // SLO 
-                //TODO tests
-                0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 => {
-                    let data = self.asl(&opcode.mode);
-                    self.or_with_register_a(data);
-                }
+                self.update_zero_and_negative_flags(self.register_a.wrapping_sub(data));
+            }
 
-                // SRE 
-                //TODO tests
-                0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
-                    let data = self.lsr(&opcode.mode);
-                    self.xor_with_register_a(data);
-                }
+            // RLA 
+            0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
+                let data = self.rol(&opcode.mode);
+                self.and_with_register_a(data);
+            }
 
-                // SKB
-                0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
-                    /* 2 byte NOP (immidiate ) */
-                    // TODO: might be worth doing the read
-                }
+            // SLO 
+            //TODO tests
+            0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 => {
+                let data = self.asl(&opcode.mode);
+                self.or_with_register_a(data);
+            }
 
-                // AXS
-                0xCB => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    let x_and_a = self.register_x & self.register_a;
-                    let result = x_and_a.wrapping_sub(data);
+            // SRE 
+            //TODO tests
+            0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
+                let data = self.lsr(&opcode.mode);
+                self.xor_with_register_a(data);
+            }
 
-                    if data <= x_and_a {
-                        self.status_register.insert(CpuFlags::CARRY);
-                    }
-                    self.update_zero_and_negative_flags(result);
+            // SKB
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
+                /* 2 byte NOP (immidiate ) */
+                // TODO: might be worth doing the read
+            }
 
-                    self.register_x = result;
-                }
+            // AXS
+            0xCB => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                let x_and_a = self.register_x & self.register_a;
+                let result = x_and_a.wrapping_sub(data);
 
-                // ARR
-                0x6B => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                    self.ror_accumulator();
-                    //TODO: registers
-                    let result = self.register_a;
-                    let bit_5 = (result >> 5) & 1;
-                    let bit_6 = (result >> 6) & 1;
-
-                    if bit_6 == 1 {
-                        self.status_register.insert(CpuFlags::CARRY)
-                    } else {
-                        self.status_register.remove(CpuFlags::CARRY)
-                    }
-
-                    if bit_5 ^ bit_6 == 1 {
-                        self.status_register.insert(CpuFlags::OVERFLOW);
-                    } else {
-                        self.status_register.remove(CpuFlags::OVERFLOW);
-                    }
-
-                    self.update_zero_and_negative_flags(result);
+                if data <= x_and_a {
+                    self.status_register.insert(CpuFlags::CARRY);
                 }
+                self.update_zero_and_negative_flags(result);
 
-                // unofficial SBC
-                0xeb => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.sub_from_register_a(data);
-                }
+                self.register_x = result;
+            }
+
+            // ARR
+            0x6B => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_register_a(data);
+                self.ror_accumulator();
+                //TODO: registers
+                let result = self.register_a;
+                let bit_5 = (result >> 5) & 1;
+                let bit_6 = (result >> 6) & 1;
 
-                // ANC
-                0x0b | 0x2b => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                    if self.status_register.contains(CpuFlags::NEGATIVE) {
-                        self.status_register.insert(CpuFlags::CARRY);
-                    } else {
-                        self.status_register.remove(CpuFlags::CARRY);
-                    }
+                if bit_6 == 1 {
+                    self.status_register.insert(CpuFlags::CARRY)
+                } else {
+                    self.status_register.remove(CpuFlags::CARRY)
                 }
 
-                // ALR
-                0x4b => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                    self.lsr_accumulator();
+                if bit_5 ^ bit_6 == 1 {
+                    self.status_register.insert(CpuFlags::OVERFLOW);
+                } else {
+                    self.status_register.remove(CpuFlags::OVERFLOW);
                 }
 
-                //TODO: test for everything bellow
+                self.update_zero_and_negative_flags(result);
+            }
 
-                // NOP read
-                #[allow(unused_variables)]
-                0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c
-                | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    // do nothing
-                }
+            // unofficial SBC
+            0xeb => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.sub_from_register_a(data);
+            }
 
-                // RRA
-                0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
-                    let data = self.ror(&opcode.mode);
-                    self.add_to_register_a(data);
+            // ANC
+            0x0b | 0x2b => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_register_a(data);
+                if self.status_register.contains(CpuFlags::NEGATIVE) {
+                    self.status_register.insert(CpuFlags::CARRY);
+                } else {
+                    self.status_register.remove(CpuFlags::CARRY);
                 }
+            }
 
-                // ISB
-                0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
-                    let data = self.inc(&opcode.mode);
-                    self.sub_from_register_a(data);
-                }
+            // ALR
+            0x4b => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_register_a(data);
+                self.lsr_accumulator();
+            }
 
-                // NOPs
-                0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2
-                | 0xf2 => { /* do nothing */ }
+            //TODO: test for everything bellow
 
-                0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => { /* do nothing */ }
+            // NOP read
+            #[allow(unused_variables)]
+            0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c
+            | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                // do nothing
+            }
 
-                // LAX
-                0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.set_register_a(data);
-                    self.register_x = self.register_a;
-                }
+            // RRA
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
+                let data = self.ror(&opcode.mode);
+                self.add_to_register_a(data);
+            }
 
-                // SAX
-                0x87 | 0x97 | 0x8f | 0x83 => {
-                    let data = self.register_a & self.register_x;
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, data);
-                }
+            // ISB
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
+                let data = self.inc(&opcode.mode);
+                self.sub_from_register_a(data);
+            }
 
-                // LXA
-                0xab => {
-                    self.lda(&opcode.mode);
-                    self.tax();
-                }
+            // NOPs
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2
+            | 0xf2 => { /* do nothing */ }
 
-                // XAA
-                0x8b => {
-                    self.register_a = self.register_x;
-                    self.update_zero_and_negative_flags(self.register_a);
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                }
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => { /* do nothing */ }
 
-                // LAS
-                0xbb => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let mut data = self.mem_read(addr);
-                    data = data & self.stack_pointer;
-                    self.register_a = data;
-                    self.register_x = data;
-                    self.stack_pointer = data;
-                    self.update_zero_and_negative_flags(data);
-                }
+            // LAX
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.set_register_a(data);
+                self.register_x = self.register_a;
+            }
 
-                // TAS
-                0x9b => {
-                    let data = self.register_a & self.register_x;
-                    self.stack_pointer = data;
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_y as u16;
+            // SAX
+            0x87 | 0x97 | 0x8f | 0x83 => {
+                let data = self.register_a & self.register_x;
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, data);
+            }
 
-                    let data = ((mem_address >> 8) as u8 + 1) & self.stack_pointer;
-                    self.mem_write(mem_address, data)
-                }
+            // LXA
+            0xab => {
+                self.lda(&opcode.mode);
+                self.tax();
+            }
 
-                // AHX  Indirect Y
-                0x93 => {
-                    let pos: u8 = self.mem_read(self.program_counter);
-                    let mem_address = self.mem_read_u16(pos as u16) + self.register_y as u16;
-                    let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
-                    self.mem_write(mem_address, data)
-                }
+            // XAA
+            0x8b => {
+                self.register_a = self.register_x;
+                self.update_zero_and_negative_flags(self.register_a);
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_register_a(data);
+            }
 
-                // AHX Absolute Y
-                0x9f => {
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_y as u16;
+            // LAS
+            0xbb => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let mut data = self.mem_read(addr);
+                data &= self.stack_pointer;
+                self.register_a = data;
+                self.register_x = data;
+                self.stack_pointer = data;
+                self.update_zero_and_negative_flags(data);
+            }
 
-                    let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
-                    self.mem_write(mem_address, data)
-                }
+            // TAS
+            0x9b => {
+                let data = self.register_a & self.register_x;
+                self.stack_pointer = data;
+                let mem_address =
+                    self.mem_read_u16(self.program_counter) + self.register_y as u16;
 
-                // SHX
-                0x9e => {
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_y as u16;
+                let data = ((mem_address >> 8) as u8 + 1) & self.stack_pointer;
+                self.mem_write(mem_address, data)
+            }
 
-                    // TODO: if cross page boundry {
-                    //     mem_address &= (self.x as u16) << 8;
-                    // }
-                    let data = self.register_x & ((mem_address >> 8) as u8 + 1);
-                    self.mem_write(mem_address, data)
-                }
+            // AHX  Indirect Y
+            0x93 => {
+                let pos: u8 = self.mem_read(self.program_counter);
+                let mem_address = self.mem_read_u16(pos as u16) + self.register_y as u16;
+                let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
+                self.mem_write(mem_address, data)
+            }
 
-                // SHY 
-                0x9c => {
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_x as u16;
-                    let data = self.register_y & ((mem_address >> 8) as u8 + 1);
-                    self.mem_write(mem_address, data)
-                }
+            // AHX Absolute Y
+            0x9f => {
+                let mem_address =
+                    self.mem_read_u16(self.program_counter) + self.register_y as u16;
+
+                let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
+                self.mem_write(mem_address, data)
+            }
+
+            // SHX
+            0x9e => {
+                let mem_address =
+                    self.mem_read_u16(self.program_counter) + self.register_y as u16;
 
+                // TODO: if cross page boundry {
+                //     mem_address &= (self.x as u16) << 8;
+                // }
+                let data = self.register_x & ((mem_address >> 8) as u8 + 1);
+                self.mem_write(mem_address, data)
             }
 
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len - 1) as u16;
+            // SHY 
+            0x9c => {
+                let mem_address =
+                    self.mem_read_u16(self.program_counter) + self.register_x as u16;
+                let data = self.register_y & ((mem_address >> 8) as u8 + 1);
+                self.mem_write(mem_address, data)
             }
 
-            callback(self);
         }
+
+        self.bus.tick(opcode.cycles);
+        if self.bus.write_protection.has_violations() {
+            self.bus.write_protection.stamp_pc(program_counter_state - 1);
+        }
+
+        if program_counter_state == self.program_counter {
+            self.program_counter += (opcode.len - 1) as u16;
+        }
+        self.call_stack.unwind(self.stack_pointer);
+
+        if let (Some(profiler), Some(location)) = (self.profiler.as_mut(), location) {
+            profiler.record(location, code, self.bus.cycles() - start_cycles);
+        }
+        true
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use alloc::vec;
     use crate::rom::test;
 
+    #[test]
+    fn test_write_to_protected_ram_is_reported() {
+        let bus = BUS::new(test::test_rom()).unwrap();
+        let mut cpu = CPU::new(bus);
+        cpu.mem_write(0x10, 0x55);
+        cpu.bus
+            .write_protection
+            .protect(0x0010..=0x0010, crate::protect::Protection::ReadOnly);
+
+        // LDA #$99; STA $10; BRK
+        cpu.load_and_run(vec![0xa9, 0x99, 0x85, 0x10, 0x00]);
+
+        assert_eq!(cpu.mem_read(0x10), 0x55);
+        let violations = cpu.bus.write_protection.take_violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].addr, 0x0010);
+        assert_eq!(violations[0].value, 0x99);
+        assert_eq!(violations[0].previous, Some(0x55));
+        assert_eq!(violations[0].pc, Some(0x0602));
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let bus = BUS::new(test::test_rom()).unwrap();
+        let mut cpu = CPU::new(bus);
+        // LDA #$42; STA $10; STA $2001; LDX #$07; BRK
+        cpu.load_and_run(vec![
+            0xa9, 0x42, 0x85, 0x10, 0x8d, 0x01, 0x20, 0xa2, 0x07, 0x00,
+        ]);
+        cpu.bus.apu.write_register(0x4015, 0x01);
+        cpu.bus.apu.write_register(0x4003, 0x08);
+        let state = cpu.save_state();
+
+        cpu.register_x = 0;
+        cpu.mem_write(0x10, 0);
+        cpu.mem_write(0x2001, 0);
+        cpu.bus.apu.write_register(0x4015, 0x00);
+        cpu.bus.tick(30);
+        cpu.load_state(&state).unwrap();
+        // checked first, reads change the open bus value
+        assert_eq!(cpu.save_state(), state);
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.register_x, 0x07);
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+        assert_eq!(cpu.bus.ppu.mask.bits(), 0x42);
+        assert_eq!(cpu.bus.apu.read_status() & 0x01, 0x01);
+    }
+
+    #[test]
+    fn test_bad_state_leaves_the_machine_alone() {
+        let bus = BUS::new(test::test_rom()).unwrap();
+        let mut cpu = CPU::new(bus);
+        cpu.load_and_run(vec![0xa9, 0x42, 0x85, 0x10, 0x00]);
+        let state = cpu.save_state();
+        cpu.mem_write(0x10, 0x55);
+
+        assert_eq!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(cpu.mem_read(0x10), 0x55);
+        assert_eq!(
+            cpu.load_state(b"NESS\x09\x00"),
+            Err(StateError::UnsupportedVersion(9))
+        );
+    }
+
+    #[test]
+    fn test_brk_interrupts_through_fffe() {
+        let bus = BUS::new(test::test_rom()).unwrap();
+        let mut cpu = CPU::new(bus);
+        cpu.halt_on_brk = false;
+        // BRK, padding byte, INX; the test ROM's vector points at RTI in RAM
+        cpu.load(vec![0x00, 0xff, 0xe8]);
+        cpu.mem_write(0x0101, 0x40);
+        cpu.program_counter = 0x0600;
+        cpu.status_register.remove(CpuFlags::INTERRUPT_DISABLE);
+
+        assert!(cpu.step());
+        assert_eq!(cpu.program_counter, 0x0101);
+        assert!(cpu.status_register.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.mem_read(0x01fb) & 0b0011_0000, 0b0011_0000);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_apu_frame_irq() {
+        let bus = BUS::new(test::test_rom()).unwrap();
+        let mut cpu = CPU::new(bus);
+        // JMP $0600, and LDA $4015; INY; RTI as the handler
+        cpu.load(vec![0x4c, 0x00, 0x06]);
+        for (i, byte) in [0xad, 0x15, 0x40, 0xc8, 0x40].iter().enumerate() {
+            cpu.mem_write(0x0101 + i as u16, *byte);
+        }
+        cpu.program_counter = 0x0600;
+
+        // ignored while interrupts are disabled
+        while cpu.bus.cycles() < 30_000 {
+            cpu.step();
+        }
+        assert!(cpu.bus.irq_line());
+        assert_eq!(cpu.program_counter, 0x0600);
+
+        cpu.status_register.remove(CpuFlags::INTERRUPT_DISABLE);
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0104);
+        assert!(!cpu.bus.irq_line());
+        assert_eq!(cpu.mem_read(0x01fb) & 0b0011_0000, 0b0010_0000);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0600);
+        assert_eq!(cpu.register_y, 1);
+        assert!(!cpu.status_register.contains(CpuFlags::INTERRUPT_DISABLE));
+    }
+
     #[test]
     fn test_0xa9_lda_immidiate_load_data() {
-        let bus = BUS::new(test::test_rom());
+        let bus = BUS::new(test::test_rom()).unwrap();
         let mut cpu = CPU::new(bus);
         cpu.load_and_run(vec![0xa9, 0x05, 0x00]);
         assert_eq!(cpu.register_a, 5);
@@ -1143,7 +1563,7 @@ mod test {
 
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
-        let bus = BUS::new(test::test_rom());
+        let bus = BUS::new(test::test_rom()).unwrap();
         let mut cpu = CPU::new(bus);
         cpu.register_a = 10;
         cpu.load_and_run(vec![0xaa, 0x00]);
@@ -1153,7 +1573,7 @@ mod test {
 
     #[test]
     fn test_5_ops_working_together() {
-        let bus = BUS::new(test::test_rom());
+        let bus = BUS::new(test::test_rom()).unwrap();
         let mut cpu = CPU::new(bus);
         cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
 
@@ -1162,7 +1582,7 @@ mod test {
 
     #[test]
     fn test_inx_overflow() {
-        let bus = BUS::new(test::test_rom());
+        let bus = BUS::new(test::test_rom()).unwrap();
         let mut cpu = CPU::new(bus);
         cpu.register_x = 0xff;
         cpu.load_and_run(vec![0xe8, 0xe8, 0x00]);
@@ -1172,7 +1592,7 @@ mod test {
 
     #[test]
     fn test_lda_from_memory() {
-        let bus = BUS::new(test::test_rom());
+        let bus = BUS::new(test::test_rom()).unwrap();
         let mut cpu = CPU::new(bus);
         cpu.mem_write(0x10, 0x55);
 
@@ -0,0 +1,753 @@
+use crate::cpu::CPU;
+use crate::error::BusFault;
+use crate::expr::Expr;
+use crate::trace;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Breakpoints stop before the instruction at their address runs, watchpoints
+// after the instruction that touched their address. Watchpoints on RAM
+// also catch accesses through its mirrors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    pub(crate) fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub access: Access,
+    pub value: u8,
+}
+
+// Watched addresses on the CPU bus, checked by the BUS on every access
+#[derive(Default)]
+pub struct Watchpoints {
+    points: Vec<(u16, Access)>,
+    hit: Option<WatchHit>,
+}
+
+// RAM mirrors down to $0000-$07FF
+pub(crate) fn normalize(addr: u16) -> u16 {
+    if addr < 0x2000 {
+        addr & 0x07ff
+    } else {
+        addr
+    }
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints::default()
+    }
+
+    pub fn add(&mut self, addr: u16, access: Access) {
+        self.points.push((normalize(addr), access));
+    }
+
+    pub fn remove(&mut self, addr: u16) {
+        let addr = normalize(addr);
+        self.points.retain(|&(point, _)| point != addr);
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.hit = None;
+    }
+
+    pub fn check(&mut self, addr: u16, access: Access, value: u8) {
+        if self.points.is_empty() || self.hit.is_some() {
+            return;
+        }
+        let addr = normalize(addr);
+        let watched = self
+            .points
+            .iter()
+            .any(|&(point, watch)| point == addr && watch.covers(access));
+        if watched {
+            self.hit = Some(WatchHit {
+                addr,
+                access,
+                value,
+            });
+        }
+    }
+
+    // the first watched access since the last call
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+const DOTS_PER_SCANLINE: u32 = 341;
+
+// PPU positions to stop at, for debugging raster effects: checked by the
+// BUS each time the PPU runs. The CPU only stops between instructions, so
+// it stops after the instruction during which the PPU got there, with the
+// PPU a few dots past the position; DebugState has where exactly.
+#[derive(Default)]
+pub struct PpuBreakpoints {
+    points: Vec<(u16, u16)>,
+    hit: Option<(u16, u16)>,
+}
+
+impl PpuBreakpoints {
+    pub fn new() -> Self {
+        PpuBreakpoints::default()
+    }
+
+    // scanline 0 is the first visible line, dot 0 the idle dot before it
+    pub fn add(&mut self, scanline: u16, dot: u16) {
+        if !self.points.contains(&(scanline, dot)) {
+            self.points.push((scanline, dot));
+        }
+    }
+
+    pub fn remove(&mut self, scanline: u16, dot: u16) {
+        self.points.retain(|&point| point != (scanline, dot));
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.hit = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    // The PPU went from `from` to `to`, as (scanline, dot), in a frame of
+    // `scanlines` lines, crossing into the next frame if it has to
+    pub fn check(&mut self, from: (u16, u16), to: (u16, u16), scanlines: u16) {
+        if self.points.is_empty() || self.hit.is_some() {
+            return;
+        }
+        let frame = scanlines as u32 * DOTS_PER_SCANLINE;
+        let position =
+            |(scanline, dot): (u16, u16)| scanline as u32 * DOTS_PER_SCANLINE + dot as u32;
+        let start = position(from);
+        let distance = |point| (position(point) + frame - start) % frame;
+        let ran = distance(to);
+        // the point passed first, the one the PPU got to
+        self.hit = self
+            .points
+            .iter()
+            .copied()
+            .filter(|&point| (1..=ran).contains(&distance(point)))
+            .min_by_key(|&point| distance(point));
+    }
+
+    // the first position reached since the last call
+    pub fn take_hit(&mut self) -> Option<(u16, u16)> {
+        self.hit.take()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Subroutine,
+    Nmi,
+    Irq,
+    Brk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    // the JSR or BRK, or the instruction an NMI or IRQ came before
+    pub from: u16,
+    // the subroutine or interrupt handler
+    pub to: u16,
+    // before the call pushed anything
+    pub stack_pointer: u8,
+}
+
+// The stack only has room for 128 return addresses
+const MAX_CALL_DEPTH: usize = 128;
+
+// The JSRs and interrupts the program hasn't returned from, kept by the CPU.
+// Games don't always return the usual way: some drop the return address
+// with two PLAs and jump, some reset the stack with TXS. So a call is over
+// once the stack pointer is back up to where it was before it, however it
+// got there.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    pub fn push(&mut self, frame: CallFrame) {
+        if self.frames.len() == MAX_CALL_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // drops the calls the stack pointer has come back up past
+    pub fn unwind(&mut self, stack_pointer: u8) {
+        while let Some(frame) = self.frames.last() {
+            if frame.stack_pointer > stack_pointer {
+                break;
+            }
+            self.frames.pop();
+        }
+    }
+
+    // outermost first
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    // the instruction at the breakpoint is next
+    Breakpoint(u16),
+    Watchpoint(WatchHit),
+    // this watch expression became true
+    WatchExpression(WatchId),
+    // the PPU reached a PPU breakpoint during the last instruction
+    PpuPosition { scanline: u16, dot: u16 },
+    // the requested step, step over or number of cycles is done
+    Step,
+    FrameComplete,
+    // BRK with the CPU set to halt on it
+    Halted,
+    // the console is paused, nothing ran
+    Paused,
+    // an access with no defined effect, in strict mode
+    Fault(BusFault),
+}
+
+pub enum Until {
+    Step,
+    // back from the JSR at the current instruction
+    Return { pc: u16, stack_pointer: u8 },
+    FrameComplete,
+    // the call stack no deeper than this
+    Depth(usize),
+    // the bus reaching this cycle count, or the end of a frame before that
+    FrameOrCycle(usize),
+}
+
+// CPU and PPU state at the point the debugger stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+    pub scanline: u16,
+    pub dot: u16,
+    pub cycles: usize,
+    // the next instruction, nestest.log style
+    pub next: String,
+    // the label at pc, with symbols loaded
+    pub label: Option<String>,
+    pub call_stack: Vec<CallFrame>,
+}
+
+impl DebugState {
+    pub fn capture(cpu: &CPU) -> Self {
+        DebugState {
+            pc: cpu.program_counter,
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            status: cpu.status_register.bits(),
+            stack_pointer: cpu.stack_pointer,
+            scanline: cpu.bus.ppu.scanline,
+            dot: cpu.bus.ppu.dot,
+            cycles: cpu.bus.cycles(),
+            next: trace::trace(cpu),
+            label: cpu.label(cpu.program_counter).map(str::to_string),
+            call_stack: cpu.call_stack.frames().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for DebugState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{}:\n{}", label, self.next),
+            None => write!(f, "{}", self.next),
+        }
+    }
+}
+
+// Where the program stops, before the instruction at the breakpoint's
+// address runs. Executions with `condition` false don't count; of the ones
+// that do, it stops from the `hit_count`th on, every time with 0 or 1. A
+// temporary breakpoint is gone once it's stopped, for running to a point
+// like step over and step out do.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    pub hit_count: usize,
+    pub temporary: bool,
+    // executions counted so far
+    pub hits: usize,
+}
+
+// An expression to show the value of whenever the debugger stops, see
+// expr.rs. One that breaks is also evaluated after every instruction, and
+// stops the run when it goes from false to true.
+// stays the same for as long as the watch is there, whatever else is
+// added or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WatchId(usize);
+
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub expr: Expr,
+    pub break_on_true: bool,
+    was_true: bool,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    // the breakpoint the last run stopped at, which the next run starts on
+    stopped_at: Option<u16>,
+    watches: BTreeMap<WatchId, Watch>,
+    next_watch_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.set_breakpoint(addr, Breakpoint::default());
+    }
+
+    // replacing any breakpoint already there
+    pub fn set_breakpoint(&mut self, addr: u16, breakpoint: Breakpoint) {
+        self.breakpoints.insert(addr, breakpoint);
+    }
+
+    pub fn add_temporary_breakpoint(&mut self, addr: u16) {
+        let breakpoint = Breakpoint {
+            temporary: true,
+            ..Breakpoint::default()
+        };
+        self.set_breakpoint(addr, breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn breakpoint(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.keys()
+    }
+
+    // whether the breakpoint at pc, if there is one, stops the program now
+    fn hit_breakpoint(&mut self, cpu: &CPU) -> bool {
+        let pc = cpu.program_counter;
+        let breakpoint = match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+        if let Some(condition) = &breakpoint.condition {
+            if !condition.is_true(cpu) {
+                return false;
+            }
+        }
+        breakpoint.hits += 1;
+        if breakpoint.hits < breakpoint.hit_count {
+            return false;
+        }
+        if breakpoint.temporary {
+            self.breakpoints.remove(&pc);
+        }
+        true
+    }
+
+    pub fn add_watch(&mut self, cpu: &CPU, expr: Expr, break_on_true: bool) -> WatchId {
+        let id = WatchId(self.next_watch_id);
+        self.next_watch_id += 1;
+        let watch = Watch {
+            was_true: expr.is_true(cpu),
+            expr,
+            break_on_true,
+        };
+        self.watches.insert(id, watch);
+        id
+    }
+
+    pub fn remove_watch(&mut self, id: WatchId) -> Option<Watch> {
+        self.watches.remove(&id)
+    }
+
+    // in the order they were added
+    pub fn watches(&self) -> impl Iterator<Item = (WatchId, &Watch)> {
+        self.watches.iter().map(|(&id, watch)| (id, watch))
+    }
+
+    // the first breaking watch to become true
+    fn check_watches(&mut self, cpu: &CPU) -> Option<WatchId> {
+        let mut became_true = None;
+        for (&id, watch) in self.watches.iter_mut() {
+            if !watch.break_on_true {
+                continue;
+            }
+            let is_true = watch.expr.is_true(cpu);
+            if is_true && !watch.was_true && became_true.is_none() {
+                became_true = Some(id);
+            }
+            watch.was_true = is_true;
+        }
+        became_true
+    }
+
+    // the program was moved back to `pc`; carry on from a breakpoint there
+    // rather than stopping at it again
+    pub fn resume_at(&mut self, pc: u16) {
+        self.stopped_at = self.breakpoints.contains_key(&pc).then_some(pc);
+    }
+
+    // the program was loaded again, so it isn't stopped at any breakpoint
+    pub fn forget_stop(&mut self) {
+        self.stopped_at = None;
+    }
+
+    // Runs `cpu` until `until` is reached or something stops it first. A
+    // program stopped at a breakpoint carries on from it.
+    pub fn run(&mut self, cpu: &mut CPU, until: Until) -> StopReason {
+        let mut resume_from = self.stopped_at.take();
+        loop {
+            let pc = cpu.program_counter;
+            if resume_from != Some(pc) && self.hit_breakpoint(cpu) {
+                self.stopped_at = Some(pc);
+                return StopReason::Breakpoint(pc);
+            }
+            resume_from = None;
+
+            if !cpu.step() {
+                return StopReason::Halted;
+            }
+            if let Some(hit) = cpu.bus.watchpoints.take_hit() {
+                return StopReason::Watchpoint(hit);
+            }
+            if let Some((scanline, dot)) = cpu.bus.ppu_breakpoints.take_hit() {
+                return StopReason::PpuPosition { scanline, dot };
+            }
+            if !self.watches.is_empty() {
+                if let Some(id) = self.check_watches(cpu) {
+                    return StopReason::WatchExpression(id);
+                }
+            }
+            if let Some(fault) = cpu.bus.take_fault() {
+                return StopReason::Fault(fault);
+            }
+            match until {
+                Until::Step => return StopReason::Step,
+                Until::Return { pc, stack_pointer } => {
+                    if cpu.program_counter == pc && cpu.stack_pointer >= stack_pointer {
+                        return StopReason::Step;
+                    }
+                }
+                Until::Depth(depth) => {
+                    if cpu.call_stack.depth() <= depth {
+                        return StopReason::Step;
+                    }
+                }
+                Until::FrameComplete => {
+                    if cpu.bus.poll_frame_complete() {
+                        return StopReason::FrameComplete;
+                    }
+                }
+                Until::FrameOrCycle(cycle) => {
+                    if cpu.bus.poll_frame_complete() {
+                        return StopReason::FrameComplete;
+                    }
+                    if cpu.bus.cycles() >= cycle {
+                        return StopReason::Step;
+                    }
+                }
+            }
+        }
+    }
+
+    // like a single step, except that a JSR runs until its subroutine returns
+    pub fn step_over(&mut self, cpu: &mut CPU) -> StopReason {
+        let pc = cpu.program_counter;
+        if cpu.bus.peek(pc) == 0x20 {
+            let until = Until::Return {
+                pc: pc.wrapping_add(3),
+                stack_pointer: cpu.stack_pointer,
+            };
+            self.run(cpu, until)
+        } else {
+            self.run(cpu, Until::Step)
+        }
+    }
+
+    // Runs until the subroutine or interrupt handler the program is in
+    // returns, see CallStack; outside of one it's a single step
+    pub fn step_out(&mut self, cpu: &mut CPU) -> StopReason {
+        match cpu.call_stack.depth() {
+            0 => self.run(cpu, Until::Step),
+            depth => self.run(cpu, Until::Depth(depth - 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::BUS;
+    use crate::cpu::Mem;
+    use crate::rom::test::test_rom;
+    use crate::symbols::Symbols;
+    use alloc::vec;
+
+    // $0600: JSR $0610; INX; STA $0800; BRK
+    // $0610: INY; INY; RTS
+    fn cpu() -> CPU {
+        let mut cpu = CPU::new(BUS::new(test_rom()).unwrap());
+        cpu.load(vec![0x20, 0x10, 0x06, 0xe8, 0x8d, 0x00, 0x08, 0x00]);
+        for (i, byte) in [0xc8, 0xc8, 0x60].iter().enumerate() {
+            cpu.mem_write(0x0610 + i as u16, *byte);
+        }
+        cpu.program_counter = 0x0600;
+        cpu
+    }
+
+    #[test]
+    fn test_step_and_step_over() {
+        let mut debugger = Debugger::new();
+        let mut cpu = cpu();
+        assert_eq!(debugger.run(&mut cpu, Until::Step), StopReason::Step);
+        assert_eq!(cpu.program_counter, 0x0610);
+
+        let mut cpu = self::cpu();
+        assert_eq!(debugger.step_over(&mut cpu), StopReason::Step);
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.register_y, 2);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0611);
+        debugger.add_breakpoint(0x0603);
+        let mut cpu = cpu();
+
+        // breakpoints inside a stepped over subroutine still stop it
+        assert_eq!(debugger.step_over(&mut cpu), StopReason::Breakpoint(0x0611));
+        assert_eq!(cpu.register_y, 1);
+
+        let state = DebugState::capture(&cpu);
+        assert_eq!(state.pc, 0x0611);
+        assert!(state.to_string().starts_with("0611  C8        INY"));
+        let mut symbols = Symbols::new();
+        symbols.load_nl("$0611#count#", None).unwrap();
+        cpu.set_symbols(symbols);
+        let state = DebugState::capture(&cpu);
+        assert_eq!(state.label.as_deref(), Some("count"));
+        assert!(state.to_string().starts_with("count:\n0611  C8"));
+
+        assert_eq!(
+            debugger.run(&mut cpu, Until::FrameComplete),
+            StopReason::Breakpoint(0x0603)
+        );
+        debugger.remove_breakpoint(0x0603);
+        assert_eq!(
+            debugger.run(&mut cpu, Until::FrameComplete),
+            StopReason::Halted
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = cpu();
+        cpu.register_a = 0x42;
+        // $0800 is a mirror of $0000
+        cpu.bus.watchpoints.add(0x0000, Access::Write);
+
+        assert_eq!(
+            debugger.run(&mut cpu, Until::FrameComplete),
+            StopReason::Watchpoint(WatchHit {
+                addr: 0x0000,
+                access: Access::Write,
+                value: 0x42,
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x0607);
+
+        cpu.bus.watchpoints.clear();
+        cpu.bus.watchpoints.add(0x0607, Access::Read);
+        cpu.bus.watchpoints.add(0x0010, Access::ReadWrite);
+        assert_eq!(cpu.mem_read(0x0010), 0);
+        assert_eq!(cpu.bus.watchpoints.take_hit().unwrap().access, Access::Read);
+        assert_eq!(cpu.bus.watchpoints.take_hit(), None);
+    }
+
+    #[test]
+    fn test_ppu_breakpoints() {
+        let mut points = PpuBreakpoints::new();
+        points.check((0, 0), (10, 0), 262);
+        assert_eq!(points.take_hit(), None);
+
+        points.add(100, 50);
+        points.add(100, 48);
+        // from the point itself isn't reaching it
+        points.check((100, 48), (100, 49), 262);
+        assert_eq!(points.take_hit(), None);
+        points.check((100, 40), (100, 49), 262);
+        assert_eq!(points.take_hit(), Some((100, 48)));
+        points.check((99, 340), (100, 47), 262);
+        assert_eq!(points.take_hit(), None);
+
+        // into the next frame
+        points.remove(100, 48);
+        points.add(0, 2);
+        points.check((261, 339), (0, 3), 262);
+        assert_eq!(points.take_hit(), Some((0, 2)));
+        // or all the way around to the point
+        points.check((100, 60), (100, 51), 262);
+        assert_eq!(points.take_hit(), Some((0, 2)));
+
+        points.clear();
+        points.check((100, 40), (100, 60), 262);
+        assert_eq!(points.take_hit(), None);
+    }
+
+    #[test]
+    fn test_conditional_breakpoints() {
+        // $0600: LDX #0; INX; CPX #10; BNE back to the INX; BRK
+        let mut cpu = CPU::new(BUS::new(test_rom()).unwrap());
+        cpu.load(vec![0xa2, 0x00, 0xe8, 0xe0, 0x0a, 0xd0, 0xfb, 0x00]);
+        cpu.program_counter = 0x0600;
+        let mut debugger = Debugger::new();
+        let breakpoint = Breakpoint {
+            hit_count: 5,
+            ..Breakpoint::default()
+        };
+        debugger.set_breakpoint(0x0602, breakpoint);
+        let until = || Until::FrameComplete;
+
+        // the fifth time round and every time after
+        assert_eq!(
+            debugger.run(&mut cpu, until()),
+            StopReason::Breakpoint(0x0602)
+        );
+        assert_eq!(cpu.register_x, 4);
+        assert_eq!(
+            debugger.run(&mut cpu, until()),
+            StopReason::Breakpoint(0x0602)
+        );
+        assert_eq!(cpu.register_x, 5);
+        assert_eq!(debugger.breakpoint(0x0602).unwrap().hits, 6);
+
+        let breakpoint = Breakpoint {
+            condition: Some(Expr::parse("x == 8").unwrap()),
+            ..Breakpoint::default()
+        };
+        debugger.set_breakpoint(0x0602, breakpoint);
+        assert_eq!(
+            debugger.run(&mut cpu, until()),
+            StopReason::Breakpoint(0x0602)
+        );
+        assert_eq!(cpu.register_x, 8);
+        assert_eq!(debugger.breakpoint(0x0602).unwrap().hits, 1);
+
+        // a temporary one stops once
+        debugger.add_temporary_breakpoint(0x0602);
+        assert_eq!(
+            debugger.run(&mut cpu, until()),
+            StopReason::Breakpoint(0x0602)
+        );
+        assert_eq!(cpu.register_x, 9);
+        assert!(debugger.breakpoint(0x0602).is_none());
+        assert_eq!(debugger.run(&mut cpu, until()), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_call_stack_and_step_out() {
+        let mut cpu = CPU::new(BUS::new(test_rom()).unwrap());
+        // $0600: JSR $0610; JSR $0620; BRK
+        // $0610: JSR $0620; RTS
+        // $0620: INY; PLA; PLA; RTS, dropping its own return address
+        cpu.load(vec![0x20, 0x10, 0x06, 0x20, 0x20, 0x06, 0x00]);
+        for (addr, code) in [
+            (0x0610, &[0x20, 0x20, 0x06, 0x60][..]),
+            (0x0620, &[0xc8, 0x68, 0x68, 0x60]),
+        ] {
+            for (i, byte) in code.iter().enumerate() {
+                cpu.mem_write(addr + i as u16, *byte);
+            }
+        }
+        cpu.program_counter = 0x0600;
+        let mut debugger = Debugger::new();
+
+        debugger.run(&mut cpu, Until::Step);
+        debugger.run(&mut cpu, Until::Step);
+        assert_eq!(
+            cpu.call_stack.frames(),
+            [
+                CallFrame {
+                    kind: CallKind::Subroutine,
+                    from: 0x0600,
+                    to: 0x0610,
+                    stack_pointer: 0xfd,
+                },
+                CallFrame {
+                    kind: CallKind::Subroutine,
+                    from: 0x0610,
+                    to: 0x0620,
+                    stack_pointer: 0xfb,
+                }
+            ]
+        );
+        assert_eq!(DebugState::capture(&cpu).call_stack.len(), 2);
+
+        // the PLAs end the inner call, the RTS then returns from the outer
+        assert_eq!(debugger.step_out(&mut cpu), StopReason::Step);
+        assert_eq!(cpu.program_counter, 0x0623);
+        assert_eq!(cpu.call_stack.depth(), 1);
+        assert_eq!(debugger.step_out(&mut cpu), StopReason::Step);
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.call_stack.depth(), 0);
+
+        // outside of a call, a step
+        assert_eq!(debugger.step_out(&mut cpu), StopReason::Step);
+        assert_eq!(cpu.program_counter, 0x0620);
+        assert_eq!(debugger.step_out(&mut cpu), StopReason::Step);
+        assert_eq!(cpu.program_counter, 0x0623);
+        assert_eq!(cpu.register_y, 2);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::cpu::AddressingMode;
+use crate::opcodes::OPCODES;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Disassembly of code that isn't running, like a PRG bank straight out of a
+// ROM file, with the same columns as the start of a trace line:
+//
+//   C000  4C F5 C5  JMP $C5F5
+//
+// Without a CPU there are no registers or memory to show, so operands are
+// written as they are in the source, and there's no telling code from data:
+// data comes out as whatever instructions its bytes happen to make.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub addr: u16,
+    // the opcode and its operand, fewer than the opcode takes when the code
+    // ends partway through it
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let asm = format!(
+            "{:04X}  {:8} {: >4} {}",
+            self.addr,
+            hex.join(" "),
+            self.mnemonic,
+            self.operand
+        );
+        write!(f, "{}", asm.trim_end())
+    }
+}
+
+// `code` as loaded at `origin`, one instruction after another
+pub fn disassemble(code: &[u8], origin: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let addr = origin.wrapping_add(offset as u16);
+        let opcode = &OPCODES[code[offset] as usize];
+        let end = (offset + opcode.len as usize).min(code.len());
+        let bytes = code[offset..end].to_vec();
+        let operand = if bytes.len() < opcode.len as usize {
+            // cut off, so the operand isn't known
+            String::new()
+        } else {
+            operand(opcode.code, opcode.mode, addr, &bytes[1..])
+        };
+        instructions.push(Instruction {
+            addr,
+            bytes,
+            mnemonic: opcode.mnemonic,
+            operand,
+        });
+        offset = end;
+    }
+    instructions
+}
+
+fn operand(code: u8, mode: AddressingMode, addr: u16, operand: &[u8]) -> String {
+    match *operand {
+        [] => match code {
+            0x0a | 0x4a | 0x2a | 0x6a => String::from("A"),
+            _ => String::new(),
+        },
+        [byte] => match mode {
+            AddressingMode::Immediate => format!("#${:02X}", byte),
+            AddressingMode::ZeroPage => format!("${:02X}", byte),
+            AddressingMode::ZeroPage_X => format!("${:02X},X", byte),
+            AddressingMode::ZeroPage_Y => format!("${:02X},Y", byte),
+            AddressingMode::Indirect_X => format!("(${:02X},X)", byte),
+            AddressingMode::Indirect_Y => format!("(${:02X}),Y", byte),
+            // branches: the target, relative to the next instruction
+            _ => format!(
+                "${:04X}",
+                addr.wrapping_add(2).wrapping_add(byte as i8 as u16)
+            ),
+        },
+        [lo, hi] => {
+            let address = u16::from_le_bytes([lo, hi]);
+            match mode {
+                AddressingMode::NoneAddressing if code == 0x6c => format!("(${:04X})", address),
+                AddressingMode::Absolute_X => format!("${:04X},X", address),
+                AddressingMode::Absolute_Y => format!("${:04X},Y", address),
+                _ => format!("${:04X}", address),
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_disassemble() {
+        // LDA #$10; STA $0200,X; BNE back to the LDA; JMP ($FFFC); ASL A;
+        // LAX ($20),Y; and a JMP cut short
+        let code = [
+            0xa9, 0x10, 0x9d, 0x00, 0x02, 0xd0, 0xf9, 0x6c, 0xfc, 0xff, 0x0a, 0xb3, 0x20, 0x4c,
+            0x00,
+        ];
+        let lines: Vec<String> = disassemble(&code, 0xc000)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "C000  A9 10     LDA #$10",
+                "C002  9D 00 02  STA $0200,X",
+                "C005  D0 F9     BNE $C000",
+                "C007  6C FC FF  JMP ($FFFC)",
+                "C00A  0A        ASL A",
+                "C00B  B3 20    *LAX ($20),Y",
+                "C00D  4C 00     JMP",
+            ]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::render::frame::Frame;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// A reinforcement learning environment in the style of OpenAI Gym and
+// nes-py: `reset` puts the console back to a start state, `step` holds one
+// of a fixed set of button combinations for a few frames and reports what
+// the agent sees, how well it did and whether the episode is over.
+//
+// Rewards and the end of an episode come from closures reading the
+// console, usually a few RAM addresses a game keeps its score, position or
+// lives at; ram_delta, ram_value and ram_equals cover the usual cases.
+//
+//   let mut env = Env::new(nes, SIMPLE_MOVEMENT);
+//   env.set_frame_skip(4);
+//   env.set_reward(ram_delta(0x0086));
+//   env.set_done(ram_equals(0x000e, 0x0b));
+//   env.reset();
+//   loop {
+//       let step = env.step(agent.act());
+//       if step.done { env.reset(); }
+//   }
+
+// what nes-py calls the action spaces for Super Mario Bros.
+pub const RIGHT_ONLY: &[Button] = &[
+    Button::empty(),
+    Button::RIGHT,
+    Button::RIGHT.union(Button::A),
+    Button::RIGHT.union(Button::B),
+    Button::RIGHT.union(Button::A).union(Button::B),
+];
+
+pub const SIMPLE_MOVEMENT: &[Button] = &[
+    Button::empty(),
+    Button::RIGHT,
+    Button::RIGHT.union(Button::A),
+    Button::RIGHT.union(Button::B),
+    Button::RIGHT.union(Button::A).union(Button::B),
+    Button::A,
+    Button::LEFT,
+];
+
+type RewardFn = Box<dyn FnMut(&Nes) -> f32>;
+type DoneFn = Box<dyn FnMut(&Nes) -> bool>;
+
+// what the agent sees after a reset or a step
+pub struct Observation<'a> {
+    pub frame: &'a Frame,
+    pub ram: &'a [u8],
+}
+
+pub struct Step<'a> {
+    pub observation: Observation<'a>,
+    // summed over the skipped frames
+    pub reward: f32,
+    pub done: bool,
+}
+
+pub struct Env {
+    nes: Nes,
+    start: Vec<u8>,
+    actions: Vec<Button>,
+    frame_skip: usize,
+    reward: Option<RewardFn>,
+    done: Option<DoneFn>,
+}
+
+impl Env {
+    // Episodes start from the console as it is now, see set_start for
+    // starting them somewhere else
+    pub fn new(nes: Nes, actions: &[Button]) -> Self {
+        assert!(!actions.is_empty(), "an environment needs an action");
+        Env {
+            start: nes.save_state(),
+            nes,
+            actions: actions.to_vec(),
+            frame_skip: 1,
+            reward: None,
+            done: None,
+        }
+    }
+
+    // how many frames each step holds its action for, 1 or more
+    pub fn set_frame_skip(&mut self, frames: usize) {
+        self.frame_skip = frames.max(1);
+    }
+
+    pub fn frame_skip(&self) -> usize {
+        self.frame_skip
+    }
+
+    pub fn actions(&self) -> &[Button] {
+        &self.actions
+    }
+
+    // Called after every frame, the step's reward is the sum. reset calls it
+    // once and throws the result away, so closures keeping the last value
+    // of something start from the start state.
+    pub fn set_reward<F: FnMut(&Nes) -> f32 + 'static>(&mut self, reward: F) {
+        self.reward = Some(Box::new(reward));
+    }
+
+    // Called after every frame, the episode ends the first time it's true;
+    // without one episodes never end
+    pub fn set_done<F: FnMut(&Nes) -> bool + 'static>(&mut self, done: F) {
+        self.done = Some(Box::new(done));
+    }
+
+    // where reset goes back to from now on: the console as it is now, for
+    // episodes starting past a game's title screen
+    pub fn set_start(&mut self) {
+        self.start = self.nes.save_state();
+    }
+
+    pub fn reset(&mut self) -> Observation<'_> {
+        self.nes
+            .load_state(&self.start)
+            .expect("the start state came from this console");
+        self.nes.set_input(1, Button::empty());
+        self.nes.audio_samples();
+        if let Some(reward) = self.reward.as_mut() {
+            reward(&self.nes);
+        }
+        self.observation()
+    }
+
+    // Holds actions()[action] on controller 1 for frame_skip frames, or
+    // fewer if the episode ends first
+    pub fn step(&mut self, action: usize) -> Step<'_> {
+        self.nes.set_input(1, self.actions[action]);
+        let mut reward = 0.0;
+        let mut done = false;
+        for _ in 0..self.frame_skip {
+            self.nes.run_frame();
+            if let Some(reward_fn) = self.reward.as_mut() {
+                reward += reward_fn(&self.nes);
+            }
+            if let Some(done_fn) = self.done.as_mut() {
+                done = done_fn(&self.nes);
+            }
+            if done {
+                break;
+            }
+        }
+        // nobody listens, don't let it pile up
+        self.nes.audio_samples();
+        Step {
+            observation: self.observation(),
+            reward,
+            done,
+        }
+    }
+
+    fn observation(&self) -> Observation<'_> {
+        Observation {
+            frame: self.nes.frame_buffer(),
+            ram: self.nes.ram(),
+        }
+    }
+
+    pub fn nes(&self) -> &Nes {
+        &self.nes
+    }
+
+    pub fn nes_mut(&mut self) -> &mut Nes {
+        &mut self.nes
+    }
+}
+
+// how much the byte at `addr` went up since the last call
+pub fn ram_delta(addr: u16) -> impl FnMut(&Nes) -> f32 {
+    let mut last = None;
+    move |nes| {
+        let value = nes.peek(addr);
+        let delta = last.map_or(0.0, |last| value as f32 - last as f32);
+        last = Some(value);
+        delta
+    }
+}
+
+// the byte at `addr` itself
+pub fn ram_value(addr: u16) -> impl FnMut(&Nes) -> f32 {
+    move |nes| nes.peek(addr) as f32
+}
+
+// for set_done: the byte at `addr` is `value`
+pub fn ram_equals(addr: u16, value: u8) -> impl FnMut(&Nes) -> bool {
+    move |nes| nes.peek(addr) == value
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::nmi_rom;
+
+    // counts frames at $10: INC $10
+    const COUNT_FRAMES: [u8; 2] = [0xe6, 0x10];
+
+    #[test]
+    fn test_env() {
+        let mut nes = Nes::new(&nmi_rom(&COUNT_FRAMES)).unwrap();
+        nes.run_frame();
+        let mut env = Env::new(nes, RIGHT_ONLY);
+        env.set_frame_skip(4);
+        env.set_reward(ram_delta(0x10));
+        env.set_done(|nes| nes.peek(0x10) >= 10);
+
+        let start = env.reset().ram[0x10];
+        let step = env.step(2);
+        assert_eq!(step.reward, 4.0);
+        assert!(!step.done);
+        assert_eq!(step.observation.ram[0x10], start + 4);
+        assert_eq!(env.nes_mut().input(1), Button::RIGHT | Button::A);
+
+        // the episode ends part way through a step
+        env.step(0);
+        let step = env.step(0);
+        assert!(step.done);
+        assert_eq!(step.observation.ram[0x10], 10);
+        assert_eq!(step.reward, (10 - start - 8) as f32);
+
+        // and starts over from where it did
+        assert_eq!(env.reset().ram[0x10], start);
+        assert_eq!(env.nes_mut().input(1), Button::empty());
+        assert_eq!(env.step(1).reward, 4.0);
+    }
+
+    #[test]
+    fn test_set_start() {
+        let mut env = Env::new(Nes::new(&nmi_rom(&COUNT_FRAMES)).unwrap(), SIMPLE_MOVEMENT);
+        env.set_reward(ram_value(0x10));
+        env.step(0);
+        env.step(0);
+        env.set_start();
+        let start = env.nes().peek(0x10);
+        env.step(0);
+        assert_eq!(env.reset().ram[0x10], start);
+        assert_eq!(env.step(0).reward, (start + 1) as f32);
+    }
+}
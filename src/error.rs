@@ -0,0 +1,129 @@
+#[cfg(feature = "std")]
+use crate::archive::ArchiveError;
+use crate::cheats::CheatError;
+use crate::debugger::Access;
+use crate::expr::ExprError;
+use crate::movie::MovieError;
+use crate::rom::RomError;
+use crate::state::StateError;
+
+use core::fmt;
+
+// An access with no defined effect on the console: a read of an address
+// nothing drives, a write nothing listens to. Normally these read back open
+// bus and writes are dropped, as on the console; in strict mode they also
+// stop the run, see BUS::strict.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusFault {
+    pub addr: u16,
+    pub access: Access,
+}
+
+impl fmt::Display for BusFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self.access {
+            Access::Read => "read of",
+            Access::Write => "write to",
+            Access::ReadWrite => "access to",
+        };
+        write!(f, "{} unmapped address ${:04X}", access, self.addr)
+    }
+}
+
+// Everything that can go wrong through the Nes API
+#[derive(Debug, PartialEq)]
+pub enum EmulatorError {
+    InvalidRom(RomError),
+    UnsupportedMapper(u16),
+    InvalidState(StateError),
+    InvalidCheat(CheatError),
+    InvalidMovie(MovieError),
+    InvalidExpression(ExprError),
+    BusFault(BusFault),
+    #[cfg(feature = "std")]
+    InvalidArchive(ArchiveError),
+    // the emulation thread of a ThreadedNes has gone, see threaded.rs
+    Stopped,
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::InvalidRom(err) => write!(f, "{}", err),
+            EmulatorError::UnsupportedMapper(mapper) => {
+                write!(f, "Mapper {} is not supported", mapper)
+            }
+            EmulatorError::InvalidState(err) => write!(f, "{}", err),
+            EmulatorError::InvalidCheat(err) => write!(f, "{}", err),
+            EmulatorError::InvalidMovie(err) => write!(f, "{}", err),
+            EmulatorError::InvalidExpression(err) => write!(f, "{}", err),
+            EmulatorError::BusFault(fault) => write!(f, "{}", fault),
+            #[cfg(feature = "std")]
+            EmulatorError::InvalidArchive(err) => write!(f, "{}", err),
+            EmulatorError::Stopped => write!(f, "the emulation thread has stopped"),
+        }
+    }
+}
+
+impl core::error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            EmulatorError::InvalidRom(err) => Some(err),
+            EmulatorError::InvalidState(err) => Some(err),
+            EmulatorError::InvalidCheat(err) => Some(err),
+            EmulatorError::InvalidMovie(err) => Some(err),
+            EmulatorError::InvalidExpression(err) => Some(err),
+            #[cfg(feature = "std")]
+            EmulatorError::InvalidArchive(err) => Some(err),
+            EmulatorError::UnsupportedMapper(_)
+            | EmulatorError::BusFault(_)
+            | EmulatorError::Stopped => None,
+        }
+    }
+}
+
+impl From<RomError> for EmulatorError {
+    fn from(err: RomError) -> Self {
+        match err {
+            RomError::UnsupportedMapper(mapper) => EmulatorError::UnsupportedMapper(mapper),
+            err => EmulatorError::InvalidRom(err),
+        }
+    }
+}
+
+impl From<StateError> for EmulatorError {
+    fn from(err: StateError) -> Self {
+        EmulatorError::InvalidState(err)
+    }
+}
+
+impl From<CheatError> for EmulatorError {
+    fn from(err: CheatError) -> Self {
+        EmulatorError::InvalidCheat(err)
+    }
+}
+
+impl From<MovieError> for EmulatorError {
+    fn from(err: MovieError) -> Self {
+        EmulatorError::InvalidMovie(err)
+    }
+}
+
+impl From<ExprError> for EmulatorError {
+    fn from(err: ExprError) -> Self {
+        EmulatorError::InvalidExpression(err)
+    }
+}
+
+impl From<BusFault> for EmulatorError {
+    fn from(fault: BusFault) -> Self {
+        EmulatorError::BusFault(fault)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ArchiveError> for EmulatorError {
+    fn from(err: ArchiveError) -> Self {
+        EmulatorError::InvalidArchive(err)
+    }
+}
@@ -0,0 +1,270 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// Lifecycle events of the console, for tools that want to know when things
+// happen without each one owning the console's only callback: a debugger, a
+// recorder and a script can all subscribe at once. Callbacks run as the
+// event happens, in the middle of an instruction for most of them, so like
+// MemoryHooks they only get the event, not the console.
+//
+//   let id = nes.subscribe(Events::NMI_FIRED | Events::IRQ_FIRED, |event| {
+//       println!("{:?}", event);
+//   });
+//   ...
+//   nes.unsubscribe(id);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // the picture is finished and rendered, see Nes::run_frame
+    FrameCompleted,
+    // the PPU set the vblank flag
+    VblankStart,
+    // the PPU moved on to this scanline, the pre-render line included
+    ScanlineStart(u16),
+    // the CPU jumped through the NMI or IRQ vector
+    NmiFired,
+    IrqFired,
+    // a CPU write to the cartridge changed which PRG ROM is mapped in
+    MapperBankSwitched { addr: u16, value: u8 },
+}
+
+bitflags! {
+    // which events a subscriber is called for
+    pub struct Events: u8 {
+        const FRAME_COMPLETED      = 0b0000_0001;
+        const VBLANK_START         = 0b0000_0010;
+        const SCANLINE_START       = 0b0000_0100;
+        const NMI_FIRED            = 0b0000_1000;
+        const IRQ_FIRED            = 0b0001_0000;
+        const MAPPER_BANK_SWITCHED = 0b0010_0000;
+    }
+}
+
+impl Event {
+    pub fn kind(&self) -> Events {
+        match self {
+            Event::FrameCompleted => Events::FRAME_COMPLETED,
+            Event::VblankStart => Events::VBLANK_START,
+            Event::ScanlineStart(_) => Events::SCANLINE_START,
+            Event::NmiFired => Events::NMI_FIRED,
+            Event::IrqFired => Events::IRQ_FIRED,
+            Event::MapperBankSwitched { .. } => Events::MAPPER_BANK_SWITCHED,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionId(usize);
+
+type Callback = Box<dyn FnMut(&Event)>;
+
+struct Subscriber {
+    id: SubscriptionId,
+    events: Events,
+    callback: Callback,
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Subscriber>,
+    next_id: usize,
+    // everything any subscriber wants, so the rest cost nothing to skip
+    wanted: Events,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Events::empty()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    pub fn subscribe(&mut self, events: Events, callback: Callback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push(Subscriber {
+            id,
+            events,
+            callback,
+        });
+        self.wanted |= events;
+        id
+    }
+
+    // false if there was no such subscription
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscribers.len();
+        self.subscribers.retain(|subscriber| subscriber.id != id);
+        self.wanted = self
+            .subscribers
+            .iter()
+            .fold(Events::empty(), |wanted, subscriber| {
+                wanted | subscriber.events
+            });
+        self.subscribers.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.subscribers.clear();
+        self.wanted = Events::empty();
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    // whether anyone listens for any of `events`, for events that take
+    // work to notice
+    pub fn wants(&self, events: Events) -> bool {
+        self.wanted.intersects(events)
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        let kind = event.kind();
+        if !self.wanted.intersects(kind) {
+            return;
+        }
+        for subscriber in &mut self.subscribers {
+            if subscriber.events.intersects(kind) {
+                (subscriber.callback)(&event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::test::RomBuilder;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_event_bus() {
+        let mut bus = EventBus::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let interrupts = bus.subscribe(
+            Events::NMI_FIRED | Events::IRQ_FIRED,
+            Box::new(move |event| log.borrow_mut().push(*event)),
+        );
+        let log = seen.clone();
+        bus.subscribe(
+            Events::NMI_FIRED,
+            Box::new(move |_| log.borrow_mut().push(Event::VblankStart)),
+        );
+        assert!(bus.wants(Events::IRQ_FIRED));
+        assert!(!bus.wants(Events::SCANLINE_START));
+
+        bus.emit(Event::ScanlineStart(3));
+        bus.emit(Event::IrqFired);
+        bus.emit(Event::NmiFired);
+        assert_eq!(
+            *seen.borrow(),
+            [Event::IrqFired, Event::NmiFired, Event::VblankStart]
+        );
+
+        assert!(bus.unsubscribe(interrupts));
+        assert!(!bus.unsubscribe(interrupts));
+        assert!(!bus.wants(Events::IRQ_FIRED));
+        assert_eq!(bus.len(), 1);
+        bus.clear();
+        assert!(bus.is_empty());
+    }
+
+    fn record(nes: &mut Nes, events: Events) -> Rc<RefCell<Vec<Event>>> {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        nes.subscribe(events, move |event| log.borrow_mut().push(*event));
+        seen
+    }
+
+    // NROM with NMIs and the APU frame IRQ on, each handler counting itself
+    fn interrupting_rom() -> Vec<u8> {
+        RomBuilder::new()
+            // LDA #$80; STA $2000; CLI; JMP $8006
+            .code(
+                0x8000,
+                &[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x58, 0x4c, 0x06, 0x80],
+            )
+            // LDA $4015 to acknowledge it
+            .irq(0x8200, &[0xad, 0x15, 0x40])
+            .build()
+    }
+
+    #[test]
+    fn test_frame_events() {
+        let mut nes = Nes::new(&interrupting_rom()).unwrap();
+        nes.run_frame();
+        let frames = record(&mut nes, Events::FRAME_COMPLETED | Events::VBLANK_START);
+        let scanlines = record(&mut nes, Events::SCANLINE_START);
+        let interrupts = record(&mut nes, Events::NMI_FIRED | Events::IRQ_FIRED);
+        nes.run_frame();
+        nes.run_frame();
+
+        assert_eq!(
+            *frames.borrow(),
+            [
+                Event::VblankStart,
+                Event::FrameCompleted,
+                Event::VblankStart,
+                Event::FrameCompleted
+            ]
+        );
+        let scanlines = scanlines.borrow();
+        assert_eq!(scanlines.len(), 2 * 262);
+        assert_eq!(
+            scanlines[..2],
+            [Event::ScanlineStart(242), Event::ScanlineStart(243)]
+        );
+        assert!(scanlines.contains(&Event::ScanlineStart(0)));
+        assert!(scanlines.contains(&Event::ScanlineStart(261)));
+        let interrupts = interrupts.borrow();
+        let times = |event| interrupts.iter().filter(|&&seen| seen == event).count();
+        assert_eq!(times(Event::NmiFired), 2);
+        // the frame counter's IRQ comes every 4 steps, about once a frame
+        assert!((1..=3).contains(&times(Event::IrqFired)));
+
+        // rollback's second run of frames goes unseen, like the frame callback
+        let before = frames.borrow().len();
+        nes.resimulate(1, |_| {});
+        assert_eq!(frames.borrow().len(), before);
+    }
+
+    #[test]
+    fn test_bank_switch_events() {
+        // UxROM with two 16KB banks, the code in the fixed last one:
+        // LDA #1; STA $8000; STA $8000; JMP to itself
+        let rom = RomBuilder::new()
+            .mapper(2)
+            .chr(Vec::new())
+            .code(
+                0xc000,
+                &[
+                    0xa9, 0x01, 0x8d, 0x00, 0x80, 0x8d, 0x00, 0x80, 0x4c, 0x08, 0xc0,
+                ],
+            )
+            .reset(0xc000)
+            .build();
+
+        let mut nes = Nes::new(&rom).unwrap();
+        let switches = record(&mut nes, Events::MAPPER_BANK_SWITCHED);
+        nes.run_frame();
+        // the second write maps in the bank that's already there
+        assert_eq!(
+            *switches.borrow(),
+            [Event::MapperBankSwitched {
+                addr: 0x8000,
+                value: 1
+            }]
+        );
+    }
+}
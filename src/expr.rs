@@ -0,0 +1,487 @@
+use crate::cpu::{CpuFlags, CPU};
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Debugger expressions over the CPU and PPU, like "A == $3F && [$00FE] > 5"
+// or "v & 0x7000", for watches and breakpoint conditions.
+//
+// Numbers are decimal, or hex with $ or 0x in front. Names aren't case
+// sensitive:
+//
+// | Name                          | Value                                 |
+// |-------------------------------|---------------------------------------|
+// | a, x, y, p, sp, pc            | CPU registers, p the status flags     |
+// | carry, zero, interrupt,       | status flags, 0 or 1                  |
+// | decimal, overflow, negative   |                                       |
+// | cycles                        | CPU cycles since power on             |
+// | scanline, dot                 | where the PPU is                      |
+// | v, t                          | the PPU's internal VRAM addresses     |
+// | ctrl, mask, status            | PPUCTRL, PPUMASK and PPUSTATUS        |
+//
+// [addr] is the byte at addr and {addr} the little-endian word there, read
+// without side effects. The operators are C's, with C's precedence:
+// ! ~ - (unary), * / %, + -, << >>, < <= > >=, == !=, &, ^, |, && and ||.
+// Comparisons and logic give 1 or 0; dividing by 0 gives 0.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprError {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    // a token where the expression should have continued some other way
+    Unexpected(String),
+    UnknownName(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprError::UnexpectedCharacter(c) => write!(f, "'{}' can't be in an expression", c),
+            ExprError::UnexpectedEnd => write!(f, "the expression ends too soon"),
+            ExprError::Unexpected(token) => write!(f, "didn't expect '{}'", token),
+            ExprError::UnknownName(name) => write!(f, "there is nothing called '{}'", name),
+        }
+    }
+}
+
+impl core::error::Error for ExprError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Name {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    Flag(CpuFlags),
+    Cycles,
+    Scanline,
+    Dot,
+    V,
+    T,
+    Ctrl,
+    Mask,
+    Status,
+}
+
+impl Name {
+    fn parse(name: &str) -> Option<Name> {
+        let name = match name.to_ascii_lowercase().as_str() {
+            "a" => Name::A,
+            "x" => Name::X,
+            "y" => Name::Y,
+            "p" => Name::P,
+            "sp" => Name::Sp,
+            "pc" => Name::Pc,
+            "carry" => Name::Flag(CpuFlags::CARRY),
+            "zero" => Name::Flag(CpuFlags::ZERO),
+            "interrupt" => Name::Flag(CpuFlags::INTERRUPT_DISABLE),
+            "decimal" => Name::Flag(CpuFlags::DECIMAL),
+            "overflow" => Name::Flag(CpuFlags::OVERFLOW),
+            "negative" => Name::Flag(CpuFlags::NEGATIVE),
+            "cycles" => Name::Cycles,
+            "scanline" => Name::Scanline,
+            "dot" => Name::Dot,
+            "v" => Name::V,
+            "t" => Name::T,
+            "ctrl" => Name::Ctrl,
+            "mask" => Name::Mask,
+            "status" => Name::Status,
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    fn value(self, cpu: &CPU) -> i64 {
+        let ppu = &cpu.bus.ppu;
+        match self {
+            Name::A => cpu.register_a as i64,
+            Name::X => cpu.register_x as i64,
+            Name::Y => cpu.register_y as i64,
+            Name::P => cpu.status_register.bits() as i64,
+            Name::Sp => cpu.stack_pointer as i64,
+            Name::Pc => cpu.program_counter as i64,
+            Name::Flag(flag) => cpu.status_register.contains(flag) as i64,
+            Name::Cycles => cpu.bus.cycles() as i64,
+            Name::Scanline => ppu.scanline as i64,
+            Name::Dot => ppu.dot as i64,
+            Name::V => ppu.address.v as i64,
+            Name::T => ppu.address.t as i64,
+            Name::Ctrl => ppu.control.bits() as i64,
+            Name::Mask => ppu.mask.bits() as i64,
+            Name::Status => ppu.status.bits() as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unary {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Binary {
+    Multiply,
+    Divide,
+    Remainder,
+    Add,
+    Subtract,
+    ShiftLeft,
+    ShiftRight,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Xor,
+    Or,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl Binary {
+    fn from_token(token: &str) -> Option<Binary> {
+        let op = match token {
+            "*" => Binary::Multiply,
+            "/" => Binary::Divide,
+            "%" => Binary::Remainder,
+            "+" => Binary::Add,
+            "-" => Binary::Subtract,
+            "<<" => Binary::ShiftLeft,
+            ">>" => Binary::ShiftRight,
+            "<" => Binary::Less,
+            "<=" => Binary::LessEqual,
+            ">" => Binary::Greater,
+            ">=" => Binary::GreaterEqual,
+            "==" => Binary::Equal,
+            "!=" => Binary::NotEqual,
+            "&" => Binary::And,
+            "^" => Binary::Xor,
+            "|" => Binary::Or,
+            "&&" => Binary::LogicalAnd,
+            "||" => Binary::LogicalOr,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            Binary::Multiply | Binary::Divide | Binary::Remainder => 10,
+            Binary::Add | Binary::Subtract => 9,
+            Binary::ShiftLeft | Binary::ShiftRight => 8,
+            Binary::Less | Binary::LessEqual | Binary::Greater | Binary::GreaterEqual => 7,
+            Binary::Equal | Binary::NotEqual => 6,
+            Binary::And => 5,
+            Binary::Xor => 4,
+            Binary::Or => 3,
+            Binary::LogicalAnd => 2,
+            Binary::LogicalOr => 1,
+        }
+    }
+
+    fn apply(self, left: i64, right: i64) -> i64 {
+        match self {
+            Binary::Multiply => left.wrapping_mul(right),
+            Binary::Divide => left.checked_div(right).unwrap_or(0),
+            Binary::Remainder => left.checked_rem(right).unwrap_or(0),
+            Binary::Add => left.wrapping_add(right),
+            Binary::Subtract => left.wrapping_sub(right),
+            Binary::ShiftLeft => left.wrapping_shl(right as u32),
+            Binary::ShiftRight => left.wrapping_shr(right as u32),
+            Binary::Less => (left < right) as i64,
+            Binary::LessEqual => (left <= right) as i64,
+            Binary::Greater => (left > right) as i64,
+            Binary::GreaterEqual => (left >= right) as i64,
+            Binary::Equal => (left == right) as i64,
+            Binary::NotEqual => (left != right) as i64,
+            Binary::And => left & right,
+            Binary::Xor => left ^ right,
+            Binary::Or => left | right,
+            Binary::LogicalAnd => (left != 0 && right != 0) as i64,
+            Binary::LogicalOr => (left != 0 || right != 0) as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(i64),
+    Name(Name),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Binary, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, cpu: &CPU) -> i64 {
+        match self {
+            Node::Number(value) => *value,
+            Node::Name(name) => name.value(cpu),
+            Node::Byte(addr) => cpu.bus.peek(addr.eval(cpu) as u16) as i64,
+            Node::Word(addr) => {
+                let addr = addr.eval(cpu) as u16;
+                let lo = cpu.bus.peek(addr) as i64;
+                let hi = cpu.bus.peek(addr.wrapping_add(1)) as i64;
+                hi << 8 | lo
+            }
+            Node::Unary(op, node) => {
+                let value = node.eval(cpu);
+                match op {
+                    Unary::Not => (value == 0) as i64,
+                    Unary::Complement => !value,
+                    Unary::Negate => value.wrapping_neg(),
+                }
+            }
+            Node::Binary(op, left, right) => {
+                let left = left.eval(cpu);
+                // && and || don't read what they don't need
+                match op {
+                    Binary::LogicalAnd if left == 0 => 0,
+                    Binary::LogicalOr if left != 0 => 1,
+                    _ => op.apply(left, right.eval(cpu)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    // operators and brackets
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+// longest first, so "<<" isn't read as two "<"
+const SYMBOLS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*", "/", "%", "+", "-", "<", ">", "&", "^",
+    "|", "!", "~", "(", ")", "[", "]", "{", "}",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, length) = if c.is_ascii_digit() || c == '$' {
+            let (digits, radix, prefix) = if let Some(hex) = rest.strip_prefix('$') {
+                (hex, 16, 1)
+            } else if let Some(hex) = rest.strip_prefix("0x").or(rest.strip_prefix("0X")) {
+                (hex, 16, 2)
+            } else {
+                (rest, 10, 0)
+            };
+            let length = digits
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(digits.len());
+            let value = i64::from_str_radix(&digits[..length], radix)
+                .map_err(|_| ExprError::Unexpected(rest[..prefix + length].to_string()))?;
+            (Token::Number(value), prefix + length)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            (Token::Name(rest[..length].to_string()), length)
+        } else {
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => (Token::Symbol(symbol), symbol.len()),
+                None => return Err(ExprError::UnexpectedCharacter(c)),
+            }
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<Token, ExprError> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token.ok_or(ExprError::UnexpectedEnd)
+    }
+
+    fn peek_binary(&self) -> Option<Binary> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) => Binary::from_token(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ExprError> {
+        match self.next()? {
+            Token::Symbol(found) if found == symbol => Ok(()),
+            token => Err(ExprError::Unexpected(token.to_string())),
+        }
+    }
+
+    // precedence climbing: operators binding at least as tight as `min`
+    fn expression(&mut self, min: u8) -> Result<Node, ExprError> {
+        let mut left = self.operand()?;
+        while let Some(op) = self.peek_binary().filter(|op| op.precedence() >= min) {
+            self.position += 1;
+            let right = self.expression(op.precedence() + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Node, ExprError> {
+        let node = match self.next()? {
+            Token::Number(value) => Node::Number(value),
+            Token::Name(name) => match Name::parse(&name) {
+                Some(name) => Node::Name(name),
+                None => return Err(ExprError::UnknownName(name)),
+            },
+            Token::Symbol("(") => {
+                let node = self.expression(0)?;
+                self.expect(")")?;
+                node
+            }
+            Token::Symbol("[") => {
+                let node = self.expression(0)?;
+                self.expect("]")?;
+                Node::Byte(Box::new(node))
+            }
+            Token::Symbol("{") => {
+                let node = self.expression(0)?;
+                self.expect("}")?;
+                Node::Word(Box::new(node))
+            }
+            Token::Symbol(symbol) => {
+                let op = match symbol {
+                    "!" => Unary::Not,
+                    "~" => Unary::Complement,
+                    "-" => Unary::Negate,
+                    _ => return Err(ExprError::Unexpected(symbol.to_string())),
+                };
+                Node::Unary(op, Box::new(self.operand()?))
+            }
+        };
+        Ok(node)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let root = parser.expression(0)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(ExprError::Unexpected(token.to_string()));
+        }
+        Ok(Expr {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        self.root.eval(cpu)
+    }
+
+    // anything but 0
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.eval(cpu) != 0
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::BUS;
+    use crate::cpu::Mem;
+    use crate::rom::test::test_rom;
+
+    fn eval(cpu: &CPU, source: &str) -> i64 {
+        Expr::parse(source).unwrap().eval(cpu)
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut cpu = CPU::new(BUS::new(test_rom()).unwrap());
+        cpu.register_a = 0x3f;
+        cpu.register_x = 2;
+        cpu.status_register = CpuFlags::CARRY | CpuFlags::NEGATIVE;
+        cpu.mem_write(0x00fe, 6);
+        cpu.mem_write(0x00ff, 0x12);
+        cpu.bus.ppu.address.v = 0x2345;
+
+        assert_eq!(eval(&cpu, "A==0x3F && [0x00FE]>5"), 1);
+        assert_eq!(eval(&cpu, "a == $3f && [$fe] > 6"), 0);
+        assert_eq!(eval(&cpu, "v & 0x7000"), 0x2000);
+        assert_eq!(eval(&cpu, "{$fe}"), 0x1206);
+        assert_eq!(eval(&cpu, "[$fc + x * 2 - 1]"), 0x12);
+        assert_eq!(eval(&cpu, "1 + 2 * 3 << 1"), 14);
+        assert_eq!(eval(&cpu, "(1 + 2) * 3 - -1"), 10);
+        assert_eq!(eval(&cpu, "1 | 2 ^ 3 & 6"), 1);
+        assert_eq!(eval(&cpu, "carry + Negative * 2 + !zero * 4"), 7);
+        assert_eq!(eval(&cpu, "~a & 0xff"), 0xc0);
+        assert_eq!(eval(&cpu, "10 / 0 + 10 % 3"), 1);
+        assert_eq!(eval(&cpu, "0 || 5 >= 5"), 1);
+        assert_eq!(eval(&cpu, "scanline"), 0);
+        assert!(Expr::parse("sp != 0 ").unwrap().is_true(&cpu));
+        assert_eq!(Expr::parse(" A == 1").unwrap().to_string(), "A == 1");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Expr::parse("a +"), Err(ExprError::UnexpectedEnd));
+        assert_eq!(Expr::parse(""), Err(ExprError::UnexpectedEnd));
+        assert_eq!(
+            Expr::parse("a == @"),
+            Err(ExprError::UnexpectedCharacter('@'))
+        );
+        assert_eq!(
+            Expr::parse("lives > 2"),
+            Err(ExprError::UnknownName("lives".into()))
+        );
+        assert_eq!(Expr::parse("[$10"), Err(ExprError::UnexpectedEnd));
+        assert_eq!(Expr::parse("(a 1)"), Err(ExprError::Unexpected("1".into())));
+        assert_eq!(Expr::parse("a 1"), Err(ExprError::Unexpected("1".into())));
+        assert_eq!(Expr::parse("$fg"), Err(ExprError::Unexpected("$fg".into())));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::error::EmulatorError;
+use crate::joypad::Button;
+use crate::nes::Nes;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+// C bindings, for frontends in C, C++, C#, Python and anything else that
+// can load a shared library. Built into one, librust_nes_emu_ffi, with
+// `cargo build --release -p rust-nes-emu-ffi`, see ffi/;
+// include/rust_nes_emu.h declares everything here and is made with
+// `cbindgen --config cbindgen.toml --output include/rust_nes_emu.h src/ffi.rs`.
+//
+//   NesHandle *nes = nes_create(48000);
+//   if (nes_load_rom(nes, rom, rom_len) != NES_OK) { ... }
+//   // once per frame, at nes_frame_rate(nes) frames a second:
+//   nes_set_button(nes, 1, NES_BUTTON_A, a_held);
+//   nes_run_frame(nes);
+//   draw(nes_framebuffer_ptr(nes), NES_WIDTH, NES_HEIGHT);
+//   size_t count = nes_audio_samples(nes, samples, sizeof(samples) / sizeof(float));
+//   nes_destroy(nes);
+//
+// A handle isn't thread safe, each one has to stay on the thread that made it.
+// A panic inside the emulator is caught before it gets to C, which it
+// can't unwind through, and the function returns NES_ERROR_PANIC, null, 0
+// or nothing; the console is then best reset or loaded again.
+
+pub const NES_WIDTH: u32 = 256;
+pub const NES_HEIGHT: u32 = 240;
+
+// what the functions returning an int32_t return
+pub const NES_OK: i32 = 0;
+pub const NES_ERROR_NULL_POINTER: i32 = -1;
+pub const NES_ERROR_NO_ROM: i32 = -2;
+pub const NES_ERROR_INVALID_ROM: i32 = -3;
+pub const NES_ERROR_UNSUPPORTED_MAPPER: i32 = -4;
+pub const NES_ERROR_INVALID_STATE: i32 = -5;
+pub const NES_ERROR_PANIC: i32 = -6;
+
+// for nes_set_button, the standard controller's buttons
+pub const NES_BUTTON_A: u8 = 0b0000_0001;
+pub const NES_BUTTON_B: u8 = 0b0000_0010;
+pub const NES_BUTTON_SELECT: u8 = 0b0000_0100;
+pub const NES_BUTTON_START: u8 = 0b0000_1000;
+pub const NES_BUTTON_UP: u8 = 0b0001_0000;
+pub const NES_BUTTON_DOWN: u8 = 0b0010_0000;
+pub const NES_BUTTON_LEFT: u8 = 0b0100_0000;
+pub const NES_BUTTON_RIGHT: u8 = 0b1000_0000;
+
+// A console without a cartridge until nes_load_rom, opaque to C
+pub struct NesHandle {
+    nes: Option<Nes>,
+    sample_rate: u32,
+    // sound nes_audio_samples had no room for yet
+    samples: Vec<f32>,
+}
+
+fn error_code(err: EmulatorError) -> i32 {
+    match err {
+        EmulatorError::UnsupportedMapper(_) => NES_ERROR_UNSUPPORTED_MAPPER,
+        EmulatorError::InvalidState(_) => NES_ERROR_INVALID_STATE,
+        _ => NES_ERROR_INVALID_ROM,
+    }
+}
+
+// runs the body of an exported function, with `on_panic` if it panics
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Makes a console with no cartridge in, putting out sound at
+/// `sample_rate`, or the default 44100Hz for 0. Free it with nes_destroy.
+#[no_mangle]
+pub extern "C" fn nes_create(sample_rate: u32) -> *mut NesHandle {
+    guard(ptr::null_mut(), || {
+        let sample_rate = if sample_rate == 0 {
+            Nes::SAMPLE_RATE
+        } else {
+            sample_rate
+        };
+        Box::into_raw(Box::new(NesHandle {
+            nes: None,
+            sample_rate,
+            samples: Vec::new(),
+        }))
+    })
+}
+
+/// # Safety
+/// `nes` is null or came from nes_create and hasn't been destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(nes: *mut NesHandle) {
+    guard((), || {
+        if !nes.is_null() {
+            drop(Box::from_raw(nes));
+        }
+    })
+}
+
+/// Loads an iNES or NES 2.0 file, replacing any cartridge already in.
+///
+/// # Safety
+/// `nes` is null or a live handle, `data` points to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(nes: *mut NesHandle, data: *const u8, len: usize) -> i32 {
+    guard(NES_ERROR_PANIC, || {
+        let (Some(handle), false) = (nes.as_mut(), data.is_null()) else {
+            return NES_ERROR_NULL_POINTER;
+        };
+        match Nes::with_sample_rate(slice::from_raw_parts(data, len), handle.sample_rate) {
+            Ok(loaded) => {
+                handle.nes = Some(loaded);
+                handle.samples.clear();
+                NES_OK
+            }
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// # Safety
+/// `nes` is null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(nes: *mut NesHandle) -> i32 {
+    guard(NES_ERROR_PANIC, || match nes.as_mut() {
+        Some(NesHandle { nes: Some(nes), .. }) => {
+            nes.run_frame();
+            NES_OK
+        }
+        Some(_) => NES_ERROR_NO_ROM,
+        None => NES_ERROR_NULL_POINTER,
+    })
+}
+
+/// The last frame as NES_WIDTH x NES_HEIGHT RGB pixels, 3 bytes each, row
+/// by row; null without a cartridge. Valid until the next call with `nes`.
+///
+/// # Safety
+/// `nes` is null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_framebuffer_ptr(nes: *const NesHandle) -> *const u8 {
+    guard(ptr::null(), || {
+        match nes.as_ref().and_then(|handle| handle.nes.as_ref()) {
+            Some(nes) => nes.frame_buffer().data.as_ptr(),
+            None => ptr::null(),
+        }
+    })
+}
+
+/// Presses or releases NES_BUTTON_* buttons on controller `player` (1-4).
+/// Other players are ignored.
+///
+/// # Safety
+/// `nes` is null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_button(
+    nes: *mut NesHandle,
+    player: u32,
+    button: u8,
+    pressed: bool,
+) {
+    guard((), || {
+        // Nes::input panics on other players, which are documented as ignored
+        if !(1..=4).contains(&player) {
+            return;
+        }
+        if let Some(NesHandle { nes: Some(nes), .. }) = nes.as_mut() {
+            let mut buttons = nes.input(player as usize);
+            buttons.set(Button::from_bits_truncate(button), pressed);
+            nes.set_input(player as usize, buttons);
+        }
+    })
+}
+
+/// Copies the sound made since the last call into `out`, up to `capacity`
+/// samples, and returns how many it copied. What doesn't fit is kept for
+/// the next call.
+///
+/// # Safety
+/// `nes` is null or a live handle, `out` points to room for `capacity`
+/// floats.
+#[no_mangle]
+pub unsafe extern "C" fn nes_audio_samples(
+    nes: *mut NesHandle,
+    out: *mut f32,
+    capacity: usize,
+) -> usize {
+    guard(0, || {
+        let Some(handle) = nes.as_mut() else {
+            return 0;
+        };
+        if let Some(nes) = handle.nes.as_mut() {
+            handle.samples.extend(nes.audio_samples());
+        }
+        let count = handle.samples.len().min(capacity);
+        if count > 0 && !out.is_null() {
+            ptr::copy_nonoverlapping(handle.samples.as_ptr(), out, count);
+            handle.samples.drain(..count);
+            count
+        } else {
+            0
+        }
+    })
+}
+
+/// Writes a save state into `out` if it fits in `capacity` bytes, and
+/// returns its size either way, so a null `out` asks how big it is. 0
+/// without a cartridge.
+///
+/// # Safety
+/// `nes` is null or a live handle, `out` is null or points to room for
+/// `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(
+    nes: *const NesHandle,
+    out: *mut u8,
+    capacity: usize,
+) -> usize {
+    guard(0, || {
+        let Some(nes) = nes.as_ref().and_then(|handle| handle.nes.as_ref()) else {
+            return 0;
+        };
+        let state = nes.save_state();
+        if !out.is_null() && state.len() <= capacity {
+            ptr::copy_nonoverlapping(state.as_ptr(), out, state.len());
+        }
+        state.len()
+    })
+}
+
+/// Restores a state from nes_save_state, made with the same cartridge. On
+/// error the console carries on as it was.
+///
+/// # Safety
+/// `nes` is null or a live handle, `data` points to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(nes: *mut NesHandle, data: *const u8, len: usize) -> i32 {
+    guard(NES_ERROR_PANIC, || {
+        let (Some(handle), false) = (nes.as_mut(), data.is_null()) else {
+            return NES_ERROR_NULL_POINTER;
+        };
+        let Some(nes) = handle.nes.as_mut() else {
+            return NES_ERROR_NO_ROM;
+        };
+        match nes.load_state(slice::from_raw_parts(data, len)) {
+            Ok(()) => NES_OK,
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// Presses the console's reset button.
+///
+/// # Safety
+/// `nes` is null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_reset(nes: *mut NesHandle) {
+    guard((), || {
+        if let Some(NesHandle { nes: Some(nes), .. }) = nes.as_mut() {
+            nes.soft_reset();
+        }
+    })
+}
+
+/// Frames a second for the cartridge's region, 60 without one.
+///
+/// # Safety
+/// `nes` is null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_frame_rate(nes: *const NesHandle) -> f64 {
+    guard(60.0, || {
+        nes.as_ref()
+            .and_then(|handle| handle.nes.as_ref())
+            .map_or(60.0, |nes| nes.frame_rate())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::looping_rom;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let nes = nes_create(0);
+            assert_eq!(nes_run_frame(nes), NES_ERROR_NO_ROM);
+            assert!(nes_framebuffer_ptr(nes).is_null());
+            assert_eq!(nes_save_state(nes, ptr::null_mut(), 0), 0);
+
+            let bad = [0u8; 16];
+            assert_eq!(
+                nes_load_rom(nes, bad.as_ptr(), bad.len()),
+                NES_ERROR_INVALID_ROM
+            );
+            let rom = looping_rom();
+            assert_eq!(nes_load_rom(nes, rom.as_ptr(), rom.len()), NES_OK);
+            assert_eq!(nes_run_frame(nes), NES_OK);
+            assert!(!nes_framebuffer_ptr(nes).is_null());
+            assert_eq!(
+                nes_frame_rate(nes),
+                (*nes).nes.as_ref().unwrap().frame_rate()
+            );
+
+            // a frame's sound taken a bit at a time
+            let mut samples = [0.0; 1000];
+            assert_eq!(nes_audio_samples(nes, samples.as_mut_ptr(), 100), 100);
+            let rest = nes_audio_samples(nes, samples.as_mut_ptr(), 1000);
+            assert!((500..900).contains(&rest));
+            assert_eq!(nes_audio_samples(nes, samples.as_mut_ptr(), 1000), 0);
+
+            nes_set_button(nes, 1, NES_BUTTON_A | NES_BUTTON_START, true);
+            nes_set_button(nes, 1, NES_BUTTON_START, false);
+            let buttons = (*nes).nes.as_mut().unwrap().input(1);
+            assert_eq!(buttons, Button::A);
+            nes_set_button(nes, 0, NES_BUTTON_A, true);
+            nes_set_button(nes, 5, NES_BUTTON_A, true);
+
+            let size = nes_save_state(nes, ptr::null_mut(), 0);
+            let mut state = vec![0; size];
+            assert_eq!(nes_save_state(nes, state.as_mut_ptr(), size), size);
+            nes_run_frame(nes);
+            assert_eq!(nes_load_state(nes, state.as_ptr(), size), NES_OK);
+            assert_eq!(nes_save_state(nes, ptr::null_mut(), 0), size);
+            assert_eq!(
+                nes_load_state(nes, state.as_ptr(), 3),
+                NES_ERROR_INVALID_STATE
+            );
+
+            nes_reset(nes);
+            nes_destroy(nes);
+            nes_destroy(ptr::null_mut());
+            assert_eq!(nes_run_frame(ptr::null_mut()), NES_ERROR_NULL_POINTER);
+        }
+    }
+
+    #[test]
+    fn test_guard() {
+        assert_eq!(guard(NES_ERROR_PANIC, || NES_OK), NES_OK);
+        assert_eq!(
+            guard(NES_ERROR_PANIC, || panic!("caught before it gets to C")),
+            NES_ERROR_PANIC
+        );
+    }
+
+    // every function and constant here is declared in the header
+    #[test]
+    fn test_header() {
+        let header = include_str!("../include/rust_nes_emu.h");
+        let source = include_str!("ffi.rs");
+        for line in source.lines() {
+            let name = if let Some(rest) = line.strip_prefix("pub const ") {
+                rest.split(':').next()
+            } else {
+                line.split("extern \"C\" fn ")
+                    .nth(1)
+                    .and_then(|rest| rest.split('(').next())
+            };
+            if let Some(name) = name {
+                assert!(header.contains(name), "{} isn't in the header", name);
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::joypad::Joypad;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Four Score four player adapter
+// from: https://www.nesdev.org/wiki/Four_Score
+//
+// Each port reports two controllers followed by a signature, one bit per
+// read like a standard controller:
+// | Reads | $4016         | $4017         |
+// |-------|---------------|---------------|
+// | 1-8   | Controller 1  | Controller 2  |
+// | 9-16  | Controller 3  | Controller 4  |
+// | 17-24 | Signature $10 | Signature $20 |
+// After that reads return 1. The strobe starts all of them over.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FourScore {
+    // the port's first and second controller
+    joypads: [Joypad; 2],
+    signature: u8,
+    strobe: bool,
+    index: u8,
+}
+
+impl FourScore {
+    // half of the adapter, for port 1 or 2
+    pub fn new(port: usize) -> Self {
+        FourScore {
+            joypads: [Joypad::new(), Joypad::new()],
+            signature: if port == 1 { 0x10 } else { 0x20 },
+            strobe: false,
+            index: 0,
+        }
+    }
+
+    pub fn joypad_mut(&mut self, slot: usize) -> Option<&mut Joypad> {
+        self.joypads.get_mut(slot)
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.index = 0;
+        }
+        for joypad in self.joypads.iter_mut() {
+            joypad.write(data);
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let response = match self.index {
+            0..=7 => self.joypads[0].read(),
+            8..=15 => self.joypads[1].read(),
+            16..=23 => (self.signature >> (self.index - 16)) & 1,
+            _ => return 1,
+        };
+        if !self.strobe {
+            self.index += 1;
+        }
+        response
+    }
+}
+
+impl Snapshot for FourScore {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe);
+        state.write_u8(self.index);
+        for joypad in self.joypads.iter() {
+            joypad.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = state.read_bool()?;
+        self.index = state.read_u8()?;
+        for joypad in self.joypads.iter_mut() {
+            joypad.load_state(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::Button;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_report_order() {
+        let mut four_score = FourScore::new(2);
+        four_score.joypad_mut(0).unwrap().set_buttons(Button::A);
+        four_score.joypad_mut(1).unwrap().set_buttons(Button::B);
+        four_score.write(1);
+        four_score.write(0);
+
+        let reads: Vec<u8> = (0..26).map(|_| four_score.read()).collect();
+        assert_eq!(reads[..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(reads[8..16], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(reads[16..24], [0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(reads[24..], [1, 1]);
+    }
+}
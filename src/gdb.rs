@@ -0,0 +1,561 @@
+use crate::cpu::{CpuFlags, Mem};
+use crate::debugger::{Access, StopReason};
+use crate::nes::Nes;
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+// A GDB remote serial protocol stub, so GDB, IDA, VS Code and other RSP
+// clients can debug the emulated 6502 over TCP
+// from: https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//
+// Packets are "$data#checksum", acknowledged with '+' until the client asks
+// for no-ack mode, and a lone 0x03 byte interrupts a running target. GDB
+// has no 6502 architecture, so the registers come from the target
+// description below: a, x, y, p, sp and a 16-bit pc, pc little-endian in
+// 'g' replies like everything else.
+//
+// The stub never blocks the emulator. The frontend polls it every frame and
+// runs frames only while the client has the target running, reporting how
+// each run stopped; see main.rs.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rust-nes-emu.6502">
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// the most memory an 'm' packet reads at once
+const MAX_READ: usize = 0x800;
+
+// POSIX signal numbers, as stop replies carry them
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// the stop reply for a run that ended for `reason`; None when it only ran
+// to the end of a frame
+pub fn stop_reply(reason: StopReason) -> Option<String> {
+    match reason {
+        StopReason::Breakpoint(_)
+        | StopReason::WatchExpression(_)
+        | StopReason::PpuPosition { .. }
+        | StopReason::Step
+        | StopReason::Halted => Some(format!("S{:02x}", SIGTRAP)),
+        StopReason::Watchpoint(hit) => {
+            let kind = match hit.access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::ReadWrite => "awatch",
+            };
+            Some(format!("T{:02x}{}:{:04x};", SIGTRAP, kind, hit.addr))
+        }
+        StopReason::Fault(_) => Some(format!("S{:02x}", SIGSEGV)),
+        StopReason::FrameComplete | StopReason::Paused => None,
+    }
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+// `data` framed as a packet
+pub fn frame(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+#[derive(Debug, PartialEq)]
+enum Incoming {
+    Packet(String),
+    BadChecksum,
+    Interrupt,
+}
+
+// the next whole packet or interrupt in `input`, dropping acks on the way
+fn take_incoming(input: &mut Vec<u8>) -> Option<Incoming> {
+    let start = input
+        .iter()
+        .position(|&byte| byte == b'$' || byte == 0x03)?;
+    input.drain(..start);
+    if input[0] == 0x03 {
+        input.remove(0);
+        return Some(Incoming::Interrupt);
+    }
+    let hash = input.iter().position(|&byte| byte == b'#')?;
+    if input.len() < hash + 3 {
+        return None;
+    }
+    let packet: Vec<u8> = input.drain(..hash + 3).collect();
+    let data = String::from_utf8_lossy(&packet[1..hash]).into_owned();
+    let sent = std::str::from_utf8(&packet[hash + 1..])
+        .ok()
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+    if sent == Some(checksum(&data)) {
+        Some(Incoming::Packet(data))
+    } else {
+        Some(Incoming::BadChecksum)
+    }
+}
+
+fn parse_hex(digits: &str) -> Option<u16> {
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(digits.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// "addr,len"
+fn parse_range(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn registers(nes: &Nes) -> Vec<u8> {
+    let cpu = nes.cpu();
+    let pc = cpu.program_counter.to_le_bytes();
+    vec![
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status_register.bits(),
+        cpu.stack_pointer,
+        pc[0],
+        pc[1],
+    ]
+}
+
+fn set_registers(nes: &mut Nes, bytes: &[u8]) {
+    let cpu = nes.cpu_mut();
+    cpu.register_a = bytes[0];
+    cpu.register_x = bytes[1];
+    cpu.register_y = bytes[2];
+    cpu.status_register = CpuFlags::from_bits_truncate(bytes[3]);
+    cpu.stack_pointer = bytes[4];
+    cpu.program_counter = u16::from_le_bytes([bytes[5], bytes[6]]);
+}
+
+// where register `n` is in the 'g' layout
+fn register_bytes(n: usize) -> Option<std::ops::Range<usize>> {
+    match n {
+        0..=4 => Some(n..n + 1),
+        5 => Some(5..7),
+        _ => None,
+    }
+}
+
+// The protocol without the connection: a packet in, its reply out
+pub struct Session {
+    // a continue is in progress; the frontend runs frames until it stops
+    pub running: bool,
+    // the client is still there; detach and kill end the session
+    pub attached: bool,
+    // no-ack mode, where packets go without '+' and '-'
+    pub no_ack: bool,
+    last_stop: String,
+}
+
+impl Session {
+    // the target starts out stopped, as clients expect when they connect
+    pub fn new() -> Self {
+        Session {
+            running: false,
+            attached: true,
+            no_ack: false,
+            last_stop: format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    // The reply to `packet`, unframed. A continue gets none until its run
+    // stops, a kill none at all.
+    pub fn handle(&mut self, nes: &mut Nes, packet: &str) -> Option<String> {
+        let mut chars = packet.chars();
+        let command = chars.next()?;
+        let args = chars.as_str();
+        let reply = match command {
+            '?' => self.last_stop.clone(),
+            'g' => hex(&registers(nes)),
+            'G' => match parse_bytes(args) {
+                Some(bytes) if bytes.len() == 7 => {
+                    set_registers(nes, &bytes);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            'p' => {
+                let range = usize::from_str_radix(args, 16)
+                    .ok()
+                    .and_then(register_bytes);
+                match range {
+                    Some(range) => hex(&registers(nes)[range]),
+                    None => "E01".to_string(),
+                }
+            }
+            'P' => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    let range = usize::from_str_radix(n, 16).ok().and_then(register_bytes)?;
+                    let value = parse_bytes(value).filter(|bytes| bytes.len() == range.len())?;
+                    Some((range, value))
+                });
+                match register {
+                    Some((range, value)) => {
+                        let mut bytes = registers(nes);
+                        bytes[range].copy_from_slice(&value);
+                        set_registers(nes, &bytes);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            'm' => match parse_range(args) {
+                Some((addr, len)) => hex(&nes.peek_range(addr, len.min(MAX_READ))),
+                None => "E01".to_string(),
+            },
+            'M' => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    parse_bytes(data)
+                        .filter(|bytes| bytes.len() == len)
+                        .map(|bytes| (addr, bytes))
+                });
+                match write {
+                    Some((addr, bytes)) => {
+                        for (offset, &byte) in bytes.iter().enumerate() {
+                            nes.bus_mut()
+                                .mem_write(addr.wrapping_add(offset as u16), byte);
+                        }
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            'Z' | 'z' => self.breakpoint(nes, command == 'Z', args),
+            's' => {
+                let reason = nes.step();
+                return self
+                    .stopped(reason)
+                    .or_else(|| Some(self.last_stop.clone()));
+            }
+            'c' => {
+                self.running = true;
+                return None;
+            }
+            // reverse step, with the start of the history reported as such
+            'b' if args == "s" => {
+                let reply = if nes.step_back() {
+                    format!("S{:02x}", SIGTRAP)
+                } else {
+                    format!("T{:02x}replaylog:begin;", SIGTRAP)
+                };
+                self.last_stop = reply.clone();
+                reply
+            }
+            'q' => self.query(args),
+            'Q' if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            'H' => "OK".to_string(),
+            'D' => {
+                self.attached = false;
+                "OK".to_string()
+            }
+            'k' => {
+                self.attached = false;
+                return None;
+            }
+            // anything else isn't supported, which the empty reply says
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // Z or z: type 0 and 1 are breakpoints, 2 to 4 write, read and access
+    // watchpoints; "type,addr,kind"
+    fn breakpoint(&mut self, nes: &mut Nes, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = match fields.next().and_then(parse_hex) {
+            Some(addr) => addr,
+            None => return "E01".to_string(),
+        };
+        let access = match kind {
+            Some("0") | Some("1") => {
+                if insert {
+                    nes.add_breakpoint(addr);
+                } else {
+                    nes.remove_breakpoint(addr);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => Access::Write,
+            Some("3") => Access::Read,
+            Some("4") => Access::ReadWrite,
+            _ => return String::new(),
+        };
+        if insert {
+            nes.add_watchpoint(addr, access);
+        } else {
+            nes.remove_watchpoint(addr);
+        }
+        "OK".to_string()
+    }
+
+    fn query(&mut self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+"
+                .to_string();
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, len) = match range.split_once(',') {
+                Some((offset, len)) => (
+                    usize::from_str_radix(offset, 16).unwrap_or(0),
+                    usize::from_str_radix(len, 16).unwrap_or(0),
+                ),
+                None => return "E01".to_string(),
+            };
+            // 'm' for more to come, 'l' for the last part
+            let start = offset.min(TARGET_XML.len());
+            let end = (start + len).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+        match args {
+            "Attached" => "1",
+            "fThreadInfo" => "m1",
+            "sThreadInfo" => "l",
+            "C" => "QC1",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    // The reply for the continue in progress, when a run stopped for
+    // `reason`; None if it should carry on
+    pub fn stopped(&mut self, reason: StopReason) -> Option<String> {
+        let reply = stop_reply(reason)?;
+        self.running = false;
+        self.last_stop = reply.clone();
+        Some(reply)
+    }
+
+    // the client's 0x03 while running
+    pub fn interrupt(&mut self) -> String {
+        self.running = false;
+        self.last_stop = format!("S{:02x}", SIGINT);
+        self.last_stop.clone()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    input: Vec<u8>,
+    session: Session,
+}
+
+impl Client {
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        self.stream.write_all(frame(reply).as_bytes())
+    }
+
+    fn poll(&mut self, nes: &mut Nes) -> io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.input.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        self.stream.set_nonblocking(false)?;
+
+        while let Some(incoming) = take_incoming(&mut self.input) {
+            match incoming {
+                Incoming::Interrupt => {
+                    if self.session.running {
+                        let reply = self.session.interrupt();
+                        self.send(&reply)?;
+                    }
+                }
+                Incoming::BadChecksum => {
+                    if !self.session.no_ack {
+                        self.stream.write_all(b"-")?;
+                    }
+                }
+                Incoming::Packet(packet) => {
+                    if !self.session.no_ack {
+                        self.stream.write_all(b"+")?;
+                    }
+                    if let Some(reply) = self.session.handle(nes, &packet) {
+                        self.send(&reply)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Listens for one client at a time
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+impl GdbStub {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Takes a new client, or handles what the current one sent since the
+    // last call. True while the emulator should run frames: with no client
+    // attached or after a continue.
+    pub fn poll(&mut self, nes: &mut Nes) -> bool {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                self.client = Some(Client {
+                    stream,
+                    input: Vec::new(),
+                    session: Session::new(),
+                });
+            }
+        }
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return true,
+        };
+        // a lost connection lets the game go on, as a detach does
+        if client.poll(nes).is_err() || !client.session.attached {
+            self.client = None;
+            return true;
+        }
+        client.session.running
+    }
+
+    // How a run_frame went, for a client waiting on a continue. True while
+    // the emulator should carry on running.
+    pub fn report(&mut self, reason: StopReason) -> bool {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return true,
+        };
+        if let Some(reply) = client.session.stopped(reason) {
+            if client.send(&reply).is_err() {
+                self.client = None;
+                return true;
+            }
+        }
+        client.session.running
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::nrom;
+
+    #[test]
+    fn test_framing() {
+        assert_eq!(frame("OK"), "$OK#9a");
+        let mut input = b"+$g#67$m0,1#00\x03$c#6".to_vec();
+        assert_eq!(
+            take_incoming(&mut input),
+            Some(Incoming::Packet("g".into()))
+        );
+        assert_eq!(take_incoming(&mut input), Some(Incoming::BadChecksum));
+        assert_eq!(take_incoming(&mut input), Some(Incoming::Interrupt));
+        // the rest of the packet hasn't arrived yet
+        assert_eq!(take_incoming(&mut input), None);
+        input.push(b'3');
+        assert_eq!(
+            take_incoming(&mut input),
+            Some(Incoming::Packet("c".into()))
+        );
+    }
+
+    #[test]
+    fn test_session() {
+        // LDA #$42; STA $10; JMP $8000
+        let mut nes = Nes::new(&nrom(&[0xa9, 0x42, 0x85, 0x10, 0x4c, 0x00, 0x80], &[])).unwrap();
+        let mut session = Session::new();
+        let mut handle = |nes: &mut Nes, packet: &str| session.handle(nes, packet);
+
+        assert_eq!(handle(&mut nes, "?").unwrap(), "S05");
+        assert!(handle(&mut nes, "qSupported:swbreak+")
+            .unwrap()
+            .contains("qXfer"));
+        assert!(handle(&mut nes, "qXfer:features:read:target.xml:0,1000")
+            .unwrap()
+            .starts_with("l<?xml"));
+        assert_eq!(handle(&mut nes, "vMustReplyEmpty").unwrap(), "");
+
+        // registers: a, x, y, p, sp, then pc little-endian
+        assert_eq!(handle(&mut nes, "p5").unwrap(), "0080");
+        assert_eq!(handle(&mut nes, "P0=7f").unwrap(), "OK");
+        assert_eq!(nes.cpu().register_a, 0x7f);
+        assert_eq!(handle(&mut nes, "g").unwrap()[..2], *"7f");
+
+        assert_eq!(handle(&mut nes, "M200,2:beef").unwrap(), "OK");
+        assert_eq!(handle(&mut nes, "m200,2").unwrap(), "beef");
+        assert_eq!(handle(&mut nes, "m8000,2").unwrap(), "a942");
+
+        // steps and breakpoints
+        assert_eq!(handle(&mut nes, "s").unwrap(), "S05");
+        assert_eq!(nes.cpu().program_counter, 0x8002);
+        assert_eq!(handle(&mut nes, "bs").unwrap(), "T05replaylog:begin;");
+        nes.enable_reverse_step(1 << 20);
+        assert_eq!(handle(&mut nes, "s").unwrap(), "S05");
+        assert_eq!(handle(&mut nes, "bs").unwrap(), "S05");
+        assert_eq!(nes.cpu().program_counter, 0x8002);
+        assert_eq!(handle(&mut nes, "s").unwrap(), "S05");
+        assert_eq!(handle(&mut nes, "Z0,8004,1").unwrap(), "OK");
+        assert_eq!(handle(&mut nes, "c"), None);
+        assert_eq!(nes.run_frame(), StopReason::Breakpoint(0x8004));
+        assert_eq!(handle(&mut nes, "z0,8004,1").unwrap(), "OK");
+        assert_eq!(handle(&mut nes, "Z2,10,1").unwrap(), "OK");
+        let stop = nes.run_frame();
+        assert_eq!(session.stopped(stop).unwrap(), "T05watch:0010;");
+        assert!(!session.running);
+
+        assert_eq!(session.handle(&mut nes, "D").unwrap(), "OK");
+        assert!(!session.attached);
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Per-address access counters for debug frontends.
+//
+// Counts are accumulated while a frame runs and latched by `end_frame`, so a
+// frontend always reads a complete frame's worth of traffic. A frame is at
+// most ~30k CPU cycles, so a u16 per address is enough and keeps the buffers
+// small (4KB per direction for the 2KB CPU RAM).
+pub struct AccessHeatmap {
+    pub enabled: bool,
+    pending_reads: Vec<u16>,
+    pending_writes: Vec<u16>,
+    reads: Vec<u16>,
+    writes: Vec<u16>,
+}
+
+impl AccessHeatmap {
+    pub fn new(size: usize) -> Self {
+        AccessHeatmap {
+            enabled: false,
+            pending_reads: vec![0; size],
+            pending_writes: vec![0; size],
+            reads: vec![0; size],
+            writes: vec![0; size],
+        }
+    }
+
+    pub fn record_read(&mut self, index: usize) {
+        if self.enabled {
+            self.pending_reads[index] = self.pending_reads[index].saturating_add(1);
+        }
+    }
+
+    pub fn record_write(&mut self, index: usize) {
+        if self.enabled {
+            self.pending_writes[index] = self.pending_writes[index].saturating_add(1);
+        }
+    }
+
+    // latch the counts of the frame that just finished and start a new one
+    pub fn end_frame(&mut self) {
+        core::mem::swap(&mut self.pending_reads, &mut self.reads);
+        core::mem::swap(&mut self.pending_writes, &mut self.writes);
+        self.pending_reads.iter_mut().for_each(|c| *c = 0);
+        self.pending_writes.iter_mut().for_each(|c| *c = 0);
+    }
+
+    // read counts per address for the last completed frame
+    pub fn reads(&self) -> &[u16] {
+        &self.reads
+    }
+
+    // write counts per address for the last completed frame
+    pub fn writes(&self) -> &[u16] {
+        &self.writes
+    }
+
+    // addresses touched at all during the last completed frame
+    pub fn touched(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.reads.len())
+            .filter(move |&i| self.reads[i] != 0 || self.writes[i] != 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counts_are_latched_per_frame() {
+        let mut heatmap = AccessHeatmap::new(0x10);
+        heatmap.enabled = true;
+        heatmap.record_read(3);
+        heatmap.record_read(3);
+        heatmap.record_write(5);
+        assert_eq!(heatmap.reads()[3], 0);
+
+        heatmap.end_frame();
+        assert_eq!(heatmap.reads()[3], 2);
+        assert_eq!(heatmap.writes()[5], 1);
+        assert_eq!(heatmap.touched().collect::<Vec<_>>(), vec![3, 5]);
+
+        heatmap.end_frame();
+        assert_eq!(heatmap.reads()[3], 0);
+        assert_eq!(heatmap.touched().count(), 0);
+    }
+
+    #[test]
+    fn test_disabled_heatmap_does_not_count() {
+        let mut heatmap = AccessHeatmap::new(0x10);
+        heatmap.record_read(1);
+        heatmap.record_write(1);
+        heatmap.end_frame();
+        assert_eq!(heatmap.touched().count(), 0);
+    }
+}
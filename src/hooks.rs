@@ -0,0 +1,123 @@
+use crate::debugger::{self, Access};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+// Callbacks on CPU bus accesses, for library users watching memory traffic
+// (scripts, achievement checkers, analyzers) without touching the BUS. They
+// run during the access, get its address and the value read or written,
+// and can't change either. RAM addresses are passed as $0000-$07FF, so a
+// hook on RAM also sees accesses through its mirrors.
+//
+//   let id = bus.on_write(0x0075..=0x0075, |addr, value| println!("lives {}", value));
+//   ...
+//   bus.remove_hook(id);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(usize);
+
+type Callback = Box<dyn FnMut(u16, u8)>;
+
+struct Hook {
+    id: HookId,
+    access: Access,
+    range: RangeInclusive<u16>,
+    callback: Callback,
+}
+
+#[derive(Default)]
+pub struct MemoryHooks {
+    hooks: Vec<Hook>,
+    next_id: usize,
+}
+
+impl MemoryHooks {
+    pub fn new() -> Self {
+        MemoryHooks::default()
+    }
+
+    pub fn add(
+        &mut self,
+        access: Access,
+        range: RangeInclusive<u16>,
+        callback: Callback,
+    ) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook {
+            id,
+            access,
+            range,
+            callback,
+        });
+        id
+    }
+
+    // false if there was no such hook
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn check(&mut self, addr: u16, access: Access, value: u8) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let addr = debugger::normalize(addr);
+        for hook in &mut self.hooks {
+            if hook.access.covers(access) && hook.range.contains(&addr) {
+                (hook.callback)(addr, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_hooks() {
+        let mut hooks = MemoryHooks::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let writes = hooks.add(
+            Access::Write,
+            0x0000..=0x00ff,
+            Box::new(move |addr, value| log.borrow_mut().push((addr, value))),
+        );
+        let log = seen.clone();
+        hooks.add(
+            Access::ReadWrite,
+            0x6000..=0x7fff,
+            Box::new(move |addr, value| log.borrow_mut().push((addr, value))),
+        );
+
+        hooks.check(0x0810, Access::Write, 1);
+        hooks.check(0x0010, Access::Read, 2);
+        hooks.check(0x0100, Access::Write, 3);
+        hooks.check(0x6000, Access::Read, 4);
+        assert_eq!(*seen.borrow(), [(0x0010, 1), (0x6000, 4)]);
+
+        assert!(hooks.remove(writes));
+        assert!(!hooks.remove(writes));
+        hooks.check(0x0010, Access::Write, 5);
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(seen.borrow().len(), 2);
+    }
+}
@@ -0,0 +1,481 @@
+use crate::arkanoid::Arkanoid;
+use crate::joypad::{Button, Joypad};
+use crate::peripheral::Peripheral;
+use crate::power_pad::PowerPad;
+use crate::zapper::Zapper;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Which keys and gamepad controls press which controller buttons, and what
+// is plugged into each port, for a frontend to load from a file and keep
+// up to date with its events. The names are SDL's, so the SDL frontend
+// passes them straight through, but nothing here needs SDL.
+//
+// A mapping file has a setting to a line:
+//
+//   port2 = zapper
+//   deadzone = 8000
+//   1 a = key:S
+//   1 a = pad1:b
+//   1 left = pad1:-leftx
+//
+// A port takes a joypad, zapper, arkanoid or power-pad. A binding is a
+// player 1 or 2, the joypads in ports 1 and 2, and one of a, b, select,
+// start, up, down, left, right, set from a key, a button of the nth
+// gamepad plugged in, or an axis of one pushed past the deadzone, + or -
+// saying which way. A button can have any number of bindings. Empty lines
+// and lines starting with # are skipped.
+#[derive(Debug, PartialEq)]
+pub enum InputMapError {
+    // a line that isn't a valid setting, counting from 1
+    InvalidLine(usize),
+}
+
+impl fmt::Display for InputMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputMapError::InvalidLine(line) => {
+                write!(f, "invalid input mapping on line {}", line)
+            }
+        }
+    }
+}
+
+impl core::error::Error for InputMapError {}
+
+// something the player presses; pads count from 0 here and from 1 in files
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+    Key(String),
+    PadButton {
+        pad: usize,
+        button: String,
+    },
+    PadAxis {
+        pad: usize,
+        axis: String,
+        positive: bool,
+    },
+}
+
+impl Control {
+    fn parse(text: &str) -> Option<Control> {
+        let (source, name) = text.split_once(':')?;
+        if name.is_empty() {
+            return None;
+        }
+        if source == "key" {
+            return Some(Control::Key(name.to_string()));
+        }
+        let pad = source
+            .strip_prefix("pad")?
+            .parse::<usize>()
+            .ok()?
+            .checked_sub(1)?;
+        let axis = match (name.strip_prefix('+'), name.strip_prefix('-')) {
+            (Some(axis), _) => Some((axis, true)),
+            (_, Some(axis)) => Some((axis, false)),
+            _ => None,
+        };
+        Some(match axis {
+            Some((axis, positive)) if !axis.is_empty() => Control::PadAxis {
+                pad,
+                axis: axis.to_string(),
+                positive,
+            },
+            _ => Control::PadButton {
+                pad,
+                button: name.to_string(),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Control::Key(key) => write!(f, "key:{}", key),
+            Control::PadButton { pad, button } => write!(f, "pad{}:{}", pad + 1, button),
+            Control::PadAxis {
+                pad,
+                axis,
+                positive,
+            } => {
+                let sign = if *positive { '+' } else { '-' };
+                write!(f, "pad{}:{}{}", pad + 1, sign, axis)
+            }
+        }
+    }
+}
+
+// what a port has plugged in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Device {
+    Joypad,
+    Zapper,
+    Arkanoid,
+    PowerPad,
+}
+
+impl Device {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "joypad" => Some(Device::Joypad),
+            "zapper" => Some(Device::Zapper),
+            "arkanoid" => Some(Device::Arkanoid),
+            "power-pad" => Some(Device::PowerPad),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Device::Joypad => "joypad",
+            Device::Zapper => "zapper",
+            Device::Arkanoid => "arkanoid",
+            Device::PowerPad => "power-pad",
+        }
+    }
+
+    pub fn peripheral(&self) -> Peripheral {
+        match self {
+            Device::Joypad => Peripheral::Joypad(Joypad::new()),
+            Device::Zapper => Peripheral::Zapper(Zapper::new()),
+            Device::Arkanoid => Peripheral::Arkanoid(Arkanoid::new()),
+            Device::PowerPad => Peripheral::PowerPad(PowerPad::new()),
+        }
+    }
+}
+
+const BUTTON_NAMES: [(&str, Button); 8] = [
+    ("a", Button::A),
+    ("b", Button::B),
+    ("select", Button::SELECT),
+    ("start", Button::START),
+    ("up", Button::UP),
+    ("down", Button::DOWN),
+    ("left", Button::LEFT),
+    ("right", Button::RIGHT),
+];
+
+fn button_name(button: Button) -> &'static str {
+    BUTTON_NAMES
+        .iter()
+        .find(|(_, named)| *named == button)
+        .map_or("?", |(name, _)| name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub player: usize,
+    pub button: Button,
+    pub control: Control,
+}
+
+pub struct InputMap {
+    bindings: Vec<Binding>,
+    // whether each binding's control is down, by index
+    pressed: Vec<bool>,
+    ports: [Device; 2],
+    // how far an axis goes, out of 32767, before it counts
+    pub deadzone: i16,
+}
+
+impl Default for InputMap {
+    // the keyboard for player 1 and the first two gamepads for players 1
+    // and 2, the NES's B and A where a pad's left and bottom buttons are
+    fn default() -> Self {
+        let mut map = InputMap::empty();
+        let keys = [
+            (Button::A, "A"),
+            (Button::B, "S"),
+            (Button::SELECT, "Space"),
+            (Button::START, "Return"),
+            (Button::UP, "Up"),
+            (Button::DOWN, "Down"),
+            (Button::LEFT, "Left"),
+            (Button::RIGHT, "Right"),
+        ];
+        for (button, key) in keys {
+            map.bind(1, button, Control::Key(key.to_string()));
+        }
+        for pad in 0..2 {
+            let buttons = [
+                (Button::A, "b"),
+                (Button::B, "a"),
+                (Button::SELECT, "back"),
+                (Button::START, "start"),
+                (Button::UP, "dpup"),
+                (Button::DOWN, "dpdown"),
+                (Button::LEFT, "dpleft"),
+                (Button::RIGHT, "dpright"),
+            ];
+            for (button, name) in buttons {
+                let name = name.to_string();
+                map.bind(pad + 1, button, Control::PadButton { pad, button: name });
+            }
+            let axes = [
+                (Button::UP, "lefty", false),
+                (Button::DOWN, "lefty", true),
+                (Button::LEFT, "leftx", false),
+                (Button::RIGHT, "leftx", true),
+            ];
+            for (button, axis, positive) in axes {
+                let axis = axis.to_string();
+                let control = Control::PadAxis {
+                    pad,
+                    axis,
+                    positive,
+                };
+                map.bind(pad + 1, button, control);
+            }
+        }
+        map
+    }
+}
+
+impl InputMap {
+    // no bindings at all and joypads in both ports
+    pub fn empty() -> Self {
+        InputMap {
+            bindings: Vec::new(),
+            pressed: Vec::new(),
+            ports: [Device::Joypad; 2],
+            deadzone: 8000,
+        }
+    }
+
+    // Reads a mapping file, see the top of the file
+    pub fn parse(text: &str) -> Result<Self, InputMapError> {
+        let mut map = InputMap::empty();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || InputMapError::InvalidLine(number + 1);
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "port1" | "port2" => {
+                    let device = Device::from_name(value).ok_or_else(invalid)?;
+                    map.ports[(key == "port2") as usize] = device;
+                }
+                "deadzone" => {
+                    map.deadzone = value
+                        .parse()
+                        .ok()
+                        .filter(|&deadzone: &i16| deadzone >= 0)
+                        .ok_or_else(invalid)?
+                }
+                _ => {
+                    let (player, button) = key.split_once(' ').ok_or_else(invalid)?;
+                    let player = player.parse::<usize>().map_err(|_| invalid())?;
+                    let button = BUTTON_NAMES
+                        .iter()
+                        .find(|(name, _)| *name == button.trim())
+                        .map(|&(_, button)| button)
+                        .ok_or_else(invalid)?;
+                    let control = Control::parse(value).ok_or_else(invalid)?;
+                    if !(1..=2).contains(&player) {
+                        return Err(invalid());
+                    }
+                    map.bind(player, button, control);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    // the mapping as a file parse reads back
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (port, device) in self.ports.iter().enumerate() {
+            text += &format!("port{} = {}\n", port + 1, device.name());
+        }
+        text += &format!("deadzone = {}\n", self.deadzone);
+        for binding in &self.bindings {
+            let button = button_name(binding.button);
+            text += &format!("{} {} = {}\n", binding.player, button, binding.control);
+        }
+        text
+    }
+
+    pub fn bind(&mut self, player: usize, button: Button, control: Control) {
+        self.bindings.push(Binding {
+            player,
+            button,
+            control,
+        });
+        self.pressed.push(false);
+    }
+
+    // drops every binding of the player's button
+    pub fn unbind(&mut self, player: usize, button: Button) {
+        let mut i = 0;
+        while i < self.bindings.len() {
+            if self.bindings[i].player == player && self.bindings[i].button == button {
+                self.bindings.remove(i);
+                self.pressed.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    // port 1 or 2
+    pub fn device(&self, port: usize) -> Device {
+        self.ports[port - 1]
+    }
+
+    pub fn set_device(&mut self, port: usize, device: Device) {
+        self.ports[port - 1] = device;
+    }
+
+    // a key or pad button went down or up
+    pub fn set_pressed(&mut self, control: &Control, pressed: bool) {
+        for (binding, down) in self.bindings.iter().zip(self.pressed.iter_mut()) {
+            if binding.control == *control {
+                *down = pressed;
+            }
+        }
+    }
+
+    // a pad's axis moved to `value`, which presses the bindings on its
+    // side once past the deadzone and releases the others
+    pub fn set_axis(&mut self, pad: usize, axis: &str, value: i16) {
+        for (binding, down) in self.bindings.iter().zip(self.pressed.iter_mut()) {
+            if let Control::PadAxis {
+                pad: bound_pad,
+                axis: bound_axis,
+                positive,
+            } = &binding.control
+            {
+                if *bound_pad == pad && bound_axis == axis {
+                    // as i32, which -32768 fits in negated
+                    let (value, deadzone) = (i32::from(value), i32::from(self.deadzone));
+                    *down = if *positive {
+                        value > deadzone
+                    } else {
+                        value < -deadzone
+                    };
+                }
+            }
+        }
+    }
+
+    // Lets go of everything on the pads, for when one is unplugged: the
+    // ones after it move down a place, so they aren't the same pads anymore
+    pub fn release_pads(&mut self) {
+        for (binding, down) in self.bindings.iter().zip(self.pressed.iter_mut()) {
+            if !matches!(binding.control, Control::Key(_)) {
+                *down = false;
+            }
+        }
+    }
+
+    // what the player is holding down
+    pub fn buttons(&self, player: usize) -> Button {
+        self.bindings
+            .iter()
+            .zip(self.pressed.iter())
+            .filter(|(binding, &down)| down && binding.player == player)
+            .fold(Button::empty(), |buttons, (binding, _)| {
+                buttons | binding.button
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_write_back() {
+        let text = "# player 2 on the first pad\n\
+                    port2 = zapper\n\
+                    deadzone = 12000\n\
+                    2 start = key:Return\n\
+                    2 left = pad1:-leftx\n\
+                    2 a = pad2:b\n";
+        let map = InputMap::parse(text).unwrap();
+        assert_eq!(map.device(1), Device::Joypad);
+        assert_eq!(map.device(2), Device::Zapper);
+        assert_eq!(map.deadzone, 12000);
+        assert_eq!(
+            map.bindings()[1],
+            Binding {
+                player: 2,
+                button: Button::LEFT,
+                control: Control::PadAxis {
+                    pad: 0,
+                    axis: "leftx".to_string(),
+                    positive: false
+                }
+            }
+        );
+        let written = map.to_text();
+        assert_eq!(written.lines().count(), 6);
+        assert_eq!(
+            InputMap::parse(&written).unwrap().bindings(),
+            map.bindings()
+        );
+
+        let default = InputMap::default();
+        assert_eq!(
+            InputMap::parse(&default.to_text()).unwrap().bindings(),
+            default.bindings()
+        );
+
+        assert_eq!(
+            InputMap::parse("port1 = joypad\n1 turbo = key:T").err(),
+            Some(InputMapError::InvalidLine(2))
+        );
+        assert!(InputMap::parse("3 a = key:A").is_err());
+        assert!(InputMap::parse("deadzone = -1").is_err());
+        assert!(InputMap::parse("1 a = pad1:é").is_ok());
+        assert!(InputMap::parse("1 a = pad0:a").is_err());
+        assert!(InputMap::parse("port3 = zapper").is_err());
+    }
+
+    #[test]
+    fn test_controls_press_buttons() {
+        let mut map = InputMap::default();
+        let key = |name: &str| Control::Key(name.to_string());
+        map.set_pressed(&key("A"), true);
+        map.set_pressed(&key("Up"), true);
+        assert_eq!(map.buttons(1), Button::A | Button::UP);
+
+        // the second pad is player 2's
+        let pad_button = Control::PadButton {
+            pad: 1,
+            button: "start".to_string(),
+        };
+        map.set_pressed(&pad_button, true);
+        assert_eq!(map.buttons(2), Button::START);
+
+        // only past the deadzone
+        map.set_axis(1, "leftx", -4000);
+        assert_eq!(map.buttons(2), Button::START);
+        map.set_axis(1, "leftx", -20000);
+        assert_eq!(map.buttons(2), Button::START | Button::LEFT);
+        map.set_axis(1, "leftx", 30000);
+        assert_eq!(map.buttons(2), Button::START | Button::RIGHT);
+        map.deadzone = i16::MIN;
+        map.set_axis(1, "leftx", i16::MIN);
+        assert_eq!(map.buttons(2), Button::START | Button::LEFT);
+
+        map.release_pads();
+        assert!(map.buttons(2).is_empty());
+        map.set_pressed(&key("A"), false);
+        map.unbind(1, Button::UP);
+        assert!(map.buttons(1).is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+bitflags! {
+    // standard controller buttons, in the order the controller reports them
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Button: u8 {
+        const A      = 0b0000_0001;
+        const B      = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START  = 0b0000_1000;
+        const UP     = 0b0001_0000;
+        const DOWN   = 0b0010_0000;
+        const LEFT   = 0b0100_0000;
+        const RIGHT  = 0b1000_0000;
+    }
+}
+
+// Writing 1 to $4016 (strobe) keeps reloading the shift register from the
+// buttons, so reads keep returning A. After strobe goes back to 0 each read
+// returns the next button; once all 8 are shifted out reads return 1.
+//
+// Turbo buttons held down are pressed for `on` frames and released for
+// `off` frames over and over. The console steps them once a frame, see
+// Nes::run_frame, and what the game saw is what a movie records, so turbo
+// needs no help to play back the same. Save states keep where each turbo
+// button is in its repeat, so loading one, or rolling back to it, carries
+// on in step.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    // what the game sees: the buttons held, less turbo ones in their off
+    // frames
+    button_status: Button,
+    held: Button,
+    turbo: Button,
+    turbo_rate: (u8, u8),
+    turbo_frame: u16,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: Button::from_bits_truncate(0),
+            held: Button::empty(),
+            turbo: Button::empty(),
+            turbo_rate: (2, 2),
+            turbo_frame: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.button_status.bits() >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
+        self.button_status.set(button, pressed);
+        self.held.set(button, pressed);
+    }
+
+    pub fn set_buttons(&mut self, buttons: Button) {
+        self.button_status = buttons;
+        self.held = buttons;
+    }
+
+    // which buttons repeat by themselves while held
+    pub fn set_turbo(&mut self, buttons: Button) {
+        self.turbo = buttons;
+    }
+
+    pub fn turbo(&self) -> Button {
+        self.turbo
+    }
+
+    // frames pressed and released in each repeat, at least 1 of each
+    pub fn set_turbo_rate(&mut self, on: u8, off: u8) {
+        self.turbo_rate = (on.max(1), off.max(1));
+        self.turbo_frame = 0;
+    }
+
+    pub fn turbo_rate(&self) -> (u8, u8) {
+        self.turbo_rate
+    }
+
+    // a new frame: turbo buttons held go on or off
+    pub fn step_turbo(&mut self) {
+        let (on, off) = self.turbo_rate;
+        self.button_status = if self.turbo_frame < on as u16 {
+            self.held
+        } else {
+            self.held - self.turbo
+        };
+        self.turbo_frame = (self.turbo_frame + 1) % (on as u16 + off as u16);
+    }
+
+    pub fn button_status(&self) -> Button {
+        self.button_status
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe);
+        state.write_u8(self.button_index);
+        state.write_u8(self.button_status.bits());
+        state.write_u8(self.held.bits());
+        state.write_u8(self.turbo.bits());
+        state.write_u8(self.turbo_rate.0);
+        state.write_u8(self.turbo_rate.1);
+        state.write_u16(self.turbo_frame);
+    }
+
+    // version 5 and older states have no turbo
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = state.read_bool()?;
+        self.button_index = state.read_u8()?;
+        self.button_status = Button::from_bits_truncate(state.read_u8()?);
+        if state.version() >= 6 {
+            self.held = Button::from_bits_truncate(state.read_u8()?);
+            self.turbo = Button::from_bits_truncate(state.read_u8()?);
+            self.turbo_rate = (state.read_u8()?.max(1), state.read_u8()?.max(1));
+            self.turbo_frame =
+                state.read_u16()? % (self.turbo_rate.0 as u16 + self.turbo_rate.1 as u16);
+        } else {
+            self.held = self.button_status;
+            self.turbo = Button::empty();
+            self.turbo_rate = (2, 2);
+            self.turbo_frame = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_turbo() {
+        let mut joypad = Joypad::new();
+        joypad.set_turbo(Button::A);
+        joypad.set_turbo_rate(1, 2);
+        joypad.set_buttons(Button::A | Button::B);
+        let mut seen = vec![];
+        for _ in 0..6 {
+            joypad.step_turbo();
+            seen.push(joypad.button_status());
+        }
+        let both = Button::A | Button::B;
+        assert_eq!(
+            seen,
+            [both, Button::B, Button::B, both, Button::B, Button::B]
+        );
+
+        // released buttons stay released
+        joypad.set_buttons(Button::empty());
+        joypad.step_turbo();
+        assert_eq!(joypad.button_status(), Button::empty());
+    }
+
+    #[test]
+    fn test_strobe_mode() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.set_button_pressed(Button::A, true);
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1);
+        }
+    }
+
+    #[test]
+    fn test_strobe_mode_on_off() {
+        let mut joypad = Joypad::new();
+
+        joypad.write(0);
+        joypad.set_button_pressed(Button::RIGHT, true);
+        joypad.set_button_pressed(Button::LEFT, true);
+        joypad.set_button_pressed(Button::SELECT, true);
+        joypad.set_button_pressed(Button::B, true);
+
+        for _ in 0..=1 {
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 1);
+
+            for _ in 0..10 {
+                assert_eq!(joypad.read(), 1);
+            }
+            joypad.write(1);
+            joypad.write(0);
+        }
+    }
+}
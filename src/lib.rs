@@ -0,0 +1,80 @@
+// Without the std feature only the console itself is built, on core and
+// alloc, for embedded targets and sandboxes. Files, sockets, threads and the
+// frontends need std.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod accuracy;
+pub mod achievements;
+pub mod apu;
+#[cfg(feature = "std")]
+pub mod archive;
+pub mod arkanoid;
+pub mod audio;
+pub mod audit;
+pub mod bus;
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod compat;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod coop;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod env;
+pub mod error;
+pub mod events;
+pub mod expr;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod four_score;
+#[cfg(feature = "std")]
+pub mod gdb;
+pub mod heatmap;
+pub mod hooks;
+pub mod input_map;
+pub mod joypad;
+pub mod mappers;
+pub mod md5;
+pub mod movie;
+pub mod nes;
+#[cfg(feature = "std")]
+pub mod netplay;
+pub mod nsf;
+pub mod opcodes;
+pub mod peripheral;
+pub mod png;
+pub mod power_pad;
+pub mod ppu;
+pub mod ppu_log;
+pub mod profiler;
+pub mod protect;
+pub mod ram_search;
+pub mod region;
+pub mod render;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod romdb;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sha1;
+pub mod state;
+pub mod symbols;
+pub mod testrom;
+#[cfg(feature = "std")]
+pub mod threaded;
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod watch;
+pub mod wav;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod zapper;
+
+#[macro_use]
+extern crate bitflags;
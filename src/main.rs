@@ -1,157 +1,1336 @@
-pub mod bus;
-pub mod rom;
-pub mod cpu;
-pub mod opcodes;
-pub mod ppu;
-
-use bus::BUS;
-use rom::ROM;
-use cpu::Mem;
-use cpu::CPU;
-use rand::Rng;
+use rust_nes_emu::accuracy::AccuracyConfig;
+use rust_nes_emu::apu::Channel;
+use rust_nes_emu::archive;
+use rust_nes_emu::arkanoid::Arkanoid;
+use rust_nes_emu::audio::{RingBuffer, SharedRingBuffer};
+use rust_nes_emu::clock::Clock;
+use rust_nes_emu::compat;
+use rust_nes_emu::config::Config;
+#[cfg(feature = "scripting")]
+use rust_nes_emu::debugger::StopReason;
+use rust_nes_emu::disasm::disassemble;
+use rust_nes_emu::gdb::GdbStub;
+use rust_nes_emu::input_map::{Control, Device, InputMap};
+use rust_nes_emu::joypad::Button;
+use rust_nes_emu::movie::Movie;
+use rust_nes_emu::nes::Nes;
+use rust_nes_emu::netplay::{Netplay, NetplayError, Rollback};
+use rust_nes_emu::nsf::{Nsf, NsfPlayer};
+use rust_nes_emu::peripheral::Peripheral;
+use rust_nes_emu::png::Image;
+use rust_nes_emu::power_pad::PowerPad;
+use rust_nes_emu::region::Region;
+use rust_nes_emu::render::filter::{Filter, VideoFilter};
+use rust_nes_emu::render::frame::Frame;
+use rust_nes_emu::render::palette::Palette;
+use rust_nes_emu::render::scaler::Scaler;
+use rust_nes_emu::rom::ROM;
+use rust_nes_emu::romdb::RomDatabase;
+#[cfg(feature = "scripting")]
+use rust_nes_emu::script::Script;
+use rust_nes_emu::testrom::{run_test_rom, TestResult};
+use rust_nes_emu::threaded::ThreadedNes;
+use rust_nes_emu::watch::RomWatcher;
+use rust_nes_emu::zapper::Zapper;
 
+use clap::{Args, Parser, Subcommand};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::GameController;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::EventPump;
+use sdl2::GameControllerSubsystem;
 
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[macro_use]
-extern crate lazy_static;
+const SCALE: u32 = 3;
+// disasm's banks, the PRG size unit of the iNES header
+const PRG_BANK_SIZE: usize = 0x4000;
+// about a minute of emulated time, which the slowest blargg ROMs fit in
+const TEST_ROM_FRAMES: usize = 60 * 60;
+const SAMPLE_RATE: i32 = 44_100;
+// holding backspace plays the game backwards
+const REWIND_INTERVAL: usize = 2;
+const REWIND_BUDGET: usize = 32 << 20;
+// frame states kept for GDB's reverse stepping
+const STEP_HISTORY_BUDGET: usize = 8 << 20;
+// holding tab fast-forwards
+const FAST_FORWARD_SPEED: f32 = 4.0;
+// turbo buttons go on and off 15 times a second
+const TURBO_FRAMES: u8 = 2;
+// frames of input delay in netplay, for the other player's input to arrive
+const NETPLAY_DELAY: usize = 2;
+// rollback guesses at late input, so it needs less
+const ROLLBACK_DELAY: usize = 1;
 
-#[macro_use]
-extern crate bitflags;
+struct AudioPlayer {
+    ring: SharedRingBuffer,
+}
+
+impl AudioCallback for AudioPlayer {
+    type Channel = f32;
 
-fn color(byte: u8) -> Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
+    fn callback(&mut self, out: &mut [f32]) {
+        self.ring.lock().unwrap().fill(out);
     }
 }
 
-fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
+// The game controllers plugged in, in the order they were, which is how
+// an InputMap numbers them. SDL reports the ones already there as plugged
+// in when it starts.
+struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<GameController>,
+}
+
+impl Gamepads {
+    fn new(sdl_context: &sdl2::Sdl) -> Self {
+        Gamepads {
+            subsystem: sdl_context.game_controller().unwrap(),
+            pads: Vec::new(),
         }
-        frame_idx += 3;
     }
-    update
-}
 
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => std::process::exit(0),
+    // passes keys, pad buttons and axes on to `map`, and opens and drops
+    // pads as they come and go
+    fn handle(&mut self, event: &Event, map: &mut InputMap) {
+        let pad = |pads: &[GameController], which: u32| {
+            pads.iter().position(|pad| pad.instance_id() == which)
+        };
+        match *event {
             Event::KeyDown {
-                keycode: Some(Keycode::W),
+                keycode: Some(keycode),
                 ..
-            }| Event::KeyDown{
-                keycode: Some(Keycode::Up),
-                ..}
-             => {
-                cpu.mem_write(0xff, 0x77);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
+            } => map.set_pressed(&Control::Key(keycode.name()), true),
+            Event::KeyUp {
+                keycode: Some(keycode),
                 ..
-            } | Event::KeyDown{
-                keycode: Some(Keycode::Down),
-                ..}=> {
-                cpu.mem_write(0xff, 0x73);
+            } => map.set_pressed(&Control::Key(keycode.name()), false),
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(pad) => self.pads.push(pad),
+                Err(err) => eprintln!("could not open gamepad {}: {}", which, err),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.pads.retain(|pad| pad.instance_id() != which);
+                map.release_pads();
             }
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } | Event::KeyDown{
-                keycode: Some(Keycode::Left),
-                ..}=> {
-                cpu.mem_write(0xff, 0x61);
+            Event::ControllerButtonDown { which, button, .. }
+            | Event::ControllerButtonUp { which, button, .. } => {
+                if let Some(pad) = pad(&self.pads, which) {
+                    let pressed = matches!(event, Event::ControllerButtonDown { .. });
+                    let button = button.string();
+                    map.set_pressed(&Control::PadButton { pad, button }, pressed);
+                }
             }
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
-            } | Event::KeyDown{
-                keycode: Some(Keycode::Right),
-                ..}=> {
-                cpu.mem_write(0xff, 0x64);
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                if let Some(pad) = pad(&self.pads, which) {
+                    map.set_axis(pad, &axis.string(), value);
+                }
             }
-            _ => { /* do nothing */ }
+            _ => {}
         }
     }
 }
 
-fn main() {
-    // init sdl2
-    let sdl_context = sdl2::init().unwrap();
+// the key and pad bindings in `path`, or the defaults written there for
+// editing when there's no such file yet
+fn load_input_map(path: &str) -> Result<InputMap, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => InputMap::parse(&text).map_err(|err| err.to_string()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let map = InputMap::default();
+            std::fs::write(path, map.to_text()).map_err(|err| err.to_string())?;
+            Ok(map)
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// the Power Pad's buttons 1-12 in its rows of 4
+const POWER_PAD_KEYS: [Keycode; 12] = [
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Q,
+    Keycode::W,
+    Keycode::E,
+    Keycode::R,
+    Keycode::Z,
+    Keycode::X,
+    Keycode::C,
+    Keycode::V,
+];
+
+// a dump the database knows has its header put right
+fn load_rom(path: &str, database: &RomDatabase) -> Result<ROM, String> {
+    let bytes =
+        archive::read_rom(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+    let mut rom =
+        ROM::from_bytes(&bytes).map_err(|err| format!("could not load {}: {}", path, err))?;
+    if let Some(game) = database.lookup(&rom) {
+        let fixed = game.apply(&mut rom);
+        println!(
+            "{}{}",
+            game.title,
+            if fixed { " (header corrected)" } else { "" }
+        );
+    }
+    Ok(rom)
+}
+
+// battery saves live next to the ROM as <rom>.sav, or in the config's
+// saves directory
+fn load_nes(
+    path: &str,
+    sample_rate: u32,
+    database: &RomDatabase,
+    config: &Config,
+) -> Result<Nes, String> {
+    start_nes(path, load_rom(path, database)?, sample_rate, config)
+}
+
+fn start_nes(path: &str, rom: ROM, sample_rate: u32, config: &Config) -> Result<Nes, String> {
+    let mut nes = Nes::from_rom(rom, sample_rate)
+        .map_err(|err| format!("could not load {}: {}", path, err))?;
+    let save = config.save_file(Path::new(path));
+    nes.bus_mut()
+        .load_sram(&save)
+        .map_err(|err| format!("could not read {}: {}", save.display(), err))?;
+    Ok(nes)
+}
+
+// screenshots and recordings go in the working directory, or the config's
+// captures directory, as <rom>-<unix time>.png or .wav
+fn capture_file(rom_path: &str, extension: &str, config: &Config) -> PathBuf {
+    let rom_name = Path::new(rom_path).file_stem().unwrap_or_default();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    config.capture_file(&format!(
+        "{}-{}.{}",
+        rom_name.to_string_lossy(),
+        time,
+        extension
+    ))
+}
+
+fn save_capture(file: &Path, bytes: &[u8]) {
+    match std::fs::write(file, bytes) {
+        Ok(()) => println!("saved {}", file.display()),
+        Err(err) => eprintln!("could not write {}: {}", file.display(), err),
+    }
+}
+
+fn save_audio_recording(rom_path: &str, nes: &mut Nes, config: &Config) {
+    if let Some(recorder) = nes.stop_audio_recording() {
+        save_capture(&capture_file(rom_path, "wav", config), &recorder.to_wav());
+    }
+}
+
+// None if the file isn't an NSF, so it gets loaded as a ROM
+fn load_nsf(path: &str) -> Result<Option<Nsf>, String> {
+    let bytes =
+        archive::read_rom(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+    if !Nsf::is_nsf(&bytes) {
+        return Ok(None);
+    }
+    Nsf::from_bytes(&bytes)
+        .map(Some)
+        .map_err(|err| format!("could not load {}: {}", path, err))
+}
+
+fn nsf_title(player: &NsfPlayer) -> String {
+    let nsf = player.nsf();
+    let mut title = format!(
+        "{} - {} ({}/{})",
+        nsf.title,
+        nsf.artist,
+        player.track() + 1,
+        nsf.songs
+    );
+    if let Some(name) = nsf.track_name(player.track()) {
+        title.push_str(": ");
+        title.push_str(name);
+    }
+    title
+}
+
+// NSF files play as music with nothing to see; left and right pick the
+// track, shown in the window title
+fn play_nsf(
+    nsf: Nsf,
+    sdl_context: &sdl2::Sdl,
+    audio_device: &AudioDevice<AudioPlayer>,
+    ring: &SharedRingBuffer,
+    sample_rate: u32,
+) {
+    if nsf.expansion != 0 {
+        eprintln!("expansion sound chips are not emulated, parts of the tune will be missing");
+    }
+    let mut player = NsfPlayer::new(nsf, sample_rate);
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
-        .window("Snake game", (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
+        .window(
+            &nsf_title(&player),
+            Frame::WIDTH as u32 * SCALE,
+            Frame::HEIGHT as u32 * SCALE,
+        )
         .position_centered()
         .build()
         .unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut clock = Clock::new(player.nes().frame_rate());
+    audio_device.resume();
+
+    loop {
+        for _ in 0..clock.tick() {
+            let samples = player.run_frame();
+            if !clock.audio_muted() {
+                let mut ring = ring.lock().unwrap();
+                for sample in samples {
+                    ring.push(sample);
+                }
+            }
+        }
+        canvas.clear();
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::Left | Keycode::Right)),
+                    ..
+                } => {
+                    if keycode == Keycode::Right {
+                        player.next_track();
+                    } else {
+                        player.previous_track();
+                    }
+                    canvas.window_mut().set_title(&nsf_title(&player)).unwrap();
+                }
+                _ => {}
+            }
+        }
+        clock.wait();
+    }
+}
 
+// --threaded runs the console on a thread of its own, see threaded.rs;
+// this loop only draws the newest frame at the display's refresh rate and
+// passes the keyboard on. P pauses, tab fast-forwards and F6 and F7 save
+// and load a state kept in memory
+fn play_threaded(
+    mut nes: ThreadedNes,
+    sdl_context: &sdl2::Sdl,
+    audio_device: &AudioDevice<AudioPlayer>,
+    ring: &SharedRingBuffer,
+    filter: VideoFilter,
+    scaler: Option<Scaler>,
+    mut input_map: InputMap,
+) {
+    if let Some(audio) = nes.take_audio() {
+        let ring = ring.clone();
+        std::thread::spawn(move || {
+            for samples in audio {
+                let mut ring = ring.lock().unwrap();
+                for sample in samples {
+                    ring.push(sample);
+                }
+            }
+        });
+    }
+    audio_device.resume();
+
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            "NES",
+            Frame::WIDTH as u32 * SCALE,
+            Frame::HEIGHT as u32 * SCALE,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
 
+    let mut filter = Filter::new(filter);
+    let (width, height) = match scaler {
+        Some(scaler) => scaler.output_size(filter.width(), filter.height()),
+        None => (filter.width(), filter.height()),
+    };
+    let mut scaled = Vec::new();
     let creator = canvas.texture_creator();
     let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32)
+        .create_texture_target(PixelFormatEnum::RGB24, width as u32, height as u32)
         .unwrap();
 
-    //load the game
-    let bytes: Vec<u8> = std::fs::read("snake.nes").unwrap();
-    let rom = ROM::new(&bytes).unwrap();
+    let mut gamepads = Gamepads::new(sdl_context);
+    let mut buttons = [Button::empty(); 2];
+    let mut paused = false;
+    let mut state = None;
+    while nes.is_running() {
+        if let Some(frame) = nes.latest_frame() {
+            let (filter_width, filter_height) = (filter.width(), filter.height());
+            let picture = filter.apply(&frame);
+            let picture = match scaler {
+                Some(scaler) => {
+                    scaler.scale(picture, filter_width, filter_height, &mut scaled);
+                    &scaled
+                }
+                None => picture,
+            };
+            texture.update(None, picture, width * 3).unwrap();
+        }
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
 
-    let bus = BUS::new(rom);
-    let mut cpu = CPU::new(bus);
-    cpu.reset();
+        let previous = buttons;
+        for event in event_pump.poll_iter() {
+            gamepads.handle(&event, &mut input_map);
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => nes.set_speed(FAST_FORWARD_SPEED),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => nes.set_speed(1.0),
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => {
+                    paused = !paused;
+                    if paused {
+                        nes.pause();
+                    } else {
+                        nes.unpause();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => match nes.save_state() {
+                    Ok(saved) => state = Some(saved),
+                    Err(err) => eprintln!("could not save the state: {}", err),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(saved) = &state {
+                        if let Err(err) = nes.load_state(saved.clone()) {
+                            eprintln!("could not load the state: {}", err);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        buttons = [input_map.buttons(1), input_map.buttons(2)];
+        for (player, &held) in buttons.iter().enumerate() {
+            if held != previous[player] {
+                nes.set_input(player + 1, held);
+            }
+        }
+    }
+    eprintln!("the emulation stopped");
+}
 
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::thread_rng();
+enum NetplaySession {
+    Lockstep(Netplay),
+    Rollback(Rollback),
+}
 
-    // run the game cycle
-    cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
+impl NetplaySession {
+    fn run_frame(&mut self, nes: &mut Nes, buttons: Button) -> Result<(), NetplayError> {
+        match self {
+            NetplaySession::Lockstep(netplay) => netplay.run_frame(nes, buttons)?,
+            NetplaySession::Rollback(rollback) => rollback.run_frame(nes, buttons)?,
+        };
+        Ok(())
+    }
+}
+
+// nes run, record and play open a window on a ROM, or play an NSF as music,
+// see play_nsf; disasm and test-rom work without one
+#[derive(Parser)]
+#[command(name = "nes", version, about = "A NES emulator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Plays a ROM, or an NSF as music
+    Run {
+        #[arg(value_name = "ROM")]
+        path: String,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Plays a ROM and writes the input to an FM2 movie on exit
+    Record {
+        #[arg(value_name = "ROM")]
+        path: String,
+        #[arg(value_name = "MOVIE")]
+        movie: String,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Plays an FM2 movie back, on the ROM it names unless another is given
+    Play {
+        #[arg(value_name = "MOVIE")]
+        movie: String,
+        #[arg(value_name = "ROM")]
+        path: Option<String>,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Disassembles a 16KB bank of a ROM's PRG
+    Disasm {
+        #[arg(value_name = "ROM")]
+        path: String,
+        #[arg(long, default_value_t = 0)]
+        bank: usize,
+        /// Where the bank is mapped, $C000 for the last bank and $8000 for
+        /// the rest by default
+        #[arg(long, value_parser = parse_address)]
+        origin: Option<u16>,
+    },
+    /// Runs blargg's test ROMs, a single one or every .nes under a
+    /// directory, and reports how each did
+    TestRom {
+        path: PathBuf,
+        /// How long each ROM gets to finish
+        #[arg(long, default_value_t = TEST_ROM_FRAMES)]
+        frames: usize,
+    },
+}
+
+#[derive(Args)]
+struct RunOptions {
+    /// Reloads the ROM whenever the file changes on disk, keeping the
+    /// breakpoints, watches, labels and cheats
+    #[arg(long)]
+    watch: bool,
+    /// A save state loaded after every --watch reload; F8 writes the
+    /// current state there
+    #[arg(long, value_name = "STATE", requires = "watch")]
+    bookmark: Option<String>,
+    /// Plugs a Zapper aimed with the mouse into port 2
+    #[arg(long, group = "port2")]
+    zapper: bool,
+    /// Plugs the Arkanoid paddle, steered with the mouse, into port 2
+    #[arg(long, group = "port2")]
+    arkanoid: bool,
+    /// Plugs the Power Pad, on the keys 1-4, Q-R and Z-V, into port 2
+    #[arg(long, group = "port2")]
+    power_pad: bool,
+    /// Runs as NTSC whatever the ROM header says
+    #[arg(long, group = "region")]
+    ntsc: bool,
+    /// Runs as PAL whatever the ROM header says
+    #[arg(long, group = "region")]
+    pal: bool,
+    /// Runs as a Dendy whatever the ROM header says
+    #[arg(long, group = "region")]
+    dendy: bool,
+    /// Loads the colours from a .pal file
+    #[arg(long, value_name = "PAL")]
+    palette: Option<PathBuf>,
+    /// Filters the picture: none, crt or ntsc
+    #[arg(long, value_parser = parse_filter)]
+    filter: Option<VideoFilter>,
+    /// Upscales the picture with nearest or scale2x before the GPU stretches it
+    #[arg(long, value_parser = parse_scaler)]
+    scaler: Option<Scaler>,
+    /// Runs a Rhai script every frame
+    #[cfg(feature = "scripting")]
+    #[arg(long, value_name = "RHAI")]
+    script: Option<String>,
+    /// Listens for GDB clients on a local port
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+    /// Waits for a second player to connect for netplay
+    #[arg(long, value_name = "PORT", conflicts_with = "connect")]
+    host: Option<u16>,
+    /// Connects to a host for netplay
+    #[arg(long, value_name = "HOST:PORT")]
+    connect: Option<String>,
+    /// Has netplay guess at late input and roll back, instead of lockstep
+    #[arg(long)]
+    rollback: bool,
+    /// Looks the ROM up in a database of known dumps, see romdb.rs
+    #[arg(long, value_name = "GAMES")]
+    romdb: Option<String>,
+    /// Trades accuracy for speed, see accuracy.rs
+    #[arg(long)]
+    fast: bool,
+    /// Writes a hash of the console's state per frame on exit, for diffing
+    /// against another run of the same movie, see audit.rs
+    #[arg(long, value_name = "HASHES")]
+    audit: Option<String>,
+    /// Runs the emulation on a thread of its own, without the movie,
+    /// scripting, debugging, netplay and reloading options
+    #[arg(long)]
+    threaded: bool,
+    /// Reads the keyboard and gamepad bindings and what's in each port from
+    /// a file, see input_map.rs, written with the defaults if it isn't there
+    #[arg(long, value_name = "BINDINGS")]
+    input: Option<String>,
+    /// Reads the settings from a TOML file, see config.rs, written with the
+    /// defaults if it isn't there; the options here go over what it says
+    #[arg(long, value_name = "TOML")]
+    config: Option<String>,
+}
+
+fn parse_filter(name: &str) -> Result<VideoFilter, String> {
+    VideoFilter::from_name(name).ok_or_else(|| format!("unknown filter {:?}", name))
+}
+
+fn parse_scaler(name: &str) -> Result<Scaler, String> {
+    Scaler::from_name(name, SCALE as usize).ok_or_else(|| format!("unknown scaler {:?}", name))
+}
+
+// $C000, 0xC000 or C000
+fn parse_address(address: &str) -> Result<u16, String> {
+    let hex = address
+        .strip_prefix('$')
+        .or_else(|| address.strip_prefix("0x"))
+        .unwrap_or(address);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("invalid address {:?}", address))
+}
 
-        cpu.mem_write(0xfe, rng.gen_range(1, 16));
+fn main() {
+    match Cli::parse().command {
+        Command::Run { path, options } => run(path, options, None, None),
+        Command::Record {
+            path,
+            movie,
+            options,
+        } => run(path, options, Some(movie), None),
+        Command::Play {
+            movie,
+            path,
+            options,
+        } => {
+            let path = path.unwrap_or_else(|| {
+                movie_rom(&movie).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                })
+            });
+            run(path, options, None, Some(movie))
+        }
+        Command::Disasm { path, bank, origin } => {
+            if let Err(err) = disasm(&path, bank, origin) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::TestRom { path, frames } => {
+            if !test_roms(&path, frames) {
+                std::process::exit(1);
+            }
+        }
+    }
+}
 
-        if read_screen_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
+// the ROM a movie was recorded on, which is named without its extension,
+// looked for next to the movie
+fn movie_rom(movie_path: &str) -> Result<String, String> {
+    let movie = std::fs::read_to_string(movie_path)
+        .map_err(|err| err.to_string())
+        .and_then(|fm2| Movie::from_fm2(&fm2).map_err(|err| err.to_string()))
+        .map_err(|err| format!("could not play {}: {}", movie_path, err))?;
+    if movie.rom_name.is_empty() {
+        return Err(format!(
+            "{} doesn't name its ROM, give one after it",
+            movie_path
+        ));
+    }
+    let dir = Path::new(movie_path).parent().unwrap_or(Path::new(""));
+    let rom = dir.join(&movie.rom_name).with_extension("nes");
+    Ok(rom.to_string_lossy().into_owned())
+}
 
-            canvas.copy(&texture, None, None).unwrap();
+fn disasm(path: &str, bank: usize, origin: Option<u16>) -> Result<(), String> {
+    let rom = archive::read_rom(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| ROM::from_bytes(&bytes).map_err(|err| err.to_string()))
+        .map_err(|err| format!("could not load {}: {}", path, err))?;
+    let banks = rom.prg_rom.len().div_ceil(PRG_BANK_SIZE);
+    if bank >= banks {
+        return Err(format!(
+            "{} has {} PRG banks, 0 to {}",
+            path,
+            banks,
+            banks - 1
+        ));
+    }
+    let origin = origin.unwrap_or(if bank == banks - 1 { 0xc000 } else { 0x8000 });
+    let end = (bank * PRG_BANK_SIZE + PRG_BANK_SIZE).min(rom.prg_rom.len());
+    for instruction in disassemble(&rom.prg_rom[bank * PRG_BANK_SIZE..end], origin) {
+        println!("{}", instruction);
+    }
+    Ok(())
+}
 
-            canvas.present();
+// whether every test ROM passed
+fn test_roms(path: &Path, frames: usize) -> bool {
+    let roms = if path.is_dir() {
+        compat::find_roms(path)
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut failures = 0;
+    for rom in &roms {
+        let name = rom.strip_prefix(path).unwrap_or(rom).display().to_string();
+        let name = if name.is_empty() {
+            rom.display().to_string()
+        } else {
+            name
+        };
+        let result = archive::read_rom(rom)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| run_test_rom(&bytes, frames).map_err(|err| err.to_string()));
+        match result {
+            Ok(TestResult::Passed(_)) => println!("{}: passed", name),
+            Ok(TestResult::Failed { code, text }) => {
+                println!("{}: failed with {}\n{}", name, code, text);
+                failures += 1;
+            }
+            Ok(TestResult::TimedOut(text)) => {
+                println!("{}: timed out\n{}", name, text);
+                failures += 1;
+            }
+            Err(err) => {
+                println!("{}: {}", name, err);
+                failures += 1;
+            }
         }
+    }
+    println!("{} of {} passed", roms.len() - failures, roms.len());
+    failures == 0
+}
 
-        if std::env::consts::OS != "windows" {
-            // works for unix based systems only
-            std::thread::sleep(Duration::new(0, 70_000));
-        }else {
-            // TODO: fix windows sleep
-            std::thread::sleep(Duration::new(0, 0));
+fn run(path: String, options: RunOptions, record: Option<String>, play: Option<String>) {
+    let RunOptions {
+        watch,
+        bookmark,
+        zapper,
+        arkanoid,
+        power_pad,
+        ntsc,
+        pal,
+        dendy,
+        palette: palette_path,
+        filter,
+        scaler,
+        #[cfg(feature = "scripting")]
+        script: script_path,
+        gdb: gdb_port,
+        host: host_port,
+        connect,
+        rollback,
+        romdb: romdb_path,
+        fast,
+        audit,
+        threaded,
+        input: input_path,
+        config: config_path,
+    } = options;
+    let region = if ntsc {
+        Some(Region::Ntsc)
+    } else if pal {
+        Some(Region::Pal)
+    } else if dendy {
+        Some(Region::Dendy)
+    } else {
+        None
+    };
+    let config = match &config_path {
+        Some(config_path) => Config::load_or_create(config_path).unwrap_or_else(|err| {
+            eprintln!("could not load {}: {}", config_path, err);
+            std::process::exit(1);
+        }),
+        None => Config::default(),
+    };
+    for dir in [&config.paths.saves, &config.paths.captures]
+        .into_iter()
+        .flatten()
+    {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            eprintln!("could not create {}: {}", dir.display(), err);
+            std::process::exit(1);
+        }
+    }
+    // from_toml checked the names, so these can't fail
+    let filter = filter.unwrap_or_else(|| config.filter().unwrap());
+    let scaler = scaler.or_else(|| config.scaler(SCALE as usize).unwrap());
+    let region = region.or_else(|| config.region().unwrap());
+    let accuracy = if fast {
+        AccuracyConfig::FAST
+    } else {
+        config.accuracy().unwrap()
+    };
+    let palette_path = palette_path.or(config.video.palette.clone());
+    let database = match &romdb_path {
+        Some(romdb_path) => {
+            let mut database = RomDatabase::new();
+            std::fs::read_to_string(romdb_path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| database.load(&text).map_err(|err| err.to_string()))
+                .unwrap_or_else(|err| {
+                    eprintln!("could not load {}: {}", romdb_path, err);
+                    std::process::exit(1);
+                });
+            database
+        }
+        None => RomDatabase::new(),
+    };
+    let palette = palette_path.map(|palette_path| {
+        std::fs::read(&palette_path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| Palette::from_pal_file(&bytes).map_err(|err| err.to_string()))
+            .unwrap_or_else(|err| {
+                eprintln!("could not load {}: {}", palette_path.display(), err);
+                std::process::exit(1);
+            })
+    });
+    let mut gdb = gdb_port.map(|port| match GdbStub::bind(("127.0.0.1", port)) {
+        Ok(gdb) => {
+            println!("GDB stub listening on 127.0.0.1:{}", port);
+            gdb
+        }
+        Err(err) => {
+            eprintln!("could not listen on port {}: {}", port, err);
+            std::process::exit(1);
+        }
+    });
+    let mut input_map = match &input_path {
+        Some(input_path) => load_input_map(input_path).unwrap_or_else(|err| {
+            eprintln!("could not load {}: {}", input_path, err);
+            std::process::exit(1);
+        }),
+        None => config.input_map().unwrap(),
+    };
+    let ports = [input_map.device(1), input_map.device(2)];
+    let mut watcher = if watch {
+        Some(RomWatcher::new(&path))
+    } else {
+        None
+    };
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let ring = Arc::new(Mutex::new(RingBuffer::new(
+        config.audio_buffer_samples(SAMPLE_RATE as u32),
+    )));
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let audio_device = audio_subsystem
+        .open_playback(None, &desired, |_| AudioPlayer { ring: ring.clone() })
+        .unwrap();
+    let sample_rate = audio_device.spec().freq as u32;
+
+    match load_nsf(&path) {
+        Ok(Some(nsf)) => {
+            play_nsf(nsf, &sdl_context, &audio_device, &ring, sample_rate);
+            return;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if threaded {
+        let rom = load_rom(&path, &database).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        let nes = ThreadedNes::spawn(move || {
+            let mut nes = start_nes(&path, rom, sample_rate, &config)?;
+            for (port, device) in ports.iter().enumerate() {
+                nes.set_peripheral(port + 1, device.peripheral());
+            }
+            if let Some(region) = region {
+                nes.set_region(region);
+            }
+            if let Some(palette) = palette {
+                nes.set_palette(palette);
+            }
+            nes.set_accuracy(accuracy);
+            Ok::<Nes, String>(nes)
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        play_threaded(
+            nes,
+            &sdl_context,
+            &audio_device,
+            &ring,
+            filter,
+            scaler,
+            input_map,
+        );
+        return;
+    }
+
+    let load = |path: &str| {
+        let mut nes = load_nes(path, sample_rate, &database, &config)?;
+        for (port, device) in ports.iter().enumerate() {
+            if *device != Device::Joypad {
+                nes.set_peripheral(port + 1, device.peripheral());
             }
         }
-    );
+        if zapper {
+            nes.set_peripheral(2, Peripheral::Zapper(Zapper::new()));
+        } else if arkanoid {
+            nes.set_peripheral(2, Peripheral::Arkanoid(Arkanoid::new()));
+        } else if power_pad {
+            nes.set_peripheral(2, Peripheral::PowerPad(PowerPad::new()));
+        }
+        if let Some(region) = region {
+            nes.set_region(region);
+        }
+        if let Some(palette) = &palette {
+            nes.set_palette(palette.clone());
+        }
+        nes.set_accuracy(accuracy);
+        nes.enable_rewind(REWIND_INTERVAL, REWIND_BUDGET);
+        if gdb_port.is_some() {
+            nes.enable_reverse_step(STEP_HISTORY_BUDGET);
+        }
+        Ok::<Nes, String>(nes)
+    };
+    let mut nes = load(&path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if let Some(movie) = &play {
+        let played = std::fs::read_to_string(movie)
+            .map_err(|err| err.to_string())
+            .and_then(|fm2| Movie::from_fm2(&fm2).map_err(|err| err.to_string()))
+            .and_then(|movie| nes.play_movie(movie).map_err(|err| err.to_string()));
+        if let Err(err) = played {
+            eprintln!("could not play {}: {}", movie, err);
+            std::process::exit(1);
+        }
+    } else if record.is_some() {
+        nes.record_movie();
+    }
+    if audit.is_some() {
+        nes.enable_audit();
+    }
+    #[cfg(feature = "scripting")]
+    let mut script = script_path.map(|script_path| {
+        std::fs::read_to_string(&script_path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| Script::new(&source).map_err(|err| err.to_string()))
+            .unwrap_or_else(|err| {
+                eprintln!("could not run {}: {}", script_path, err);
+                std::process::exit(1);
+            })
+    });
+    // netplay has both consoles run the same frames, so there's no going
+    // back on one of them
+    let netplay = match (host_port, &connect) {
+        (Some(port), _) => {
+            println!("waiting for the other player on port {}", port);
+            let addr = ("0.0.0.0", port);
+            Some(if rollback {
+                Rollback::host(addr, &nes, ROLLBACK_DELAY).map(NetplaySession::Rollback)
+            } else {
+                Netplay::host(addr, &nes, NETPLAY_DELAY).map(NetplaySession::Lockstep)
+            })
+        }
+        (None, Some(addr)) => Some(if rollback {
+            Rollback::connect(addr.as_str(), &nes, ROLLBACK_DELAY).map(NetplaySession::Rollback)
+        } else {
+            Netplay::connect(addr.as_str(), &nes, NETPLAY_DELAY).map(NetplaySession::Lockstep)
+        }),
+        (None, None) => None,
+    };
+    let mut netplay = netplay.map(|netplay| {
+        nes.disable_rewind();
+        netplay.unwrap_or_else(|err| {
+            eprintln!("could not start netplay: {}", err);
+            std::process::exit(1);
+        })
+    });
+    audio_device.resume();
+
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            "NES",
+            Frame::WIDTH as u32 * SCALE,
+            Frame::HEIGHT as u32 * SCALE,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let mut canvas = window.into_canvas().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(SCALE as f32, SCALE as f32).unwrap();
+
+    // filters and scalers make pictures of other sizes, stretched to fit
+    // the window
+    let mut filter = Filter::new(filter);
+    let (width, height) = match scaler {
+        Some(scaler) => scaler.output_size(filter.width(), filter.height()),
+        None => (filter.width(), filter.height()),
+    };
+    let mut scaled = Vec::new();
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .unwrap();
+
+    let mut gamepads = Gamepads::new(&sdl_context);
+    let mut buttons = Button::empty();
+    let mut aim = None;
+    let mut trigger = false;
+    // where the mouse last was across the screen, for the paddle
+    let mut mouse_x = 0;
+    let mut pad_buttons = 0u16;
+    let mut rewinding = false;
+    let mut fast_forward = false;
+    // minus and equals halve and double the speed, P pauses
+    let mut speed = 1.0;
+    let mut clock = Clock::new(nes.frame_rate());
+    // holding M speaks into the second controller's microphone, T turns
+    // turbo on and off for A and B
+    let mut microphone = false;
+    let mut turbo = false;
+    // F12 saves the picture as shown, shift+F12 the frame without filters;
+    // F8 saves the --bookmark; F9 starts and stops recording the sound;
+    // F1-F5 mute and unmute the sound channels
+    let channel_keys = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+    ];
+    let mut screenshot = None;
+
+    // run the game cycle
+    loop {
+        // a broken build keeps the old ROM running until the next change
+        if watcher.as_mut().is_some_and(|watcher| watcher.poll()) {
+            // the new console reads the battery save as it's loaded, so this
+            // one's has to be on disk first
+            if let Err(err) = nes.bus_mut().flush_sram() {
+                eprintln!("could not write the battery save: {}", err);
+            }
+            match load(&path) {
+                Ok(mut reloaded) => {
+                    save_audio_recording(&path, &mut nes, &config);
+                    reloaded.keep_debugging(&mut nes);
+                    if let Some(bookmark) = &bookmark {
+                        let loaded = std::fs::read(bookmark)
+                            .map_err(|err| err.to_string())
+                            .and_then(|state| {
+                                reloaded.load_state(&state).map_err(|err| err.to_string())
+                            });
+                        if let Err(err) = loaded {
+                            eprintln!("could not load {}: {}", bookmark, err);
+                        }
+                    }
+                    nes = reloaded
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+
+        // only the last of the frames run when fast-forwarding is shown
+        clock.set_speed(if fast_forward {
+            FAST_FORWARD_SPEED
+        } else {
+            speed
+        });
+        // while a debugger has the game stopped only the picture and the
+        // input carry on
+        let mut running = gdb.as_mut().is_none_or(|gdb| gdb.poll(&mut nes));
+        for _ in 0..clock.tick() {
+            if !running {
+                break;
+            }
+            // a frame only runs once the other player's input is in; a
+            // broken connection leaves the game to the local player
+            if let Some(session) = &mut netplay {
+                if let Err(err) = session.run_frame(&mut nes, buttons) {
+                    eprintln!("{}", err);
+                    netplay = None;
+                }
+                continue;
+            }
+            // a rewound state has the picture from before it was saved
+            if rewinding {
+                nes.rewind(REWIND_INTERVAL);
+            }
+            // a failing script is stopped, the game carries on without it
+            #[cfg(feature = "scripting")]
+            let reason = match script.as_mut().map(|script| script.run_frame(&mut nes)) {
+                Some(Ok(reason)) => reason,
+                Some(Err(err)) => {
+                    eprintln!("{}", err);
+                    script = None;
+                    StopReason::FrameComplete
+                }
+                None => nes.run_frame(),
+            };
+            #[cfg(not(feature = "scripting"))]
+            let reason = nes.run_frame();
+            if let Some(gdb) = &mut gdb {
+                running = gdb.report(reason);
+            }
+        }
+        let (filter_width, filter_height) = (filter.width(), filter.height());
+        let picture = filter.apply(nes.frame_buffer());
+        let picture = match scaler {
+            Some(scaler) => {
+                scaler.scale(picture, filter_width, filter_height, &mut scaled);
+                &scaled
+            }
+            None => picture,
+        };
+        texture.update(None, picture, width * 3).unwrap();
+        if let Some(raw) = screenshot.take() {
+            let image = if raw {
+                nes.screenshot()
+            } else {
+                Image {
+                    width,
+                    height,
+                    data: picture.to_vec(),
+                }
+            };
+            save_capture(&capture_file(&path, "png", &config), &image.to_png());
+        }
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        let samples = nes.audio_samples();
+        if !clock.audio_muted() {
+            let mut ring = ring.lock().unwrap();
+            for sample in samples {
+                ring.push(sample);
+            }
+        }
+
+        for event in event_pump.poll_iter() {
+            gamepads.handle(&event, &mut input_map);
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    if let (Some(file), Some(mut movie)) = (&record, nes.stop_movie()) {
+                        let rom_name = Path::new(&path).file_stem().unwrap_or_default();
+                        movie.rom_name = rom_name.to_string_lossy().into_owned();
+                        if let Err(err) = std::fs::write(file, movie.to_fm2()) {
+                            eprintln!("could not write {}: {}", file, err);
+                        }
+                    }
+                    if let (Some(file), Some(audit)) = (&audit, nes.disable_audit()) {
+                        if let Err(err) = std::fs::write(file, audit.to_text()) {
+                            eprintln!("could not write {}: {}", file, err);
+                        }
+                    }
+                    save_audio_recording(&path, &mut nes, &config);
+                    std::process::exit(0)
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                } => speed = (speed / 2.0).max(Clock::MIN_SPEED),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                } => speed = (speed * 2.0).min(Clock::MAX_SPEED),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => screenshot = Some(keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(bookmark) = &bookmark {
+                        match std::fs::write(bookmark, nes.save_state()) {
+                            Ok(()) => println!("saved {}", bookmark),
+                            Err(err) => eprintln!("could not write {}: {}", bookmark, err),
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => {
+                    if nes.is_recording_audio() {
+                        save_audio_recording(&path, &mut nes, &config);
+                    } else {
+                        nes.start_audio_recording();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } if channel_keys.contains(&keycode) => {
+                    let index = channel_keys.iter().position(|&key| key == keycode).unwrap();
+                    let channel = Channel::ALL[index];
+                    let enabled = nes.channel_state(channel).enabled;
+                    nes.set_channel_enabled(channel, !enabled);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    repeat: false,
+                    ..
+                } => {
+                    // the other console would see the buttons held down
+                    if netplay.is_some() {
+                        eprintln!("turbo is off in netplay");
+                        continue;
+                    }
+                    turbo = !turbo;
+                    let buttons = if turbo {
+                        Button::A | Button::B
+                    } else {
+                        Button::empty()
+                    };
+                    nes.set_turbo(1, buttons, TURBO_FRAMES, TURBO_FRAMES);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => microphone = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => microphone = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => {
+                    if nes.is_paused() {
+                        nes.unpause();
+                    } else {
+                        nes.pause();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(i) = POWER_PAD_KEYS.iter().position(|&key| key == keycode) {
+                        pad_buttons |= 1 << i;
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(i) = POWER_PAD_KEYS.iter().position(|&key| key == keycode) {
+                        pad_buttons &= !(1 << i);
+                    }
+                }
+                Event::MouseMotion { x, y, .. } => {
+                    let x = x / SCALE as i32;
+                    let y = y / SCALE as i32;
+                    mouse_x = x.clamp(0, Frame::WIDTH as i32 - 1) as u32;
+                    let on_screen = (0..Frame::WIDTH as i32).contains(&x)
+                        && (0..Frame::HEIGHT as i32).contains(&y);
+                    aim = if on_screen {
+                        Some((x as u8, y as u8))
+                    } else {
+                        None
+                    };
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => trigger = true,
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => trigger = false,
+                _ => { /* do nothing */ }
+            }
+        }
+        buttons = input_map.buttons(1);
+        // a movie's input replaces the keyboard's until it ends, netplay
+        // sets both controllers itself
+        if !nes.is_playing() && netplay.is_none() {
+            nes.set_input(1, buttons);
+            nes.set_input(2, input_map.buttons(2));
+            nes.set_zapper(2, aim, trigger);
+            nes.set_paddle(2, mouse_x, Frame::WIDTH as u32, trigger);
+            nes.set_power_pad(2, pad_buttons);
+            nes.set_microphone(microphone);
+        }
+
+        // hold the emulation to the region's frame rate
+        clock.set_frame_rate(nes.frame_rate());
+        clock.wait();
+    }
 }
@@ -0,0 +1,147 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec::Vec;
+
+// Mapper 7: AxROM
+// from: https://www.nesdev.org/wiki/AxROM
+//
+// Any write to $8000-$FFFF sets ...M .PPP: PPP the 32KB PRG bank, M which
+// of the two nametables is shown on all four screens. CHR is 8KB of
+// unbanked RAM. Like UxROM, NES 2.0 submapper 2 marks boards with bus
+// conflicts.
+const PRG_BANK_SIZE: usize = 0x8000;
+
+pub struct AxROM {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    chr: Chr,
+    register: u8,
+    bus_conflicts: bool,
+}
+
+impl AxROM {
+    pub fn new(rom: ROM) -> Self {
+        AxROM {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            register: 0,
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+
+    fn update_banks(&mut self) {
+        self.prg_banks.set(0, (self.register & 0b111) as usize);
+    }
+}
+
+impl Mapper for AxROM {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.register = if self.bus_conflicts {
+                data & self.cpu_peek(addr)
+            } else {
+                data
+            };
+            self.update_banks();
+        }
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0x10 == 0 {
+            Mirroring::ONE_SCREEN_LOWER
+        } else {
+            Mirroring::ONE_SCREEN_UPPER
+        }
+    }
+}
+
+// the bank window follows from the register
+impl Snapshot for AxROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+    use alloc::vec;
+
+    #[test]
+    fn test_banks_and_mirroring() {
+        let mut rom = test::test_rom();
+        rom.mapper = 7;
+        rom.prg_rom = (0..8u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = vec![];
+        let mut axrom = AxROM::new(rom);
+        assert_eq!(axrom.cpu_read(0xffff), 0);
+        assert_eq!(axrom.mirroring(), Mirroring::ONE_SCREEN_LOWER);
+
+        axrom.cpu_write(0x8000, 0x15);
+        assert_eq!(axrom.cpu_read(0x8000), 5);
+        assert_eq!(axrom.cpu_read(0xffff), 5);
+        assert_eq!(axrom.mirroring(), Mirroring::ONE_SCREEN_UPPER);
+
+        axrom.ppu_write(0x1234, 0x42);
+        assert_eq!(axrom.ppu_read(0x1234), 0x42);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = test::test_rom();
+        rom.mapper = 7;
+        rom.submapper = 2;
+        rom.prg_rom = (0..8u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = vec![];
+        let mapper = crate::mappers::create(rom).unwrap();
+        let mut mapper = mapper.borrow_mut();
+        // bank 0 is all zeroes, so nothing gets through
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+
+        mapper.set_bus_conflicts(false);
+        mapper.cpu_write(0x8000, 0x15);
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+    }
+}
@@ -0,0 +1,131 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec::Vec;
+
+// Mapper 3: CNROM
+// from: https://www.nesdev.org/wiki/INES_Mapper_003
+//
+// PRG is 16KB or 32KB like NROM. Any write to $8000-$FFFF selects the 8KB
+// CHR bank. As on UxROM the write goes to a latch while the ROM drives the
+// bus; NES 2.0 submapper 2 marks boards with bus conflicts.
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct CNROM {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    chr: Chr,
+    chr_banks: Banks,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+}
+
+impl CNROM {
+    pub fn new(rom: ROM) -> Self {
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        CNROM {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, 0x4000),
+            prg_rom: rom.prg_rom,
+            chr_banks: Banks::new(chr.len(), CHR_BANK_SIZE, CHR_BANK_SIZE),
+            chr,
+            mirroring: rom.screen_mirroring,
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+}
+
+impl Mapper for CNROM {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            let data = if self.bus_conflicts {
+                data & self.cpu_peek(addr)
+            } else {
+                data
+            };
+            self.chr_banks.set(0, data as usize);
+        }
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for CNROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr_banks.save_state(state);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.chr_banks.load_state(state)?;
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+    use alloc::vec;
+
+    fn cnrom(banks: usize) -> CNROM {
+        let mut rom = test::test_rom();
+        rom.mapper = 3;
+        rom.prg_rom = vec![0xff; 0x8000];
+        rom.prg_rom[0] = 0x01;
+        rom.chr_rom = (0..banks)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        CNROM::new(rom)
+    }
+
+    #[test]
+    fn test_chr_bank_switching() {
+        let mut cnrom = cnrom(4);
+        assert_eq!(cnrom.ppu_read(0x1fff), 0);
+
+        cnrom.cpu_write(0x8000, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 3);
+        assert_eq!(cnrom.ppu_read(0x1fff), 3);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut cnrom = cnrom(4);
+        cnrom.set_bus_conflicts(true);
+
+        // $8000 holds 1, so only bit 0 survives
+        cnrom.cpu_write(0x8000, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 1);
+        cnrom.cpu_write(0x8001, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 2);
+    }
+}
@@ -0,0 +1,542 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mapper 69: Sunsoft FME-7, and the 5B that adds sound to it
+// from: https://www.nesdev.org/wiki/Sunsoft_FME-7
+//
+// | Address     | Register                                            |
+// |-------------|-----------------------------------------------------|
+// | $8000-$9FFF | Command: which register the next parameter is for   |
+// | $A000-$BFFF | Parameter                                           |
+// | $C000-$DFFF | 5B sound register select                            |
+// | $E000-$FFFF | 5B sound register write                             |
+//
+// | Command | Parameter                                               |
+// |---------|---------------------------------------------------------|
+// | $0-$7   | 1KB CHR bank at $0000-$1C00                             |
+// | $8      | ERbb bbbb: $6000 bank, R for RAM, E enables it          |
+// | $9-$B   | 8KB PRG bank at $8000, $A000, $C000                     |
+// | $C      | Mirroring: vertical, horizontal, one-screen lower/upper |
+// | $D      | C... ...T: C counts, T raises IRQs; acknowledges one    |
+// | $E-$F   | IRQ counter low and high byte                           |
+//
+// The last 8KB bank is fixed at $E000. The IRQ counter counts down every
+// CPU cycle and raises the IRQ when it wraps from $0000 to $FFFF.
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+// a channel at full volume is about as loud as a 2A03 pulse at full volume
+// from: https://www.nesdev.org/wiki/Sunsoft_5B_audio
+const VOLUME: f32 = 0.15;
+// each of the 32 levels is 1.5dB under the one above, 10^(-1.5 / 20)
+const LEVEL_STEP: f32 = 0.841_395_1;
+
+// The 5B's sound: a YM2149F, a relative of the AY-3-8910, with three
+// square wave channels, a noise generator they can each mix in and an
+// envelope they can each take their volume from.
+//
+// | Register | Contents                                              |
+// |----------|-------------------------------------------------------|
+// | $0-$5    | Tone period of A, B and C, low byte then high nibble  |
+// | $6       | Noise period                                          |
+// | $7       | ..CB Acba: noise (upper) and tone (lower) disabled    |
+// | $8-$A    | ...E VVVV: volume of A, B and C, E for the envelope   |
+// | $B-$C    | Envelope period, low and high byte                    |
+// | $D       | Envelope shape: continue, attack, alternate, hold     |
+//
+// Tones and noise run off the CPU clock divided by 16, the envelope by 8,
+// so a tone's frequency is CPU / (32 * period). Volume is logarithmic, 3dB
+// a step, and the envelope has twice the steps at 1.5dB.
+struct Sunsoft5B {
+    select: u8,
+    registers: [u8; 16],
+    divider: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    // 17 bit LFSR
+    noise: u32,
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+    // amplitude of each of the 32 levels
+    levels: [f32; 32],
+}
+
+impl Sunsoft5B {
+    fn new() -> Self {
+        let mut levels = [0.0; 32];
+        let mut level = 1.0;
+        for amplitude in levels[1..].iter_mut().rev() {
+            *amplitude = level;
+            level *= LEVEL_STEP;
+        }
+        Sunsoft5B {
+            select: 0,
+            registers: [0; 16],
+            divider: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+            levels,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        let register = self.select as usize;
+        if register >= 0x0e {
+            return;
+        }
+        self.registers[register] = data;
+        if register == 0x0d {
+            self.envelope_attack = data & 0b0100 != 0;
+            self.envelope_step = 0;
+            self.envelope_counter = 0;
+            self.envelope_holding = false;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = u16::from_le_bytes([
+            self.registers[channel * 2],
+            self.registers[channel * 2 + 1] & 0x0f,
+        ]);
+        period.max(1)
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        let period = u16::from_le_bytes([self.registers[0x0b], self.registers[0x0c]]).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter < period {
+            return;
+        }
+        self.envelope_counter = 0;
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        // the end of a ramp
+        let shape = self.registers[0x0d];
+        let (continues, alternate, hold) = (
+            shape & 0b1000 != 0,
+            shape & 0b0010 != 0,
+            shape & 0b0001 != 0,
+        );
+        if !continues {
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if hold {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+        // held at the level the ramp ended on, or silence
+        if self.envelope_holding {
+            self.envelope_step = 31;
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider = (self.divider + 1) % 16;
+        if self.divider.is_multiple_of(8) {
+            self.clock_envelope();
+        }
+        if self.divider != 0 {
+            return;
+        }
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+        // the noise has an extra divider by 2
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[6] & 0x1f).max(1) * 2 {
+            self.noise_counter = 0;
+            let bit = (self.noise ^ (self.noise >> 3)) & 1;
+            self.noise = (self.noise >> 1) | (bit << 16);
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let mixer = self.registers[7];
+        let noise = self.noise & 1 != 0;
+        (0..3)
+            .map(|channel| {
+                let tone = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+                let noise = noise || mixer & (8 << channel) != 0;
+                if !(tone && noise) {
+                    return 0.0;
+                }
+                let volume = self.registers[8 + channel];
+                let level = if volume & 0x10 != 0 {
+                    self.envelope_level()
+                } else if volume & 0x0f == 0 {
+                    0
+                } else {
+                    (volume & 0x0f) * 2 + 1
+                };
+                self.levels[level as usize]
+            })
+            .sum::<f32>()
+            * VOLUME
+    }
+}
+
+impl Snapshot for Sunsoft5B {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.select);
+        state.write_bytes(&self.registers);
+        state.write_u8(self.divider);
+        for channel in 0..3 {
+            state.write_u16(self.tone_counters[channel]);
+            state.write_bool(self.tone_outputs[channel]);
+        }
+        state.write_u8(self.noise_counter);
+        state.write_u32(self.noise);
+        state.write_u16(self.envelope_counter);
+        state.write_u8(self.envelope_step);
+        state.write_bool(self.envelope_attack);
+        state.write_bool(self.envelope_holding);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.select = state.read_u8()?;
+        state.read_bytes_into(&mut self.registers)?;
+        self.divider = state.read_u8()?;
+        for channel in 0..3 {
+            self.tone_counters[channel] = state.read_u16()?;
+            self.tone_outputs[channel] = state.read_bool()?;
+        }
+        self.noise_counter = state.read_u8()?;
+        self.noise = state.read_u32()?;
+        self.envelope_counter = state.read_u16()?;
+        self.envelope_step = state.read_u8()?;
+        self.envelope_attack = state.read_bool()?;
+        self.envelope_holding = state.read_bool()?;
+        Ok(())
+    }
+}
+
+pub struct FME7 {
+    prg_rom: Vec<u8>,
+    // $6000-$FFFF, the first window only used when it holds ROM
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_banks: Banks,
+
+    command: u8,
+    chr_registers: [u8; 8],
+    prg_registers: [u8; 4],
+    mirroring: u8,
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5B,
+}
+
+impl FME7 {
+    pub fn new(rom: ROM) -> Self {
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        let mut fme7 = FME7 {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0xa000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(0x2000)],
+            chr_banks: Banks::new(chr.len(), 0x2000, CHR_BANK_SIZE),
+            chr,
+            command: 0,
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            prg_registers: [0, 0, 1, 2],
+            mirroring: 0,
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5B::new(),
+        };
+        fme7.update_banks();
+        fme7
+    }
+
+    fn update_banks(&mut self) {
+        for (window, &bank) in self.prg_registers.iter().enumerate() {
+            self.prg_banks.set(window, (bank & 0x3f) as usize);
+        }
+        self.prg_banks.set(4, self.prg_banks.bank_count() - 1);
+        for (window, &bank) in self.chr_registers.iter().enumerate() {
+            self.chr_banks.set(window, bank as usize);
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_registers[self.command as usize] = data,
+            0x8..=0xb => self.prg_registers[self.command as usize - 8] = data,
+            0xc => self.mirroring = data & 0b11,
+            0xd => {
+                self.irq_control = data;
+                self.irq_pending = false;
+            }
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | ((data as u16) << 8),
+        }
+        self.update_banks();
+    }
+
+    // $6000-$7FFF holds RAM rather than ROM
+    fn ram_selected(&self) -> bool {
+        self.prg_registers[0] & 0x40 != 0
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.prg_registers[0] & 0x80 != 0
+    }
+}
+
+impl Mapper for FME7 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_selected() && self.ram_enabled() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            // RAM switched off
+            0x6000..=0x7fff if self.ram_selected() => 0,
+            0x6000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x6000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_selected() && self.ram_enabled() => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0x9fff => self.command = data & 0x0f,
+            0xa000..=0xbfff => self.write_parameter(data),
+            0xc000..=0xdfff => self.audio.select = data & 0x0f,
+            0xe000..=0xffff => self.audio.write(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x6000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONE_SCREEN_LOWER,
+            _ => Mirroring::ONE_SCREEN_UPPER,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn clock(&mut self) {
+        if self.irq_control & 0x80 != 0 {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_control & 1 != 0 {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+// the bank windows follow from the registers
+impl Snapshot for FME7 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.command);
+        state.write_bytes(&self.chr_registers);
+        state.write_bytes(&self.prg_registers);
+        state.write_u8(self.mirroring);
+        state.write_u8(self.irq_control);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_pending);
+        self.audio.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.command = state.read_u8()?;
+        state.read_bytes_into(&mut self.chr_registers)?;
+        state.read_bytes_into(&mut self.prg_registers)?;
+        self.mirroring = state.read_u8()?;
+        self.irq_control = state.read_u8()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        self.audio.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    // 8KB PRG banks and 1KB CHR banks filled with their bank number
+    fn fme7() -> FME7 {
+        let mut rom = test::test_rom();
+        rom.mapper = 69;
+        rom.prg_rom = (0..16u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..32u8)
+            .flat_map(|bank| vec![bank; CHR_BANK_SIZE])
+            .collect();
+        FME7::new(rom)
+    }
+
+    fn command(fme7: &mut FME7, command: u8, parameter: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xa000, parameter);
+    }
+
+    fn sound(fme7: &mut FME7, register: u8, value: u8) {
+        fme7.cpu_write(0xc000, register);
+        fme7.cpu_write(0xe000, value);
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x9, 3);
+        command(&mut fme7, 0xb, 9);
+        command(&mut fme7, 0x5, 20);
+        command(&mut fme7, 0xc, 1);
+        assert_eq!(fme7.cpu_read(0x8000), 3);
+        assert_eq!(fme7.cpu_read(0xc000), 9);
+        assert_eq!(fme7.cpu_read(0xe000), 15);
+        assert_eq!(fme7.ppu_read(0x1400), 20);
+        assert_eq!(fme7.mirroring(), Mirroring::HORIZONTAL);
+
+        // ROM at $6000, then RAM, then RAM switched off
+        command(&mut fme7, 0x8, 7);
+        assert_eq!(fme7.cpu_read(0x6000), 7);
+        command(&mut fme7, 0x8, 0xc0);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 0x42);
+        command(&mut fme7, 0x8, 0x40);
+        assert_eq!(fme7.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0xe, 2);
+        command(&mut fme7, 0xf, 0);
+        command(&mut fme7, 0xd, 0x81);
+        // 2, 1, 0, then the wrap
+        for _ in 0..2 {
+            fme7.clock();
+        }
+        assert!(!fme7.irq_pending());
+        fme7.clock();
+        assert!(fme7.irq_pending());
+        command(&mut fme7, 0xd, 0x81);
+        assert!(!fme7.irq_pending());
+
+        // counting without raising the IRQ
+        command(&mut fme7, 0xd, 0x80);
+        for _ in 0..0x10000 {
+            fme7.clock();
+        }
+        assert!(!fme7.irq_pending());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut fme7 = fme7();
+        assert_eq!(fme7.audio_output(), 0.0);
+
+        // channel A alone, at full volume, with a period of 2
+        sound(&mut fme7, 0x0, 2);
+        sound(&mut fme7, 0x7, 0b11_1110);
+        sound(&mut fme7, 0x8, 0x0f);
+        let mut outputs = Vec::new();
+        for _ in 0..256 {
+            fme7.clock();
+            outputs.push(fme7.audio_output());
+        }
+        // high for 32 cycles, low for 32
+        let high = outputs.iter().filter(|&&output| output > 0.0).count();
+        assert_eq!(high, 128);
+        let peak = outputs.iter().cloned().fold(0.0, f32::max);
+        assert!((peak - VOLUME).abs() < 0.0001);
+
+        // each volume step is 3dB down
+        sound(&mut fme7, 0x7, 0b11_1111);
+        sound(&mut fme7, 0x8, 0x0e);
+        let quieter = fme7.audio_output();
+        assert!((20.0 * (quieter / VOLUME).log10() + 3.0).abs() < 0.01);
+
+        // a decaying envelope that stays down
+        sound(&mut fme7, 0xb, 1);
+        sound(&mut fme7, 0xd, 0b0000);
+        sound(&mut fme7, 0x8, 0x10);
+        let start = fme7.audio_output();
+        for _ in 0..8 * 40 {
+            fme7.clock();
+        }
+        assert!(start > 0.0);
+        assert_eq!(fme7.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec::Vec;
+
+// Mapper 66: GxROM, and mapper 11: Color Dreams
+// from: https://www.nesdev.org/wiki/GxROM
+// from: https://www.nesdev.org/wiki/Color_Dreams
+//
+// Any write to $8000-$FFFF picks a 32KB PRG bank and an 8KB CHR bank:
+//
+// | Board        | Register  | Contents                                 |
+// |--------------|-----------|------------------------------------------|
+// | GxROM        | ..PP ..CC | PRG bank PP, CHR bank CC                 |
+// | Color Dreams | CCCC ..PP | PRG bank PP, CHR bank CCCC               |
+//
+// Color Dreams' bits 2-3 drove the CIC defeat, which doesn't matter here.
+// Both boards have bus conflicts.
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct GxROM {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    chr: Chr,
+    chr_banks: Banks,
+    mirroring: Mirroring,
+    // mapper 11
+    color_dreams: bool,
+    register: u8,
+    bus_conflicts: bool,
+}
+
+impl GxROM {
+    pub fn new(rom: ROM) -> Self {
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        GxROM {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            chr_banks: Banks::new(chr.len(), CHR_BANK_SIZE, CHR_BANK_SIZE),
+            chr,
+            mirroring: rom.screen_mirroring,
+            color_dreams: rom.mapper == 11,
+            register: 0,
+            bus_conflicts: true,
+        }
+    }
+
+    fn update_banks(&mut self) {
+        let (prg, chr) = if self.color_dreams {
+            (self.register & 0b11, self.register >> 4)
+        } else {
+            ((self.register >> 4) & 0b11, self.register & 0b11)
+        };
+        self.prg_banks.set(0, prg as usize);
+        self.chr_banks.set(0, chr as usize);
+    }
+}
+
+impl Mapper for GxROM {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.register = if self.bus_conflicts {
+                data & self.cpu_peek(addr)
+            } else {
+                data
+            };
+            self.update_banks();
+        }
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+// the bank windows follow from the register
+impl Snapshot for GxROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+    use alloc::vec;
+
+    // PRG and CHR banks filled with their bank number
+    fn gxrom(mapper: u16) -> GxROM {
+        let mut rom = test::test_rom();
+        rom.mapper = mapper;
+        rom.prg_rom = (0..4u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..16u8)
+            .flat_map(|bank| vec![bank; CHR_BANK_SIZE])
+            .collect();
+        let mut gxrom = GxROM::new(rom);
+        gxrom.set_bus_conflicts(false);
+        gxrom
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let mut gxrom = gxrom(66);
+        gxrom.cpu_write(0x8000, 0x21);
+        assert_eq!(gxrom.cpu_read(0x8000), 2);
+        assert_eq!(gxrom.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    fn test_color_dreams() {
+        let mut color_dreams = gxrom(11);
+        color_dreams.cpu_write(0x8000, 0xa3);
+        assert_eq!(color_dreams.cpu_read(0xffff), 3);
+        assert_eq!(color_dreams.ppu_read(0x1fff), 10);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut gxrom = gxrom(66);
+        gxrom.set_bus_conflicts(true);
+        // bank 0 is all zeroes, so nothing gets through
+        gxrom.cpu_write(0x8000, 0x33);
+        assert_eq!(gxrom.cpu_read(0x8000), 0);
+        assert_eq!(gxrom.ppu_read(0x0000), 0);
+    }
+}
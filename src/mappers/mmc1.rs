@@ -0,0 +1,311 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mapper 1: Nintendo MMC1 (SxROM boards)
+// from: https://www.nesdev.org/wiki/MMC1
+//
+// Registers are loaded serially: each write to $8000-$FFFF shifts bit 0 into
+// a 5-bit shift register, and the fifth write copies it into the register
+// picked by bits 13-14 of that write's address. A write with bit 7 set
+// resets the shift register and locks the PRG mode to 3.
+//
+// | Address     | Register    | Contents                                        |
+// |-------------|-------------|-------------------------------------------------|
+// | $8000-$9FFF | Control     | CPPMM: CHR mode, PRG mode, mirroring            |
+// | $A000-$BFFF | CHR bank 0  | 4KB bank at $0000, or 8KB bank ignoring bit 0   |
+// | $C000-$DFFF | CHR bank 1  | 4KB bank at $1000, unused in 8KB mode           |
+// | $E000-$FFFF | PRG bank    | RPPPP: PRG RAM disable, 16KB PRG bank           |
+//
+// PRG modes: 0/1 switch 32KB at $8000, 2 fixes the first bank at $8000 and
+// switches $C000, 3 fixes the last bank at $C000 and switches $8000.
+// On 512KB boards (SUROM) bit 4 of the CHR bank registers picks which
+// 256KB half of the PRG ROM is in use.
+const SHIFT_RESET: u8 = 0b1_0000;
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+pub struct MMC1 {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_banks: Banks,
+
+    shift: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+}
+
+impl MMC1 {
+    pub fn new(rom: ROM) -> Self {
+        // boards without CHR ROM have 8KB of CHR RAM
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        let mut mmc1 = MMC1 {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(0x2000)],
+            chr_banks: Banks::new(chr.len(), 0x2000, CHR_BANK_SIZE),
+            chr,
+            shift: SHIFT_RESET,
+            control: 0x0c,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+        };
+        mmc1.update_banks();
+        mmc1
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9fff => self.control = value,
+            0xa000..=0xbfff => self.chr_bank0 = value,
+            0xc000..=0xdfff => self.chr_bank1 = value,
+            _ => self.prg_bank = value,
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        // SUROM: 256KB outer PRG bank from the CHR bank register
+        let outer = if self.prg_rom.len() > 0x40000 {
+            (self.chr_bank0 & 0b1_0000) as usize
+        } else {
+            0
+        };
+        let bank = outer | (self.prg_bank & 0b1111) as usize;
+        let last = outer | ((self.prg_banks.bank_count() - 1) & 0b1111);
+
+        match (self.control >> 2) & 0b11 {
+            0 | 1 => {
+                self.prg_banks.set(0, bank & !1);
+                self.prg_banks.set(1, bank | 1);
+            }
+            2 => {
+                self.prg_banks.set(0, outer);
+                self.prg_banks.set(1, bank);
+            }
+            _ => {
+                self.prg_banks.set(0, bank);
+                self.prg_banks.set(1, last);
+            }
+        }
+
+        if self.control & 0b1_0000 == 0 {
+            let bank = (self.chr_bank0 & 0b1_1110) as usize;
+            self.chr_banks.set(0, bank);
+            self.chr_banks.set(1, bank | 1);
+        } else {
+            self.chr_banks.set(0, self.chr_bank0 as usize);
+            self.chr_banks.set(1, self.chr_bank1 as usize);
+        }
+    }
+}
+
+impl Mapper for MMC1 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xffff => {
+                if data & 0b1000_0000 != 0 {
+                    self.shift = SHIFT_RESET;
+                    self.control |= 0x0c;
+                    self.update_banks();
+                    return;
+                }
+
+                // the reset bit reaches bit 0 on the fifth write
+                let full = self.shift & 1 != 0;
+                self.shift = (self.shift >> 1) | ((data & 1) << 4);
+                if full {
+                    let value = self.shift;
+                    self.shift = SHIFT_RESET;
+                    self.write_register(addr, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::ONE_SCREEN_LOWER,
+            1 => Mirroring::ONE_SCREEN_UPPER,
+            2 => Mirroring::VERTICAL,
+            _ => Mirroring::HORIZONTAL,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+// the bank windows follow from the registers
+impl Snapshot for MMC1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.shift);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank0);
+        state.write_u8(self.chr_bank1);
+        state.write_u8(self.prg_bank);
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank0 = state.read_u8()?;
+        self.chr_bank1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    // PRG banks filled with their bank number, CHR 4KB banks likewise
+    fn mmc1(prg_banks: usize, chr_banks: usize) -> MMC1 {
+        let mut rom = test::test_rom();
+        rom.mapper = 1;
+        rom.prg_rom = (0..prg_banks)
+            .flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..chr_banks)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        MMC1::new(rom)
+    }
+
+    fn write_serial(mmc1: &mut MMC1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mut mmc1 = mmc1(8, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_serial_writes() {
+        let mut mmc1 = mmc1(8, 2);
+        write_serial(&mut mmc1, 0xe000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+        assert_eq!(mmc1.cpu_read(0xffff), 7);
+
+        // a reset in the middle drops the partial value
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 0x80);
+        write_serial(&mut mmc1, 0xe000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 2);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc1 = mmc1(8, 2);
+        write_serial(&mut mmc1, 0xe000, 5);
+
+        // 32KB mode ignores the low bit
+        write_serial(&mut mmc1, 0x8000, 0b0_00_11);
+        assert_eq!(mmc1.cpu_read(0x8000), 4);
+        assert_eq!(mmc1.cpu_read(0xc000), 5);
+
+        // first bank fixed at $8000
+        write_serial(&mut mmc1, 0x8000, 0b0_10_11);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 5);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc1 = mmc1(2, 8);
+        write_serial(&mut mmc1, 0xa000, 5);
+        write_serial(&mut mmc1, 0xc000, 2);
+
+        // 8KB mode uses bank 0 with its low bit cleared
+        assert_eq!(mmc1.ppu_read(0x0000), 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 5);
+
+        write_serial(&mut mmc1, 0x8000, 0b1_11_11);
+        assert_eq!(mmc1.ppu_read(0x0000), 5);
+        assert_eq!(mmc1.ppu_read(0x1000), 2);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc1 = mmc1(2, 2);
+        for (value, mirroring) in [
+            (0, Mirroring::ONE_SCREEN_LOWER),
+            (1, Mirroring::ONE_SCREEN_UPPER),
+            (2, Mirroring::VERTICAL),
+            (3, Mirroring::HORIZONTAL),
+        ] {
+            write_serial(&mut mmc1, 0x8000, 0b0_11_00 | value);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_chr_ram_and_prg_ram() {
+        let mut mmc1 = mmc1(2, 0);
+        mmc1.ppu_write(0x1234, 0x42);
+        assert_eq!(mmc1.ppu_read(0x1234), 0x42);
+
+        mmc1.cpu_write(0x6000, 0x99);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x99);
+
+        // bit 4 of the PRG bank register disables PRG RAM
+        write_serial(&mut mmc1, 0xe000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+    }
+}
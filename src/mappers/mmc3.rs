@@ -0,0 +1,411 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mapper 4: Nintendo MMC3 (TxROM boards)
+// from: https://www.nesdev.org/wiki/MMC3
+//
+// | Address     | Even write                   | Odd write                  |
+// |-------------|------------------------------|----------------------------|
+// | $8000-$9FFF | Bank select: CP...RRR        | Bank data for R0-R7        |
+// | $A000-$BFFF | Mirroring (0 vert, 1 horiz)  | PRG RAM enable/protect     |
+// | $C000-$DFFF | IRQ latch                    | IRQ reload                 |
+// | $E000-$FFFF | IRQ disable and acknowledge  | IRQ enable                 |
+//
+// PRG is four 8KB windows. With P clear R6 sits at $8000 and the second to
+// last bank at $C000; with P set the two swap. R7 is always at $A000 and the
+// last bank always at $E000.
+//
+// CHR is eight 1KB windows: R0 and R1 select 2KB banks (low bit ignored) at
+// $0000/$0800, R2-R5 select 1KB banks at $1000-$1C00. With C set the two
+// halves of the pattern table swap.
+//
+// The IRQ counter is clocked by rising edges of PPU A12, which happen once
+// per scanline while rendering with backgrounds and sprites in different
+// pattern tables.
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+pub struct MMC3 {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_banks: Banks,
+
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    four_screen: bool,
+    prg_ram_enabled: bool,
+    prg_ram_protected: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+}
+
+impl MMC3 {
+    pub fn new(rom: ROM) -> Self {
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        let mut mmc3 = MMC3 {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(0x2000)],
+            chr_banks: Banks::new(chr.len(), 0x2000, CHR_BANK_SIZE),
+            chr,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            four_screen: rom.screen_mirroring == Mirroring::FOUR_SCREEN,
+            mirroring: rom.screen_mirroring,
+            prg_ram_enabled: true,
+            prg_ram_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+        };
+        mmc3.update_banks();
+        mmc3
+    }
+
+    fn update_banks(&mut self) {
+        // a single 8KB bank, which NES 2.0 sizes allow, fills every window
+        let last = self.prg_banks.bank_count() - 1;
+        let second_last = last.saturating_sub(1);
+        let r6 = self.registers[6] as usize;
+        if self.bank_select & 0b0100_0000 == 0 {
+            self.prg_banks.set(0, r6);
+            self.prg_banks.set(2, second_last);
+        } else {
+            self.prg_banks.set(0, second_last);
+            self.prg_banks.set(2, r6);
+        }
+        self.prg_banks.set(1, self.registers[7] as usize);
+        self.prg_banks.set(3, last);
+
+        // with C set the 2KB banks move to $1000
+        let flip = if self.bank_select & 0b1000_0000 == 0 {
+            0
+        } else {
+            4
+        };
+        let r0 = (self.registers[0] & 0xfe) as usize;
+        let r1 = (self.registers[1] & 0xfe) as usize;
+        self.chr_banks.set(flip, r0);
+        self.chr_banks.set(flip + 1, r0 | 1);
+        self.chr_banks.set(flip + 2, r1);
+        self.chr_banks.set(flip + 3, r1 | 1);
+        for i in 0..4 {
+            self.chr_banks
+                .set((4 - flip) + i, self.registers[2 + i] as usize);
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (0x8000..=0x9fff, true) => {
+                self.bank_select = data;
+                self.update_banks();
+            }
+            (0x8000..=0x9fff, false) => {
+                self.registers[(self.bank_select & 0b111) as usize] = data;
+                self.update_banks();
+            }
+            (0xa000..=0xbfff, true) => {
+                if !self.four_screen {
+                    self.mirroring = if data & 1 == 0 {
+                        Mirroring::VERTICAL
+                    } else {
+                        Mirroring::HORIZONTAL
+                    };
+                }
+            }
+            (0xa000..=0xbfff, false) => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_protected = data & 0b0100_0000 != 0;
+            }
+            (0xc000..=0xdfff, true) => self.irq_latch = data,
+            (0xc000..=0xdfff, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, false) => self.irq_enabled = true,
+        }
+    }
+}
+
+impl Mapper for MMC3 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled && !self.prg_ram_protected => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xffff => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 {
+            self.clock_irq_counter();
+        }
+        self.a12 = a12;
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+// the bank windows follow from the registers
+impl Snapshot for MMC3 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.bank_select);
+        for &register in &self.registers {
+            state.write_u8(register);
+        }
+        state.write_bool(self.mirroring == Mirroring::HORIZONTAL);
+        state.write_bool(self.prg_ram_enabled);
+        state.write_bool(self.prg_ram_protected);
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_bool(self.irq_reload);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.a12);
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = state.read_u8()?;
+        for register in self.registers.iter_mut() {
+            *register = state.read_u8()?;
+        }
+        let horizontal = state.read_bool()?;
+        if !self.four_screen {
+            self.mirroring = if horizontal {
+                Mirroring::HORIZONTAL
+            } else {
+                Mirroring::VERTICAL
+            };
+        }
+        self.prg_ram_enabled = state.read_bool()?;
+        self.prg_ram_protected = state.read_bool()?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_reload = state.read_bool()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.a12 = state.read_bool()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    // 8KB PRG banks and 1KB CHR banks filled with their bank number
+    fn mmc3(prg_banks: usize, chr_banks: usize) -> MMC3 {
+        let mut rom = test::test_rom();
+        rom.mapper = 4;
+        rom.prg_rom = (0..prg_banks)
+            .flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..chr_banks)
+            .flat_map(|bank| vec![bank as u8; CHR_BANK_SIZE])
+            .collect();
+        MMC3::new(rom)
+    }
+
+    fn set_register(mmc3: &mut MMC3, mode: u8, register: u8, value: u8) {
+        mmc3.cpu_write(0x8000, mode | register);
+        mmc3.cpu_write(0x8001, value);
+    }
+
+    fn scanline(mmc3: &mut MMC3) {
+        mmc3.ppu_address(0x0000);
+        mmc3.ppu_address(0x1000);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc3 = mmc3(16, 8);
+        set_register(&mut mmc3, 0, 6, 3);
+        set_register(&mut mmc3, 0, 7, 9);
+        assert_eq!(mmc3.cpu_read(0x8000), 3);
+        assert_eq!(mmc3.cpu_read(0xa000), 9);
+        assert_eq!(mmc3.cpu_read(0xc000), 14);
+        assert_eq!(mmc3.cpu_read(0xe000), 15);
+
+        mmc3.cpu_write(0x8000, 0b0100_0000);
+        assert_eq!(mmc3.cpu_read(0x8000), 14);
+        assert_eq!(mmc3.cpu_read(0xc000), 3);
+        assert_eq!(mmc3.cpu_read(0xe000), 15);
+
+        // a single bank is in every window
+        let mut single = self::mmc3(1, 8);
+        set_register(&mut single, 0, 6, 3);
+        single.cpu_write(0x8000, 0b0100_0000);
+        for addr in [0x8000, 0xa000, 0xc000, 0xe000] {
+            assert_eq!(single.cpu_read(addr), 0);
+        }
+    }
+
+    #[test]
+    fn test_save_state_restores_banks_and_irq() {
+        let mut saved = mmc3(16, 8);
+        set_register(&mut saved, 0b0100_0000, 6, 3);
+        saved.cpu_write(0xc000, 5);
+        saved.cpu_write(0xc001, 0);
+        saved.cpu_write(0x6000, 0x42);
+        let mut state = StateWriter::new();
+        state.section(b"CART", |state| saved.save_state(state));
+        let state = state.finish();
+
+        let mut loaded = mmc3(16, 8);
+        let reader = StateReader::new(&state).unwrap();
+        loaded
+            .load_state(&mut reader.section(b"CART").unwrap())
+            .unwrap();
+        assert_eq!(loaded.cpu_read(0xc000), 3);
+        assert_eq!(loaded.cpu_read(0x8000), 14);
+        assert_eq!(loaded.cpu_read(0x6000), 0x42);
+        assert_eq!(loaded.irq_latch, 5);
+        assert!(loaded.irq_reload);
+
+        // a board with less PRG RAM can't take the state
+        let mut other = mmc3(16, 8);
+        other.prg_ram = vec![0; 0x1000];
+        let reader = StateReader::new(&state).unwrap();
+        assert_eq!(
+            other.load_state(&mut reader.section(b"CART").unwrap()),
+            Err(StateError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc3 = mmc3(4, 32);
+        set_register(&mut mmc3, 0, 0, 9);
+        set_register(&mut mmc3, 0, 2, 20);
+        set_register(&mut mmc3, 0, 5, 31);
+        assert_eq!(mmc3.ppu_read(0x0000), 8);
+        assert_eq!(mmc3.ppu_read(0x0400), 9);
+        assert_eq!(mmc3.ppu_read(0x1000), 20);
+        assert_eq!(mmc3.ppu_read(0x1c00), 31);
+
+        mmc3.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mmc3.ppu_read(0x1000), 8);
+        assert_eq!(mmc3.ppu_read(0x1400), 9);
+        assert_eq!(mmc3.ppu_read(0x0000), 20);
+        assert_eq!(mmc3.ppu_read(0x0c00), 31);
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram_protect() {
+        let mut mmc3 = mmc3(4, 8);
+        mmc3.cpu_write(0xa000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::HORIZONTAL);
+        mmc3.cpu_write(0xa000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::VERTICAL);
+
+        mmc3.cpu_write(0x6000, 0x42);
+        mmc3.cpu_write(0xa001, 0b1100_0000);
+        mmc3.cpu_write(0x6000, 0x99);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = mmc3(4, 8);
+        mmc3.cpu_write(0xc000, 2);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        // reload to 2, then count down 1, 0
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq_pending());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq_pending());
+
+        // A12 staying high doesn't clock the counter
+        mmc3.cpu_write(0xe000, 0);
+        assert!(!mmc3.irq_pending());
+        mmc3.ppu_address(0x1008);
+        assert_eq!(mmc3.irq_counter, 0);
+
+        // disabled IRQs count but don't fire
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq_pending());
+    }
+}
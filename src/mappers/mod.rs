@@ -0,0 +1,299 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod fme7;
+pub mod gxrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod n163;
+pub mod nrom;
+pub mod uxrom;
+pub mod vrc;
+pub mod vrc4;
+pub mod vrc6;
+
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use crate::rom::{Mirroring, RomError, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use axrom::AxROM;
+use cnrom::CNROM;
+use fme7::FME7;
+use gxrom::GxROM;
+use mmc1::MMC1;
+use mmc3::MMC3;
+use n163::N163;
+use nrom::NROM;
+use uxrom::UxROM;
+use vrc4::VRC4;
+use vrc6::VRC6;
+
+// Cartridge hardware, as seen from both buses:
+// | Bus | Address range | Contents                                     |
+// |-----|---------------|----------------------------------------------|
+// | CPU | $4020-$5FFF   | Expansion area, mostly unused                |
+// | CPU | $6000-$7FFF   | PRG RAM, often battery backed                |
+// | CPU | $8000-$FFFF   | PRG ROM and bank switching registers         |
+// | PPU | $0000-$1FFF   | Pattern tables (CHR ROM or CHR RAM)          |
+// Nametable mirroring is wired on the board, so it comes from here too.
+// Save states cover the board's registers and RAM, not its ROM.
+pub trait Mapper: Snapshot {
+    // what a read would return, without the side effects some boards have
+    // on reads; debuggers look at memory through these
+    fn cpu_peek(&self, addr: u16) -> u8;
+    fn ppu_peek(&self, addr: u16) -> u8;
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr)
+    }
+    fn cpu_write(&mut self, addr: u16, data: u8);
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    // PRG RAM at $6000-$7FFF, empty on boards without any
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // called with every address the PPU puts on its bus, for boards that
+    // snoop it (MMC3 counts scanlines off A12)
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    // where in PRG ROM a CPU address in $8000-$FFFF is mapped to right now
+    fn prg_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    // called once per CPU cycle, for boards with timers of their own
+    fn clock(&mut self) {}
+
+    // the board's own sound channels mixed, on the scale of audio::mix;
+    // the console adds it to the APU's output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // Scales one of the board's sound channels in audio_output only, 0.0
+    // mutes it; like APU::set_channel_enabled the game can't tell. Boards
+    // without sound, or without that channel, ignore it.
+    fn set_audio_channel_gain(&mut self, _channel: usize, _gain: f32) {}
+
+    // Boards built both with and without bus conflicts start out as the
+    // header says; ROMs with old headers don't say, so frontends can turn
+    // them on or off. Other boards ignore it.
+    fn set_bus_conflicts(&mut self, _enabled: bool) {}
+
+    // true while the cartridge is pulling the CPU's IRQ line low
+    fn irq_pending(&self) -> bool {
+        false
+    }
+}
+
+// the cartridge is plugged into both the CPU and the PPU bus
+pub type MapperRef = Rc<RefCell<dyn Mapper>>;
+
+type Constructor = fn(ROM) -> MapperRef;
+
+const MAPPERS: &[(u16, Constructor)] = &[
+    (0, |rom| Rc::new(RefCell::new(NROM::new(rom)))),
+    (1, |rom| Rc::new(RefCell::new(MMC1::new(rom)))),
+    (2, |rom| Rc::new(RefCell::new(UxROM::new(rom)))),
+    (3, |rom| Rc::new(RefCell::new(CNROM::new(rom)))),
+    (4, |rom| Rc::new(RefCell::new(MMC3::new(rom)))),
+    (7, |rom| Rc::new(RefCell::new(AxROM::new(rom)))),
+    (11, |rom| Rc::new(RefCell::new(GxROM::new(rom)))),
+    (19, |rom| Rc::new(RefCell::new(N163::new(rom)))),
+    (21, |rom| Rc::new(RefCell::new(VRC4::new(rom)))),
+    (22, |rom| Rc::new(RefCell::new(VRC4::new(rom)))),
+    (23, |rom| Rc::new(RefCell::new(VRC4::new(rom)))),
+    (24, |rom| Rc::new(RefCell::new(VRC6::new(rom, false)))),
+    (25, |rom| Rc::new(RefCell::new(VRC4::new(rom)))),
+    (26, |rom| Rc::new(RefCell::new(VRC6::new(rom, true)))),
+    (66, |rom| Rc::new(RefCell::new(GxROM::new(rom)))),
+    (69, |rom| Rc::new(RefCell::new(FME7::new(rom)))),
+];
+
+pub fn create(rom: ROM) -> Result<MapperRef, RomError> {
+    match MAPPERS.iter().find(|(mapper, _)| *mapper == rom.mapper) {
+        Some((_, constructor)) => Ok(constructor(rom)),
+        None => Err(RomError::UnsupportedMapper(rom.mapper)),
+    }
+}
+
+// Maps an address range onto ROM or RAM through fixed size windows, each
+// pointing at one bank. Windows start out mapped to the first banks in
+// order, and addresses past the end of the memory wrap around, which also
+// covers boards that mirror a small ROM into a bigger window.
+pub struct Banks {
+    window_size: usize,
+    windows: Vec<usize>,
+    len: usize,
+}
+
+impl Banks {
+    pub fn new(len: usize, range: usize, window_size: usize) -> Self {
+        Banks {
+            window_size,
+            windows: (0..range / window_size)
+                .map(|bank| bank * window_size)
+                .collect(),
+            len,
+        }
+    }
+
+    pub fn bank_count(&self) -> usize {
+        (self.len / self.window_size).max(1)
+    }
+
+    pub fn set(&mut self, window: usize, bank: usize) {
+        self.windows[window] = (bank % self.bank_count()) * self.window_size;
+    }
+
+    // offset into the backing memory for an address relative to the range
+    pub fn translate(&self, addr: usize) -> usize {
+        let offset = self.windows[addr / self.window_size] + addr % self.window_size;
+        offset % self.len
+    }
+}
+
+// Pattern table memory: the CHR ROM, or CHR RAM when the cartridge has
+// none. Boards without CHR ROM get at least 8KB of RAM, more when an NES 2.0
+// header asks for it. Writes to CHR ROM are ignored.
+pub struct Chr {
+    data: Vec<u8>,
+    is_ram: bool,
+}
+
+impl Chr {
+    pub fn new(chr_rom: Vec<u8>, chr_ram_size: usize) -> Self {
+        if chr_rom.is_empty() {
+            Chr {
+                data: vec![0; chr_ram_size.max(0x2000)],
+                is_ram: true,
+            }
+        } else {
+            Chr {
+                data: chr_rom,
+                is_ram: false,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.is_ram
+    }
+
+    pub fn read(&self, index: usize) -> u8 {
+        self.data[index % self.data.len()]
+    }
+
+    pub fn write(&mut self, index: usize, data: u8) {
+        if self.is_ram {
+            let len = self.data.len();
+            self.data[index % len] = data;
+        }
+    }
+}
+
+impl Snapshot for Banks {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.windows.len() as u8);
+        for &window in &self.windows {
+            state.write_u32(window as u32);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if state.read_u8()? as usize != self.windows.len() {
+            return Err(StateError::Mismatch);
+        }
+        for window in self.windows.iter_mut() {
+            *window = state.read_u32()? as usize % self.len.max(1);
+        }
+        Ok(())
+    }
+}
+
+// only CHR RAM is saved, CHR ROM comes with the cartridge
+impl Snapshot for Chr {
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.is_ram {
+            state.write_bytes(&self.data);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.is_ram {
+            state.read_bytes_into(&mut self.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    #[test]
+    fn test_registry() {
+        assert!(create(test::test_rom()).is_ok());
+
+        let mut rom = test::test_rom();
+        rom.mapper = 0x0fff;
+        match create(rom) {
+            Err(err) => assert_eq!(err, RomError::UnsupportedMapper(0x0fff)),
+            Ok(_) => panic!("mapper 0xfff should not exist"),
+        }
+    }
+
+    #[test]
+    fn test_banks() {
+        let memory: Vec<u8> = (0..32u8).flat_map(|bank| vec![bank; 0x400]).collect();
+        let mut banks = Banks::new(memory.len(), 0x2000, 0x400);
+        assert_eq!(memory[banks.translate(0x0000)], 0);
+        assert_eq!(memory[banks.translate(0x1fff)], 7);
+
+        banks.set(0, 20);
+        banks.set(7, 31);
+        assert_eq!(memory[banks.translate(0x03ff)], 20);
+        assert_eq!(memory[banks.translate(0x1c00)], 31);
+
+        // bank numbers wrap at the size of the memory
+        banks.set(1, 33);
+        assert_eq!(memory[banks.translate(0x0400)], 1);
+    }
+
+    #[test]
+    fn test_chr_ram_without_chr_rom() {
+        let mut chr = Chr::new(vec![], 0);
+        assert!(chr.is_ram());
+        assert_eq!(chr.len(), 0x2000);
+        chr.write(0x1234, 0x42);
+        assert_eq!(chr.read(0x1234), 0x42);
+
+        let mut chr = Chr::new(vec![7; 0x2000], 0);
+        assert!(!chr.is_ram());
+        chr.write(0x1234, 0x42);
+        assert_eq!(chr.read(0x1234), 7);
+    }
+
+    #[test]
+    fn test_small_memory_is_mirrored() {
+        let banks = Banks::new(0x4000, 0x8000, 0x2000);
+        assert_eq!(banks.translate(0x0123), 0x0123);
+        assert_eq!(banks.translate(0x4123), 0x0123);
+    }
+}
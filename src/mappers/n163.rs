@@ -0,0 +1,552 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mapper 19: Namco 163, and the wavetable sound inside it
+// from: https://www.nesdev.org/wiki/INES_Mapper_019
+//
+// | Address     | Register                                            |
+// |-------------|-----------------------------------------------------|
+// | $4800-$4FFF | Sound RAM data, at the address set through $F800    |
+// | $5000-$57FF | IRQ counter low byte                                |
+// | $5800-$5FFF | EHHH HHHH: IRQ counter high bits, E enables it      |
+// | $8000-$BFFF | 1KB CHR banks R0-R7, one every $800                 |
+// | $C000-$DFFF | Nametables R8-R11, one every $800                   |
+// | $E000-$E7FF | .Sbb bbbb: 8KB PRG bank at $8000, S silences sound  |
+// | $E800-$EFFF | ..bb bbbb: 8KB PRG bank at $A000                    |
+// | $F000-$F7FF | ..bb bbbb: 8KB PRG bank at $C000                    |
+// | $F800-$FFFF | IAAA AAAA: sound RAM address, I auto-increments     |
+//
+// The last 8KB bank is fixed at $E000. $F800 also write protects PRG RAM:
+// it's only writable while the upper nibble is $4, and then bits 0-3 each
+// protect a 2KB page.
+//
+// The IRQ counter is 15 bits counting up every CPU cycle; it stops at
+// $7FFF and raises the IRQ there. Writing either half acknowledges it.
+//
+// Nametables $E0 and up pick one of the console's two nametables, and those
+// are turned into the closest mirroring. Nametables from CHR ROM, and the
+// console's nametables used as CHR, aren't supported: a game asking for
+// them gets CHR ROM banks instead.
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+// NES 2.0 submappers tell how loud the board mixes the 163: 2 not at all,
+// 4 about 4dB louder than the rest
+// from: https://www.nesdev.org/wiki/Namco_163_audio#Mixing
+const VOLUME: f32 = 0.0026;
+const LOUD_VOLUME: f32 = 0.0042;
+
+// one channel is updated every 15 CPU cycles
+const UPDATE_PERIOD: u8 = 15;
+
+// The 163's sound: up to eight channels playing 4-bit waveforms out of 128
+// bytes of RAM, which also holds their registers. Channel n's registers are
+// at $40 + 8n:
+//
+// | Offset | Contents                                              |
+// |--------|-------------------------------------------------------|
+// | 0      | Frequency low byte                                    |
+// | 1      | Phase low byte                                        |
+// | 2      | Frequency middle byte                                 |
+// | 3      | Phase middle byte                                     |
+// | 4      | LLLL LLFF: wave length 256 - 4L samples, frequency FF |
+// | 5      | Phase high byte                                       |
+// | 6      | Wave address, in samples                              |
+// | 7      | .CCC VVVV: volume; C the channels enabled - 1 at $7F  |
+//
+// Samples are nibbles, the low one first. The chip runs one channel at a
+// time, from channel 7 down, so the more channels are enabled the slower
+// each plays; the output is the average of the enabled channels.
+struct Namco163 {
+    ram: [u8; 0x80],
+    address: u8,
+    auto_increment: bool,
+    disabled: bool,
+    divider: u8,
+    // the channel updated next, counting down from 7
+    channel: u8,
+    outputs: [i16; 8],
+    volume: f32,
+    // set by the frontend, not the game, so not in save states
+    gains: [f32; 8],
+}
+
+impl Namco163 {
+    fn new(volume: f32) -> Self {
+        Namco163 {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            disabled: false,
+            divider: 0,
+            channel: 7,
+            outputs: [0; 8],
+            volume,
+            gains: [1.0; 8],
+        }
+    }
+
+    fn write_address(&mut self, data: u8) {
+        self.address = data & 0x7f;
+        self.auto_increment = data & 0x80 != 0;
+    }
+
+    fn peek(&self) -> u8 {
+        self.ram[self.address as usize]
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7f;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let data = self.peek();
+        self.step_address();
+        data
+    }
+
+    fn write(&mut self, data: u8) {
+        self.ram[self.address as usize] = data;
+        self.step_address();
+    }
+
+    fn channel_count(&self) -> u8 {
+        ((self.ram[0x7f] >> 4) & 0b111) + 1
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let base = 0x40 + channel as usize * 8;
+        let registers = &mut self.ram[base..base + 8];
+        let frequency = u32::from_le_bytes([registers[0], registers[2], registers[4] & 0b11, 0]);
+        let phase = u32::from_le_bytes([registers[1], registers[3], registers[5], 0]);
+        let length = (256 - (registers[4] & 0xfc) as u32) << 16;
+        let phase = (phase + frequency) % length;
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let sample_address = (registers[6] as u32 + (phase >> 16)) as u8;
+        let volume = (registers[7] & 0x0f) as i16;
+        let sample = (self.ram[sample_address as usize / 2] >> ((sample_address & 1) * 4)) & 0x0f;
+        self.outputs[channel as usize] = (sample as i16 - 8) * volume;
+    }
+
+    fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.divider += 1;
+        if self.divider < UPDATE_PERIOD {
+            return;
+        }
+        self.divider = 0;
+        let first = 8 - self.channel_count();
+        if self.channel < first {
+            self.channel = 7;
+        }
+        self.update_channel(self.channel);
+        self.channel = if self.channel == first {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    fn output(&self) -> f32 {
+        if self.disabled {
+            return 0.0;
+        }
+        let count = self.channel_count();
+        let first = 8 - count as usize;
+        let sum: f32 = self.outputs[first..]
+            .iter()
+            .zip(&self.gains[first..])
+            .map(|(&output, &gain)| output as f32 * gain)
+            .sum();
+        sum / count as f32 * self.volume
+    }
+}
+
+impl Snapshot for Namco163 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.address);
+        state.write_bool(self.auto_increment);
+        state.write_bool(self.disabled);
+        state.write_u8(self.divider);
+        state.write_u8(self.channel);
+        for output in self.outputs {
+            state.write_u16(output as u16);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.address = state.read_u8()? & 0x7f;
+        self.auto_increment = state.read_bool()?;
+        self.disabled = state.read_bool()?;
+        self.divider = state.read_u8()?;
+        self.channel = state.read_u8()? & 0b111;
+        for output in self.outputs.iter_mut() {
+            *output = state.read_u16()? as i16;
+        }
+        Ok(())
+    }
+}
+
+pub struct N163 {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_banks: Banks,
+
+    chr_registers: [u8; 8],
+    nametable_registers: [u8; 4],
+    prg_registers: [u8; 3],
+    write_protect: u8,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio: Namco163,
+}
+
+impl N163 {
+    pub fn new(rom: ROM) -> Self {
+        let volume = match rom.submapper {
+            2 => 0.0,
+            4 => LOUD_VOLUME,
+            _ => VOLUME,
+        };
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        let mut n163 = N163 {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(0x2000)],
+            chr_banks: Banks::new(chr.len(), 0x2000, CHR_BANK_SIZE),
+            chr,
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            nametable_registers: [0xe0, 0xe1, 0xe0, 0xe1],
+            prg_registers: [0, 1, 2],
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163::new(volume),
+        };
+        n163.update_banks();
+        n163
+    }
+
+    fn update_banks(&mut self) {
+        for (window, &bank) in self.prg_registers.iter().enumerate() {
+            self.prg_banks.set(window, (bank & 0x3f) as usize);
+        }
+        self.prg_banks.set(3, self.prg_banks.bank_count() - 1);
+        for (window, &bank) in self.chr_registers.iter().enumerate() {
+            self.chr_banks.set(window, bank as usize);
+        }
+        self.audio.disabled = self.prg_registers[0] & 0x40 != 0;
+    }
+
+    fn ram_writable(&self, addr: u16) -> bool {
+        let page = (addr - 0x6000) / 0x800;
+        self.write_protect & 0xf0 == 0x40 && self.write_protect & (1 << page) == 0
+    }
+}
+
+impl Mapper for N163 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4fff => self.audio.peek(),
+            0x5000..=0x57ff => self.irq_counter as u8,
+            0x5800..=0x5fff => (self.irq_counter >> 8) as u8 | ((self.irq_enabled as u8) << 7),
+            0x6000..=0x7fff => self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()],
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4fff => self.audio.read(),
+            _ => self.cpu_peek(addr),
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4fff => self.audio.write(data),
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | ((data as u16 & 0x7f) << 8);
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7fff if self.ram_writable(addr) => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xbfff => {
+                self.chr_registers[(addr as usize - 0x8000) / 0x800] = data;
+                self.update_banks();
+            }
+            0xc000..=0xdfff => self.nametable_registers[(addr as usize - 0xc000) / 0x800] = data,
+            0xe000..=0xf7ff => {
+                self.prg_registers[(addr as usize - 0xe000) / 0x800] = data;
+                self.update_banks();
+            }
+            0xf800..=0xffff => {
+                self.write_protect = data;
+                self.audio.write_address(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_registers.map(|bank| bank & 1) {
+            [0, 0, 1, 1] => Mirroring::HORIZONTAL,
+            [0, 0, 0, 0] => Mirroring::ONE_SCREEN_LOWER,
+            [1, 1, 1, 1] => Mirroring::ONE_SCREEN_UPPER,
+            _ => Mirroring::VERTICAL,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7fff {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    // channels 0-7, as numbered in sound RAM
+    fn set_audio_channel_gain(&mut self, channel: usize, gain: f32) {
+        if let Some(channel_gain) = self.audio.gains.get_mut(channel) {
+            *channel_gain = gain;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+// the bank windows follow from the registers
+impl Snapshot for N163 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.chr_registers);
+        state.write_bytes(&self.nametable_registers);
+        state.write_bytes(&self.prg_registers);
+        state.write_u8(self.write_protect);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        self.audio.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.chr_registers)?;
+        state.read_bytes_into(&mut self.nametable_registers)?;
+        state.read_bytes_into(&mut self.prg_registers)?;
+        self.write_protect = state.read_u8()?;
+        self.irq_counter = state.read_u16()? & 0x7fff;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.audio.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    // 8KB PRG banks and 1KB CHR banks filled with their bank number
+    fn n163() -> N163 {
+        let mut rom = test::test_rom();
+        rom.mapper = 19;
+        rom.prg_rom = (0..16u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..32u8)
+            .flat_map(|bank| vec![bank; CHR_BANK_SIZE])
+            .collect();
+        N163::new(rom)
+    }
+
+    // writes `data` to sound RAM from `address` on
+    fn sound(n163: &mut N163, address: u8, data: &[u8]) {
+        n163.cpu_write(0xf800, 0x80 | address);
+        for &byte in data {
+            n163.cpu_write(0x4800, byte);
+        }
+    }
+
+    #[test]
+    fn test_banks_and_mirroring() {
+        let mut n163 = n163();
+        n163.cpu_write(0xe000, 3);
+        n163.cpu_write(0xf000, 9);
+        n163.cpu_write(0x9800, 20);
+        assert_eq!(n163.cpu_read(0x8000), 3);
+        assert_eq!(n163.cpu_read(0xc000), 9);
+        assert_eq!(n163.cpu_read(0xe000), 15);
+        assert_eq!(n163.ppu_read(0x0c00), 20);
+        assert_eq!(n163.mirroring(), Mirroring::VERTICAL);
+
+        for (addr, bank) in [
+            (0xc000, 0xe0),
+            (0xc800, 0xe0),
+            (0xd000, 0xe1),
+            (0xd800, 0xe1),
+        ] {
+            n163.cpu_write(addr, bank);
+        }
+        assert_eq!(n163.mirroring(), Mirroring::HORIZONTAL);
+
+        // PRG RAM is write protected until $F800 unlocks it
+        n163.cpu_write(0x6000, 0x42);
+        assert_eq!(n163.cpu_read(0x6000), 0);
+        n163.cpu_write(0xf800, 0x40);
+        n163.cpu_write(0x6000, 0x42);
+        assert_eq!(n163.cpu_read(0x6000), 0x42);
+        n163.cpu_write(0xf800, 0x41);
+        n163.cpu_write(0x6000, 0x43);
+        assert_eq!(n163.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut n163 = n163();
+        n163.cpu_write(0x5000, 0xfd);
+        n163.cpu_write(0x5800, 0xff);
+        // $7FFE, then $7FFF
+        n163.clock();
+        assert!(!n163.irq_pending());
+        n163.clock();
+        assert!(n163.irq_pending());
+        assert_eq!(n163.cpu_read(0x5000), 0xff);
+        assert_eq!(n163.cpu_read(0x5800), 0xff);
+
+        // it stops there; writing the counter acknowledges it
+        n163.clock();
+        assert_eq!(n163.cpu_read(0x5000), 0xff);
+        n163.cpu_write(0x5000, 0);
+        assert!(!n163.irq_pending());
+
+        // disabled, it doesn't count
+        n163.cpu_write(0x5800, 0x7f);
+        for _ in 0..0x100 {
+            n163.clock();
+        }
+        assert!(!n163.irq_pending());
+    }
+
+    #[test]
+    fn test_sound_ram() {
+        let mut n163 = n163();
+        sound(&mut n163, 0x10, &[1, 2, 3]);
+        n163.cpu_write(0xf800, 0x90);
+        assert_eq!(n163.cpu_peek(0x4800), 1);
+        assert_eq!(n163.cpu_read(0x4800), 1);
+        assert_eq!(n163.cpu_read(0x4800), 2);
+        // without auto-increment the address stays put
+        n163.cpu_write(0xf800, 0x12);
+        assert_eq!(n163.cpu_read(0x4800), 3);
+        assert_eq!(n163.cpu_read(0x4800), 3);
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut n163 = n163();
+        assert_eq!(n163.audio_output(), 0.0);
+
+        // a 4 sample wave at $00: 15, 15, 0, 0
+        sound(&mut n163, 0x00, &[0xff, 0x00]);
+        // channel 7 alone at full volume
+        // with a frequency of $10000, one sample each update
+        sound(
+            &mut n163,
+            0x78,
+            &[0x00, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x00, 0x0f],
+        );
+
+        let mut outputs = Vec::new();
+        for _ in 0..4 * UPDATE_PERIOD as usize {
+            n163.clock();
+            if n163.audio.divider == 0 {
+                outputs.push(n163.audio_output());
+            }
+        }
+        let high = 7.0 * 15.0 * VOLUME;
+        let low = -8.0 * 15.0 * VOLUME;
+        assert_eq!(outputs, vec![high, low, low, high]);
+
+        // two channels take turns, and are averaged
+        sound(&mut n163, 0x7f, &[0x1f]);
+        for _ in 0..2 * UPDATE_PERIOD as usize {
+            n163.clock();
+        }
+        assert_eq!(
+            n163.audio_output(),
+            (n163.audio.outputs[7] as f32 / 2.0) * VOLUME
+        );
+
+        // the frontend can turn either down, or off
+        n163.set_audio_channel_gain(6, 0.0);
+        n163.set_audio_channel_gain(7, 0.5);
+        assert_eq!(
+            n163.audio_output(),
+            (n163.audio.outputs[7] as f32 * 0.5 / 2.0) * VOLUME
+        );
+        n163.set_audio_channel_gain(7, 0.0);
+        assert_eq!(n163.audio_output(), 0.0);
+        n163.set_audio_channel_gain(8, 0.0);
+
+        // and bit 6 of $E000 silences it
+        n163.cpu_write(0xe000, 0x40);
+        assert_eq!(n163.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,128 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mapper 0: 16KB or 32KB of PRG ROM and 8KB of CHR ROM (or RAM), no bank
+// switching.
+// A 16KB PRG ROM is mirrored into both halves of $8000-$FFFF.
+pub struct NROM {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+}
+
+impl NROM {
+    pub fn new(rom: ROM) -> Self {
+        NROM {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, 0x2000),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; rom.prg_ram_size + rom.prg_nvram_size],
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for NROM {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index]
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            if !self.prg_ram.is_empty() {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for NROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    #[test]
+    fn test_16kb_prg_rom_is_mirrored() {
+        let mut rom = test::test_rom();
+        rom.prg_rom = (0..0x4000).map(|i| (i >> 8) as u8).collect();
+        let mut nrom = NROM::new(rom);
+
+        assert_eq!(nrom.cpu_read(0x8100), 0x01);
+        assert_eq!(nrom.cpu_read(0xc100), 0x01);
+        assert_eq!(nrom.cpu_read(0xfff0), 0x3f);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut nrom = NROM::new(test::test_rom());
+        nrom.cpu_write(0x6005, 0x42);
+        assert_eq!(nrom.cpu_read(0x6005), 0x42);
+
+        // writes to ROM are ignored
+        let before = nrom.cpu_read(0x8000);
+        nrom.cpu_write(0x8000, before.wrapping_add(1));
+        assert_eq!(nrom.cpu_read(0x8000), before);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut rom = test::test_rom();
+        rom.chr_rom = vec![];
+        let mut nrom = NROM::new(rom);
+        nrom.ppu_write(0x1ff0, 0x42);
+        assert_eq!(nrom.ppu_read(0x1ff0), 0x42);
+
+        let mut nrom = NROM::new(test::test_rom());
+        let before = nrom.ppu_read(0x1ff0);
+        nrom.ppu_write(0x1ff0, before.wrapping_add(1));
+        assert_eq!(nrom.ppu_read(0x1ff0), before);
+    }
+}
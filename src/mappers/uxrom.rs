@@ -0,0 +1,155 @@
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec::Vec;
+
+// Mapper 2: UxROM
+// from: https://www.nesdev.org/wiki/UxROM
+//
+// Any write to $8000-$FFFF selects the 16KB PRG bank at $8000; the last
+// bank is fixed at $C000. CHR is 8KB of unbanked RAM.
+//
+// The write goes to a latch while the ROM also drives the data bus, so on
+// boards with bus conflicts the latch gets the value ANDed with the ROM
+// byte at that address. NES 2.0 submapper 2 marks those boards.
+const PRG_BANK_SIZE: usize = 0x4000;
+
+pub struct UxROM {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+}
+
+impl UxROM {
+    pub fn new(rom: ROM) -> Self {
+        let mut prg_banks = Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE);
+        prg_banks.set(1, prg_banks.bank_count() - 1);
+        UxROM {
+            prg_rom: rom.prg_rom,
+            prg_banks,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+            bus_conflicts: rom.submapper == 2,
+        }
+    }
+}
+
+impl Mapper for UxROM {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            let data = if self.bus_conflicts {
+                data & self.cpu_peek(addr)
+            } else {
+                data
+            };
+            self.prg_banks.set(0, data as usize);
+        }
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for UxROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.prg_banks.save_state(state);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_banks.load_state(state)?;
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+    use alloc::vec;
+
+    fn uxrom(banks: usize) -> UxROM {
+        let mut rom = test::test_rom();
+        rom.mapper = 2;
+        rom.prg_rom = (0..banks)
+            .flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = vec![];
+        UxROM::new(rom)
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut uxrom = uxrom(8);
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+        assert_eq!(uxrom.cpu_read(0xc000), 7);
+
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), 5);
+        assert_eq!(uxrom.cpu_read(0xffff), 7);
+
+        uxrom.ppu_write(0x0123, 0x42);
+        assert_eq!(uxrom.ppu_read(0x0123), 0x42);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut uxrom = uxrom(8);
+        uxrom.set_bus_conflicts(true);
+
+        // the fixed bank is all 7s, so 0b101 & 0b111 goes through
+        uxrom.cpu_write(0xc000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), 5);
+
+        // the switched bank holds 5s, which masks off bit 1
+        uxrom.cpu_write(0x8000, 6);
+        assert_eq!(uxrom.cpu_read(0x8000), 4);
+    }
+
+    #[test]
+    fn test_bus_conflicts_from_the_header() {
+        let mut rom = test::test_rom();
+        rom.mapper = 2;
+        rom.submapper = 2;
+        rom.prg_rom = (0..8u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        let mapper = crate::mappers::create(rom).unwrap();
+        let mut mapper = mapper.borrow_mut();
+        mapper.cpu_write(0x8000, 6);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+
+        mapper.set_bus_conflicts(false);
+        mapper.cpu_write(0x8000, 6);
+        assert_eq!(mapper.cpu_read(0x8000), 6);
+    }
+}
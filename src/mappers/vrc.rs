@@ -0,0 +1,148 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// The IRQ counter Konami put in the VRC4, VRC6 and VRC7
+// from: https://www.nesdev.org/wiki/VRC_IRQ
+//
+// An 8-bit counter counting up from the latch; when it overflows past $FF
+// it's reloaded and raises an IRQ. In scanline mode a prescaler divides the
+// CPU clock by 113.667 (341 / 3) to count scanlines, in cycle mode every
+// CPU cycle counts.
+//
+// The control register is ...M AE: M cycle mode, E enable, A what enable
+// becomes once the IRQ is acknowledged.
+const PRESCALER_PERIOD: i16 = 341;
+
+#[derive(Default)]
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq::default()
+    }
+
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    // the VRC4 takes the latch a nibble at a time
+    pub fn write_latch_low(&mut self, data: u8) {
+        self.latch = (self.latch & 0xf0) | (data & 0x0f);
+    }
+
+    pub fn write_latch_high(&mut self, data: u8) {
+        self.latch = (self.latch & 0x0f) | (data << 4);
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += PRESCALER_PERIOD;
+        }
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Snapshot for VrcIrq {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.latch);
+        state.write_u8(self.counter);
+        state.write_u16(self.prescaler as u16);
+        state.write_bool(self.enabled);
+        state.write_bool(self.enable_after_ack);
+        state.write_bool(self.cycle_mode);
+        state.write_bool(self.pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.latch = state.read_u8()?;
+        self.counter = state.read_u8()?;
+        self.prescaler = state.read_u16()? as i16;
+        self.enabled = state.read_bool()?;
+        self.enable_after_ack = state.read_bool()?;
+        self.cycle_mode = state.read_bool()?;
+        self.pending = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cycle_and_scanline_modes() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xfd);
+        irq.write_control(0b111);
+        // $FE, $FF, then the overflow
+        for _ in 0..2 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        // acknowledging leaves it enabled, as A was set
+        irq.acknowledge();
+        assert!(!irq.pending());
+        for _ in 0..3 {
+            irq.clock();
+        }
+        assert!(irq.pending());
+
+        // a scanline is 113 or 114 cycles
+        irq.write_latch(0xff);
+        irq.write_control(0b010);
+        for _ in 0..113 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+        irq.acknowledge();
+        for _ in 0..1000 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+    }
+}
@@ -0,0 +1,318 @@
+use super::vrc::VrcIrq;
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4
+// from: https://www.nesdev.org/wiki/VRC2_and_VRC4
+//
+// | Address     | Register                                            |
+// |-------------|-----------------------------------------------------|
+// | $8000-$8003 | 8KB PRG bank at $8000, or $C000 in swap mode        |
+// | $9000-$9001 | Mirroring                                           |
+// | $9002-$9003 | ......M.: M swap mode (VRC4)                        |
+// | $A000-$A003 | 8KB PRG bank at $A000                               |
+// | $B000-$E003 | 1KB CHR banks R0-R7, low then high nibble each      |
+// | $F000-$F001 | IRQ latch, low then high nibble (VRC4)              |
+// | $F002-$F003 | IRQ control and acknowledge (VRC4), see vrc.rs      |
+//
+// The last 8KB bank is fixed at $E000, the second to last at $C000 or, in
+// swap mode, $8000. The VRC2 only has the vertical and horizontal
+// mirrorings, on all of $9000-$9003; the VRC4 adds one-screen lower and
+// upper. The VRC4's CHR banks have a 5 bit high nibble, the VRC2's 4.
+//
+// The boards wire different CPU address lines to the register select
+// pins, and each mapper number covers more than one wiring. The lines of
+// all of them are ORed together, which works as games only write to the
+// registers through one wiring:
+//
+// | Mapper | Boards        | Bit 0  | Bit 1  |
+// |--------|---------------|--------|--------|
+// | 21     | VRC4a, VRC4c  | A1, A6 | A2, A7 |
+// | 22     | VRC2a         | A1     | A0     |
+// | 23     | VRC2b, VRC4e  | A0, A2 | A1, A3 |
+// | 25     | VRC2c, VRC4bd | A1, A3 | A0, A2 |
+//
+// VRC2a drops the lowest bit of the CHR banks. Mappers 23 and 25 are taken
+// to be a VRC4 unless NES 2.0 submapper 3 says VRC2, which tells apart the
+// few games that write to the VRC4 only registers by mistake.
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+pub struct VRC4 {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_banks: Banks,
+    // the address lines wired to register select bits 0 and 1
+    select_lines: [u16; 2],
+    vrc2: bool,
+    // VRC2a
+    chr_shift: bool,
+
+    prg_registers: [u8; 2],
+    chr_registers: [u16; 8],
+    mirroring: u8,
+    swap_mode: bool,
+    irq: VrcIrq,
+}
+
+impl VRC4 {
+    pub fn new(rom: ROM) -> Self {
+        let select_lines = match rom.mapper {
+            21 => [0b0100_0010, 0b1000_0100],
+            22 => [0b0010, 0b0001],
+            23 => [0b0101, 0b1010],
+            _ => [0b1010, 0b0101],
+        };
+        let vrc2 = rom.mapper == 22 || (rom.mapper != 21 && rom.submapper == 3);
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        let mut vrc4 = VRC4 {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(0x2000)],
+            chr_banks: Banks::new(chr.len(), 0x2000, CHR_BANK_SIZE),
+            chr,
+            select_lines,
+            vrc2,
+            chr_shift: rom.mapper == 22,
+            prg_registers: [0, 1],
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            mirroring: 0,
+            swap_mode: false,
+            irq: VrcIrq::new(),
+        };
+        vrc4.update_banks();
+        vrc4
+    }
+
+    fn update_banks(&mut self) {
+        let last = self.prg_banks.bank_count() - 1;
+        let second_last = last.saturating_sub(1);
+        let first = (self.prg_registers[0] & 0x1f) as usize;
+        let (bank_8000, bank_c000) = if self.swap_mode {
+            (second_last, first)
+        } else {
+            (first, second_last)
+        };
+        self.prg_banks.set(0, bank_8000);
+        self.prg_banks
+            .set(1, (self.prg_registers[1] & 0x1f) as usize);
+        self.prg_banks.set(2, bank_c000);
+        self.prg_banks.set(3, last);
+        for (window, &bank) in self.chr_registers.iter().enumerate() {
+            let bank = if self.chr_shift { bank >> 1 } else { bank };
+            self.chr_banks.set(window, bank as usize);
+        }
+    }
+
+    // the register an address selects, as $x000-$x003
+    fn register(&self, addr: u16) -> u16 {
+        let bit0 = (addr & self.select_lines[0] != 0) as u16;
+        let bit1 = (addr & self.select_lines[1] != 0) as u16;
+        (addr & 0xf000) | (bit1 << 1) | bit0
+    }
+
+    fn write_chr(&mut self, register: u16, data: u8) {
+        let index = ((register >> 12) - 0xb) as usize * 2 + (register as usize & 2) / 2;
+        let bank = &mut self.chr_registers[index];
+        if register & 1 == 0 {
+            *bank = (*bank & 0x1f0) | (data as u16 & 0x0f);
+        } else {
+            let high = if self.vrc2 { 0x0f } else { 0x1f };
+            *bank = (*bank & 0x0f) | ((data as u16 & high) << 4);
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_registers[0] = data,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = data & 1,
+            0x9000..=0x9001 => self.mirroring = data & 0b11,
+            0x9002..=0x9003 => self.swap_mode = data & 0b10 != 0,
+            0xa000..=0xa003 => self.prg_registers[1] = data,
+            register @ 0xb000..=0xefff => self.write_chr(register, data),
+            _ if self.vrc2 => {}
+            0xf000 => self.irq.write_latch_low(data),
+            0xf001 => self.irq.write_latch_high(data),
+            0xf002 => self.irq.write_control(data),
+            0xf003 => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+}
+
+impl Mapper for VRC4 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()],
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xffff => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONE_SCREEN_LOWER,
+            _ => Mirroring::ONE_SCREEN_UPPER,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+// the bank windows follow from the registers
+impl Snapshot for VRC4 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_registers);
+        for &register in &self.chr_registers {
+            state.write_u16(register);
+        }
+        state.write_u8(self.mirroring);
+        state.write_bool(self.swap_mode);
+        self.irq.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.prg_registers)?;
+        for register in self.chr_registers.iter_mut() {
+            *register = state.read_u16()? & 0x1ff;
+        }
+        self.mirroring = state.read_u8()? & 0b11;
+        self.swap_mode = state.read_bool()?;
+        self.irq.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    // 8KB PRG banks and 1KB CHR banks filled with their bank number
+    fn vrc4(mapper: u16, submapper: u8) -> VRC4 {
+        let mut rom = test::test_rom();
+        rom.mapper = mapper;
+        rom.submapper = submapper;
+        rom.prg_rom = (0..16u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..64u8)
+            .flat_map(|bank| vec![bank; CHR_BANK_SIZE])
+            .collect();
+        VRC4::new(rom)
+    }
+
+    #[test]
+    fn test_banks_and_mirroring() {
+        let mut vrc4 = vrc4(25, 0);
+        vrc4.cpu_write(0x8000, 3);
+        vrc4.cpu_write(0xa000, 5);
+        assert_eq!(vrc4.cpu_read(0x8000), 3);
+        assert_eq!(vrc4.cpu_read(0xa000), 5);
+        assert_eq!(vrc4.cpu_read(0xc000), 14);
+        assert_eq!(vrc4.cpu_read(0xe000), 15);
+
+        // swap mode through $9002, which is A0 on mapper 25
+        vrc4.cpu_write(0x9001, 0b10);
+        assert_eq!(vrc4.cpu_read(0x8000), 14);
+        assert_eq!(vrc4.cpu_read(0xc000), 3);
+
+        // R1's nibbles: A1 then A0 and A1, or A3 then A2 and A3 on VRC4d
+        vrc4.cpu_write(0xb001, 0x04);
+        vrc4.cpu_write(0xb003, 0x02);
+        assert_eq!(vrc4.ppu_read(0x0400), 0x24);
+        vrc4.cpu_write(0xb00c, 0x03);
+        assert_eq!(vrc4.ppu_read(0x0400), 0x34);
+
+        vrc4.cpu_write(0x9000, 3);
+        assert_eq!(vrc4.mirroring(), Mirroring::ONE_SCREEN_UPPER);
+        vrc4.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc4.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_vrc2() {
+        // VRC2a drops the lowest CHR bank bit
+        let mut vrc2 = vrc4(22, 0);
+        vrc2.cpu_write(0xc000, 0x08);
+        vrc2.cpu_write(0xc002, 0x03);
+        assert_eq!(vrc2.ppu_read(0x0800), 0x1c);
+
+        // no swap mode or one-screen mirroring, and no IRQ
+        let mut vrc2 = vrc4(23, 3);
+        vrc2.cpu_write(0x8000, 3);
+        vrc2.cpu_write(0x9002, 0b11);
+        assert_eq!(vrc2.cpu_read(0x8000), 3);
+        assert_eq!(vrc2.mirroring(), Mirroring::HORIZONTAL);
+        vrc2.cpu_write(0xf002, 0b110);
+        for _ in 0..0x200 {
+            vrc2.clock();
+        }
+        assert!(!vrc2.irq_pending());
+    }
+
+    #[test]
+    fn test_irq() {
+        // mapper 21 through the VRC4c lines, A6 and A7
+        let mut vrc4 = vrc4(21, 0);
+        vrc4.cpu_write(0xf000, 0x0e);
+        vrc4.cpu_write(0xf040, 0x0f);
+        vrc4.cpu_write(0xf080, 0b110);
+        vrc4.clock();
+        assert!(!vrc4.irq_pending());
+        vrc4.clock();
+        assert!(vrc4.irq_pending());
+        vrc4.cpu_write(0xf0c0, 0);
+        assert!(!vrc4.irq_pending());
+    }
+}
@@ -0,0 +1,485 @@
+use super::vrc::VrcIrq;
+use super::{Banks, Chr, Mapper};
+use crate::rom::{Mirroring, ROM};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Mappers 24 and 26: Konami VRC6
+// from: https://www.nesdev.org/wiki/VRC6
+//
+// | Address     | Register                                            |
+// |-------------|-----------------------------------------------------|
+// | $8000-$8003 | 16KB PRG bank at $8000                              |
+// | $9000-$9003 | Pulse 1, then frequency control at $9003            |
+// | $A000-$A002 | Pulse 2                                             |
+// | $B000-$B002 | Sawtooth                                            |
+// | $B003       | W...MM..: W PRG RAM enable, MM mirroring            |
+// | $C000-$C003 | 8KB PRG bank at $C000                               |
+// | $D000-$E003 | 1KB CHR banks R0-R7                                 |
+// | $F000-$F002 | IRQ latch, control and acknowledge, see vrc.rs      |
+//
+// The last 8KB bank is fixed at $E000. Mapper 26 boards swap A0 and A1,
+// so their registers are $x000, $x002, $x001, $x003. Mirroring is
+// vertical, horizontal, one-screen lower, one-screen upper. Only the usual
+// PPU banking mode, eight 1KB CHR banks, is supported.
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+// mixed at about the loudness of the 2A03's pulses, whose output grows by
+// roughly this much per level at low volumes
+// from: https://www.nesdev.org/wiki/VRC6_audio
+const VOLUME: f32 = 0.00752;
+
+// MDDD VVVV: M ignores the duty and always plays the volume
+#[derive(Default)]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.ignore_duty = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0x0f;
+            }
+            1 => self.period = (self.period & 0x0f00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0x0f) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // 0-15
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.volume);
+        state.write_u8(self.duty);
+        state.write_bool(self.ignore_duty);
+        state.write_u16(self.period);
+        state.write_bool(self.enabled);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.volume = state.read_u8()?;
+        self.duty = state.read_u8()?;
+        self.ignore_duty = state.read_bool()?;
+        self.period = state.read_u16()?;
+        self.enabled = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        Ok(())
+    }
+}
+
+// An accumulator that has `rate` added on every other clock of the timer
+// and is cleared on the 14th, its top 5 bits making the ramp
+#[derive(Default)]
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3f,
+            1 => self.period = (self.period & 0x0f00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0x0f) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % 14;
+            if self.step == 0 {
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // 0-31
+    fn output(&self) -> u8 {
+        if self.enabled {
+            self.accumulator >> 3
+        } else {
+            0
+        }
+    }
+}
+
+impl Snapshot for Sawtooth {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rate);
+        state.write_u16(self.period);
+        state.write_bool(self.enabled);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+        state.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rate = state.read_u8()?;
+        self.period = state.read_u16()?;
+        self.enabled = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        self.accumulator = state.read_u8()?;
+        Ok(())
+    }
+}
+
+pub struct VRC6 {
+    prg_rom: Vec<u8>,
+    prg_banks: Banks,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_banks: Banks,
+    // mapper 26 wiring
+    swapped: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_registers: [u8; 8],
+    control: u8,
+
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+    // $9003: .... .ABH, H halts the channels, B and A shift their periods
+    // right by 4 and 8 bits
+    frequency_control: u8,
+    irq: VrcIrq,
+}
+
+impl VRC6 {
+    pub fn new(rom: ROM, swapped: bool) -> Self {
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        let mut vrc6 = VRC6 {
+            prg_banks: Banks::new(rom.prg_rom.len(), 0x8000, PRG_BANK_SIZE),
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; (rom.prg_ram_size + rom.prg_nvram_size).max(0x2000)],
+            chr_banks: Banks::new(chr.len(), 0x2000, CHR_BANK_SIZE),
+            chr,
+            swapped,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            control: 0,
+            pulse1: Pulse::default(),
+            pulse2: Pulse::default(),
+            sawtooth: Sawtooth::default(),
+            frequency_control: 0,
+            irq: VrcIrq::new(),
+        };
+        vrc6.update_banks();
+        vrc6
+    }
+
+    fn update_banks(&mut self) {
+        let prg_16k = (self.prg_16k & 0x0f) as usize * 2;
+        self.prg_banks.set(0, prg_16k);
+        self.prg_banks.set(1, prg_16k + 1);
+        self.prg_banks.set(2, (self.prg_8k & 0x1f) as usize);
+        self.prg_banks.set(3, self.prg_banks.bank_count() - 1);
+        for (window, &bank) in self.chr_registers.iter().enumerate() {
+            self.chr_banks.set(window, bank as usize);
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let addr = if self.swapped {
+            (addr & 0xfffc) | ((addr & 1) << 1) | ((addr & 2) >> 1)
+        } else {
+            addr
+        };
+        match addr & 0xf003 {
+            0x8000..=0x8003 => self.prg_16k = data,
+            0x9000..=0x9002 => self.pulse1.write(addr & 3, data),
+            0x9003 => self.frequency_control = data,
+            0xa000..=0xa002 => self.pulse2.write(addr & 3, data),
+            0xb000..=0xb002 => self.sawtooth.write(addr & 3, data),
+            0xb003 => self.control = data,
+            0xc000..=0xc003 => self.prg_8k = data,
+            0xd000..=0xd003 => self.chr_registers[(addr & 3) as usize] = data,
+            0xe000..=0xe003 => self.chr_registers[4 + (addr & 3) as usize] = data,
+            0xf000 => self.irq.write_latch(data),
+            0xf001 => self.irq.write_control(data),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+}
+
+impl Mapper for VRC6 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xffff => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_banks.translate(addr as usize))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_banks.translate(addr as usize);
+        self.chr.write(index, data);
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.prg_banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.control >> 2) & 0b11 {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONE_SCREEN_LOWER,
+            _ => Mirroring::ONE_SCREEN_UPPER,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        if self.frequency_control & 1 != 0 {
+            return;
+        }
+        let shift = if self.frequency_control & 0b100 != 0 {
+            8
+        } else if self.frequency_control & 0b010 != 0 {
+            4
+        } else {
+            0
+        };
+        self.pulse1.clock(shift);
+        self.pulse2.clock(shift);
+        self.sawtooth.clock(shift);
+    }
+
+    fn audio_output(&self) -> f32 {
+        let level = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        level as f32 * VOLUME
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+// the bank windows follow from the registers
+impl Snapshot for VRC6 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_16k);
+        state.write_u8(self.prg_8k);
+        for &register in &self.chr_registers {
+            state.write_u8(register);
+        }
+        state.write_u8(self.control);
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.sawtooth.save_state(state);
+        state.write_u8(self.frequency_control);
+        self.irq.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_16k = state.read_u8()?;
+        self.prg_8k = state.read_u8()?;
+        for register in self.chr_registers.iter_mut() {
+            *register = state.read_u8()?;
+        }
+        self.control = state.read_u8()?;
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.sawtooth.load_state(state)?;
+        self.frequency_control = state.read_u8()?;
+        self.irq.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+
+    // 8KB PRG banks and 1KB CHR banks filled with their bank number
+    fn vrc6(swapped: bool) -> VRC6 {
+        let mut rom = test::test_rom();
+        rom.mapper = if swapped { 26 } else { 24 };
+        rom.prg_rom = (0..16u8)
+            .flat_map(|bank| vec![bank; PRG_BANK_SIZE])
+            .collect();
+        rom.chr_rom = (0..32u8)
+            .flat_map(|bank| vec![bank; CHR_BANK_SIZE])
+            .collect();
+        VRC6::new(rom, swapped)
+    }
+
+    #[test]
+    fn test_banks_and_mirroring() {
+        // mapper 26 has A0 and A1 the other way round
+        let mut swapped = vrc6(true);
+        swapped.cpu_write(0xd002, 20);
+        assert_eq!(swapped.ppu_read(0x0400), 20);
+
+        let mut vrc6 = vrc6(false);
+        vrc6.cpu_write(0x8000, 3);
+        vrc6.cpu_write(0xc000, 9);
+        vrc6.cpu_write(0xd001, 20);
+        vrc6.cpu_write(0xe003, 31);
+        assert_eq!(vrc6.cpu_read(0x8000), 6);
+        assert_eq!(vrc6.cpu_read(0xa000), 7);
+        assert_eq!(vrc6.cpu_read(0xc000), 9);
+        assert_eq!(vrc6.cpu_read(0xe000), 15);
+        assert_eq!(vrc6.ppu_read(0x0400), 20);
+        assert_eq!(vrc6.ppu_read(0x1c00), 31);
+
+        vrc6.cpu_write(0xb003, 0b1000_0100);
+        assert_eq!(vrc6.mirroring(), Mirroring::HORIZONTAL);
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_read(0x6000), 0x42);
+        vrc6.cpu_write(0xb003, 0b0000_1100);
+        assert_eq!(vrc6.mirroring(), Mirroring::ONE_SCREEN_UPPER);
+        assert_eq!(vrc6.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut vrc6 = vrc6(false);
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // pulse 1 at 50% duty, volume 10, with a period of 8 cycles a step
+        vrc6.cpu_write(0x9000, 0b0111_1010);
+        vrc6.cpu_write(0x9001, 7);
+        vrc6.cpu_write(0x9002, 0x80);
+        let mut levels = Vec::new();
+        for _ in 0..256 {
+            vrc6.clock();
+            levels.push(vrc6.pulse1.output());
+        }
+        let high = levels.iter().filter(|&&level| level == 10).count();
+        assert_eq!(high, 128);
+        assert!(levels.iter().all(|&level| level == 0 || level == 10));
+
+        // the sawtooth climbs by the rate every other step, 6 times, and
+        // starts over on the 14th
+        vrc6.cpu_write(0x9002, 0);
+        vrc6.cpu_write(0xb000, 0x2a);
+        vrc6.cpu_write(0xb002, 0x80);
+        let mut peak = 0;
+        for _ in 0..14 {
+            vrc6.clock();
+            peak = peak.max(vrc6.sawtooth.output());
+        }
+        assert_eq!(peak, (0x2a * 6) >> 3);
+        assert_eq!(vrc6.sawtooth.output(), 0);
+        assert!(vrc6.audio_output() == 0.0);
+
+        // halted channels hold still
+        vrc6.cpu_write(0x9003, 1);
+        let step = vrc6.sawtooth.step;
+        vrc6.clock();
+        assert_eq!(vrc6.sawtooth.step, step);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut vrc6 = vrc6(false);
+        vrc6.cpu_write(0xf000, 0xfe);
+        vrc6.cpu_write(0xf001, 0b110);
+        vrc6.clock();
+        assert!(!vrc6.irq_pending());
+        vrc6.clock();
+        assert!(vrc6.irq_pending());
+        vrc6.cpu_write(0xf002, 0);
+        assert!(!vrc6.irq_pending());
+    }
+}
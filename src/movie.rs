@@ -0,0 +1,348 @@
+use crate::joypad::Button;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Input movies in FCEUX's FM2 format
+// from: https://fceux.com/web/help/fm2.html
+//
+// A text header of "key value" lines, then one line of input per frame:
+//   |commands|RLDUTSBA|RLDUTSBA||
+// with a letter for every held button and '.' for the rest. Commands are
+// bits: 1 soft reset, 2 power cycle, both before the frame runs. With a Four
+// Score there are four controllers on each line.
+//
+// As in FCEUX, "microphone 1" in the header makes player 2's Start the
+// Famicom microphone; its second controller has no Start of its own.
+//
+// Movies start at power-on, with RAM from the fixed seed. Since nothing the
+// console does depends on the wall clock, feeding the same input on the same
+// frames gives the same game, so a movie only has to store the buttons.
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+pub const SOFT_RESET: u8 = 0b01;
+pub const POWER: u8 = 0b10;
+
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    // header line that isn't "key value", or a malformed input line
+    InvalidLine(usize),
+    UnsupportedVersion(String),
+    // only standard controllers are supported, in ports 0 and 1
+    UnsupportedPort(String),
+    // the movie was recorded on a ROM with this checksum
+    WrongRom(String),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::InvalidLine(line) => write!(f, "line {} isn't valid FM2", line),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "FM2 version {} isn't supported", version)
+            }
+            MovieError::UnsupportedPort(device) => {
+                write!(f, "only standard controllers are supported, not {}", device)
+            }
+            MovieError::WrongRom(checksum) => {
+                write!(f, "the movie was recorded on another ROM ({})", checksum)
+            }
+        }
+    }
+}
+
+impl core::error::Error for MovieError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    // players 1-4; 3 and 4 only with a Four Score
+    pub buttons: [Button; 4],
+}
+
+impl Default for MovieFrame {
+    fn default() -> Self {
+        MovieFrame {
+            commands: 0,
+            buttons: [Button::empty(); 4],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_name: String,
+    // MD5 of the PRG and CHR ROM, without the header
+    pub rom_checksum: [u8; 16],
+    pub pal: bool,
+    pub four_score: bool,
+    // player 2's Start is the microphone
+    pub microphone: bool,
+    pub rerecords: u32,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_checksum: [u8; 16]) -> Self {
+        Movie {
+            rom_name: String::new(),
+            rom_checksum,
+            pal: false,
+            four_score: false,
+            microphone: false,
+            rerecords: 0,
+            frames: vec![],
+        }
+    }
+
+    pub fn checksum_string(checksum: &[u8; 16]) -> String {
+        format!("base64:{}", base64_encode(checksum))
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut fm2 = String::new();
+        fm2.push_str("version 3\n");
+        fm2.push_str("emuVersion 22020\n");
+        fm2.push_str(&format!("rerecordCount {}\n", self.rerecords));
+        fm2.push_str(&format!("palFlag {}\n", self.pal as u8));
+        fm2.push_str(&format!("romFilename {}\n", self.rom_name));
+        fm2.push_str(&format!(
+            "romChecksum {}\n",
+            Movie::checksum_string(&self.rom_checksum)
+        ));
+        fm2.push_str("guid 00000000-0000-0000-0000-000000000000\n");
+        fm2.push_str(&format!("fourscore {}\n", self.four_score as u8));
+        fm2.push_str(&format!("microphone {}\n", self.microphone as u8));
+        // with a Four Score the port types are ignored
+        let port = if self.four_score { 0 } else { 1 };
+        fm2.push_str(&format!("port0 {}\nport1 {}\nport2 0\n", port, port));
+        fm2.push_str("FDS 0\nNewPPU 0\n");
+
+        let players = if self.four_score { 4 } else { 2 };
+        for frame in self.frames.iter() {
+            fm2.push_str(&format!("|{}|", frame.commands));
+            for buttons in frame.buttons[..players].iter() {
+                fm2.push_str(&format_buttons(*buttons));
+                fm2.push('|');
+            }
+            // the expansion port
+            if !self.four_score {
+                fm2.push('|');
+            }
+            fm2.push('\n');
+        }
+        fm2
+    }
+
+    pub fn from_fm2(fm2: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::new([0; 16]);
+        for (number, line) in fm2.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let number = number + 1;
+            if line.starts_with('|') {
+                movie
+                    .frames
+                    .push(parse_frame(line, movie.four_score, number)?);
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once(' ')
+                .map(|(key, value)| (key, value.trim()))
+                .unwrap_or((line, ""));
+            match key {
+                "version" if value != "3" => {
+                    return Err(MovieError::UnsupportedVersion(value.to_string()))
+                }
+                "rerecordCount" => {
+                    movie.rerecords = value.parse().map_err(|_| MovieError::InvalidLine(number))?
+                }
+                "palFlag" => movie.pal = value == "1",
+                "romFilename" => movie.rom_name = value.to_string(),
+                "romChecksum" => {
+                    movie.rom_checksum = value
+                        .strip_prefix("base64:")
+                        .and_then(base64_decode)
+                        .and_then(|bytes| bytes.try_into().ok())
+                        .ok_or(MovieError::InvalidLine(number))?
+                }
+                "fourscore" => movie.four_score = value == "1",
+                "microphone" => movie.microphone = value == "1",
+                // SI_NONE, SI_GAMEPAD; a Four Score overrides them
+                "port0" | "port1" if !movie.four_score && value != "1" => {
+                    return Err(MovieError::UnsupportedPort(format!("{} {}", key, value)))
+                }
+                "port2" if value != "0" => {
+                    return Err(MovieError::UnsupportedPort(format!("{} {}", key, value)))
+                }
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+}
+
+fn format_buttons(buttons: Button) -> String {
+    BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(i, &letter)| {
+            if buttons.bits() & (0x80 >> i) != 0 {
+                letter as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+// anything but '.' or ' ' is a held button
+fn parse_buttons(field: &str) -> Option<Button> {
+    if field.is_empty() {
+        return Some(Button::empty());
+    }
+    if field.len() != 8 {
+        return None;
+    }
+    let bits = field
+        .bytes()
+        .enumerate()
+        .filter(|&(_, c)| c != b'.' && c != b' ')
+        .fold(0, |bits, (i, _)| bits | 0x80 >> i);
+    Some(Button::from_bits_truncate(bits))
+}
+
+fn parse_frame(line: &str, four_score: bool, number: usize) -> Result<MovieFrame, MovieError> {
+    let fields: Vec<&str> = line[1..].split('|').collect();
+    let players = if four_score { 4 } else { 2 };
+    if fields.len() < players + 1 {
+        return Err(MovieError::InvalidLine(number));
+    }
+    let mut frame = MovieFrame {
+        commands: fields[0]
+            .parse()
+            .map_err(|_| MovieError::InvalidLine(number))?,
+        ..MovieFrame::default()
+    };
+    for (player, field) in fields[1..=players].iter().enumerate() {
+        frame.buttons[player] = parse_buttons(field).ok_or(MovieError::InvalidLine(number))?;
+    }
+    Ok(frame)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let digits = encoded
+        .trim_end_matches('=')
+        .bytes()
+        .map(|c| {
+            BASE64
+                .iter()
+                .position(|&digit| digit == c)
+                .map(|d| d as u32)
+        })
+        .collect::<Option<Vec<u32>>>()?;
+    let mut bytes = vec![];
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &digit)| bits | digit << (18 - 6 * i));
+        for i in 0..chunk.len() - 1 {
+            bytes.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+        assert_eq!(base64_decode("TWE="), Some(b"Ma".to_vec()));
+        assert_eq!(base64_decode("TQ=="), Some(b"M".to_vec()));
+        assert_eq!(base64_decode("T!=="), None);
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let mut movie = Movie::new([0xab; 16]);
+        movie.rom_name = "smb".to_string();
+        movie.frames.push(MovieFrame {
+            commands: POWER,
+            ..MovieFrame::default()
+        });
+        movie.frames.push(MovieFrame {
+            commands: 0,
+            buttons: [
+                Button::RIGHT | Button::A,
+                Button::START,
+                Button::empty(),
+                Button::empty(),
+            ],
+        });
+
+        let fm2 = movie.to_fm2();
+        assert!(fm2.contains("romChecksum base64:q6urq6urq6urq6urq6urqw==\n"));
+        assert!(fm2.ends_with("|2|........|........||\n|0|R......A|....T...||\n"));
+        assert_eq!(Movie::from_fm2(&fm2), Ok(movie.clone()));
+
+        movie.microphone = true;
+        let fm2 = movie.to_fm2();
+        assert!(fm2.contains("microphone 1\n"));
+        assert_eq!(Movie::from_fm2(&fm2), Ok(movie.clone()));
+
+        movie.four_score = true;
+        movie.frames[1].buttons[3] = Button::UP;
+        let fm2 = movie.to_fm2();
+        assert!(fm2.ends_with("|0|R......A|....T...|........|...U....|\n"));
+        assert_eq!(Movie::from_fm2(&fm2), Ok(movie));
+    }
+
+    #[test]
+    fn test_fm2_errors() {
+        assert_eq!(
+            Movie::from_fm2("version 2\n"),
+            Err(MovieError::UnsupportedVersion("2".to_string()))
+        );
+        assert_eq!(
+            Movie::from_fm2("version 3\nport1 2\n"),
+            Err(MovieError::UnsupportedPort("port1 2".to_string()))
+        );
+        assert_eq!(
+            Movie::from_fm2("version 3\n|0|RLDU|\n"),
+            Err(MovieError::InvalidLine(2))
+        );
+    }
+}
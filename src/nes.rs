@@ -0,0 +1,1764 @@
+use crate::accuracy::AccuracyConfig;
+use crate::apu::scope::ChannelScope;
+use crate::apu::{Channel, ChannelState};
+use crate::audit::Audit;
+#[cfg(feature = "std")]
+use crate::archive;
+use crate::audio::{self, Resampler};
+use crate::bus::BUS;
+use crate::cpu::CPU;
+use crate::debugger::{
+    Access, Breakpoint, CallFrame, DebugState, Debugger, StopReason, Until, WatchId,
+};
+use crate::error::EmulatorError;
+use crate::events::{Event, Events, SubscriptionId};
+use crate::expr::Expr;
+use crate::four_score::FourScore;
+use crate::joypad::{Button, Joypad};
+use crate::md5::md5;
+use crate::movie::{Movie, MovieError, MovieFrame, POWER, SOFT_RESET};
+use crate::peripheral::Peripheral;
+use crate::png::Image;
+use crate::ppu::raster::RasterTiming;
+use crate::ppu_log::PpuWriteLog;
+use crate::profiler::Profiler;
+use crate::region::Region;
+use crate::render;
+use crate::render::frame::Frame;
+use crate::render::palette::Palette;
+use crate::rewind::Rewind;
+use crate::rom::ROM;
+#[cfg(feature = "serde")]
+use crate::state::MachineState;
+use crate::symbols::Symbols;
+use crate::wav::AudioRecorder;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// What a headless run produced: the last finished picture, the sound since
+// the previous call and why the run stopped
+pub struct RunOutput<'a> {
+    pub frame: &'a Frame,
+    pub samples: Vec<f32>,
+    pub stop: StopReason,
+}
+
+// a movie being recorded, or played back from its `frame`th frame
+enum MovieMode {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+type FrameCallback = Box<dyn FnMut(&Nes)>;
+
+// The whole console behind one type: load a ROM, set the controllers, run a
+// frame at a time and take the picture and sound it produced.
+//
+// Nothing here needs a frontend, so tests can run a ROM for a while with
+// run_until_frame or run_cycles and check the screen, sound or memory.
+pub struct Nes {
+    cpu: CPU,
+    debugger: Debugger,
+    rewind: Option<Rewind>,
+    // a state at the start of each frame, for step_back
+    step_history: Option<Rewind>,
+    movie: Option<MovieMode>,
+    // commands for the next recorded frame, see movie.rs
+    movie_commands: u8,
+    // a breakpoint stopped the last run part way through a frame
+    mid_frame: bool,
+    paused: bool,
+    rom_checksum: [u8; 16],
+    frame: Frame,
+    // a queue, as the oldest go first once the frontend falls behind
+    samples: Rc<RefCell<VecDeque<f32>>>,
+    sample_rate: u32,
+    // gets every sample too, whether the frontend takes them or not
+    recorder: Rc<RefCell<Option<AudioRecorder>>>,
+    frame_callback: Option<FrameCallback>,
+    audit: Option<Audit>,
+}
+
+impl Nes {
+    pub const SAMPLE_RATE: u32 = 44_100;
+    // see set_microphone_level
+    pub const MICROPHONE_THRESHOLD: f32 = 0.25;
+
+    pub fn new(rom_bytes: &[u8]) -> Result<Self, EmulatorError> {
+        Nes::with_sample_rate(rom_bytes, Nes::SAMPLE_RATE)
+    }
+
+    pub fn with_sample_rate(rom_bytes: &[u8], sample_rate: u32) -> Result<Self, EmulatorError> {
+        Nes::from_rom(ROM::from_bytes(rom_bytes)?, sample_rate)
+    }
+
+    // from a .nes file, or the first one in a .zip or .7z, see archive.rs
+    #[cfg(feature = "std")]
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, EmulatorError> {
+        Nes::new(&archive::read_rom(path)?)
+    }
+
+    // from a ROM already parsed, and perhaps fixed up, see romdb.rs
+    pub fn from_rom(rom: ROM, sample_rate: u32) -> Result<Self, EmulatorError> {
+        let rom_checksum = md5(&[rom.prg_rom.as_slice(), rom.chr_rom.as_slice()].concat());
+        Ok(Nes::with_bus(BUS::new(rom)?, rom_checksum, sample_rate))
+    }
+
+    // for consoles with something else than a cartridge in, see nsf.rs
+    pub(crate) fn with_bus(bus: BUS, rom_checksum: [u8; 16], sample_rate: u32) -> Self {
+        let mut cpu = CPU::new(bus);
+        cpu.halt_on_brk = false;
+        let mut nes = Nes {
+            cpu,
+            debugger: Debugger::new(),
+            rewind: None,
+            step_history: None,
+            movie: None,
+            movie_commands: 0,
+            mid_frame: false,
+            paused: false,
+            rom_checksum,
+            frame: Frame::new(),
+            samples: Rc::new(RefCell::new(VecDeque::new())),
+            sample_rate,
+            recorder: Rc::new(RefCell::new(None)),
+            frame_callback: None,
+            audit: None,
+        };
+        nes.attach_audio();
+        nes.cpu.reset();
+        nes
+    }
+
+    // Mono samples in -1.0..1.0 at the sample rate; anything the frontend
+    // doesn't take within a second is dropped, oldest first
+    fn attach_audio(&mut self) {
+        let samples = self.samples.clone();
+        let recorder = self.recorder.clone();
+        let limit = self.sample_rate as usize;
+        let clock_rate = self.cpu.bus.region().cpu_clock_rate();
+        let mut resampler = Resampler::with_clock_rate(self.sample_rate, clock_rate);
+        self.cpu.bus.apu.set_sample_callback(move |levels, expansion| {
+            if let Some(sample) = resampler.push(audio::mix(&levels, expansion)) {
+                if let Some(recorder) = recorder.borrow_mut().as_mut() {
+                    recorder.push(sample);
+                }
+                let mut samples = samples.borrow_mut();
+                if samples.len() == limit {
+                    samples.pop_front();
+                }
+                samples.push_back(sample);
+            }
+        });
+    }
+
+    // Runs until the PPU reaches vblank, then renders the finished picture.
+    // A breakpoint or watchpoint stops it part way; the next call carries on
+    // from there.
+    pub fn run_frame(&mut self) -> StopReason {
+        if self.paused {
+            return StopReason::Paused;
+        }
+        self.start_frame();
+        let reason = self.debugger.run(&mut self.cpu, Until::FrameComplete);
+        if reason == StopReason::FrameComplete {
+            self.end_frame();
+        }
+        reason
+    }
+
+    // a movie's input goes in before the frame runs
+    fn start_frame(&mut self) {
+        if self.mid_frame {
+            return;
+        }
+        self.mid_frame = true;
+        self.step_turbo();
+        match self.movie.take() {
+            Some(MovieMode::Recording(mut movie)) => {
+                let frame = MovieFrame {
+                    commands: core::mem::take(&mut self.movie_commands),
+                    buttons: self.movie_buttons(&mut movie),
+                };
+                movie.frames.push(frame);
+                self.movie = Some(MovieMode::Recording(movie));
+            }
+            // at the end of the movie the controllers are the player's again
+            Some(MovieMode::Playing { movie, frame }) if frame < movie.frames.len() => {
+                let input = movie.frames[frame];
+                if input.commands & POWER != 0 {
+                    self.power_cycle();
+                } else if input.commands & SOFT_RESET != 0 {
+                    self.cpu.soft_reset();
+                }
+                let mut buttons = input.buttons;
+                if movie.microphone {
+                    self.cpu.bus.microphone = buttons[1].contains(Button::START);
+                    buttons[1].remove(Button::START);
+                }
+                for (player, buttons) in buttons.iter().enumerate() {
+                    self.set_input(player + 1, *buttons);
+                }
+                self.movie = Some(MovieMode::Playing {
+                    movie,
+                    frame: frame + 1,
+                });
+            }
+            _ => {}
+        }
+        // after the frame's input is in, which the state has to include
+        if let Some(history) = self.step_history.as_mut() {
+            let cpu = &self.cpu;
+            history.end_frame(|| cpu.save_state());
+        }
+    }
+
+    // What the controllers send this frame, for a movie being recorded.
+    // Once the microphone has been used player 2's Start stands for it, so
+    // a Start pressed before then plays back as the microphone.
+    fn movie_buttons(&mut self, movie: &mut Movie) -> [Button; 4] {
+        let mut buttons = [Button::empty(); 4];
+        for (player, buttons) in buttons.iter_mut().enumerate() {
+            *buttons = self.input(player + 1);
+        }
+        movie.microphone |= self.cpu.bus.microphone;
+        if movie.microphone {
+            buttons[1].set(Button::START, self.cpu.bus.microphone);
+        }
+        buttons
+    }
+
+    fn end_frame(&mut self) {
+        self.mid_frame = false;
+        render::render(&self.cpu.bus.ppu, &mut self.frame);
+        if let Some(rewind) = self.rewind.as_mut() {
+            let cpu = &self.cpu;
+            rewind.end_frame(|| cpu.save_state());
+        }
+        if let Some(mut audit) = self.audit.take() {
+            audit.record(self);
+            self.audit = Some(audit);
+        }
+        self.cpu.bus.emit(Event::FrameCompleted);
+        if let Some(mut callback) = self.frame_callback.take() {
+            callback(self);
+            self.frame_callback = Some(callback);
+        }
+    }
+
+    // Runs `frames` more frames after an earlier state was loaded, for
+    // rollback netplay to redo them with other input: `before_frame` gets
+    // the console before each one to set it. They were heard, recorded and
+    // seen by the frame callback the first time round, so this time only
+    // the picture and the console's state come out of them, and their
+    // events go to nobody. The rewind history, the audit and a movie being
+    // recorded get these frames in place of the first go at them, and
+    // breakpoints and watches don't stop them.
+    pub fn resimulate<F: FnMut(&mut Nes)>(&mut self, frames: usize, mut before_frame: F) {
+        let samples = core::mem::take(&mut *self.samples.borrow_mut());
+        let recorder = self.recorder.borrow_mut().take();
+        let callback = self.frame_callback.take();
+        let mut movie = self.movie.take();
+        let events = core::mem::take(&mut self.cpu.bus.events);
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.forget(frames);
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            audit.forget(frames);
+        }
+        self.forget_step_history();
+        for redone in 0..frames {
+            before_frame(self);
+            self.start_frame();
+            if let Some(MovieMode::Recording(movie)) = movie.as_mut() {
+                let length = movie.frames.len();
+                if let Some(frame) = (length + redone).checked_sub(frames) {
+                    movie.frames[frame].buttons = self.movie_buttons(movie);
+                }
+            }
+            while self.cpu.replay_step() {
+                if self.cpu.bus.poll_frame_complete() {
+                    self.end_frame();
+                    break;
+                }
+            }
+        }
+        *self.samples.borrow_mut() = samples;
+        *self.recorder.borrow_mut() = recorder;
+        self.frame_callback = callback;
+        self.movie = movie;
+        self.cpu.bus.events = events;
+        self.cpu.bus.watchpoints.take_hit();
+        self.cpu.bus.ppu_breakpoints.take_hit();
+        self.cpu.bus.take_fault();
+    }
+
+    // While paused, run_frame and run_cycles leave the console as it is and
+    // return StopReason::Paused; stepping in the debugger still works
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn unpause(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Stop with StopReason::Fault on reads of addresses nothing drives and
+    // writes nothing listens to, instead of carrying on like the console.
+    // Handy for finding emulation bugs and homebrew mistakes.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.cpu.bus.strict = strict;
+    }
+
+    // when mid-line PPUCTRL and PPUMASK writes show up, see ppu/raster.rs
+    pub fn set_raster_timing(&mut self, timing: RasterTiming) {
+        let mut accuracy = self.accuracy();
+        accuracy.raster_timing = timing;
+        self.set_accuracy(accuracy);
+    }
+
+    pub fn accuracy(&self) -> AccuracyConfig {
+        self.cpu.bus.accuracy()
+    }
+
+    // speed against fidelity, see accuracy.rs
+    pub fn set_accuracy(&mut self, accuracy: AccuracyConfig) {
+        self.cpu.bus.set_accuracy(accuracy);
+    }
+
+    // Keeps a state every `interval` frames for `rewind`, in at most about
+    // `budget` bytes
+    pub fn enable_rewind(&mut self, interval: usize, budget: usize) {
+        self.rewind = Some(Rewind::new(interval, budget));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    // Goes back about `frames` frames, as far as the history reaches.
+    // Returns how many frames it went back.
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let rewound = self
+            .rewind
+            .as_mut()
+            .and_then(|rewind| rewind.rewind(frames));
+        match rewound {
+            // states in the history are all from this cartridge
+            Some((state, rewound)) => {
+                self.cpu.load_state(&state).unwrap();
+                self.mid_frame = false;
+                self.rewind_movie(rewound);
+                self.forget_step_history();
+                rewound
+            }
+            None => 0,
+        }
+    }
+
+    // Keeps the state at the start of every frame, in at most about
+    // `budget` bytes, for step_back
+    pub fn enable_reverse_step(&mut self, budget: usize) {
+        let mut history = Rewind::new(1, budget);
+        history.end_frame(|| self.cpu.save_state());
+        self.step_history = Some(history);
+    }
+
+    pub fn disable_reverse_step(&mut self) {
+        self.step_history = None;
+    }
+
+    // Goes back one instruction, the way Mesen does: back to the state at
+    // the start of the frame, or of the frame before at the start of one,
+    // then running it again up to the instruction before this one. The run
+    // is silent, nobody hears its sound or gets its events a second time,
+    // and a movie stays where it is. Returns false with nothing left to go
+    // back to.
+    pub fn step_back(&mut self) -> bool {
+        let now = self.cpu.bus.cycles();
+        let history = match self.step_history.as_mut() {
+            Some(history) => history,
+            None => return false,
+        };
+        // the history is all from this console, see forget_step_history
+        let mut state = match history.rewind(0) {
+            Some((state, _)) => state,
+            None => return false,
+        };
+        self.cpu.load_state(&state).unwrap();
+        if self.cpu.bus.cycles() >= now {
+            state = match history.rewind(1) {
+                Some((state, 1)) => state,
+                _ => return false,
+            };
+            self.cpu.load_state(&state).unwrap();
+        }
+
+        // the instruction before this one ended at `target` cycles
+        let samples = core::mem::take(&mut *self.samples.borrow_mut());
+        let recorder = self.recorder.borrow_mut().take();
+        let events = core::mem::take(&mut self.cpu.bus.events);
+        let ppu_write_log = self.cpu.bus.ppu_write_log.take();
+        let start = self.cpu.bus.cycles();
+        let mut target = start;
+        while self.cpu.replay_step() && self.cpu.bus.cycles() < now {
+            target = self.cpu.bus.cycles();
+        }
+        // back before the first instruction of a frame is the end of the one
+        // before, ahead of the new frame's input and turbo step
+        let mut mid_frame = true;
+        if target == start {
+            if let Some((previous, 1)) = self.step_history.as_mut().and_then(|h| h.rewind(1)) {
+                state = previous;
+                mid_frame = false;
+            }
+        }
+        self.cpu.load_state(&state).unwrap();
+        while self.cpu.bus.cycles() < target && self.cpu.replay_step() {}
+        if !mid_frame {
+            // run_frame took the frame's end when it got there
+            self.cpu.bus.poll_frame_complete();
+        }
+        *self.samples.borrow_mut() = samples;
+        *self.recorder.borrow_mut() = recorder;
+        self.cpu.bus.events = events;
+        self.cpu.bus.ppu_write_log = ppu_write_log;
+
+        self.cpu.bus.watchpoints.take_hit();
+        self.cpu.bus.ppu_breakpoints.take_hit();
+        self.cpu.bus.take_fault();
+        self.mid_frame = mid_frame;
+        self.debugger.resume_at(self.cpu.program_counter);
+        true
+    }
+
+    // the console jumped somewhere step_back's history can't get it back
+    // from
+    fn forget_step_history(&mut self) {
+        if let Some(history) = self.step_history.as_mut() {
+            history.clear();
+        }
+    }
+
+    // Rewinding a recording drops the frames gone back over and counts a
+    // rerecord; rewinding playback goes back as far in the movie
+    fn rewind_movie(&mut self, frames: usize) {
+        match self.movie.as_mut() {
+            Some(MovieMode::Recording(movie)) => {
+                let length = movie.frames.len().saturating_sub(frames);
+                movie.frames.truncate(length);
+                movie.rerecords += 1;
+            }
+            Some(MovieMode::Playing { frame, .. }) => *frame = frame.saturating_sub(frames),
+            None => {}
+        }
+    }
+
+    // Power cycles and records the input of every frame from here on
+    pub fn record_movie(&mut self) {
+        let mut movie = Movie::new(self.rom_checksum);
+        movie.pal = self.region() == Region::Pal;
+        movie.four_score = matches!(self.cpu.bus.port1, Peripheral::FourScore(_));
+        self.power_cycle();
+        self.movie_commands = 0;
+        self.movie = Some(MovieMode::Recording(movie));
+    }
+
+    // Power cycles and plays the movie's input back, one line a frame
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), EmulatorError> {
+        if movie.rom_checksum != self.rom_checksum {
+            return Err(MovieError::WrongRom(Movie::checksum_string(&movie.rom_checksum)).into());
+        }
+        if movie.pal != (self.region() == Region::Pal) {
+            self.set_region(if movie.pal { Region::Pal } else { Region::Ntsc });
+        }
+        if movie.four_score {
+            self.connect_four_score();
+        } else {
+            self.set_peripheral(1, Peripheral::Joypad(Joypad::new()));
+            self.set_peripheral(2, Peripheral::Joypad(Joypad::new()));
+        }
+        self.power_cycle();
+        self.movie = Some(MovieMode::Playing { movie, frame: 0 });
+        Ok(())
+    }
+
+    // the movie being recorded or played back
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            MovieMode::Recording(movie) | MovieMode::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.movie, Some(MovieMode::Recording(_)))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        match &self.movie {
+            Some(MovieMode::Playing { movie, frame }) => *frame < movie.frames.len(),
+            _ => false,
+        }
+    }
+
+    // MD5 of the PRG and CHR ROM, as movies identify the game
+    pub fn rom_checksum(&self) -> [u8; 16] {
+        self.rom_checksum
+    }
+
+    pub fn run_until_frame(&mut self) -> RunOutput<'_> {
+        let stop = self.run_frame();
+        self.output(stop)
+    }
+
+    // Runs at least `cycles` CPU cycles, rendering every frame finished on
+    // the way. Stops early at breakpoints and watchpoints.
+    pub fn run_cycles(&mut self, cycles: usize) -> RunOutput<'_> {
+        if self.paused {
+            return self.output(StopReason::Paused);
+        }
+        let end = self.cpu.bus.cycles() + cycles;
+        let stop = loop {
+            self.start_frame();
+            let stop = self.debugger.run(&mut self.cpu, Until::FrameOrCycle(end));
+            if stop != StopReason::FrameComplete {
+                break stop;
+            }
+            self.end_frame();
+            if self.cpu.bus.cycles() >= end {
+                break stop;
+            }
+        };
+        self.output(stop)
+    }
+
+    fn output(&mut self, stop: StopReason) -> RunOutput<'_> {
+        RunOutput {
+            samples: self.audio_samples(),
+            frame: &self.frame,
+            stop,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.debugger.add_breakpoint(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.debugger.remove_breakpoint(addr);
+    }
+
+    // stopping only when `condition` holds, see expr.rs, and from its
+    // `hit_count`th time on
+    pub fn add_conditional_breakpoint(
+        &mut self,
+        addr: u16,
+        condition: &str,
+        hit_count: usize,
+    ) -> Result<(), EmulatorError> {
+        let breakpoint = Breakpoint {
+            condition: Some(Expr::parse(condition)?),
+            hit_count,
+            ..Breakpoint::default()
+        };
+        self.debugger.set_breakpoint(addr, breakpoint);
+        Ok(())
+    }
+
+    pub fn set_breakpoint(&mut self, addr: u16, breakpoint: Breakpoint) {
+        self.debugger.set_breakpoint(addr, breakpoint);
+    }
+
+    // gone once it's stopped the program
+    pub fn add_temporary_breakpoint(&mut self, addr: u16) {
+        self.debugger.add_temporary_breakpoint(addr);
+    }
+
+    pub fn breakpoint(&self, addr: u16) -> Option<&Breakpoint> {
+        self.debugger.breakpoint(addr)
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access) {
+        self.cpu.bus.watchpoints.add(addr, access);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.cpu.bus.watchpoints.remove(addr);
+    }
+
+    // An expression to show at stops, or to stop at once it becomes true,
+    // see expr.rs; the id is what remove_watch takes
+    pub fn add_watch(&mut self, expr: &str, break_on_true: bool) -> Result<WatchId, EmulatorError> {
+        let expr = Expr::parse(expr)?;
+        Ok(self.debugger.add_watch(&self.cpu, expr, break_on_true))
+    }
+
+    pub fn remove_watch(&mut self, id: WatchId) {
+        self.debugger.remove_watch(id);
+    }
+
+    // every watch expression with its value now
+    pub fn watch_values(&self) -> Vec<(&str, i64)> {
+        self.debugger
+            .watches()
+            .map(|(_, watch)| (watch.expr.source(), watch.expr.eval(&self.cpu)))
+            .collect()
+    }
+
+    // stop once the PPU gets to this dot of this scanline, see PpuBreakpoints
+    pub fn add_ppu_breakpoint(&mut self, scanline: u16, dot: u16) {
+        self.cpu.bus.ppu_breakpoints.add(scanline, dot);
+    }
+
+    pub fn remove_ppu_breakpoint(&mut self, scanline: u16, dot: u16) {
+        self.cpu.bus.ppu_breakpoints.remove(scanline, dot);
+    }
+
+    // a Game Genie or raw code, see cheats.rs; returns its index
+    pub fn add_cheat(&mut self, code: &str) -> Result<usize, EmulatorError> {
+        Ok(self.cpu.bus.cheats.add(code)?)
+    }
+
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        self.cpu.bus.cheats.set_enabled(index, enabled);
+    }
+
+    // one instruction
+    pub fn step(&mut self) -> StopReason {
+        self.start_frame();
+        self.debugger.run(&mut self.cpu, Until::Step)
+    }
+
+    // one instruction, running subroutine calls through to their return
+    pub fn step_over(&mut self) -> StopReason {
+        self.start_frame();
+        self.debugger.step_over(&mut self.cpu)
+    }
+
+    // until the subroutine or interrupt handler the program is in returns
+    pub fn step_out(&mut self) -> StopReason {
+        self.start_frame();
+        self.debugger.step_out(&mut self.cpu)
+    }
+
+    // the calls the program hasn't returned from, outermost first
+    pub fn call_stack(&self) -> &[CallFrame] {
+        self.cpu.call_stack.frames()
+    }
+
+    // carries on after a stop until the end of the frame
+    pub fn resume(&mut self) -> StopReason {
+        self.run_frame()
+    }
+
+    pub fn debug_state(&self) -> DebugState {
+        DebugState::capture(&self.cpu)
+    }
+
+    // labels for the trace and debug_state, see symbols.rs
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.cpu.set_symbols(symbols);
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        self.cpu.symbols()
+    }
+
+    // For a rebuilt ROM loaded again: takes over the breakpoints, watches,
+    // watchpoints, labels and cheats of the console this one replaces
+    pub fn keep_debugging(&mut self, old: &mut Nes) {
+        self.debugger = core::mem::take(&mut old.debugger);
+        self.debugger.forget_stop();
+        let bus = &mut self.cpu.bus;
+        bus.watchpoints = core::mem::take(&mut old.cpu.bus.watchpoints);
+        bus.watchpoints.take_hit();
+        bus.ppu_breakpoints = core::mem::take(&mut old.cpu.bus.ppu_breakpoints);
+        bus.ppu_breakpoints.take_hit();
+        bus.cheats = core::mem::take(&mut old.cpu.bus.cheats);
+        self.cpu.set_symbols(old.cpu.take_symbols());
+    }
+
+    // CPU memory as a read would see it, without the read's side effects
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.bus.peek(addr)
+    }
+
+    // the 2KB of CPU RAM
+    pub fn ram(&self) -> &[u8] {
+        self.cpu.bus.ram()
+    }
+
+    // a copy of the cartridge's PRG RAM at $6000-$7FFF, battery backed or
+    // not; empty if it has none
+    pub fn prg_ram(&self) -> Vec<u8> {
+        self.cpu.bus.mapper().borrow_mut().prg_ram().to_vec()
+    }
+
+    // `len` bytes from `start`, wrapping around at $FFFF
+    pub fn peek_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.peek(start.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    // PPU address space: pattern tables, nametables and palettes
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        self.cpu.bus.ppu.peek(addr)
+    }
+
+    // `len` bytes from `start`, wrapping around at $3FFF
+    pub fn peek_ppu_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.peek_ppu(start.wrapping_add(offset as u16) & 0x3fff))
+            .collect()
+    }
+
+    // NTSC, PAL or Dendy, as the ROM header says unless overridden
+    pub fn region(&self) -> Region {
+        self.cpu.bus.region()
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.bus.set_region(region);
+        self.attach_audio();
+        let clock_rate = region.cpu_clock_rate();
+        if let Some(scope) = self.cpu.bus.apu.scope.as_mut() {
+            scope.set_rates(self.sample_rate, clock_rate);
+        }
+    }
+
+    // frames per second run_frame should be called at
+    pub fn frame_rate(&self) -> f64 {
+        self.region().frame_rate()
+    }
+
+    pub fn frame_buffer(&self) -> &Frame {
+        &self.frame
+    }
+
+    // for drawing over the last frame, see render/overlay.rs; the next
+    // frame replaces it all
+    pub fn frame_buffer_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
+
+    // the last frame as it was rendered, 256x240 without filters
+    pub fn screenshot(&self) -> Image {
+        Image {
+            width: self.frame.width,
+            height: self.frame.height,
+            data: self.frame.data.clone(),
+        }
+    }
+
+    // the colours frames come out in, see render/palette.rs
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.frame.set_palette(palette);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // the samples produced since the last call
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        core::mem::take(&mut *self.samples.borrow_mut()).into()
+    }
+
+    // Starts capturing the mixed output from here on, dropping anything
+    // recorded since an earlier start
+    pub fn start_audio_recording(&mut self) {
+        *self.recorder.borrow_mut() = Some(AudioRecorder::new(self.sample_rate));
+    }
+
+    pub fn is_recording_audio(&self) -> bool {
+        self.recorder.borrow().is_some()
+    }
+
+    // the sound since start_audio_recording, ready for AudioRecorder::write
+    pub fn stop_audio_recording(&mut self) -> Option<AudioRecorder> {
+        self.recorder.borrow_mut().take()
+    }
+
+    // mutes or unmutes one of the APU's channels, see APU::set_channel_enabled
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.cpu.bus.apu.set_channel_enabled(channel, enabled);
+    }
+
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        self.cpu.bus.apu.channel_state(channel)
+    }
+
+    // turns one of the cartridge's sound channels up, down or off, see
+    // Mapper::set_audio_channel_gain
+    pub fn set_expansion_channel_gain(&mut self, channel: usize, gain: f32) {
+        self.cpu.bus.mapper().borrow_mut().set_audio_channel_gain(channel, gain);
+    }
+
+    // Keeps the last `capacity` samples of each channel's output at the
+    // sample rate, for drawing them one by one, see apu/scope.rs
+    pub fn enable_channel_scope(&mut self, capacity: usize) {
+        let clock_rate = self.region().cpu_clock_rate();
+        let scope = ChannelScope::new(capacity, self.sample_rate, clock_rate);
+        self.cpu.bus.apu.scope = Some(scope);
+    }
+
+    pub fn disable_channel_scope(&mut self) -> Option<ChannelScope> {
+        self.cpu.bus.apu.scope.take()
+    }
+
+    pub fn channel_scope(&self) -> Option<&ChannelScope> {
+        self.cpu.bus.apu.scope.as_ref()
+    }
+
+    // Buttons held by player 1-4. Players 3 and 4 need a Four Score, where
+    // they share the ports with players 1 and 2.
+    pub fn set_input(&mut self, player: usize, buttons: Button) {
+        let (port, slot) = player_slot(player);
+        if let Some(joypad) = self.port_mut(port).joypad_mut(slot) {
+            joypad.set_buttons(buttons);
+        }
+    }
+
+    // what set_input last gave the player
+    pub fn input(&mut self, player: usize) -> Button {
+        let (port, slot) = player_slot(player);
+        self.port_mut(port)
+            .joypad_mut(slot)
+            .map_or(Button::empty(), |joypad| joypad.button_status())
+    }
+
+    // Buttons of player 1-4 that repeat by themselves while held, pressed
+    // for `on` frames then released for `off`, see Joypad::set_turbo
+    pub fn set_turbo(&mut self, player: usize, buttons: Button, on: u8, off: u8) {
+        let (port, slot) = player_slot(player);
+        if let Some(joypad) = self.port_mut(port).joypad_mut(slot) {
+            joypad.set_turbo(buttons);
+            joypad.set_turbo_rate(on, off);
+        }
+    }
+
+    fn step_turbo(&mut self) {
+        for port in [&mut self.cpu.bus.port1, &mut self.cpu.bus.port2] {
+            for slot in 0..2 {
+                if let Some(joypad) = port.joypad_mut(slot) {
+                    joypad.step_turbo();
+                }
+            }
+        }
+    }
+
+    pub fn connect_four_score(&mut self) {
+        self.set_peripheral(1, Peripheral::FourScore(FourScore::new(1)));
+        self.set_peripheral(2, Peripheral::FourScore(FourScore::new(2)));
+    }
+
+    // where a Zapper in port 1 or 2 points, and whether it's firing
+    pub fn set_zapper(&mut self, port: usize, aim: Option<(u8, u8)>, trigger: bool) {
+        if let Some(zapper) = self.port_mut(port).zapper_mut() {
+            zapper.aim(aim);
+            zapper.set_trigger(trigger);
+        }
+    }
+
+    // Blowing or shouting into the Famicom's second controller, which
+    // games like Zelda listen for at $4016; a frontend can hold it on with
+    // a key or pass the level of a real microphone to set_microphone_level
+    pub fn set_microphone(&mut self, active: bool) {
+        self.cpu.bus.microphone = active;
+    }
+
+    // `level` is the peak of the last input samples, 0.0-1.0; anything
+    // louder than a voice close to the microphone counts
+    pub fn set_microphone_level(&mut self, level: f32) {
+        self.set_microphone(level >= Nes::MICROPHONE_THRESHOLD);
+    }
+
+    // Arkanoid's paddle in port 1 or 2: the knob turned to `x` of a screen
+    // `width` wide, see Arkanoid::set_position
+    pub fn set_paddle(&mut self, port: usize, x: u32, width: u32, fire: bool) {
+        if let Some(paddle) = self.port_mut(port).arkanoid_mut() {
+            paddle.set_position(x, width);
+            paddle.set_fire(fire);
+        }
+    }
+
+    // the Power Pad buttons held in port 1 or 2, bit n - 1 for button n
+    pub fn set_power_pad(&mut self, port: usize, buttons: u16) {
+        if let Some(pad) = self.port_mut(port).power_pad_mut() {
+            pad.set_buttons(buttons);
+        }
+    }
+
+    pub fn set_peripheral(&mut self, port: usize, peripheral: Peripheral) {
+        *self.port_mut(port) = peripheral;
+    }
+
+    pub fn port_mut(&mut self, port: usize) -> &mut Peripheral {
+        match port {
+            1 => &mut self.cpu.bus.port1,
+            2 => &mut self.cpu.bus.port2,
+            _ => panic!("there is no controller port {}", port),
+        }
+    }
+
+    // Switching the console off and on again, see BUS::power_on for what
+    // comes back as it was at power on and what's kept.
+    pub fn reset(&mut self) {
+        self.movie_commands |= POWER;
+        self.power_cycle();
+    }
+
+    fn power_cycle(&mut self) {
+        self.cpu.bus.power_on();
+        self.cpu.reset();
+        self.mid_frame = false;
+        self.forget_step_history();
+    }
+
+    // Pressing the reset button, which leaves RAM and VRAM alone
+    pub fn soft_reset(&mut self) {
+        self.movie_commands |= SOFT_RESET;
+        self.cpu.soft_reset();
+        self.forget_step_history();
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        self.forget_step_history();
+        Ok(self.cpu.load_state(data)?)
+    }
+
+    // the same state as plain data for serde, see MachineState
+    #[cfg(feature = "serde")]
+    pub fn machine_state(&self) -> MachineState {
+        self.cpu.machine_state()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load_machine_state(&mut self, state: &MachineState) -> Result<(), EmulatorError> {
+        self.forget_step_history();
+        Ok(self.cpu.load_machine_state(state)?)
+    }
+
+    // Called with the console at the end of every frame, after it's been
+    // rendered; for achievement runtimes, see achievements.rs
+    pub fn set_frame_callback<F: FnMut(&Nes) + 'static>(&mut self, callback: F) {
+        self.frame_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_frame_callback(&mut self) {
+        self.frame_callback = None;
+    }
+
+    // Calls `callback` with every event in `events`, alongside any other
+    // subscribers, see events.rs
+    pub fn subscribe<F: FnMut(&Event) + 'static>(
+        &mut self,
+        events: Events,
+        callback: F,
+    ) -> SubscriptionId {
+        self.cpu.bus.subscribe(events, callback)
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.cpu.bus.unsubscribe(id)
+    }
+
+    // nestest.log style trace of every instruction, see trace.rs
+    pub fn set_tracer<F: FnMut(&str) + 'static>(&mut self, tracer: F) {
+        self.cpu.set_tracer(tracer);
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.cpu.clear_tracer();
+    }
+
+    // a hash of the whole console after every frame, see audit.rs
+    pub fn enable_audit(&mut self) {
+        self.audit = Some(Audit::new());
+    }
+
+    pub fn disable_audit(&mut self) -> Option<Audit> {
+        self.audit.take()
+    }
+
+    pub fn audit(&self) -> Option<&Audit> {
+        self.audit.as_ref()
+    }
+
+    // the newest `capacity` writes to PPU registers, see ppu_log.rs
+    pub fn enable_ppu_write_log(&mut self, capacity: usize) {
+        self.cpu.bus.ppu_write_log = Some(PpuWriteLog::new(capacity));
+    }
+
+    pub fn disable_ppu_write_log(&mut self) -> Option<PpuWriteLog> {
+        self.cpu.bus.ppu_write_log.take()
+    }
+
+    pub fn ppu_write_log(&self) -> Option<&PpuWriteLog> {
+        self.cpu.bus.ppu_write_log.as_ref()
+    }
+
+    // instruction and cycle counts per location, see profiler.rs
+    pub fn enable_profiler(&mut self) {
+        self.cpu.enable_profiler();
+    }
+
+    pub fn disable_profiler(&mut self) -> Option<Profiler> {
+        self.cpu.disable_profiler()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.cpu.profiler()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn bus(&self) -> &BUS {
+        &self.cpu.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut BUS {
+        &mut self.cpu.bus
+    }
+}
+
+// the port and Four Score slot player 1-4 is in
+fn player_slot(player: usize) -> (usize, usize) {
+    match player {
+        1 | 2 => (player, 0),
+        3 | 4 => (player - 2, 1),
+        _ => panic!("there is no player {}", player),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use crate::arkanoid::{Arkanoid, KNOB_MAX};
+    use crate::cpu::Mem;
+    use crate::debugger::CallKind;
+    use crate::error::BusFault;
+    use crate::movie::MovieError;
+    use crate::power_pad::PowerPad;
+    use crate::rom::test::nmi_rom;
+    use crate::state::StateError;
+    use crate::zapper::Zapper;
+
+    #[test]
+    fn test_run_frame() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.run_frame();
+        assert_eq!(nes.bus().ppu.scanline, 241);
+
+        // 29780.67 CPU cycles, give or take the last instruction
+        nes.audio_samples();
+        let cycles = nes.bus().cycles();
+        nes.run_frame();
+        let frame_cycles = nes.bus().cycles() - cycles;
+        assert!((29_774..=29_788).contains(&frame_cycles));
+
+        // about 735 samples a frame at 44.1kHz
+        let samples = nes.audio_samples().len();
+        assert!((730..=740).contains(&samples), "{}", samples);
+        assert!(nes.audio_samples().is_empty());
+    }
+
+    #[test]
+    fn test_audio_recording() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.run_frame();
+        assert!(nes.stop_audio_recording().is_none());
+
+        // the recording keeps every sample though the frontend drains them
+        nes.start_audio_recording();
+        assert!(nes.is_recording_audio());
+        for _ in 0..2 {
+            nes.run_frame();
+            nes.audio_samples();
+        }
+        let recorder = nes.stop_audio_recording().unwrap();
+        assert!(
+            (1460..=1480).contains(&recorder.len()),
+            "{}",
+            recorder.len()
+        );
+        assert!(!nes.is_recording_audio());
+        assert_eq!(recorder.to_wav().len(), 44 + recorder.len() * 2);
+    }
+
+    #[test]
+    fn test_channel_scope() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert!(nes.channel_scope().is_none());
+        nes.enable_channel_scope(1024);
+        // pulse 1 at full constant volume
+        for (addr, value) in [(0x4015, 0x01), (0x4000, 0xdf), (0x4002, 0x40), (0x4003, 0x00)] {
+            nes.bus_mut().mem_write(addr, value);
+        }
+        nes.run_frame();
+        nes.run_frame();
+
+        let scope = nes.channel_scope().unwrap();
+        // two frames of about 735 samples fill it
+        assert_eq!(scope.len(), 1024);
+        let pulse = scope.waveform(Channel::Pulse1);
+        assert!(pulse.iter().any(|&level| level > 0.9));
+        assert!(pulse.iter().any(|&level| level < 0.1));
+        assert!(scope.waveform(Channel::Pulse2).iter().all(|&level| level == 0.0));
+        assert!(nes.disable_channel_scope().is_some());
+    }
+
+    #[test]
+    fn test_frame_callback() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        nes.set_frame_callback(move |nes| log.borrow_mut().push(nes.ram()[0x10]));
+        nes.bus_mut().mem_write(0x0010, 7);
+        nes.run_frame();
+        nes.run_cycles(40_000);
+        assert_eq!(seen.borrow().len(), 2);
+        assert_eq!(seen.borrow()[0], 7);
+
+        nes.clear_frame_callback();
+        nes.run_frame();
+        assert_eq!(seen.borrow().len(), 2);
+        assert_eq!(nes.prg_ram().len(), 0x2000);
+    }
+
+    #[test]
+    fn test_resimulate() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        let state = nes.save_state();
+        nes.set_input(1, Button::A);
+        nes.run_frame();
+        nes.set_input(1, Button::B);
+        nes.run_frame();
+        let ran = nes.save_state();
+        nes.audio_samples();
+
+        // the same frames again, heard once already
+        nes.load_state(&state).unwrap();
+        let mut inputs = vec![Button::A, Button::B].into_iter();
+        nes.resimulate(2, |nes| nes.set_input(1, inputs.next().unwrap()));
+        assert!(nes.audio_samples().is_empty());
+        assert_eq!(nes.save_state(), ran);
+    }
+
+    #[test]
+    fn test_resimulate_redoes_the_history() {
+        let run = |nes: &mut Nes, inputs: &[Button]| {
+            for &input in inputs {
+                nes.set_input(1, input);
+                nes.run_frame();
+            }
+        };
+        let mut consoles = [(); 2].map(|_| {
+            let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+            nes.enable_rewind(1, 1 << 20);
+            nes.enable_audit();
+            nes.record_movie();
+            // over a second of sound nobody takes, so the oldest goes
+            run(&mut nes, &[Button::empty(); 70]);
+            nes
+        });
+        let [nes, expected] = &mut consoles;
+        let state = nes.save_state();
+        run(nes, &[Button::A; 3]);
+        run(expected, &[Button::A, Button::B, Button::B]);
+
+        // the last two frames' input was B after all
+        nes.load_state(&state).unwrap();
+        nes.add_breakpoint(0x8005);
+        let mut inputs = vec![Button::A, Button::B, Button::B].into_iter();
+        nes.resimulate(3, |nes| nes.set_input(1, inputs.next().unwrap()));
+        assert_eq!(nes.save_state(), expected.save_state());
+        assert_eq!(nes.audit(), expected.audit());
+        assert_eq!(nes.audio_samples(), expected.audio_samples());
+        assert_eq!(nes.stop_movie(), expected.stop_movie());
+        assert_eq!(nes.rewind(2), 2);
+        assert_eq!(expected.rewind(2), 2);
+        assert_eq!(nes.save_state(), expected.save_state());
+    }
+
+    #[test]
+    fn test_headless_runs() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        let start = nes.bus().cycles();
+        let output = nes.run_cycles(1000);
+        assert_eq!(output.stop, StopReason::Step);
+        assert!(output.frame.data.iter().all(|&byte| byte == 0));
+        assert!((24..=26).contains(&output.samples.len()));
+        assert!((start + 1000..start + 1010).contains(&nes.bus().cycles()));
+
+        // two frames go by, the backdrop is palette entry 0: grey
+        let output = nes.run_cycles(60_000);
+        assert_eq!(output.stop, StopReason::Step);
+        assert_eq!(output.frame.data[..3], [0x80, 0x80, 0x80]);
+        assert!((1470..=1490).contains(&output.samples.len()));
+
+        // the rest of the frame run_cycles stopped in
+        let output = nes.run_until_frame();
+        assert_eq!(output.stop, StopReason::FrameComplete);
+        assert!((1..=740).contains(&output.samples.len()));
+        assert_eq!(nes.bus().ppu.scanline, 241);
+    }
+
+    #[test]
+    fn test_pal_frames() {
+        let mut rom = nmi_rom(&[]);
+        // iNES 1.0 flags 9: PAL
+        rom[9] = 1;
+        let mut nes = Nes::new(&rom).unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+        nes.run_frame();
+
+        // 341 * 312 / 3.2 = 33247.5 CPU cycles
+        let cycles = nes.bus().cycles();
+        nes.run_frame();
+        let frame_cycles = nes.bus().cycles() - cycles;
+        assert!((33_240..=33_255).contains(&frame_cycles));
+
+        nes.set_region(Region::Dendy);
+        nes.run_frame();
+        assert_eq!(nes.bus().ppu.scanline, 291);
+        assert!((nes.frame_rate() - 50.007).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_input_and_state() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.set_input(1, Button::A | Button::START);
+        assert_eq!(
+            nes.port_mut(1).joypad_mut(0).unwrap().button_status(),
+            Button::A | Button::START
+        );
+
+        nes.connect_four_score();
+        nes.set_input(4, Button::B);
+        assert_eq!(
+            nes.port_mut(2).joypad_mut(1).unwrap().button_status(),
+            Button::B
+        );
+
+        nes.run_frame();
+        let state = nes.save_state();
+        nes.run_frame();
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.save_state(), state);
+        assert!(Nes::new(&[0; 4]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_machine_state_as_json() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.set_input(1, Button::A);
+        nes.run_frame();
+        let state = nes.save_state();
+        let json = serde_json::to_string(&nes.machine_state()).unwrap();
+        assert!(json.contains(r#""program_counter":32773"#));
+
+        nes.run_frame();
+        let machine_state = serde_json::from_str(&json).unwrap();
+        nes.load_machine_state(&machine_state).unwrap();
+        assert_eq!(nes.save_state(), state);
+
+        // a state with a joypad in port 2 doesn't fit, and isn't loaded
+        nes.run_frame();
+        nes.set_peripheral(2, Peripheral::Zapper(Zapper::new()));
+        let state = nes.save_state();
+        assert_eq!(
+            nes.load_machine_state(&machine_state),
+            Err(EmulatorError::InvalidState(StateError::Mismatch))
+        );
+        assert_eq!(nes.save_state(), state);
+    }
+
+    #[test]
+    fn test_zapper_in_port_2() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        let state = nes.save_state();
+        nes.set_peripheral(2, Peripheral::Zapper(Zapper::new()));
+        nes.set_zapper(2, Some((10, 10)), true);
+        nes.set_input(2, Button::A);
+        assert_eq!(nes.bus_mut().mem_read(0x4017) & 0b1_1000, 0b1_1000);
+
+        // a state with a joypad in port 2 doesn't fit
+        assert_eq!(
+            nes.load_state(&state),
+            Err(EmulatorError::InvalidState(StateError::Mismatch))
+        );
+    }
+
+    #[test]
+    fn test_turbo_is_recorded() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.set_turbo(1, Button::A, 1, 1);
+        nes.record_movie();
+        for _ in 0..4 {
+            nes.set_input(1, Button::A | Button::START);
+            nes.run_frame();
+        }
+        let movie = nes.stop_movie().unwrap();
+        let buttons: Vec<Button> = movie.frames.iter().map(|frame| frame.buttons[0]).collect();
+        let both = Button::A | Button::START;
+        assert_eq!(buttons, [both, Button::START, both, Button::START]);
+    }
+
+    #[test]
+    fn test_microphone() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert_eq!(nes.bus_mut().mem_read(0x4016) & 0x04, 0);
+        nes.set_microphone(true);
+        assert_eq!(nes.bus_mut().mem_read(0x4016) & 0x04, 0x04);
+        // port 2 has no microphone bit
+        assert_eq!(nes.bus_mut().mem_read(0x4017) & 0x04, 0);
+        nes.set_microphone_level(0.1);
+        assert_eq!(nes.bus_mut().mem_read(0x4016) & 0x04, 0);
+        nes.set_microphone_level(0.8);
+        assert_eq!(nes.bus_mut().mem_read(0x4016) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_microphone_in_movies() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.record_movie();
+        nes.set_input(2, Button::START);
+        nes.run_frame();
+        for frame in 0..4 {
+            nes.set_microphone(frame % 2 == 0);
+            nes.run_frame();
+        }
+        let movie = nes.stop_movie().unwrap();
+        assert!(movie.microphone);
+        let starts: Vec<bool> = movie
+            .frames
+            .iter()
+            .map(|frame| frame.buttons[1].contains(Button::START))
+            .collect();
+        assert_eq!(starts, [true, true, false, true, false]);
+
+        // played back, player 2's Start is only the microphone
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.play_movie(movie).unwrap();
+        let mut heard = Vec::new();
+        for _ in 0..5 {
+            nes.run_frame();
+            heard.push(nes.bus_mut().mem_read(0x4016) & 0x04 != 0);
+            assert_eq!(nes.input(2), Button::empty());
+        }
+        assert_eq!(heard, [true, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_paddle_and_power_pad() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.set_peripheral(2, Peripheral::Arkanoid(Arkanoid::new()));
+        nes.set_paddle(2, 255, 256, true);
+        nes.bus_mut().mem_write(0x4016, 1);
+        nes.bus_mut().mem_write(0x4016, 0);
+        let mut knob = 0;
+        for _ in 0..8 {
+            let read = nes.bus_mut().mem_read(0x4017);
+            assert_eq!(read & 0x08, 0x08);
+            knob = (knob << 1) | ((!read >> 4) & 1);
+        }
+        assert_eq!(knob, KNOB_MAX);
+
+        nes.set_peripheral(2, Peripheral::PowerPad(PowerPad::new()));
+        // button 2 comes first
+        nes.set_power_pad(2, 0b10);
+        nes.bus_mut().mem_write(0x4016, 1);
+        nes.bus_mut().mem_write(0x4016, 0);
+        assert_eq!(nes.bus_mut().mem_read(0x4017) & 0x18, 0x08);
+        assert_eq!(nes.bus_mut().mem_read(0x4017) & 0x18, 0x00);
+        let state = nes.save_state();
+        nes.load_state(&state).unwrap();
+    }
+
+    #[test]
+    fn test_reload_keeps_debugging() {
+        let mut old = Nes::new(&nmi_rom(&[])).unwrap();
+        old.add_breakpoint(0x8005);
+        old.add_watchpoint(0x0300, Access::Write);
+        old.add_cheat("8001?80:00").unwrap();
+        let mut symbols = Symbols::new();
+        symbols.load_nl("$8005#loop#", None).unwrap();
+        old.set_symbols(symbols);
+        assert_eq!(old.run_frame(), StopReason::Breakpoint(0x8005));
+
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.keep_debugging(&mut old);
+        assert_eq!(nes.cpu().label(0x8005), Some("loop"));
+        // stops at the breakpoint again rather than carrying on from it
+        assert_eq!(nes.run_frame(), StopReason::Breakpoint(0x8005));
+        // and the cheat kept NMIs off
+        assert_eq!(nes.bus().ppu.control.bits(), 0);
+        assert!(old.breakpoint(0x8005).is_none());
+    }
+
+    #[test]
+    fn test_cheats_patch_reads() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        // LDA #$80 becomes LDA #$00, so NMIs stay off
+        let cheat = nes.add_cheat("8001?80:00").unwrap();
+        nes.reset();
+        nes.run_frame();
+        assert_eq!(nes.bus().ppu.control.bits(), 0);
+
+        nes.set_cheat_enabled(cheat, false);
+        nes.reset();
+        nes.run_frame();
+        assert_eq!(nes.bus().ppu.control.bits(), 0x80);
+        assert!(nes.add_cheat("8001").is_err());
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert_eq!(nes.rewind(10), 0);
+        nes.enable_rewind(5, 1 << 20);
+        for _ in 0..12 {
+            nes.run_frame();
+        }
+        let cycles = nes.bus().cycles();
+        // states from frames 1, 6 and 11
+        assert_eq!(nes.rewind(6), 6);
+        let frame_cycles = (cycles - nes.bus().cycles()) / 6;
+        assert!((29_770..=29_790).contains(&frame_cycles));
+    }
+
+    #[test]
+    fn test_conditional_breakpoints() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert!(nes.add_conditional_breakpoint(0x8005, "cycles >", 0).is_err());
+        // only the loop in vblank counts, so not in the first frame
+        nes.add_conditional_breakpoint(0x8005, "scanline >= 241", 3).unwrap();
+        assert_eq!(nes.run_frame(), StopReason::FrameComplete);
+        assert_eq!(nes.run_frame(), StopReason::Breakpoint(0x8005));
+        assert_eq!(nes.breakpoint(0x8005).unwrap().hits, 3);
+        assert_eq!(nes.debug_state().scanline, 241);
+        assert_eq!(nes.resume(), StopReason::Breakpoint(0x8005));
+        assert_eq!(nes.breakpoint(0x8005).unwrap().hits, 4);
+        nes.remove_breakpoint(0x8005);
+        assert_eq!(nes.resume(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn test_step_out_of_nmi() {
+        // the NMI handler: NOP; RTI
+        let mut rom = nmi_rom(&[]);
+        rom[16 + 0x100..16 + 0x102].copy_from_slice(&[0xea, 0x40]);
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.add_breakpoint(0x8101);
+        nes.run_frame();
+        assert_eq!(nes.run_frame(), StopReason::Breakpoint(0x8101));
+        let call_stack = nes.call_stack();
+        assert_eq!(call_stack.len(), 1);
+        assert_eq!(call_stack[0].kind, CallKind::Nmi);
+        assert_eq!((call_stack[0].from, call_stack[0].to), (0x8005, 0x8100));
+
+        assert_eq!(nes.step_out(), StopReason::Step);
+        assert_eq!(nes.debug_state().pc, 0x8005);
+        assert!(nes.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_watch_expressions() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert!(matches!(
+            nes.add_watch("a ==", false),
+            Err(EmulatorError::InvalidExpression(_))
+        ));
+        let pc = nes.add_watch("pc", false).unwrap();
+        // PPUCTRL gets its NMI bit from the third instruction
+        let ctrl = nes.add_watch("ctrl & $80", true).unwrap();
+        assert_eq!(nes.run_frame(), StopReason::WatchExpression(ctrl));
+        assert_eq!(nes.debug_state().pc, 0x8005);
+        assert_eq!(nes.watch_values(), [("pc", 0x8005), ("ctrl & $80", 0x80)]);
+
+        // only going from false to true stops it again
+        assert_eq!(nes.resume(), StopReason::FrameComplete);
+        nes.remove_watch(ctrl);
+        assert_eq!(nes.watch_values().len(), 1);
+        assert_eq!(nes.watch_values()[0].0, "pc");
+
+        // removing one leaves the others' ids as they were
+        let a = nes.add_watch("a", false).unwrap();
+        nes.remove_watch(pc);
+        assert_eq!(nes.watch_values().len(), 1);
+        nes.remove_watch(a);
+        assert!(nes.watch_values().is_empty());
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert!(!nes.step_back());
+        nes.enable_reverse_step(1 << 20);
+        nes.run_frame();
+        nes.run_frame();
+        // a little way before the end of the frame
+        nes.run_cycles(29_700);
+        let mut states = vec![nes.save_state()];
+        for i in 0..60 {
+            if i == 40 {
+                // the frame ended a few instructions ago
+                assert_eq!(nes.run_frame(), StopReason::FrameComplete);
+            } else {
+                nes.step();
+            }
+            states.push(nes.save_state());
+        }
+        for state in states[..60].iter().rev() {
+            assert!(nes.step_back());
+            assert_eq!(&nes.save_state(), state);
+        }
+
+        // stepping back onto a breakpoint doesn't stop going on from it
+        nes.add_breakpoint(0x8005);
+        assert!(nes.step_back());
+        assert_eq!(nes.debug_state().pc, 0x8005);
+        assert_eq!(nes.step(), StopReason::Step);
+        assert_eq!(nes.save_state(), states[0]);
+
+        // nothing to go back to from a state loaded from elsewhere
+        nes.load_state(&states[20]).unwrap();
+        assert!(!nes.step_back());
+        nes.disable_reverse_step();
+        assert!(!nes.step_back());
+    }
+
+    #[test]
+    fn test_movie_playback() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.record_movie();
+        for frame in 0..10 {
+            nes.set_input(1, Button::from_bits_truncate(frame));
+            if frame == 5 {
+                nes.soft_reset();
+            }
+            nes.run_frame();
+        }
+        let state = nes.save_state();
+        let movie = nes.stop_movie().unwrap();
+        assert_eq!(movie.frames.len(), 10);
+        assert_eq!(movie.frames[3].buttons[0], Button::from_bits_truncate(3));
+        assert_eq!(movie.frames[5].commands, SOFT_RESET);
+
+        // the same input on the same frames ends in the same state
+        let movie = Movie::from_fm2(&movie.to_fm2()).unwrap();
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.set_input(1, Button::START);
+        nes.play_movie(movie.clone()).unwrap();
+        for _ in 0..10 {
+            assert!(nes.is_playing());
+            nes.run_frame();
+        }
+        assert!(!nes.is_playing());
+        assert_eq!(nes.save_state(), state);
+
+        let mut other = nmi_rom(&[]);
+        other[0x20] = 0;
+        let mut nes = Nes::new(&other).unwrap();
+        assert!(matches!(
+            nes.play_movie(movie),
+            Err(EmulatorError::InvalidMovie(MovieError::WrongRom(_)))
+        ));
+    }
+
+    #[test]
+    fn test_peeking_has_no_side_effects() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.run_frame();
+        nes.bus_mut().mem_write(0x0300, 0x12);
+        nes.bus_mut().mem_write(0x0301, 0x34);
+        assert_eq!(nes.peek_range(0x0b00, 2), vec![0x12, 0x34]);
+
+        // in vblank, and peeking at $2002 doesn't end it
+        assert_eq!(nes.peek(0x2002) & 0x80, 0x80);
+        assert_eq!(nes.peek(0x3ffa) & 0x80, 0x80);
+        assert_eq!(nes.bus_mut().mem_read(0x2002) & 0x80, 0x80);
+        assert_eq!(nes.peek(0x2002) & 0x80, 0);
+
+        // $2007 and its buffer stay put
+        nes.bus_mut().ppu.vram[0] = 0x24;
+        nes.bus_mut().ppu.palette_table[1] = 0x16;
+        nes.bus_mut().mem_write(0x2006, 0x20);
+        nes.bus_mut().mem_write(0x2006, 0x00);
+        assert_eq!(nes.peek(0x2007), 0);
+        assert_eq!(nes.peek_ppu_range(0x3000, 1), vec![0x24]);
+        assert_eq!(nes.peek_ppu(0x3f21), 0x16);
+        assert_eq!(nes.bus().ppu.address.get(), 0x2000);
+        assert_eq!(nes.peek(0x8000), 0xa9);
+    }
+
+    #[test]
+    fn test_profiler() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        assert!(nes.profiler().is_none());
+        let start = nes.bus().cycles();
+        nes.enable_profiler();
+        nes.run_frame();
+        nes.run_frame();
+
+        // nearly all the time goes into the JMP loop at $8005
+        let profiler = nes.disable_profiler().unwrap();
+        let (hottest, counts) = profiler.hot_spots()[0];
+        assert_eq!(hottest.pc, 0x8005);
+        assert_eq!(hottest.prg_offset, Some(0x0005));
+        assert_eq!(counts.cycles, counts.instructions * 3);
+        let cycles = nes.bus().cycles() - start;
+        assert_eq!(profiler.total().cycles, cycles as u64);
+        assert_eq!(profiler.opcode_counts()[&0x40], 1);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.pause();
+        assert!(nes.is_paused());
+        let cycles = nes.bus().cycles();
+        assert_eq!(nes.run_frame(), StopReason::Paused);
+        assert_eq!(nes.run_cycles(100).stop, StopReason::Paused);
+        assert_eq!(nes.bus().cycles(), cycles);
+
+        assert_eq!(nes.step(), StopReason::Step);
+        nes.unpause();
+        assert_eq!(nes.run_frame(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        // LDA $4018 in place of the loop
+        let mut rom = nmi_rom(&[]);
+        rom[16 + 5..16 + 8].copy_from_slice(&[0xad, 0x18, 0x40]);
+
+        let mut nes = Nes::new(&rom).unwrap();
+        assert_eq!(nes.run_frame(), StopReason::FrameComplete);
+
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.set_strict(true);
+        assert_eq!(
+            nes.run_frame(),
+            StopReason::Fault(BusFault {
+                addr: 0x4018,
+                access: Access::Read
+            })
+        );
+        assert_eq!(nes.cpu().program_counter, 0x8008);
+    }
+
+    #[test]
+    fn test_soft_reset_keeps_ram() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.run_frame();
+        nes.bus_mut().mem_write(0x0010, 0x42);
+        nes.bus_mut().ppu.vram[0] = 0x24;
+        let stack_pointer = nes.cpu().stack_pointer;
+
+        nes.soft_reset();
+        assert_eq!(nes.cpu().program_counter, 0x8000);
+        assert_eq!(nes.cpu().stack_pointer, stack_pointer.wrapping_sub(3));
+        assert_eq!(nes.bus().ppu.control.bits(), 0);
+        assert_eq!(nes.bus_mut().mem_read(0x0010), 0x42);
+        assert_eq!(nes.bus().ppu.vram[0], 0x24);
+
+        nes.reset();
+        assert_eq!(nes.cpu().stack_pointer, 0xfd);
+        assert_eq!(nes.bus_mut().mem_read(0x0010), 0);
+        assert_eq!(nes.bus().ppu.vram[0], 0);
+    }
+
+    #[test]
+    fn test_reset_powers_the_cartridge_on() {
+        // the same code as UxROM, bank 0 at $8000 and the last fixed above
+        let mut rom = nmi_rom(&[]);
+        rom[6] = 0x21;
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.run_frame();
+        nes.bus_mut().mem_write(0x8000, 1);
+        assert_eq!(nes.peek(0x8000), 0xea);
+        nes.bus_mut().apu.write_register(0x4015, 0x01);
+        nes.bus_mut().apu.write_register(0x4003, 0x08);
+
+        nes.reset();
+        assert_eq!(nes.peek(0x8000), 0xa9);
+        assert_eq!(nes.bus_mut().apu.read_status(), 0);
+        assert_eq!(nes.bus().ppu.scanline, 0);
+        nes.run_frame();
+        assert_eq!(nes.bus().ppu.control.bits(), 0x80);
+    }
+
+    #[test]
+    fn test_breakpoints_pause_the_frame() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.add_breakpoint(0x8005);
+        assert_eq!(nes.run_frame(), StopReason::Breakpoint(0x8005));
+        assert_eq!(nes.debug_state().pc, 0x8005);
+        assert_eq!(nes.step(), StopReason::Step);
+        assert_eq!(nes.debug_state().pc, 0x8005);
+
+        nes.remove_breakpoint(0x8005);
+        nes.add_watchpoint(0x2000, Access::Write);
+        assert_eq!(nes.resume(), StopReason::FrameComplete);
+
+        // the NMI handler doesn't touch $2000, so only a reset does
+        nes.reset();
+        match nes.run_frame() {
+            StopReason::Watchpoint(hit) => assert_eq!(hit.value, 0x80),
+            reason => panic!("{:?}", reason),
+        }
+    }
+
+    #[test]
+    fn test_ppu_breakpoints() {
+        let mut nes = Nes::new(&nmi_rom(&[])).unwrap();
+        nes.run_frame();
+        nes.add_ppu_breakpoint(120, 200);
+        let stop = nes.run_frame();
+        assert_eq!(
+            stop,
+            StopReason::PpuPosition {
+                scanline: 120,
+                dot: 200
+            }
+        );
+        // the instruction it happened during has finished
+        let state = nes.debug_state();
+        assert_eq!(state.scanline, 120);
+        assert!((200..200 + 7 * 3).contains(&state.dot));
+
+        // the frame finishes, and the next one stops a frame's cycles later
+        let cycles = nes.bus().cycles();
+        assert_eq!(nes.resume(), StopReason::FrameComplete);
+        assert_eq!(nes.run_frame(), stop);
+        assert!((29_770..29_790).contains(&(nes.bus().cycles() - cycles)));
+        nes.remove_ppu_breakpoint(120, 200);
+        assert_eq!(nes.resume(), StopReason::FrameComplete);
+        assert_eq!(nes.run_frame(), StopReason::FrameComplete);
+    }
+}
@@ -0,0 +1,672 @@
+use crate::coop::{CoopSession, Port};
+use crate::debugger::StopReason;
+use crate::joypad::Button;
+use crate::md5::md5;
+use crate::nes::Nes;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+// Two player netplay in lockstep: each console runs the same frames with
+// the same input, so only the input has to travel. The host plays on port
+// 1 and the guest on port 2; a CoopSession delays both by `delay` frames so
+// the other side's input has time to arrive, and a console waits for it
+// when it hasn't. Only joypad buttons travel: the Famicom microphone and
+// the other controllers aren't sent, so frontends leave them alone.
+//
+// Both have to be on the same ROM, in the same state: started fresh, with
+// the same settings. Every CHECKSUM_INTERVAL frames they swap an MD5 of
+// their save state, and a difference is a desync.
+//
+// Messages go over TCP, which keeps the input in order and doesn't lose
+// any; each is a tag byte and its fields:
+//   0 hello: MD5 of the ROM (16 bytes), delay (1)
+//   1 input: buttons (1), for the next frame the sender hasn't sent yet
+//   2 checksum: frame (u64, little endian), MD5 of the state (16)
+pub const CHECKSUM_INTERVAL: u64 = 60;
+
+const HELLO: u8 = 0;
+const INPUT: u8 = 1;
+const CHECKSUM: u8 = 2;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    Disconnected,
+    InvalidMessage(u8),
+    DifferentRom,
+    // the other side's delay
+    DifferentDelay(usize),
+    // the first frame the states were seen to differ at
+    Desync(u64),
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetplayError::Io(err) => write!(f, "netplay connection failed: {}", err),
+            NetplayError::Disconnected => write!(f, "the other player disconnected"),
+            NetplayError::InvalidMessage(tag) => write!(f, "invalid netplay message {}", tag),
+            NetplayError::DifferentRom => write!(f, "the other player is running another ROM"),
+            NetplayError::DifferentDelay(delay) => {
+                write!(f, "the other player has a delay of {} frames", delay)
+            }
+            NetplayError::Desync(frame) => write!(f, "desynced by frame {}", frame),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+impl From<io::Error> for NetplayError {
+    fn from(err: io::Error) -> Self {
+        NetplayError::Io(err)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Message {
+    Hello { rom: [u8; 16], delay: u8 },
+    Input(u8),
+    Checksum { frame: u64, state: [u8; 16] },
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Message::Hello { rom, delay } => [&[HELLO][..], rom, &[*delay]].concat(),
+            Message::Input(buttons) => vec![INPUT, *buttons],
+            Message::Checksum { frame, state } => {
+                [&[CHECKSUM][..], &frame.to_le_bytes(), state].concat()
+            }
+        }
+    }
+
+    // the first message in `input`, taken off it, if it's all there
+    fn take(input: &mut Vec<u8>) -> Result<Option<Message>, NetplayError> {
+        let tag = match input.first() {
+            Some(&tag) => tag,
+            None => return Ok(None),
+        };
+        let len = match tag {
+            HELLO => 18,
+            INPUT => 2,
+            CHECKSUM => 25,
+            _ => return Err(NetplayError::InvalidMessage(tag)),
+        };
+        if input.len() < len {
+            return Ok(None);
+        }
+        let bytes: Vec<u8> = input.drain(..len).collect();
+        let md5_at = |at: usize| {
+            let mut digest = [0; 16];
+            digest.copy_from_slice(&bytes[at..at + 16]);
+            digest
+        };
+        Ok(Some(match tag {
+            HELLO => Message::Hello {
+                rom: md5_at(1),
+                delay: bytes[17],
+            },
+            INPUT => Message::Input(bytes[1]),
+            _ => {
+                let mut frame = [0; 8];
+                frame.copy_from_slice(&bytes[1..9]);
+                Message::Checksum {
+                    frame: u64::from_le_bytes(frame),
+                    state: md5_at(9),
+                }
+            }
+        }))
+    }
+}
+
+// The connection and what both kinds of session do with it: the hello, and
+// comparing state checksums
+struct Connection {
+    stream: TcpStream,
+    input: Vec<u8>,
+    rom: [u8; 16],
+    delay: usize,
+    local_checksums: HashMap<u64, [u8; 16]>,
+    remote_checksums: HashMap<u64, [u8; 16]>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, nes: &Nes, delay: usize) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream,
+            input: Vec::new(),
+            rom: nes.rom_checksum(),
+            delay: delay.min(u8::MAX as usize),
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+        };
+        connection.send(&Message::Hello {
+            rom: connection.rom,
+            delay: connection.delay as u8,
+        })?;
+        Ok(connection)
+    }
+
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        self.stream.write_all(&message.encode())
+    }
+
+    fn send_input(&mut self, buttons: Button) -> io::Result<()> {
+        self.send(&Message::Input(buttons.bits()))
+    }
+
+    // the state after `frame` frames, to compare with the other side's
+    fn send_checksum(&mut self, frame: u64, state: &[u8]) -> Result<(), NetplayError> {
+        let state = md5(state);
+        self.send(&Message::Checksum { frame, state })?;
+        self.local_checksums.insert(frame, state);
+        self.compare(frame)
+    }
+
+    // Takes in whatever the other side has sent, without waiting for more,
+    // and returns the input in it
+    fn receive(&mut self) -> Result<Vec<u8>, NetplayError> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(NetplayError::Disconnected),
+                Ok(read) => self.input.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        self.stream.set_nonblocking(false)?;
+
+        let mut inputs = Vec::new();
+        while let Some(message) = Message::take(&mut self.input)? {
+            match message {
+                Message::Hello { rom, delay } => {
+                    if rom != self.rom {
+                        return Err(NetplayError::DifferentRom);
+                    }
+                    if delay as usize != self.delay {
+                        return Err(NetplayError::DifferentDelay(delay as usize));
+                    }
+                }
+                Message::Input(buttons) => inputs.push(buttons),
+                Message::Checksum { frame, state } => {
+                    self.remote_checksums.insert(frame, state);
+                    self.compare(frame)?;
+                }
+            }
+        }
+        Ok(inputs)
+    }
+
+    fn compare(&mut self, frame: u64) -> Result<(), NetplayError> {
+        if let (Some(local), Some(remote)) = (
+            self.local_checksums.get(&frame),
+            self.remote_checksums.get(&frame),
+        ) {
+            if local != remote {
+                return Err(NetplayError::Desync(frame));
+            }
+            self.local_checksums.remove(&frame);
+            self.remote_checksums.remove(&frame);
+        }
+        Ok(())
+    }
+}
+
+fn accept<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+    let (stream, _) = TcpListener::bind(addr)?.accept()?;
+    Ok(stream)
+}
+
+pub struct Netplay {
+    connection: Connection,
+    session: CoopSession,
+    // local input sent, beyond the delay's empty frames
+    sent: u64,
+}
+
+impl Netplay {
+    // Waits for a guest to connect on `addr`; the host is player 1
+    pub fn host<A: ToSocketAddrs>(addr: A, nes: &Nes, delay: usize) -> io::Result<Self> {
+        Netplay::new(accept(addr)?, Port::Two, nes, delay)
+    }
+
+    // Joins a host as player 2
+    pub fn connect<A: ToSocketAddrs>(addr: A, nes: &Nes, delay: usize) -> io::Result<Self> {
+        Netplay::new(TcpStream::connect(addr)?, Port::One, nes, delay)
+    }
+
+    // over a connection made some other way, with the other side's port
+    pub fn new(stream: TcpStream, remote_port: Port, nes: &Nes, delay: usize) -> io::Result<Self> {
+        let connection = Connection::new(stream, nes, delay)?;
+        Ok(Netplay {
+            session: CoopSession::new(remote_port, connection.delay),
+            connection,
+            sent: 0,
+        })
+    }
+
+    pub fn local_port(&self) -> Port {
+        self.session.local_port()
+    }
+
+    // frames run together so far
+    pub fn frame(&self) -> u64 {
+        self.session.frame()
+    }
+
+    // Sends `buttons` as the local player's input, once for each frame, and
+    // runs the next frame if the other side's input for it is in. Ok(None)
+    // when it isn't yet, or the console is paused: call again with the
+    // same buttons until it runs.
+    pub fn run_frame(
+        &mut self,
+        nes: &mut Nes,
+        buttons: Button,
+    ) -> Result<Option<StopReason>, NetplayError> {
+        if self.sent <= self.session.frame() {
+            self.connection.send_input(buttons)?;
+            self.session.push_local(buttons.bits());
+            self.sent += 1;
+        }
+        for buttons in self.connection.receive()? {
+            self.session.push_remote(buttons);
+        }
+        if nes.is_paused() {
+            return Ok(None);
+        }
+        let (port1, port2) = match self.session.next_frame() {
+            Some(input) => input,
+            None => return Ok(None),
+        };
+        nes.set_input(1, Button::from_bits_truncate(port1));
+        nes.set_input(2, Button::from_bits_truncate(port2));
+        let reason = nes.run_frame();
+
+        let frame = self.session.frame();
+        if frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            self.connection.send_checksum(frame, &nes.save_state())?;
+        }
+        Ok(Some(reason))
+    }
+}
+
+// Netplay that doesn't wait for the other side's input: a frame whose
+// input hasn't arrived runs with the other player holding what they held
+// last, and the console saves its state before every frame. When the real
+// input turns out different, the state from before the first wrong frame
+// is loaded and every frame since runs again with the input as it was.
+// Only when the other side is MAX_ROLLBACK frames behind does it wait.
+//
+// The messages are the same as lockstep's; checksums are taken of states
+// once every input before them is in.
+// from: https://words.infil.net/w02-netcode-p4.html
+pub const MAX_ROLLBACK: u64 = 8;
+
+pub struct Rollback {
+    connection: Connection,
+    remote_port: Port,
+    // frames run so far
+    frame: u64,
+    // every frame's input, starting with the delay's empty frames
+    local_inputs: Vec<u8>,
+    remote_inputs: Vec<u8>,
+    // what each frame last ran with for the other player
+    remote_used: Vec<u8>,
+    // frames before this ran with the other player's real input
+    verified: u64,
+    // states from before the frames that may have to run again
+    states: VecDeque<(u64, Vec<u8>)>,
+    checked: u64,
+    resimulated: u64,
+}
+
+impl Rollback {
+    // Waits for a guest to connect on `addr`; the host is player 1
+    pub fn host<A: ToSocketAddrs>(addr: A, nes: &Nes, delay: usize) -> io::Result<Self> {
+        Rollback::new(accept(addr)?, Port::Two, nes, delay)
+    }
+
+    // Joins a host as player 2
+    pub fn connect<A: ToSocketAddrs>(addr: A, nes: &Nes, delay: usize) -> io::Result<Self> {
+        Rollback::new(TcpStream::connect(addr)?, Port::One, nes, delay)
+    }
+
+    // Over a connection made some other way, with the other side's port. A
+    // delay of a frame or two still helps, making rollbacks shorter.
+    pub fn new(stream: TcpStream, remote_port: Port, nes: &Nes, delay: usize) -> io::Result<Self> {
+        let connection = Connection::new(stream, nes, delay)?;
+        let empty = vec![0; connection.delay];
+        Ok(Rollback {
+            connection,
+            remote_port,
+            frame: 0,
+            local_inputs: empty.clone(),
+            remote_inputs: empty,
+            remote_used: Vec::new(),
+            verified: 0,
+            states: VecDeque::new(),
+            checked: 0,
+            resimulated: 0,
+        })
+    }
+
+    pub fn local_port(&self) -> Port {
+        self.remote_port.other()
+    }
+
+    // frames run so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // frames run a second time or more, after a wrong guess
+    pub fn resimulated(&self) -> u64 {
+        self.resimulated
+    }
+
+    // Like Netplay::run_frame, but only returns Ok(None) when paused or
+    // MAX_ROLLBACK frames ahead of the other side's input
+    pub fn run_frame(
+        &mut self,
+        nes: &mut Nes,
+        buttons: Button,
+    ) -> Result<Option<StopReason>, NetplayError> {
+        if self.local_inputs.len() as u64 <= self.frame + self.connection.delay as u64 {
+            self.connection.send_input(buttons)?;
+            self.local_inputs.push(buttons.bits());
+        }
+        let received = self.connection.receive()?;
+        self.remote_inputs.extend(received);
+        if nes.is_paused() {
+            return Ok(None);
+        }
+        self.correct(nes);
+        self.confirm()?;
+        if self.frame >= self.remote_inputs.len() as u64 + MAX_ROLLBACK {
+            return Ok(None);
+        }
+
+        self.states.push_back((self.frame, nes.save_state()));
+        self.set_input(nes, self.frame);
+        let reason = nes.run_frame();
+        self.frame += 1;
+        Ok(Some(reason))
+    }
+
+    // the other player's input as far as it's known, or a guess
+    fn set_input(&mut self, nes: &mut Nes, frame: u64) {
+        let frame = frame as usize;
+        let local = self.local_inputs[frame];
+        let remote = match self.remote_inputs.get(frame) {
+            Some(&remote) => remote,
+            None => self.remote_inputs.last().copied().unwrap_or(0),
+        };
+        if frame < self.remote_used.len() {
+            self.remote_used[frame] = remote;
+        } else {
+            self.remote_used.push(remote);
+        }
+        let (port1, port2) = match self.remote_port {
+            Port::One => (remote, local),
+            Port::Two => (local, remote),
+        };
+        nes.set_input(1, Button::from_bits_truncate(port1));
+        nes.set_input(2, Button::from_bits_truncate(port2));
+    }
+
+    // runs the frames since the first wrong guess again
+    fn correct(&mut self, nes: &mut Nes) {
+        let known = (self.remote_inputs.len() as u64).min(self.frame);
+        let wrong = (self.verified..known)
+            .find(|&frame| self.remote_used[frame as usize] != self.remote_inputs[frame as usize]);
+        self.verified = known;
+        let wrong = match wrong {
+            Some(wrong) => wrong,
+            None => return,
+        };
+
+        let at = self
+            .states
+            .iter()
+            .position(|&(frame, _)| frame == wrong)
+            .expect("states are kept until their frame's input is in");
+        let (_, state) = self.states.remove(at).unwrap();
+        self.states.truncate(at);
+        nes.load_state(&state)
+            .expect("the console's own state should load");
+        let mut frame = wrong;
+        let frames = self.frame - wrong;
+        nes.resimulate(frames as usize, |nes| {
+            self.states.push_back((frame, nes.save_state()));
+            self.set_input(nes, frame);
+            frame += 1;
+        });
+        self.resimulated += frames;
+    }
+
+    // Checksums the states every input before is in for, and drops those
+    // that can't be rolled back to any more
+    fn confirm(&mut self) -> Result<(), NetplayError> {
+        while let Some((frame, state)) = self.states.front() {
+            let frame = *frame;
+            if frame > self.verified {
+                break;
+            }
+            if frame > self.checked && frame.is_multiple_of(CHECKSUM_INTERVAL) {
+                self.connection.send_checksum(frame, state)?;
+                self.checked = frame;
+            }
+            if frame == self.verified {
+                break;
+            }
+            self.states.pop_front();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use crate::rom::test::nrom;
+
+    // keeps what's pressed on both controllers at $10 and $11
+    const READ_INPUT: [u8; 27] = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, // LDA #1; STA $4016
+        0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #0; STA $4016
+        0xad, 0x16, 0x40, 0x45, 0x10, 0x85, 0x10, // LDA $4016; EOR $10; STA $10
+        0xad, 0x17, 0x40, 0x45, 0x11, 0x85, 0x11, // LDA $4017; EOR $11; STA $11
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+
+    // the host's end and the guest's
+    fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let guest = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (host, _) = listener.accept().unwrap();
+        (host, guest)
+    }
+
+    fn pair(host_nes: &Nes, guest_nes: &Nes, delay: usize) -> (Netplay, Netplay) {
+        let (host, guest) = connected();
+        (
+            Netplay::new(host, Port::Two, host_nes, delay).unwrap(),
+            Netplay::new(guest, Port::One, guest_nes, delay).unwrap(),
+        )
+    }
+
+    // runs both sides until each has done `frames` frames
+    fn run(sides: &mut [(&mut Netplay, &mut Nes, Button); 2], frames: u64) {
+        while sides.iter().any(|(netplay, _, _)| netplay.frame() < frames) {
+            for (netplay, nes, buttons) in sides.iter_mut() {
+                if netplay.frame() < frames {
+                    netplay.run_frame(nes, *buttons).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        for message in [
+            Message::Hello {
+                rom: [7; 16],
+                delay: 2,
+            },
+            Message::Input(0x81),
+            Message::Checksum {
+                frame: 600,
+                state: [9; 16],
+            },
+        ] {
+            let mut input = message.encode();
+            input.push(INPUT);
+            assert_eq!(Message::take(&mut input).unwrap(), Some(message));
+            assert_eq!(Message::take(&mut input).unwrap(), None);
+        }
+        assert!(matches!(
+            Message::take(&mut vec![7]),
+            Err(NetplayError::InvalidMessage(7))
+        ));
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let (mut host_nes, mut guest_nes) = (
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+        );
+        let (mut host, mut guest) = pair(&host_nes, &guest_nes, 2);
+        assert_eq!(host.local_port(), Port::One);
+
+        let mut sides = [
+            (&mut host, &mut host_nes, Button::A),
+            (&mut guest, &mut guest_nes, Button::START),
+        ];
+        run(&mut sides, CHECKSUM_INTERVAL + 1);
+        // each sees both players' input on the right port
+        for nes in [&mut host_nes, &mut guest_nes] {
+            assert_eq!(nes.input(1), Button::A);
+            assert_eq!(nes.input(2), Button::START);
+        }
+        assert_eq!(host_nes.save_state(), guest_nes.save_state());
+    }
+
+    #[test]
+    fn test_desync() {
+        let (mut host_nes, mut guest_nes) = (
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+        );
+        let (mut host, mut guest) = pair(&host_nes, &guest_nes, 0);
+        // something the guest's game doesn't have
+        guest_nes.bus_mut().mem_write(0x0200, 1);
+
+        let mut failed = None;
+        while failed.is_none() && host.frame() < 2 * CHECKSUM_INTERVAL {
+            for (netplay, nes) in [(&mut host, &mut host_nes), (&mut guest, &mut guest_nes)] {
+                if let Err(err) = netplay.run_frame(nes, Button::empty()) {
+                    failed = Some(err);
+                    break;
+                }
+            }
+        }
+        assert!(matches!(
+            failed,
+            Some(NetplayError::Desync(CHECKSUM_INTERVAL))
+        ));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let (mut host_nes, mut guest_nes) = (
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+        );
+        let (host, guest) = connected();
+        let mut host = Rollback::new(host, Port::Two, &host_nes, 0).unwrap();
+        let mut guest = Rollback::new(guest, Port::One, &guest_nes, 0).unwrap();
+        assert_eq!(guest.local_port(), Port::Two);
+
+        // the host goes on without the guest as far as it can, guessing
+        // nothing is pressed on port 2
+        for _ in 0..MAX_ROLLBACK {
+            assert!(host.run_frame(&mut host_nes, Button::A).unwrap().is_some());
+        }
+        assert_eq!(host.run_frame(&mut host_nes, Button::A).unwrap(), None);
+
+        let frames = CHECKSUM_INTERVAL + 1;
+        while guest.frame() < frames {
+            guest.run_frame(&mut guest_nes, Button::START).unwrap();
+            if host.frame() < frames {
+                host.run_frame(&mut host_nes, Button::A).unwrap();
+            }
+        }
+        assert_eq!(host.resimulated(), MAX_ROLLBACK);
+        assert_eq!(host_nes.input(2), Button::START);
+        assert_eq!(host_nes.save_state(), guest_nes.save_state());
+    }
+
+    #[test]
+    fn test_rollback_with_turbo() {
+        let (mut host_nes, mut guest_nes) = (
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+        );
+        for nes in [&mut host_nes, &mut guest_nes] {
+            nes.set_turbo(1, Button::A, 1, 2);
+        }
+        let (host, guest) = connected();
+        let mut host = Rollback::new(host, Port::Two, &host_nes, 0).unwrap();
+        let mut guest = Rollback::new(guest, Port::One, &guest_nes, 0).unwrap();
+
+        // the host runs 2 frames ahead and has to redo them, with its turbo
+        // in step with the guest's
+        let frames = CHECKSUM_INTERVAL + 1;
+        for _ in 0..2 {
+            host.run_frame(&mut host_nes, Button::A).unwrap();
+        }
+        while guest.frame() < frames {
+            guest.run_frame(&mut guest_nes, Button::START).unwrap();
+            if host.frame() < frames {
+                host.run_frame(&mut host_nes, Button::A).unwrap();
+            }
+        }
+        assert_eq!(host.resimulated(), 2);
+        assert_eq!(host_nes.save_state(), guest_nes.save_state());
+    }
+
+    #[test]
+    fn test_rollback_desync() {
+        let (mut host_nes, mut guest_nes) = (
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+            Nes::new(&nrom(&READ_INPUT, &[])).unwrap(),
+        );
+        let (host, guest) = connected();
+        let mut host = Rollback::new(host, Port::Two, &host_nes, 1).unwrap();
+        let mut guest = Rollback::new(guest, Port::One, &guest_nes, 1).unwrap();
+        guest_nes.bus_mut().mem_write(0x0200, 1);
+
+        let mut failed = None;
+        while failed.is_none() && host.frame() < 2 * CHECKSUM_INTERVAL {
+            for (rollback, nes) in [(&mut host, &mut host_nes), (&mut guest, &mut guest_nes)] {
+                if let Err(err) = rollback.run_frame(nes, Button::B) {
+                    failed = Some(err);
+                    break;
+                }
+            }
+        }
+        assert!(matches!(
+            failed,
+            Some(NetplayError::Desync(CHECKSUM_INTERVAL))
+        ));
+    }
+}
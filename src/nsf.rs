@@ -0,0 +1,682 @@
+use crate::bus::BUS;
+use crate::cpu::Mem;
+use crate::debugger::StopReason;
+use crate::mappers::{Banks, Mapper};
+use crate::md5::md5;
+use crate::nes::Nes;
+use crate::region::Region;
+use crate::rom::{Mirroring, Timing};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+
+// NES Sound Format: the music code and data of a game without the game,
+// as an init routine that sets up a track and a play routine called at a
+// steady rate, usually 60 times a second. NSFe keeps the same in chunks
+// and adds track names.
+// from: https://www.nesdev.org/wiki/NSF
+// from: https://www.nesdev.org/wiki/NSFe
+//
+// | Offset | Size | Contents                                       |
+// |--------|------|------------------------------------------------|
+// | $00    | 5    | "NESM" $1A                                     |
+// | $06    | 1    | number of songs                                |
+// | $07    | 1    | first song to play, counting from 1            |
+// | $08    | 2    | load, init and play addresses, 2 bytes each    |
+// | $0E    | 96   | title, artist and copyright, 32 bytes each     |
+// | $6E    | 2    | microseconds between play calls on NTSC        |
+// | $70    | 8    | initial banks, all 0 if they aren't switched   |
+// | $78    | 2    | microseconds between play calls on PAL         |
+// | $7A    | 1    | bit 0: PAL, bit 1: both                        |
+// | $7B    | 1    | expansion sound chips                          |
+// | $7D    | 3    | NSF2: length of the data, 0 for the whole file |
+// | $80    |      | the data, loaded at the load address           |
+//
+// Only the console's own APU plays; tunes for expansion chips are missing
+// those parts.
+const NSF_TAG: &[u8] = b"NESM\x1a";
+const NSFE_TAG: &[u8] = b"NSFE";
+const HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+// NSFe files without a RATE chunk play at these
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+
+#[derive(Debug, PartialEq)]
+pub enum NsfError {
+    InvalidTag,
+    // the file ends inside the header or a chunk
+    Truncated,
+    // an NSFe chunk every file needs, by its name
+    MissingChunk(&'static str),
+    // an NSFe chunk players have to understand to play the file
+    UnsupportedChunk(String),
+    InvalidLoadAddress(u16),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::InvalidTag => write!(f, "File is not in NSF or NSFe format"),
+            NsfError::Truncated => write!(f, "File is truncated"),
+            NsfError::MissingChunk(chunk) => write!(f, "NSFe file has no {} chunk", chunk),
+            NsfError::UnsupportedChunk(chunk) => {
+                write!(f, "NSFe chunk {} is not supported", chunk)
+            }
+            NsfError::InvalidLoadAddress(addr) => {
+                write!(f, "Load address ${:04X} is outside of ROM", addr)
+            }
+        }
+    }
+}
+
+impl core::error::Error for NsfError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: u8,
+    // counting from 0
+    pub start_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    // microseconds between play calls
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub timing: Timing,
+    // the 4KB banks at $8000-$FFFF to start with, for tunes that switch
+    // them through $5FF8-$5FFF
+    pub banks: Option<[u8; 8]>,
+    // bit 0 VRC6, 1 VRC7, 2 FDS, 3 MMC5, 4 Namco 163, 5 Sunsoft 5B
+    pub expansion: u8,
+    // NSFe only, may cover fewer tracks than there are
+    pub track_names: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+// up to the first NUL, which fixed size header fields are padded with
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+fn timing(flags: u8) -> Timing {
+    match flags & 0b11 {
+        0b00 => Timing::Ntsc,
+        0b01 => Timing::Pal,
+        _ => Timing::MultiRegion,
+    }
+}
+
+fn banks(bytes: &[u8]) -> Option<[u8; 8]> {
+    let mut banks = [0; 8];
+    banks[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+    if banks.iter().any(|&bank| bank != 0) {
+        Some(banks)
+    } else {
+        None
+    }
+}
+
+impl Nsf {
+    pub fn is_nsf(bytes: &[u8]) -> bool {
+        bytes.starts_with(NSF_TAG) || bytes.starts_with(NSFE_TAG)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NsfError> {
+        let nsf = if bytes.starts_with(NSF_TAG) {
+            Nsf::from_nsf(bytes)?
+        } else if bytes.starts_with(NSFE_TAG) {
+            Nsf::from_nsfe(bytes)?
+        } else {
+            return Err(NsfError::InvalidTag);
+        };
+        if nsf.load_addr < 0x8000 {
+            return Err(NsfError::InvalidLoadAddress(nsf.load_addr));
+        }
+        Ok(nsf)
+    }
+
+    fn from_nsf(bytes: &[u8]) -> Result<Self, NsfError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(NsfError::Truncated);
+        }
+        let header = &bytes[..HEADER_SIZE];
+        let mut data = &bytes[HEADER_SIZE..];
+        // NSF2 metadata after the data, which isn't loaded
+        let len = u32::from_le_bytes([header[0x7d], header[0x7e], header[0x7f], 0]) as usize;
+        if len != 0 {
+            data = data.get(..len).ok_or(NsfError::Truncated)?;
+        }
+        Ok(Nsf {
+            title: read_string(&header[0x0e..0x2e]),
+            artist: read_string(&header[0x2e..0x4e]),
+            copyright: read_string(&header[0x4e..0x6e]),
+            songs: header[0x06],
+            start_song: header[0x07].saturating_sub(1),
+            load_addr: read_u16(header, 0x08),
+            init_addr: read_u16(header, 0x0a),
+            play_addr: read_u16(header, 0x0c),
+            ntsc_speed: read_u16(header, 0x6e),
+            pal_speed: read_u16(header, 0x78),
+            timing: timing(header[0x7a]),
+            banks: banks(&header[0x70..0x78]),
+            expansion: header[0x7b],
+            track_names: Vec::new(),
+            data: data.to_vec(),
+        })
+    }
+
+    // A chunk is its length (4 bytes), its name (4 characters) and its
+    // data; names starting with a capital letter are ones a player has to
+    // understand, others can be skipped
+    fn from_nsfe(bytes: &[u8]) -> Result<Self, NsfError> {
+        let mut nsf = Nsf {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            songs: 1,
+            start_song: 0,
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            ntsc_speed: NTSC_SPEED,
+            pal_speed: PAL_SPEED,
+            timing: Timing::Ntsc,
+            banks: None,
+            expansion: 0,
+            track_names: Vec::new(),
+            data: Vec::new(),
+        };
+        let (mut info, mut data) = (false, false);
+        let mut offset = NSFE_TAG.len();
+        loop {
+            let header = bytes.get(offset..offset + 8).ok_or(NsfError::Truncated)?;
+            let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let name = &header[4..8];
+            let chunk = bytes
+                .get(offset + 8..offset + 8 + len)
+                .ok_or(NsfError::Truncated)?;
+            offset += 8 + len;
+            match name {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NsfError::Truncated);
+                    }
+                    nsf.load_addr = read_u16(chunk, 0);
+                    nsf.init_addr = read_u16(chunk, 2);
+                    nsf.play_addr = read_u16(chunk, 4);
+                    nsf.timing = timing(chunk[6]);
+                    nsf.expansion = chunk[7];
+                    nsf.songs = chunk.get(8).copied().unwrap_or(1);
+                    nsf.start_song = chunk.get(9).copied().unwrap_or(0);
+                    info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    data = true;
+                }
+                b"BANK" => nsf.banks = banks(chunk),
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        nsf.ntsc_speed = read_u16(chunk, 0);
+                    }
+                    if chunk.len() >= 4 {
+                        nsf.pal_speed = read_u16(chunk, 2);
+                    }
+                }
+                b"NEND" => break,
+                b"auth" => {
+                    let mut strings = chunk.split(|&byte| byte == 0).map(read_string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    let names = chunk.strip_suffix(&[0]).unwrap_or(chunk);
+                    nsf.track_names = names.split(|&byte| byte == 0).map(read_string).collect();
+                }
+                name if name[0].is_ascii_uppercase() => {
+                    return Err(NsfError::UnsupportedChunk(
+                        String::from_utf8_lossy(name).into_owned(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        if !info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        Ok(nsf)
+    }
+
+    // the track's name if the file has one, counting from 0
+    pub fn track_name(&self, track: u8) -> Option<&str> {
+        self.track_names
+            .get(track as usize)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+}
+
+// where init and play return to: a JMP to itself, for the CPU to wait in
+// until the next call
+const IDLE: u16 = 0x4100;
+const IDLE_LOOP: [u8; 3] = [0x4c, (IDLE & 0xff) as u8, (IDLE >> 8) as u8];
+
+// What a hardware NSF player puts in the cartridge slot: the tune's data
+// in 4KB banks at $8000-$FFFF, switched by writes to $5FF8-$5FFF if it
+// asks for that, 8KB of RAM at $6000-$7FFF and the idle loop. Tunes that
+// don't switch banks are loaded as one piece at the load address.
+struct NsfMapper {
+    prg: Vec<u8>,
+    banks: Banks,
+    bank_switching: bool,
+    ram: Vec<u8>,
+}
+
+impl NsfMapper {
+    fn new(nsf: &Nsf) -> Self {
+        let padding = if nsf.banks.is_some() {
+            nsf.load_addr as usize % BANK_SIZE
+        } else {
+            nsf.load_addr as usize - 0x8000
+        };
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(&nsf.data);
+        prg.resize(prg.len().div_ceil(BANK_SIZE).max(8) * BANK_SIZE, 0);
+        NsfMapper {
+            banks: Banks::new(prg.len(), 0x8000, BANK_SIZE),
+            prg,
+            bank_switching: nsf.banks.is_some(),
+            ram: vec![0; 0x2000],
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            IDLE..=0x4102 => IDLE_LOOP[(addr - IDLE) as usize],
+            0x6000..=0x7fff => self.ram[addr as usize - 0x6000],
+            0x8000..=0xffff => self.prg[self.banks.translate(addr as usize - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5ff8..=0x5fff if self.bank_switching => {
+                self.banks.set(addr as usize - 0x5ff8, data as usize)
+            }
+            0x6000..=0x7fff => self.ram[addr as usize - 0x6000] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::HORIZONTAL
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some(self.banks.translate(addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for NsfMapper {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.banks.save_state(state);
+        state.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.banks.load_state(state)?;
+        state.read_bytes_into(&mut self.ram)
+    }
+}
+
+// Plays an NSF on the console: sets a track up through the init routine
+// and calls the play routine at the tune's rate, a video frame's worth of
+// time per run_frame. A play routine still running when the next call is
+// due is left to finish, and that call skipped, as hardware players do.
+//
+//   let mut player = NsfPlayer::new(Nsf::from_bytes(&bytes)?, 44_100);
+//   player.next_track();
+//   let samples = player.run_frame();
+pub struct NsfPlayer {
+    nes: Nes,
+    nsf: Nsf,
+    track: u8,
+    // CPU cycles between play calls, and the cycle the next one is due on
+    play_period: usize,
+    next_play: usize,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf, sample_rate: u32) -> Self {
+        let region = Region::from_timing(nsf.timing);
+        let mapper = Rc::new(RefCell::new(NsfMapper::new(&nsf)));
+        let mut bus = BUS::with_mapper(mapper, 0);
+        bus.set_region(region);
+        let nes = Nes::with_bus(bus, md5(&nsf.data), sample_rate);
+        let speed = match region {
+            Region::Ntsc => nsf.ntsc_speed,
+            Region::Pal | Region::Dendy => nsf.pal_speed,
+        };
+        let play_period = (speed as f64 * region.cpu_clock_rate() / 1_000_000.0) as usize;
+        let mut player = NsfPlayer {
+            nes,
+            track: 0,
+            play_period: play_period.max(1),
+            next_play: 0,
+            nsf,
+        };
+        player.set_track(player.nsf.start_song);
+        player
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    // the console playing, for its picture, sound channels and memory
+    pub fn nes(&self) -> &Nes {
+        &self.nes
+    }
+
+    pub fn nes_mut(&mut self) -> &mut Nes {
+        &mut self.nes
+    }
+
+    // counting from 0
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    // Starts `track` from the beginning, the way the NSF spec says to:
+    // with RAM cleared, the APU silenced and the banks as the file has them
+    pub fn set_track(&mut self, track: u8) {
+        self.track = track % self.nsf.songs.max(1);
+        let banks = self.nsf.banks;
+        let bus = self.nes.bus_mut();
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            bus.mem_write(addr, 0);
+        }
+        if let Some(banks) = banks {
+            for (window, bank) in banks.iter().enumerate() {
+                bus.mem_write(0x5ff8 + window as u16, *bank);
+            }
+        }
+        for addr in 0x4000..=0x4013 {
+            bus.mem_write(addr, 0);
+        }
+        bus.mem_write(0x4015, 0x00);
+        bus.mem_write(0x4015, 0x0f);
+        // no frame counter IRQs
+        bus.mem_write(0x4017, 0x40);
+
+        self.nes.cpu_mut().stack_pointer = 0xfd;
+        let pal = self.nes.region() != Region::Ntsc;
+        self.call(self.nsf.init_addr, self.track, pal as u8);
+        self.next_play = self.nes.bus().cycles() + self.play_period;
+    }
+
+    pub fn next_track(&mut self) {
+        self.set_track(self.track.wrapping_add(1) % self.nsf.songs.max(1));
+    }
+
+    pub fn previous_track(&mut self) {
+        let track = match self.track {
+            0 => self.nsf.songs.max(1) - 1,
+            track => track - 1,
+        };
+        self.set_track(track);
+    }
+
+    // a JSR from the idle loop
+    fn call(&mut self, addr: u16, a: u8, x: u8) {
+        let cpu = self.nes.cpu_mut();
+        let ret = IDLE - 1;
+        for byte in [(ret >> 8) as u8, ret as u8] {
+            cpu.bus.mem_write(0x0100 + cpu.stack_pointer as u16, byte);
+            cpu.stack_pointer = cpu.stack_pointer.wrapping_sub(1);
+        }
+        cpu.register_a = a;
+        cpu.register_x = x;
+        cpu.program_counter = addr;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.nes.cpu().program_counter == IDLE
+    }
+
+    // Plays for the length of a video frame and returns the sound; stops
+    // early if the console is paused or a breakpoint is hit
+    pub fn run_frame(&mut self) -> Vec<f32> {
+        let region = self.nes.region();
+        let frame_cycles = (region.cpu_clock_rate() / region.frame_rate()) as usize;
+        let end = self.nes.bus().cycles() + frame_cycles;
+        let mut samples = Vec::new();
+        loop {
+            let cycles = self.nes.bus().cycles();
+            if cycles >= end {
+                break;
+            }
+            if cycles >= self.next_play {
+                if self.is_idle() {
+                    self.call(self.nsf.play_addr, 0, 0);
+                }
+                self.next_play += self.play_period;
+                continue;
+            }
+            let output = self.nes.run_cycles(self.next_play.min(end) - cycles);
+            samples.extend(output.samples);
+            if !matches!(output.stop, StopReason::Step | StopReason::FrameComplete) {
+                break;
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // init stores the track at $10 and turns on a square wave, play
+    // counts its calls at $11
+    fn test_nsf() -> Vec<u8> {
+        let mut nsf = vec![0; HEADER_SIZE];
+        nsf[..5].copy_from_slice(NSF_TAG);
+        nsf[5] = 1;
+        nsf[6] = 3;
+        nsf[7] = 2;
+        nsf[0x08..0x0e].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x20, 0x80]);
+        nsf[0x0e..0x14].copy_from_slice(b"Test\0x");
+        nsf[0x2e..0x32].copy_from_slice(b"Me\0\0");
+        nsf[0x6e..0x70].copy_from_slice(&NTSC_SPEED.to_le_bytes());
+        nsf[0x78..0x7a].copy_from_slice(&PAL_SPEED.to_le_bytes());
+
+        let mut code = vec![0; 0x30];
+        code[..19].copy_from_slice(&[
+            0x85, 0x10, // STA $10
+            0xa9, 0xbf, // LDA #$BF
+            0x8d, 0x00, 0x40, // STA $4000
+            0xa9, 0x80, // LDA #$80
+            0x8d, 0x02, 0x40, // STA $4002
+            0xa9, 0x08, // LDA #$08
+            0x8d, 0x03, 0x40, // STA $4003
+            0x60, // RTS
+            0x00,
+        ]);
+        code[0x20..0x23].copy_from_slice(&[
+            0xe6, 0x11, // INC $11
+            0x60, // RTS
+        ]);
+        nsf.extend(code);
+        nsf
+    }
+
+    #[test]
+    fn test_nsf_header() {
+        let nsf = Nsf::from_bytes(&test_nsf()).unwrap();
+        assert_eq!(nsf.title, "Test");
+        assert_eq!(nsf.artist, "Me");
+        assert_eq!((nsf.songs, nsf.start_song), (3, 1));
+        assert_eq!(
+            (nsf.load_addr, nsf.init_addr, nsf.play_addr),
+            (0x8000, 0x8000, 0x8020)
+        );
+        assert_eq!(nsf.timing, Timing::Ntsc);
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.data.len(), 0x30);
+
+        assert_eq!(Nsf::from_bytes(b"NES\x1a"), Err(NsfError::InvalidTag));
+        assert_eq!(
+            Nsf::from_bytes(&test_nsf()[..0x40]),
+            Err(NsfError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_nsfe() {
+        let chunk = |name: &[u8], data: &[u8]| {
+            let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+            chunk.extend_from_slice(name);
+            chunk.extend_from_slice(data);
+            chunk
+        };
+        let mut file = NSFE_TAG.to_vec();
+        file.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x20, 0x80, 0x01, 0x00, 2, 1],
+        ));
+        file.extend(chunk(b"BANK", &[0, 1]));
+        file.extend(chunk(b"DATA", &[0x60; 0x2000]));
+        file.extend(chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"));
+        file.extend(chunk(b"tlbl", b"Intro\0Theme\0"));
+        file.extend(chunk(b"psfx", &[1, 2, 3]));
+        file.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        assert_eq!(
+            (nsf.title.as_str(), nsf.artist.as_str()),
+            ("Title", "Artist")
+        );
+        assert_eq!((nsf.songs, nsf.start_song), (2, 1));
+        assert_eq!(nsf.timing, Timing::Pal);
+        assert_eq!(nsf.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.ntsc_speed, NTSC_SPEED);
+        assert_eq!(nsf.track_name(1), Some("Theme"));
+        assert_eq!(nsf.track_name(2), None);
+
+        let mut unknown = file[..file.len() - 8].to_vec();
+        unknown.extend(chunk(b"VRC7", &[]));
+        assert_eq!(
+            Nsf::from_bytes(&unknown),
+            Err(NsfError::UnsupportedChunk("VRC7".to_string()))
+        );
+        let no_data = [
+            NSFE_TAG,
+            &chunk(b"INFO", &[0x00, 0x80, 0, 0x80, 0, 0x80, 0, 0])[..],
+            &chunk(b"NEND", &[]),
+        ]
+        .concat();
+        assert_eq!(
+            Nsf::from_bytes(&no_data),
+            Err(NsfError::MissingChunk("DATA"))
+        );
+    }
+
+    #[test]
+    fn test_player() {
+        let mut player = NsfPlayer::new(Nsf::from_bytes(&test_nsf()).unwrap(), 44_100);
+        assert_eq!(player.track(), 1);
+
+        let mut samples = Vec::new();
+        for _ in 0..10 {
+            samples.extend(player.run_frame());
+        }
+        assert_eq!(player.nes().peek(0x10), 1);
+        // the play rate is a little slower than the frame rate
+        assert!((9..=10).contains(&player.nes().peek(0x11)));
+        assert!(samples.len() > 7000);
+        let (min, max) = samples.iter().fold((f32::MAX, f32::MIN), |(min, max), &s| {
+            (min.min(s), max.max(s))
+        });
+        assert!(max - min > 0.05, "the square wave should be heard");
+
+        player.next_track();
+        assert_eq!(player.track(), 2);
+        assert_eq!(player.nes().peek(0x11), 0);
+        player.run_frame();
+        assert_eq!(player.nes().peek(0x10), 2);
+        player.next_track();
+        assert_eq!(player.track(), 0);
+        player.previous_track();
+        assert_eq!(player.track(), 2);
+    }
+
+    #[test]
+    fn test_as_many_tracks_as_there_can_be() {
+        let mut nsf = test_nsf();
+        nsf[6] = 255;
+        nsf[7] = 1;
+        let mut player = NsfPlayer::new(Nsf::from_bytes(&nsf).unwrap(), 44_100);
+        assert_eq!(player.track(), 0);
+        player.previous_track();
+        assert_eq!(player.track(), 254);
+        player.next_track();
+        assert_eq!(player.track(), 0);
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut nsf = Nsf::from_bytes(&test_nsf()).unwrap();
+        nsf.load_addr = 0x8800;
+        nsf.banks = Some([1, 0, 0, 0, 0, 0, 0, 0]);
+        nsf.data = (0..3u8).flat_map(|bank| vec![bank; BANK_SIZE]).collect();
+        let mut mapper = NsfMapper::new(&nsf);
+        // the data starts $800 into the first bank
+        mapper.cpu_write(0x5ff8, 0);
+        assert_eq!(mapper.cpu_read(0x87ff), 0);
+        assert_eq!(mapper.cpu_read(0x8800), 0);
+        mapper.cpu_write(0x5ff8, 1);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0x8800), 1);
+        mapper.cpu_write(0x5ff9, 2);
+        assert_eq!(mapper.cpu_read(0x9800), 2);
+        assert_eq!(mapper.cpu_peek(IDLE), 0x4c);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::arkanoid::Arkanoid;
+use crate::four_score::FourScore;
+use crate::joypad::Joypad;
+use crate::power_pad::PowerPad;
+use crate::ppu::PPU;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use crate::zapper::Zapper;
+
+// What's plugged into a controller port. Port 1 is read at $4016, port 2 at
+// $4017; writes to $4016 go to both.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Peripheral {
+    Joypad(Joypad),
+    Zapper(Zapper),
+    // one half of the adapter in each port
+    FourScore(FourScore),
+    // Arkanoid's paddle, usually in port 2
+    Arkanoid(Arkanoid),
+    // the exercise mat, usually in port 2
+    PowerPad(PowerPad),
+}
+
+impl Peripheral {
+    // the PPU is there for light guns, which look at the picture
+    pub fn read(&mut self, ppu: &PPU) -> u8 {
+        match self {
+            Peripheral::Joypad(joypad) => joypad.read(),
+            Peripheral::Zapper(zapper) => zapper.read(ppu),
+            Peripheral::FourScore(four_score) => four_score.read(),
+            Peripheral::Arkanoid(paddle) => paddle.read(),
+            Peripheral::PowerPad(pad) => pad.read(),
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        match self {
+            Peripheral::Joypad(joypad) => joypad.write(data),
+            Peripheral::Zapper(_) => {}
+            Peripheral::FourScore(four_score) => four_score.write(data),
+            Peripheral::Arkanoid(paddle) => paddle.write(data),
+            Peripheral::PowerPad(pad) => pad.write(data),
+        }
+    }
+
+    // slot 1 is the second controller on a Four Score
+    pub fn joypad_mut(&mut self, slot: usize) -> Option<&mut Joypad> {
+        match self {
+            Peripheral::Joypad(joypad) if slot == 0 => Some(joypad),
+            Peripheral::FourScore(four_score) => four_score.joypad_mut(slot),
+            _ => None,
+        }
+    }
+
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        match self {
+            Peripheral::Zapper(zapper) => Some(zapper),
+            _ => None,
+        }
+    }
+
+    pub fn arkanoid_mut(&mut self) -> Option<&mut Arkanoid> {
+        match self {
+            Peripheral::Arkanoid(paddle) => Some(paddle),
+            _ => None,
+        }
+    }
+
+    pub fn power_pad_mut(&mut self) -> Option<&mut PowerPad> {
+        match self {
+            Peripheral::PowerPad(pad) => Some(pad),
+            _ => None,
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            Peripheral::Joypad(_) => 0,
+            Peripheral::Zapper(_) => 1,
+            Peripheral::FourScore(_) => 2,
+            Peripheral::Arkanoid(_) => 3,
+            Peripheral::PowerPad(_) => 4,
+        }
+    }
+}
+
+impl Default for Peripheral {
+    fn default() -> Self {
+        Peripheral::Joypad(Joypad::new())
+    }
+}
+
+// Starts with the kind of device, states only load into the same kind.
+// Version 2 states had a joypad in both ports and no kind.
+impl Snapshot for Peripheral {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.kind());
+        match self {
+            Peripheral::Joypad(joypad) => joypad.save_state(state),
+            Peripheral::Zapper(zapper) => zapper.save_state(state),
+            Peripheral::FourScore(four_score) => four_score.save_state(state),
+            Peripheral::Arkanoid(paddle) => paddle.save_state(state),
+            Peripheral::PowerPad(pad) => pad.save_state(state),
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let kind = if state.version() >= 3 {
+            state.read_u8()?
+        } else {
+            0
+        };
+        if kind != self.kind() {
+            return Err(StateError::Mismatch);
+        }
+        match self {
+            Peripheral::Joypad(joypad) => joypad.load_state(state),
+            Peripheral::Zapper(zapper) => zapper.load_state(state),
+            Peripheral::FourScore(four_score) => four_score.load_state(state),
+            Peripheral::Arkanoid(paddle) => paddle.load_state(state),
+            Peripheral::PowerPad(pad) => pad.load_state(state),
+        }
+    }
+}
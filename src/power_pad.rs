@@ -0,0 +1,112 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Power Pad / Family Trainer mat, 12 buttons in 3 rows of 4
+// from: https://www.nesdev.org/wiki/Power_Pad
+//
+//   1  2  3  4
+//   5  6  7  8
+//   9 10 11 12
+//
+// | Bit | Read from $4016/$4017                          |
+// |-----|------------------------------------------------|
+// | 3   | Buttons 2, 1, 5, 9, 6, 10, 11, 7, one per read |
+// | 4   | Buttons 4, 3, 12, 8, then 1s                   |
+//
+// A pressed button reads 1. The strobe latches the buttons like a standard
+// controller's, and once a line's buttons are read it reads 1.
+const LOW_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const HIGH_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerPad {
+    // bit n - 1 for button n
+    pressed: u16,
+    strobe: bool,
+    index: u8,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad::default()
+    }
+
+    // button 1-12
+    pub fn set_button_pressed(&mut self, button: usize, pressed: bool) {
+        if !(1..=12).contains(&button) {
+            return;
+        }
+        let bit = 1 << (button - 1);
+        if pressed {
+            self.pressed |= bit;
+        } else {
+            self.pressed &= !bit;
+        }
+    }
+
+    // bit n - 1 for button n
+    pub fn set_buttons(&mut self, pressed: u16) {
+        self.pressed = pressed & 0x0fff;
+    }
+
+    pub fn buttons(&self) -> u16 {
+        self.pressed
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let line = |order: &[u8]| match order.get(self.index as usize) {
+            Some(&button) => (self.pressed >> (button - 1)) as u8 & 1,
+            None => 1,
+        };
+        let response = (line(&HIGH_ORDER) << 4) | (line(&LOW_ORDER) << 3);
+        if !self.strobe && self.index < 8 {
+            self.index += 1;
+        }
+        response
+    }
+}
+
+impl Snapshot for PowerPad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.pressed);
+        state.write_bool(self.strobe);
+        state.write_u8(self.index);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pressed = state.read_u16()? & 0x0fff;
+        self.strobe = state.read_bool()?;
+        self.index = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_serial_order() {
+        let mut pad = PowerPad::new();
+        pad.set_button_pressed(1, true);
+        pad.set_button_pressed(12, true);
+        pad.set_button_pressed(13, true);
+        assert_eq!(pad.buttons(), 0b1000_0000_0001);
+        pad.write(1);
+        pad.write(0);
+        let reads: Vec<u8> = (0..10).map(|_| pad.read()).collect();
+        // button 1 is the second on bit 3, button 12 the third on bit 4
+        assert_eq!(
+            reads,
+            [0x00, 0x08, 0x10, 0x00, 0x10, 0x10, 0x10, 0x10, 0x18, 0x18]
+        );
+    }
+}
@@ -1,28 +1,76 @@
+mod pixels;
+pub mod raster;
+pub mod sprite;
+mod viewer;
 pub mod registers;
 
+#[cfg(any(feature = "serde", test))]
+use alloc::vec::Vec;
+use crate::heatmap::AccessHeatmap;
+use crate::mappers::MapperRef;
+use crate::region::Region;
 use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+#[cfg(feature = "serde")]
+use crate::state::copy_memory;
+use raster::Raster;
 use registers::control::ControlRegister;
 use registers::mask::MaskRegister;
 use registers::status::StatusRegister;
-use registers::scroll::ScrollRegister;
 use registers::address::AddressRegister;
 
+pub const DOTS_PER_SCANLINE: u16 = 341;
+// the PPU's data bus latch fades to 0 roughly 600ms after it was last driven,
+// counted in CPU cycles so it doesn't depend on the host clock
+const OPEN_BUS_DECAY_CYCLES: usize = 36 * 29781;
+// OAM is dynamic RAM that sprite evaluation refreshes while rendering is on.
+// An 8 byte row nothing has touched for this many CPU cycles with rendering
+// off has lost its contents, and reads back as $10s like it does in Mesen
+// from: https://www.nesdev.org/wiki/PPU_OAM#Dynamic_RAM_decay
+const OAM_DECAY_CYCLES: u32 = 3000;
+const OAM_ROWS: usize = 32;
+
+// $3F00-$3FFF repeats the 32 palette entries, and $3F10/$3F14/$3F18/$3F1C
+// are mirrors of $3F00/$3F04/$3F08/$3F0C
+fn palette_index(addr: u16) -> usize{
+    let index = (addr & 0x1f) as usize;
+    if index & 0x13 == 0x10 { index - 0x10 } else { index }
+}
 
 pub struct PPU{
-    chr_rom: Vec<u8>,
-    pub mirroring: Mirroring,
+    // pattern tables and nametable mirroring live on the cartridge
+    pub mapper: MapperRef,
     pub control: ControlRegister,
     pub mask: MaskRegister,
     pub status: StatusRegister,
     pub address: AddressRegister,
-    pub scroll: ScrollRegister,
     pub vram: [u8; 0x800],
     pub oam_data: [u8; 0x100],
     pub oam_addr: u8,
 
     pub palette_table: [u8; 0x20],
+    pub vram_heatmap: AccessHeatmap,
+    raster: Raster,
+    // set the overflow flag like the hardware does, see sprite.rs
+    pub sprite_overflow_bug: bool,
+    // let OAM rows decay with rendering off, see OAM_DECAY_CYCLES
+    pub oam_decay: bool,
+    // test every dot for sprite 0 hit, see is_sprite_zero_hit
+    pub sprite_zero_hit_dot: bool,
     
     internal_buffer: u8,
+    open_bus: u8,
+    open_bus_age: usize,
+    oam_row_age: [u32; OAM_ROWS],
+
+    // position of the next dot: scanlines 0-239 are visible, 240 is idle,
+    // 241-260 are vblank and 261 prepares the next frame. PAL and Dendy
+    // frames are longer, see region.rs.
+    pub scanline: u16,
+    pub dot: u16,
+    odd_frame: bool,
+    nmi_pending: bool,
+    region: Region,
 }
 
 pub trait PPUInterface{
@@ -40,25 +88,75 @@ pub trait PPUInterface{
 }
 
 impl PPU{
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> PPU{
+    pub fn new(mapper: MapperRef) -> PPU{
         PPU{
-            chr_rom,
-            mirroring,
+            mapper,
             control: ControlRegister::new(),
             mask: MaskRegister::new(),
             status: StatusRegister::new(),
             address: AddressRegister::new(),
-            scroll: ScrollRegister::new(),
             vram: [0; 0x800],
             oam_data: [0; 0x100],
             oam_addr: 0,
             palette_table: [0; 0x20],
+            vram_heatmap: AccessHeatmap::new(0x800),
+            raster: Raster::new(),
+            sprite_overflow_bug: true,
+            oam_decay: true,
+            sprite_zero_hit_dot: true,
             internal_buffer: 0,
+            open_bus: 0,
+            open_bus_age: 0,
+            oam_row_age: [0; OAM_ROWS],
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            nmi_pending: false,
+            region: Region::Ntsc,
         }
     }
 
+    #[cfg(test)]
     pub fn new_empty_rom() -> PPU{
-        PPU::new(vec![0;0x800], Mirroring::HORIZONTAL)
+        PPU::with_chr(alloc::vec![0;0x800], Mirroring::HORIZONTAL)
+    }
+
+    #[cfg(test)]
+    pub fn with_chr(chr_rom: Vec<u8>, mirroring: Mirroring) -> PPU{
+        let mut rom = crate::rom::test::test_rom();
+        rom.chr_rom = chr_rom;
+        rom.screen_mirroring = mirroring;
+        PPU::new(crate::mappers::create(rom).unwrap())
+    }
+
+    // Power on clears everything reset does, and VRAM, OAM, the palettes and
+    // the rest of the registers, and starts from the top of a frame
+    pub fn power_on(&mut self){
+        self.reset();
+        self.status = StatusRegister::new();
+        self.address = AddressRegister::new();
+        self.vram = [0; 0x800];
+        self.oam_data = [0; 0x100];
+        self.oam_addr = 0;
+        self.palette_table = [0; 0x20];
+        self.open_bus = 0;
+        self.open_bus_age = 0;
+        self.oam_row_age = [0; OAM_ROWS];
+        self.scanline = 0;
+        self.dot = 0;
+        self.nmi_pending = false;
+        self.raster.clear();
+    }
+
+    // The reset button clears PPUCTRL, PPUMASK and the write toggle but leaves
+    // VRAM, OAM and the palettes alone
+    pub fn reset(&mut self){
+        self.control = ControlRegister::new();
+        self.mask = MaskRegister::new();
+        self.address.write_control(0);
+        self.address.reset_latch();
+        self.internal_buffer = 0;
+        self.odd_frame = false;
     }
 
     // Vertical:
@@ -69,43 +167,445 @@ impl PPU{
     //   [ A ] [ a ]
     //   [ B ] [ b ]
 
+    // One screen:
+    //   [ A ] [ a ]
+    //   [ a ] [ a ]
+
     pub fn mirror_vram_address(&self, addr: u16) -> u16{
         let mirrored = addr & 0x2FFF;
         let vram_index = mirrored - 0x2000;
         let name_table = vram_index / 0x400;
 
-        match (&self.mirroring, name_table){
+        match (self.mapper.borrow().mirroring(), name_table){
             (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
             (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
+            (Mirroring::ONE_SCREEN_LOWER, _) => vram_index % 0x400,
+            (Mirroring::ONE_SCREEN_UPPER, _) => 0x400 + vram_index % 0x400,
             _ => vram_index,
         }
     }
 
+    pub fn read_chr(&self, addr: u16) -> u8{
+        self.mapper.borrow_mut().ppu_read(addr & 0x1fff)
+    }
+
+    pub fn set_region(&mut self, region: Region){
+        self.region = region;
+    }
+
+    fn rendering_enabled(&self) -> bool{
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    // Runs the PPU for `cycles` dots. Returns true when a frame has been
+    // completed, which is when vblank starts: everything the game set up for
+    // the visible part of the frame is in place.
+    pub fn tick(&mut self, cycles: u16) -> bool{
+        let mut frame_complete = false;
+        let mut cycles = cycles;
+        while cycles > 0{
+            frame_complete |= self.step();
+            cycles -= 1;
+            // without a sprite 0 test on every dot most of a line's dots do
+            // nothing and can be gone straight past
+            if !self.sprite_zero_hit_dot{
+                let idle = (self.next_busy_dot() - self.dot).min(cycles);
+                self.dot += idle;
+                cycles -= idle;
+            }
+        }
+        frame_complete
+    }
+
+    // The next dot of this line, from the current one on, that step does
+    // anything at with sprite 0 looked for once a line. At most 339: the
+    // last two always run, they finish the line and skip the odd frames' dot.
+    fn next_busy_dot(&self) -> u16{
+        let (line, dot) = (self.scanline, self.dot);
+        let pre_render_scanline = self.region.pre_render_scanline();
+        let mut busy = 339;
+        let mut at = |from: u16| if from >= dot { busy = busy.min(from) };
+        if line < 240{
+            at(0);
+        }
+        if line == self.region.vblank_scanline() || line == pre_render_scanline{
+            at(1);
+        }
+        if self.rendering_enabled() && (line < 240 || line == pre_render_scanline){
+            // the coarse X increments, then the copies from t and the fetches
+            at(dot.max(8).next_multiple_of(8).min(256));
+            for from in [257, 260, 328, 336]{
+                at(from);
+            }
+            if line == pre_render_scanline{
+                at(dot.clamp(280, 304));
+            }
+        }
+        busy.max(dot)
+    }
+
+    fn step(&mut self) -> bool{
+        let mut frame_complete = false;
+        let vblank_scanline = self.region.vblank_scanline();
+        let pre_render_scanline = self.region.pre_render_scanline();
+        if self.rendering_enabled() && (self.scanline < 240 || self.scanline == pre_render_scanline){
+            self.update_scroll();
+        }
+        if self.scanline < 240 && self.dot == 0{
+            self.latch_line();
+        }
+        // sprite evaluation for the next line is over by dot 256
+        if self.scanline < 240 && self.dot == 256 && self.rendering_enabled(){
+            self.evaluate_sprite_overflow();
+        }
+        match (self.scanline, self.dot){
+            (line, 1) if line == vblank_scanline => {
+                self.status.set_vblank_status(true);
+                if self.control.generate_nmi(){
+                    self.nmi_pending = true;
+                }
+                frame_complete = true;
+            }
+            (line, 1) if line == pre_render_scanline => {
+                self.status.set_vblank_status(false);
+                self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
+            }
+            (line, 260) if line < 240 || line == pre_render_scanline => self.scanline_fetches(),
+            (line, 0) if line < 240 && !self.sprite_zero_hit_dot
+                && self.is_sprite_zero_hit_on_line(line) => {
+                self.status.set_sprite_zero_hit(true);
+            }
+            (line, dot) if line < 240 && self.sprite_zero_hit_dot
+                && self.is_sprite_zero_hit(line, dot) => {
+                self.status.set_sprite_zero_hit(true);
+            }
+            _ => {}
+        }
+
+        self.dot += 1;
+        // with rendering on, odd frames skip the last dot of the pre-render line
+        let skip = self.scanline == pre_render_scanline && self.dot == 340
+            && self.odd_frame && self.rendering_enabled() && self.region.skips_odd_frame_dot();
+        if self.dot == DOTS_PER_SCANLINE || skip{
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines(){
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+        frame_complete
+    }
+
+    // v following the background fetches of a rendered line, see
+    // registers/address.rs
+    fn update_scroll(&mut self){
+        match self.dot{
+            256 => {
+                self.address.increment_coarse_x();
+                self.address.increment_y();
+            }
+            257 => {
+                self.address.copy_horizontal();
+                self.latch_horizontal();
+            }
+            280..=304 if self.scanline == self.region.pre_render_scanline() => self.address.copy_vertical(),
+            dot if dot > 0 && dot % 8 == 0 && (dot < 256 || dot == 328 || dot == 336) => {
+                self.address.increment_coarse_x();
+            }
+            _ => {}
+        }
+    }
+
+    // An opaque pixel of sprite 0 drawn over an opaque background pixel.
+    // Never at x = 255, nor in the leftmost 8 pixels while either layer is
+    // clipped there.
+    fn is_sprite_zero_hit(&self, scanline: u16, dot: u16) -> bool{
+        if dot == 0 || dot > 255 || self.status.contains(StatusRegister::SPRITE_ZERO_HIT){
+            return false;
+        }
+        if !self.mask.show_background() || !self.mask.show_sprites(){
+            return false;
+        }
+        let x = dot as usize - 1;
+        let y = scanline as usize;
+        if x < 8 && !(self.mask.leftmost_8pxl_background() && self.mask.leftmost_8pxl_sprite()){
+            return false;
+        }
+
+        self.sprite_pixel(0, x, y).is_some_and(|value| value != 0)
+            && self.background_pixel(x, y).0 != 0
+    }
+
+    // The same test for the whole line at once: only the dots sprite 0
+    // covers can hit, so it's 8 tests instead of 256
+    fn is_sprite_zero_hit_on_line(&self, scanline: u16) -> bool{
+        let left = self.sprite(0).x as u16;
+        (left + 1..left + 9).any(|dot| self.is_sprite_zero_hit(scanline, dot))
+    }
+
+    // true once for each NMI the PPU raised
+    pub fn poll_nmi(&mut self) -> bool{
+        let pending = self.nmi_pending;
+        self.nmi_pending = false;
+        pending
+    }
+
+    // What reading PPU address `addr` would return, without the read buffer
+    // or the cartridge noticing. $3000-$3EFF mirror the nametables and
+    // $3F20-$3FFF the palettes.
+    pub fn peek(&self, addr: u16) -> u8{
+        let addr = addr & 0x3fff;
+        match addr{
+            0..=0x1fff => self.mapper.borrow().ppu_peek(addr),
+            0x2000..=0x3eff => self.vram[self.mirror_vram_address(addr) as usize],
+            _ => self.palette_table[palette_index(addr)],
+        }
+    }
+
+    // what the next $2007 read returns: palettes come straight out, the
+    // rest through the read buffer
+    pub fn peek_data(&self) -> u8{
+        let addr = self.address.get();
+        if addr >= 0x3f00 { self.peek(addr) } else { self.internal_buffer }
+    }
+
+    // $2002 without clearing vblank or the write toggle
+    pub fn peek_status(&self) -> u8{
+        self.status.snapshot()
+    }
+
+    // last value seen on the CPU <-> PPU data bus
+    pub fn open_bus(&self) -> u8{
+        self.open_bus
+    }
+
+    pub fn refresh_open_bus(&mut self, value: u8){
+        self.open_bus = value;
+        self.open_bus_age = 0;
+    }
+
+    pub fn decay_open_bus(&mut self, cycles: usize){
+        self.open_bus_age += cycles;
+        if self.open_bus_age >= OPEN_BUS_DECAY_CYCLES{
+            self.open_bus = 0;
+            self.open_bus_age = 0;
+        }
+    }
+
+    // Ages OAM by `cycles` CPU cycles, on the same clock as the open bus.
+    // Rendering refreshes every row; so does the PAL PPU's extra refresh in
+    // its long vblank, so that doesn't count as rendering being off
+    pub fn decay_oam(&mut self, cycles: usize){
+        if !self.oam_decay{
+            return;
+        }
+        if self.rendering_enabled(){
+            self.oam_row_age = [0; OAM_ROWS];
+            return;
+        }
+        for (row, age) in self.oam_row_age.iter_mut().enumerate(){
+            let old = *age;
+            *age = age.saturating_add(cycles as u32);
+            if old < OAM_DECAY_CYCLES && *age >= OAM_DECAY_CYCLES{
+                self.oam_data[row * 8..row * 8 + 8].fill(0x10);
+            }
+        }
+    }
+
+    fn refresh_oam_row(&mut self){
+        self.oam_row_age[self.oam_addr as usize / 8] = 0;
+    }
+
+    // While the PPU is rendering, v belongs to the background fetches: a
+    // $2007 access bumps coarse X and Y together instead of adding 1 or 32
+    // from: https://www.nesdev.org/wiki/PPU_scrolling#$2007_reads_and_writes
     fn increment_vram_addr(&mut self){
-        let increment = self.control.vram_add_increment();
-        self.address.increment(increment);
+        let rendering = self.scanline < 240 || self.scanline == self.region.pre_render_scanline();
+        if self.rendering_enabled() && rendering{
+            self.address.increment_coarse_x();
+            self.address.increment_y();
+        } else {
+            let increment = self.control.vram_add_increment();
+            self.address.increment(increment);
+        }
+        self.mapper.borrow_mut().ppu_address(self.address.get());
+    }
+
+    // The pattern table fetches of one rendered scanline, all at dot 260:
+    // background tiles, then sprites, then the first tiles of the next line.
+    // The PPU steps dot by dot but draws a line at a time from what
+    // latch_line saw, so there are no real per-dot fetches to take A12 from.
+    // Dot 260 is where the sprite fetches raise A12 with the usual layout of
+    // background at $0000 and sprites at $1000, which is when the MMC3
+    // counts. 8x16 sprites are assumed to sit at $1000.
+    pub fn scanline_fetches(&mut self){
+        if !self.mask.show_background() && !self.mask.show_sprites(){
+            return;
+        }
+        let background = self.control.background_pattern_addr();
+        let sprites = if self.control.sprite_size() == 16{
+            0x1000
+        }else{
+            self.control.sprite_pattern_addr()
+        };
+
+        let mut mapper = self.mapper.borrow_mut();
+        mapper.ppu_address(background);
+        mapper.ppu_address(sprites);
+        mapper.ppu_address(background);
+    }
+
+}
+
+// the cartridge side of the PPU bus is saved with the mapper
+// the PPU's part of a MachineState
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PpuState{
+    pub control: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    pub address: AddressRegister,
+    pub vram: Vec<u8>,
+    pub oam_data: Vec<u8>,
+    pub oam_addr: u8,
+    pub palette_table: Vec<u8>,
+    pub internal_buffer: u8,
+    pub open_bus: u8,
+    pub open_bus_age: usize,
+    #[serde(default)]
+    pub oam_row_age: Vec<u32>,
+    pub scanline: u16,
+    pub dot: u16,
+    pub odd_frame: bool,
+    pub nmi_pending: bool,
+}
+
+#[cfg(feature = "serde")]
+impl PPU{
+    pub fn state(&self) -> PpuState{
+        PpuState{
+            control: self.control,
+            mask: self.mask,
+            status: self.status,
+            address: self.address.clone(),
+            vram: self.vram.to_vec(),
+            oam_data: self.oam_data.to_vec(),
+            oam_addr: self.oam_addr,
+            palette_table: self.palette_table.to_vec(),
+            internal_buffer: self.internal_buffer,
+            open_bus: self.open_bus,
+            open_bus_age: self.open_bus_age,
+            oam_row_age: self.oam_row_age.to_vec(),
+            scanline: self.scanline,
+            dot: self.dot,
+            odd_frame: self.odd_frame,
+            nmi_pending: self.nmi_pending,
+        }
     }
 
+    pub fn set_state(&mut self, state: &PpuState) -> Result<(), StateError>{
+        copy_memory(&mut self.vram, &state.vram)?;
+        copy_memory(&mut self.oam_data, &state.oam_data)?;
+        copy_memory(&mut self.palette_table, &state.palette_table)?;
+        self.control = state.control;
+        self.mask = state.mask;
+        self.status = state.status;
+        self.address = state.address.clone();
+        self.oam_addr = state.oam_addr;
+        self.internal_buffer = state.internal_buffer;
+        self.open_bus = state.open_bus;
+        self.open_bus_age = state.open_bus_age;
+        // states from before OAM decay have every row fresh
+        self.oam_row_age = [0; OAM_ROWS];
+        for (age, &saved) in self.oam_row_age.iter_mut().zip(&state.oam_row_age){
+            *age = saved;
+        }
+        self.scanline = state.scanline;
+        self.dot = state.dot;
+        self.odd_frame = state.odd_frame;
+        self.nmi_pending = state.nmi_pending;
+        self.raster.clear();
+        Ok(())
+    }
+}
+
+impl Snapshot for PPU{
+    fn save_state(&self, state: &mut StateWriter){
+        state.write_u8(self.control.bits());
+        state.write_u8(self.mask.bits());
+        state.write_u8(self.status.bits());
+        self.address.save_state(state);
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.oam_data);
+        state.write_u8(self.oam_addr);
+        state.write_bytes(&self.palette_table);
+        state.write_u8(self.internal_buffer);
+        state.write_u8(self.open_bus);
+        state.write_u32(self.open_bus_age as u32);
+        for age in self.oam_row_age{
+            state.write_u32(age);
+        }
+        state.write_u16(self.scanline);
+        state.write_u16(self.dot);
+        state.write_bool(self.odd_frame);
+        state.write_bool(self.nmi_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>{
+        self.control.update(state.read_u8()?);
+        self.mask.update(state.read_u8()?);
+        self.status = StatusRegister::from_bits_truncate(state.read_u8()?);
+        self.address.load_state(state)?;
+        state.read_bytes_into(&mut self.vram)?;
+        state.read_bytes_into(&mut self.oam_data)?;
+        self.oam_addr = state.read_u8()?;
+        state.read_bytes_into(&mut self.palette_table)?;
+        self.internal_buffer = state.read_u8()?;
+        self.open_bus = state.read_u8()?;
+        self.open_bus_age = state.read_u32()? as usize;
+        // version 4 and older states don't have the OAM rows' ages
+        self.oam_row_age = [0; OAM_ROWS];
+        if state.version() >= 5{
+            for age in self.oam_row_age.iter_mut(){
+                *age = state.read_u32()?;
+            }
+        }
+        self.scanline = state.read_u16()?;
+        self.dot = state.read_u16()?;
+        self.odd_frame = state.read_bool()?;
+        self.nmi_pending = state.read_bool()?;
+        self.raster.clear();
+        Ok(())
+    }
 }
 
 impl PPUInterface for PPU{
 
     fn write_to_control(&mut self, value: u8) {
-        let before_nmi_status = self.control.generate_nmi();
+        // turning NMIs on during vblank raises one straight away
+        let nmi_was_enabled = self.control.generate_nmi();
         self.control.update(value);
+        if !nmi_was_enabled && self.control.generate_nmi() && self.status.is_in_vblank(){
+            self.nmi_pending = true;
+        }
+        self.address.write_control(value);
+        self.latch_write();
     }
 
     fn write_to_mask(&mut self, value: u8) {
         self.mask.update(value);
+        self.latch_write();
     }
 
     fn read_from_status(&mut self) -> u8 {
         let data = self.status.snapshot();
         self.status.reset_vblank_status();
         self.address.reset_latch();
-        self.scroll.reset_latch();
         data
     }
 
@@ -114,44 +614,41 @@ impl PPUInterface for PPU{
     }
 
     fn write_to_oam_data(&mut self, value: u8) {
+        self.refresh_oam_row();
         self.oam_data[self.oam_addr as usize] = value;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
     fn read_from_oam_data(&mut self) -> u8 {
+        self.refresh_oam_row();
         self.oam_data[self.oam_addr as usize]
     }
 
     fn write_to_scroll(&mut self, value: u8) {
-        self.scroll.write(value);
+        self.address.write_scroll(value);
     }
 
     fn write_to_address(&mut self, value: u8) {
         self.address.update(value);
+        if !self.address.w{
+            self.mapper.borrow_mut().ppu_address(self.address.get());
+            self.latch_horizontal();
+        }
     }
 
     fn write_to_data(&mut self, value: u8) {
         let addr = self.address.get();
         match addr{
-            0..=0x1FFF => print!("Attempted to write to CHR-ROM at {:04X}", addr),
+            0..=0x1FFF => self.mapper.borrow_mut().ppu_write(addr, value),
             
-            0x2000..=0x2FFF => {
-                self.vram[self.mirror_vram_address(addr) as usize] = value;
+            // $3000-$3EFF mirror the nametables
+            0x2000..=0x3EFF => {
+                let vram_index = self.mirror_vram_address(addr) as usize;
+                self.vram_heatmap.record_write(vram_index);
+                self.vram[vram_index] = value;
             }
 
-            0x3000..=0x3EFF => unimplemented!("{} shouldnt be written to", addr),
-
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize] = value;
-            }
-
-            0x3f00..=0x3fff =>
-            {
-                self.palette_table[(addr - 0x3f00) as usize] = value;
-            }
-            
-            _ => panic!("Attempted to write to invalid address {:04X}", addr),
+            _ => self.palette_table[palette_index(addr)] = value,
         }
         self.increment_vram_addr();
     }
@@ -164,32 +661,31 @@ impl PPUInterface for PPU{
         match addr {
             0..=0x1fff => {
                 let result = self.internal_buffer;
-                self.internal_buffer = self.chr_rom[addr as usize];
+                self.internal_buffer = self.read_chr(addr);
                 result
             }
-            0x2000..=0x2fff => {
+            0x2000..=0x3eff => {
                 let result = self.internal_buffer;
-                self.internal_buffer = self.vram[self.mirror_vram_address(addr) as usize];
+                let vram_index = self.mirror_vram_address(addr) as usize;
+                self.vram_heatmap.record_read(vram_index);
+                self.internal_buffer = self.vram[vram_index];
                 result
             }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
 
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize]
+            // palettes skip the buffer, which gets the nametable byte
+            // "underneath" them instead
+            _ => {
+                let vram_index = self.mirror_vram_address(addr) as usize;
+                self.vram_heatmap.record_read(vram_index);
+                self.internal_buffer = self.vram[vram_index];
+                self.palette_table[palette_index(addr)]
             }
-
-            0x3f00..=0x3fff =>
-            {
-                self.palette_table[(addr - 0x3f00) as usize]
-            }
-            _ => panic!("unexpected access to mirrored space {}", addr),
         }
     }
 
     fn write_to_oam_dma(&mut self, data: &[u8; 0x100]) {
         for i in data.iter(){
+            self.refresh_oam_row();
             self.oam_data[self.oam_addr as usize] = *i;
             self.oam_addr = self.oam_addr.wrapping_add(1);
         }
@@ -201,6 +697,8 @@ impl PPUInterface for PPU{
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use alloc::vec::Vec;
+    use alloc::vec;
 
     #[test]
     fn test_ppu_vram_writes() {
@@ -292,7 +790,7 @@ pub mod test {
     //   [0x2800 a ] [0x2C00 b ]
     #[test]
     fn test_vram_vertical_mirror() {
-        let mut ppu = PPU::new(vec![0; 2048], Mirroring::VERTICAL);
+        let mut ppu = PPU::with_chr(vec![0; 2048], Mirroring::VERTICAL);
 
         ppu.write_to_address(0x20);
         ppu.write_to_address(0x05);
@@ -352,6 +850,300 @@ pub mod test {
         // assert_eq!(ppu.addr.read(), 0x0306)
     }
 
+    #[test]
+    fn test_nametable_mirror_at_3000() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_address(0x31);
+        ppu.write_to_address(0x05);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.vram[0x0105], 0x66);
+
+        ppu.write_to_address(0x21);
+        ppu.write_to_address(0x06);
+        ppu.write_to_data(0x77);
+        ppu.write_to_address(0x31);
+        ppu.write_to_address(0x05);
+        ppu.read_from_data(); //load_into_buffer
+        assert_eq!(ppu.read_from_data(), 0x66);
+        assert_eq!(ppu.read_from_data(), 0x77);
+    }
+
+    #[test]
+    fn test_chr_reads_through_mapper() {
+        let chr_rom: Vec<u8> = (0..8u8).flat_map(|bank| vec![bank; 0x400]).collect();
+        let mut ppu = PPU::with_chr(chr_rom, Mirroring::HORIZONTAL);
+        assert_eq!(ppu.read_chr(0x0000), 0);
+        assert_eq!(ppu.read_chr(0x1fff), 7);
+
+        ppu.write_to_address(0x04);
+        ppu.write_to_address(0x10);
+        ppu.read_from_data(); //load_into_buffer
+        assert_eq!(ppu.read_from_data(), 1);
+    }
+
+    #[test]
+    fn test_chr_ram_through_ppudata() {
+        let mut ppu = PPU::with_chr(vec![], Mirroring::HORIZONTAL);
+        ppu.write_to_address(0x12);
+        ppu.write_to_address(0x34);
+        ppu.write_to_data(0x66);
+        ppu.write_to_data(0x77);
+        assert_eq!(ppu.read_chr(0x1234), 0x66);
+
+        ppu.write_to_address(0x12);
+        ppu.write_to_address(0x35);
+        ppu.read_from_data(); //load_into_buffer
+        assert_eq!(ppu.read_from_data(), 0x77);
+    }
+
+    #[test]
+    fn test_vblank_and_nmi_timing() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_control(0b1000_0000);
+
+        // up to and including dot 0 of line 241
+        for _ in 0..241 * DOTS_PER_SCANLINE as usize + 1 {
+            assert!(!ppu.tick(1));
+        }
+        assert!(!ppu.status.is_in_vblank());
+        assert!(ppu.tick(1));
+        assert!(ppu.status.is_in_vblank());
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+
+        // cleared at dot 1 of the pre-render line
+        ppu.tick(255);
+        while ppu.scanline != 261 || ppu.dot != 2 {
+            assert!(ppu.status.is_in_vblank());
+            ppu.tick(1);
+        }
+        assert!(!ppu.status.is_in_vblank());
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.status.set_vblank_status(true);
+        ppu.write_to_control(0b1000_0000);
+        assert!(ppu.poll_nmi());
+
+        ppu.write_to_control(0b1000_0000);
+        assert!(!ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_odd_frames_are_a_dot_shorter_when_rendering() {
+        let mut ppu = PPU::new_empty_rom();
+        let dots_in_frame = |ppu: &mut PPU| {
+            let mut dots = 1;
+            while !ppu.tick(1) {
+                dots += 1;
+            }
+            dots
+        };
+        dots_in_frame(&mut ppu);
+        assert_eq!(dots_in_frame(&mut ppu), 341 * 262);
+
+        ppu.write_to_mask(0b0000_1000);
+        let first = dots_in_frame(&mut ppu);
+        let second = dots_in_frame(&mut ppu);
+        assert_eq!(first + second, 2 * 341 * 262 - 1);
+    }
+
+    // opaque tile 1 in the background at tile (4, 2), sprite 0 with a single
+    // opaque pixel in the middle of its top row at (38, 17)
+    fn sprite_zero_ppu() -> PPU {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        chr[32] = 0b0001_0000;
+        let mut ppu = PPU::with_chr(chr, Mirroring::HORIZONTAL);
+        ppu.vram[2 * 32 + 4] = 1;
+        ppu.oam_data[..4].copy_from_slice(&[16, 2, 0, 35]);
+        ppu.write_to_mask(0b0001_1110);
+        ppu
+    }
+
+    fn run_until_hit(ppu: &mut PPU) -> Option<(u16, u16)> {
+        for _ in 0..240 * DOTS_PER_SCANLINE as usize {
+            ppu.tick(1);
+            if ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT) {
+                return Some((ppu.scanline, ppu.dot - 1));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_on_overlap() {
+        let mut ppu = sprite_zero_ppu();
+        // set while drawing x = 38, which is dot 39
+        assert_eq!(run_until_hit(&mut ppu), Some((17, 39)));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_once_a_line() {
+        let mut ppu = sprite_zero_ppu();
+        ppu.sprite_zero_hit_dot = false;
+        assert_eq!(run_until_hit(&mut ppu), Some((17, 0)));
+
+        let mut ppu = sprite_zero_ppu();
+        ppu.sprite_zero_hit_dot = false;
+        ppu.vram[2 * 32 + 4] = 0;
+        assert_eq!(run_until_hit(&mut ppu), None);
+    }
+
+    #[test]
+    fn test_skipping_idle_dots() {
+        // dot by dot, then in the CPU's steps of 3 and DMA's long runs
+        let mut ppus = [sprite_zero_ppu(), sprite_zero_ppu(), sprite_zero_ppu()];
+        for ppu in ppus.iter_mut() {
+            ppu.sprite_zero_hit_dot = false;
+            ppu.write_to_control(0x80);
+            ppu.write_to_scroll(0x01);
+            ppu.write_to_scroll(0x05);
+        }
+        // the same number of dots for each, stopping partway through a line
+        let total = 2 * 341 * 262 + 341 * 100 + 338;
+        let mut completed = [0; 3];
+        for (ppu, (completed, dots)) in ppus.iter_mut().zip(completed.iter_mut().zip([1, 3, 1000])) {
+            for _ in 0..total / dots as u32 {
+                *completed += ppu.tick(dots) as u32;
+            }
+            *completed += ppu.tick((total % dots as u32) as u16) as u32;
+        }
+        assert_eq!(completed, [2, 2, 2]);
+        assert!(ppus.iter().all(|ppu| ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT)));
+        let states = ppus.map(|ppu| {
+            let mut state = StateWriter::new();
+            ppu.save_state(&mut state);
+            state.finish()
+        });
+        assert_eq!(states[0], states[1]);
+        assert_eq!(states[0], states[2]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_needs_opaque_background() {
+        let mut ppu = sprite_zero_ppu();
+        ppu.vram[2 * 32 + 4] = 0;
+        assert_eq!(run_until_hit(&mut ppu), None);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_respects_left_clipping() {
+        let mut ppu = sprite_zero_ppu();
+        ppu.vram[2 * 32] = 1;
+        ppu.oam_data[3] = 0;
+        ppu.write_to_mask(0b0001_1010);
+        assert_eq!(run_until_hit(&mut ppu), None);
+
+        let mut ppu = sprite_zero_ppu();
+        ppu.vram[2 * 32] = 1;
+        ppu.oam_data[3] = 0;
+        assert_eq!(run_until_hit(&mut ppu), Some((17, 4)));
+    }
+
+    #[test]
+    fn test_rendering_moves_v() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_scroll(0x08);
+        ppu.write_to_scroll(0x00);
+        ppu.write_to_mask(0b0000_1000);
+
+        // the pre-render line loads t into v, then prefetches two tiles
+        ppu.scanline = 261;
+        ppu.tick(255);
+        ppu.tick(86);
+        assert_eq!(ppu.scanline, 0);
+        assert_eq!(ppu.address.v, 0x0003);
+
+        // 32 tiles along, one line down, then back to t's column
+        ppu.tick(255);
+        ppu.tick(3);
+        assert_eq!(ppu.address.v, 0x1001);
+
+        // a mid-frame $2005 write only reaches v at the next line's copy
+        ppu.write_to_scroll(0x10);
+        assert_eq!(ppu.address.v, 0x1001);
+        ppu.tick(255);
+        ppu.tick(86);
+        assert_eq!(ppu.address.v, 0x2002);
+    }
+
+    #[test]
+    fn test_ppudata_during_rendering() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_address(0x20);
+        ppu.write_to_address(0x1f);
+        ppu.write_to_mask(0b0000_1000);
+        ppu.scanline = 100;
+
+        // coarse X wraps into the next nametable, Y moves a pixel down
+        ppu.read_from_data();
+        assert_eq!(ppu.address.v, 0x3400);
+
+        // outside the visible lines it adds 1 again
+        ppu.scanline = 241;
+        ppu.write_to_data(0);
+        assert_eq!(ppu.address.v, 0x3401);
+    }
+
+    #[test]
+    fn test_palette_reads_fill_the_buffer_from_vram() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.vram[0x0701] = 0x77;
+        ppu.palette_table[1] = 0x2a;
+        ppu.write_to_address(0x3f);
+        ppu.write_to_address(0x01);
+        assert_eq!(ppu.read_from_data(), 0x2a);
+
+        // the buffer now holds $2F01, which is $2B01 with horizontal mirroring
+        ppu.write_to_address(0x20);
+        ppu.write_to_address(0x00);
+        assert_eq!(ppu.read_from_data(), 0x77);
+    }
+
+    #[test]
+    fn test_open_bus_decays() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.refresh_open_bus(0x5a);
+
+        ppu.decay_open_bus(OPEN_BUS_DECAY_CYCLES - 1);
+        assert_eq!(ppu.open_bus(), 0x5a);
+
+        ppu.refresh_open_bus(0x3c);
+        ppu.decay_open_bus(OPEN_BUS_DECAY_CYCLES - 1);
+        assert_eq!(ppu.open_bus(), 0x3c);
+        ppu.decay_open_bus(1);
+        assert_eq!(ppu.open_bus(), 0);
+    }
+
+    #[test]
+    fn test_oam_decays_with_rendering_off() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_oam_dma(&[0x55; 0x100]);
+        ppu.decay_oam(OAM_DECAY_CYCLES as usize - 1);
+        // touching a row keeps it
+        ppu.write_to_oam_addr(0x09);
+        ppu.read_from_oam_data();
+        ppu.decay_oam(1);
+        assert_eq!(ppu.oam_data[..8], [0x10; 8]);
+        assert_eq!(ppu.oam_data[8..16], [0x55; 8]);
+        assert_eq!(ppu.oam_data[16..], [0x10; 0xf0]);
+
+        // and rendering keeps all of them
+        ppu.write_to_oam_dma(&[0x55; 0x100]);
+        ppu.write_to_mask(0x10);
+        ppu.decay_oam(2 * OAM_DECAY_CYCLES as usize);
+        ppu.write_to_mask(0x00);
+        ppu.decay_oam(OAM_DECAY_CYCLES as usize - 1);
+        assert_eq!(ppu.oam_data, [0x55; 0x100]);
+
+        ppu.oam_decay = false;
+        ppu.decay_oam(OAM_DECAY_CYCLES as usize);
+        assert_eq!(ppu.oam_data, [0x55; 0x100]);
+    }
+
     #[test]
     fn test_read_from_status_resets_vblank() {
         let mut ppu = PPU::new_empty_rom();
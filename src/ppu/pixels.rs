@@ -0,0 +1,119 @@
+use super::raster::LineRegisters;
+use super::sprite::Sprite;
+use super::PPU;
+
+// Pixel lookups shared by the renderer and the PPU's own sprite zero test
+impl PPU {
+    // 2-bit colour of pixel (x, y) within a tile, counting from its top left
+    pub fn tile_pixel(&self, bank: usize, tile: usize, x: usize, y: usize) -> u8 {
+        let addr = (bank + tile * 16 + y) as u16;
+        let lo = self.read_chr(addr);
+        let hi = self.read_chr(addr + 8);
+        (((hi >> (7 - x)) & 1) << 1) | ((lo >> (7 - x)) & 1)
+    }
+
+    // The four nametables form a 512x480 plane:
+    //   [ $2000 ] [ $2400 ]
+    //   [ $2800 ] [ $2C00 ]
+    // the screen is a 256x240 window into it, wrapping around at the edges.
+    // Returns the 2-bit colour and the palette of screen pixel (x, y).
+    pub fn background_pixel(&self, x: usize, y: usize) -> (u8, u8) {
+        self.line_background_pixel(&self.registers_at(x, y), x)
+    }
+
+    // the background at pixel x of a line drawn with `registers`
+    pub fn line_background_pixel(&self, registers: &LineRegisters, x: usize) -> (u8, u8) {
+        let (plane_x, plane_y) = registers.origin;
+        let bank = registers.control.background_pattern_addr() as usize;
+        self.nametable_pixel_in(bank, (plane_x + x) % 512, plane_y)
+    }
+
+    // where the top left of the screen is in the nametable plane
+    pub fn scroll_origin(&self) -> (usize, usize) {
+        let base = self.address.nametable() as usize;
+        let plane_x = (base & 1) * 256 + self.address.scroll_x() as usize;
+        let plane_y = (base >> 1) * 240 + self.address.scroll_y() as usize;
+        (plane_x % 512, plane_y % 480)
+    }
+
+    // 2-bit colour and palette at (x, y) of the 512x480 nametable plane
+    pub fn nametable_pixel(&self, plane_x: usize, plane_y: usize) -> (u8, u8) {
+        let bank = self.control.background_pattern_addr() as usize;
+        self.nametable_pixel_in(bank, plane_x, plane_y)
+    }
+
+    // the same with the tiles taken from the pattern table at `bank`
+    pub fn nametable_pixel_in(&self, bank: usize, plane_x: usize, plane_y: usize) -> (u8, u8) {
+        let tile_column = (plane_x % 256) / 8;
+        let tile_row = (plane_y % 240) / 8;
+        let nametable = 0x2000 + (plane_y / 240) * 0x800 + (plane_x / 256) * 0x400;
+
+        let tile_addr = nametable + tile_row * 32 + tile_column;
+        let tile = self.vram[self.mirror_vram_address(tile_addr as u16) as usize] as usize;
+        let value = self.tile_pixel(bank, tile, plane_x % 8, plane_y % 8);
+
+        // each attribute byte covers 4x4 tiles, two bits per 2x2 quadrant
+        let attr_addr = nametable + 0x3c0 + (tile_row / 4) * 8 + tile_column / 4;
+        let attr = self.vram[self.mirror_vram_address(attr_addr as u16) as usize];
+        let shift = ((tile_row % 4) / 2) * 4 + ((tile_column % 4) / 2) * 2;
+        (value, (attr >> shift) & 0b11)
+    }
+
+    // Palette RAM value the PPU puts out at screen pixel (x, y)
+    pub fn pixel_colour(&self, x: usize, y: usize) -> u8 {
+        self.line_pixel_colour(&self.registers_at(x, y), self.sprites(), x, y)
+    }
+
+    // The same for a line drawn with `registers`, out of `sprites` in OAM
+    // order: the first opaque sprite wins unless it's behind an opaque
+    // background. PPUMASK can hide either layer in the leftmost 8 pixels.
+    pub fn line_pixel_colour(
+        &self,
+        registers: &LineRegisters,
+        sprites: impl Iterator<Item = Sprite>,
+        x: usize,
+        y: usize,
+    ) -> u8 {
+        let mask = registers.mask;
+        let (value, palette) =
+            if mask.show_background() && (x >= 8 || mask.leftmost_8pxl_background()) {
+                self.line_background_pixel(registers, x)
+            } else {
+                (0, 0)
+            };
+        if mask.show_sprites() && (x >= 8 || mask.leftmost_8pxl_sprite()) {
+            let height = registers.control.sprite_size() as usize;
+            for sprite in sprites {
+                if !sprite.contains(x, y, height) {
+                    continue;
+                }
+                let column = x - sprite.x as usize;
+                let row = y - sprite.top();
+                let sprite_value =
+                    self.sprite_pattern_pixel(registers.control, &sprite, column, row);
+                if sprite_value == 0 {
+                    continue;
+                }
+                if sprite.behind_background && value != 0 {
+                    break;
+                }
+                let sprite_palette = sprite.palette as usize;
+                return self.palette_table[0x10 + sprite_palette * 4 + sprite_value as usize];
+            }
+        }
+        if value == 0 {
+            self.palette_table[0]
+        } else {
+            self.palette_table[(palette * 4 + value) as usize]
+        }
+    }
+
+    // 2-bit colour of `sprite` at screen pixel (x, y), None outside of it
+    pub fn sprite_pixel(&self, sprite: usize, x: usize, y: usize) -> Option<u8> {
+        let sprite = self.sprite(sprite);
+        if !sprite.contains(x, y, self.control.sprite_size() as usize) {
+            return None;
+        }
+        Some(self.sprite_tile_pixel(&sprite, x - sprite.x as usize, y - sprite.top()))
+    }
+}
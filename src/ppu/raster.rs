@@ -0,0 +1,218 @@
+use super::registers::control::ControlRegister;
+use super::registers::mask::MaskRegister;
+use super::PPU;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// When PPUCTRL and PPUMASK writes made during a visible line show up
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RasterTiming {
+    // from the next line on
+    Scanline,
+    // from the pixel being drawn when the write landed
+    Dot,
+}
+
+// PPUCTRL and PPUMASK as they were from pixel `x` of a visible line on, and
+// where the line's first pixel sits in the 512x480 nametable plane
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRegisters {
+    pub x: usize,
+    pub control: ControlRegister,
+    pub mask: MaskRegister,
+    pub origin: (usize, usize),
+}
+
+impl LineRegisters {
+    // the registers in effect at pixel x of a line, given in order
+    pub fn at(segments: &[LineRegisters], x: usize) -> &LineRegisters {
+        segments
+            .iter()
+            .rev()
+            .find(|registers| registers.x <= x)
+            .unwrap_or(&segments[0])
+    }
+}
+
+// The registers every visible line was drawn with, latched as the PPU gets
+// to each line. The renderer draws from these instead of the registers at
+// the end of the frame, so status bars, parallax splits and rendering
+// switched off for part of the frame come out where the game meant them.
+//
+// Scroll comes from v like on the console: the vertical part as v reaches
+// the line, the horizontal part as it's copied from t at dot 257 of the line
+// before (see registers/address.rs). It changes once a line with either
+// timing.
+pub struct Raster {
+    pub timing: RasterTiming,
+    // one or more segments per line, empty for lines not reached yet
+    lines: Vec<Vec<LineRegisters>>,
+    // plane x of v's coarse X and nametable bits at the last copy from t
+    horizontal: usize,
+}
+
+impl Raster {
+    pub fn new() -> Self {
+        Raster {
+            timing: RasterTiming::Scanline,
+            lines: vec![Vec::new(); 240],
+            horizontal: 0,
+        }
+    }
+
+    // forget every line, the renderer goes back to the live registers
+    pub fn clear(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+    }
+}
+
+impl Default for Raster {
+    fn default() -> Self {
+        Raster::new()
+    }
+}
+
+impl PPU {
+    pub fn raster_timing(&self) -> RasterTiming {
+        self.raster.timing
+    }
+
+    pub fn set_raster_timing(&mut self, timing: RasterTiming) {
+        self.raster.timing = timing;
+    }
+
+    // what visible line y was drawn with, in order of x; empty until the PPU
+    // has been there
+    pub fn line_registers(&self, y: usize) -> &[LineRegisters] {
+        &self.raster.lines[y]
+    }
+
+    // line y as the registers are now, for lines that weren't latched: the
+    // whole frame scrolled by t
+    pub fn live_registers(&self, y: usize) -> LineRegisters {
+        let (plane_x, plane_y) = self.scroll_origin();
+        LineRegisters {
+            x: 0,
+            control: self.control,
+            mask: self.mask,
+            origin: (plane_x, (plane_y + y) % 480),
+        }
+    }
+
+    // the registers pixel (x, y) was or will be drawn with
+    pub fn registers_at(&self, x: usize, y: usize) -> LineRegisters {
+        match self.line_registers(y) {
+            [] => self.live_registers(y),
+            segments => *LineRegisters::at(segments, x),
+        }
+    }
+
+    // dot 0 of a visible line
+    pub(super) fn latch_line(&mut self) {
+        let origin = (
+            (self.raster.horizontal + self.address.x as usize) % 512,
+            self.address.plane_y(),
+        );
+        let registers = LineRegisters {
+            x: 0,
+            control: self.control,
+            mask: self.mask,
+            origin,
+        };
+        let line = &mut self.raster.lines[self.scanline as usize];
+        line.clear();
+        line.push(registers);
+    }
+
+    // v's horizontal bits were just copied from t, or written through $2006
+    pub(super) fn latch_horizontal(&mut self) {
+        self.raster.horizontal = self.address.plane_x();
+    }
+
+    // a PPUCTRL or PPUMASK write; with dot timing it splits the line
+    pub(super) fn latch_write(&mut self) {
+        if self.raster.timing != RasterTiming::Dot || self.scanline >= 240 {
+            return;
+        }
+        if !(1..=256).contains(&self.dot) {
+            return;
+        }
+        let x = self.dot as usize - 1;
+        let (control, mask) = (self.control, self.mask);
+        let line = &mut self.raster.lines[self.scanline as usize];
+        let last = match line.last_mut() {
+            Some(last) => last,
+            None => return,
+        };
+        if last.x == x {
+            last.control = control;
+            last.mask = mask;
+        } else {
+            let origin = last.origin;
+            line.push(LineRegisters {
+                x,
+                control,
+                mask,
+                origin,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::{PPUInterface, DOTS_PER_SCANLINE};
+    use crate::render::{self, frame::Frame};
+    use crate::rom::Mirroring;
+
+    // nametable 0 filled with tile 1, which is solid colour 1
+    fn filled_ppu() -> PPU {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].copy_from_slice(&[0xff; 8]);
+        let mut ppu = PPU::with_chr(chr, Mirroring::HORIZONTAL);
+        ppu.vram[..0x3c0].fill(1);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x16;
+        ppu.write_to_mask(0b0000_1010);
+        ppu
+    }
+
+    fn run_dots(ppu: &mut PPU, dots: usize) {
+        for _ in 0..dots {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_mask_changes_per_line() {
+        let mut ppu = filled_ppu();
+        run_dots(&mut ppu, 100 * DOTS_PER_SCANLINE as usize + 128);
+        ppu.write_to_mask(0);
+        run_dots(&mut ppu, 140 * DOTS_PER_SCANLINE as usize);
+
+        let mut frame = Frame::new();
+        render::render(&ppu, &mut frame);
+        assert_eq!(frame.index(200, 100), 0x16);
+        assert_eq!(frame.index(0, 101), 0x0f);
+        assert_eq!(ppu.line_registers(101)[0].mask.bits(), 0);
+    }
+
+    #[test]
+    fn test_mask_changes_per_dot() {
+        let mut ppu = filled_ppu();
+        ppu.set_raster_timing(RasterTiming::Dot);
+        run_dots(&mut ppu, 100 * DOTS_PER_SCANLINE as usize + 129);
+        ppu.write_to_mask(0);
+        run_dots(&mut ppu, 140 * DOTS_PER_SCANLINE as usize);
+
+        let mut frame = Frame::new();
+        render::render(&ppu, &mut frame);
+        assert_eq!(frame.index(127, 100), 0x16);
+        assert_eq!(frame.index(128, 100), 0x0f);
+        assert_eq!(ppu.line_registers(100).len(), 2);
+    }
+}
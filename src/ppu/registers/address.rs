@@ -1,53 +1,430 @@
-pub struct AddressRegister{
-    value: (u8, u8),
-    hi_ptr: bool,
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Internal PPU address registers, shared by PPUCTRL ($2000), PPUSCROLL ($2005)
+// and PPUADDR ($2006):
+// from: https://www.nesdev.org/wiki/PPU_scrolling
+//
+// v: current VRAM address (15 bits)
+// t: temporary VRAM address (15 bits), the top left onscreen tile
+// x: fine X scroll (3 bits)
+// w: first or second write toggle, shared by $2005 and $2006
+//
+// v and t are laid out as:
+//   yyy NN YYYYY XXXXX
+//   ||| || ||||| +++++-- coarse X scroll
+//   ||| || +++++-------- coarse Y scroll
+//   ||| ++-------------- nametable select
+//   +++----------------- fine Y scroll
+//
+// | Access        | Effect                                                |
+// |---------------|-------------------------------------------------------|
+// | $2000 write   | t: ...GH.. ........ <- d: ......GH                    |
+// | $2002 read    | w:                  <- 0                              |
+// | $2005 write 1 | t: ....... ...ABCDE <- d: ABCDE...; x <- d: .....FGH  |
+// | $2005 write 2 | t: FGH..AB CDE..... <- d: ABCDEFGH                    |
+// | $2006 write 1 | t: .CDEFGH ........ <- d: ..CDEFGH; t bit 14 cleared  |
+// | $2006 write 2 | t: ....... ABCDEFGH <- d: ABCDEFGH; v <- t            |
+//
+// While rendering the PPU moves v along by itself:
+//
+// | Dot (visible and pre-render lines) | Effect                              |
+// |------------------------------------|-------------------------------------|
+// | 8, 16, .. 256, 328, 336            | coarse X + 1, into the next table   |
+// | 256                                | fine Y + 1, into coarse Y and table |
+// | 257                                | v: ....A.. ...BCDEF <- t            |
+// | 280-304 of the pre-render line     | v: GHIA.BC DEF..... <- t            |
+const COARSE_X: u16 = 0x001f;
+const COARSE_Y: u16 = 0x03e0;
+const NAMETABLE: u16 = 0x0c00;
+const FINE_Y: u16 = 0x7000;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddressRegister {
+    pub v: u16,
+    pub t: u16,
+    pub x: u8,
+    pub w: bool,
+}
+
+impl Default for AddressRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddressRegister {
+    pub fn new() -> AddressRegister {
+        AddressRegister {
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+        }
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.t = (self.t & !NAMETABLE) | (((value & 0b11) as u16) << 10);
+    }
+
+    pub fn write_scroll(&mut self, value: u8) {
+        if !self.w {
+            self.t = (self.t & !COARSE_X) | (value >> 3) as u16;
+            self.x = value & 0b111;
+        } else {
+            self.t = (self.t & !(COARSE_Y | FINE_Y))
+                | (((value & 0b111) as u16) << 12)
+                | (((value >> 3) as u16) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    pub fn update(&mut self, value: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00ff) | (((value & 0x3f) as u16) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | value as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    // address the CPU sees through $2007
+    pub fn get(&self) -> u16 {
+        self.v & 0x3fff
+    }
+
+    pub fn increment(&mut self, value: u8) {
+        self.v = self.v.wrapping_add(value as u16) & 0x7fff;
+    }
+
+    pub fn increment_coarse_x(&mut self) {
+        if self.v & COARSE_X == 31 {
+            self.v = (self.v & !COARSE_X) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // coarse Y wraps at 29 into the next nametable, at 31 it wraps in place
+    // (rows 30 and 31 are the attribute table)
+    pub fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !FINE_Y;
+        let coarse_y = (self.v & COARSE_Y) >> 5;
+        let coarse_y = match coarse_y {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    pub fn copy_horizontal(&mut self) {
+        let mask = COARSE_X | 0x0400;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    pub fn copy_vertical(&mut self) {
+        let mask = FINE_Y | COARSE_Y | 0x0800;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    pub fn reset_latch(&mut self) {
+        self.w = false;
+    }
+
+    pub fn scroll_x(&self) -> u8 {
+        (((self.t & COARSE_X) << 3) as u8) | self.x
+    }
+
+    pub fn scroll_y(&self) -> u8 {
+        ((((self.t & COARSE_Y) >> 5) << 3) as u8) | ((self.t & FINE_Y) >> 12) as u8
+    }
+
+    // where v's coarse X and nametable point in the 512x480 nametable plane
+    pub fn plane_x(&self) -> usize {
+        ((self.v & 0x0400) >> 10) as usize * 256 + (self.v & COARSE_X) as usize * 8
+    }
+
+    // where v's fine Y, coarse Y and nametable point in the plane
+    pub fn plane_y(&self) -> usize {
+        let y = ((self.v & COARSE_Y) >> 5) as usize * 8 + ((self.v & FINE_Y) >> 12) as usize;
+        (((self.v & 0x0800) >> 11) as usize * 240 + y) % 480
+    }
+
+    // base nametable selected by t, 0-3
+    pub fn nametable(&self) -> u16 {
+        (self.t & NAMETABLE) >> 10
+    }
+}
+
+impl Snapshot for AddressRegister {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.v);
+        state.write_u16(self.t);
+        state.write_u8(self.x);
+        state.write_bool(self.w);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.v = state.read_u16()?;
+        self.t = state.read_u16()?;
+        self.x = state.read_u8()?;
+        self.w = state.read_bool()?;
+        Ok(())
+    }
 }
 
-impl AddressRegister{
-    pub fn new() -> AddressRegister{
-        AddressRegister{
-            value: (0, 0),
-            hi_ptr: true,
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[derive(Debug, Clone, Copy)]
+    enum Access {
+        Control(u8),
+        Status,
+        Scroll(u8),
+        Address(u8),
+    }
+
+    fn apply(register: &mut AddressRegister, access: Access) {
+        match access {
+            Access::Control(value) => register.write_control(value),
+            Access::Status => register.reset_latch(),
+            Access::Scroll(value) => register.write_scroll(value),
+            Access::Address(value) => register.update(value),
         }
     }
 
-    pub fn update(&mut self, value: u8){
-        if self.hi_ptr{
-            self.value.0 = value;
-        }else{
-            self.value.1 = value;
+    // Reference model working on the scroll fields rather than on t's bits
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    struct Fields {
+        coarse_x: u16,
+        coarse_y: u16,
+        nametable: u16,
+        fine_y: u16,
+        fine_x: u8,
+        w: bool,
+        v: u16,
+    }
+
+    impl Fields {
+        fn t(&self) -> u16 {
+            (self.fine_y << 12) | (self.nametable << 10) | (self.coarse_y << 5) | self.coarse_x
         }
 
-        if self.get() > 0x3FFF{
-            self.set(self.get() & 0x3FFF);
+        fn set_t(&mut self, t: u16) {
+            self.coarse_x = t & 0x1f;
+            self.coarse_y = (t >> 5) & 0x1f;
+            self.nametable = (t >> 10) & 0b11;
+            self.fine_y = (t >> 12) & 0b111;
         }
 
-        self.hi_ptr = !self.hi_ptr;
+        fn apply(&mut self, access: Access) {
+            match access {
+                Access::Control(value) => self.nametable = (value & 0b11) as u16,
+                Access::Status => self.w = false,
+                Access::Scroll(value) => {
+                    if !self.w {
+                        self.coarse_x = (value / 8) as u16;
+                        self.fine_x = value % 8;
+                    } else {
+                        self.coarse_y = (value / 8) as u16;
+                        self.fine_y = (value % 8) as u16;
+                    }
+                    self.w = !self.w;
+                }
+                Access::Address(value) => {
+                    if !self.w {
+                        let low = self.t() & 0xff;
+                        self.set_t(((value as u16 % 0x40) * 0x100) + low);
+                    } else {
+                        let high = self.t() & 0x7f00;
+                        self.set_t(high + value as u16);
+                        self.v = self.t();
+                    }
+                    self.w = !self.w;
+                }
+            }
+        }
     }
 
-    fn set(&mut self, data: u16){
-        self.value.0 = (data >> 8) as u8;
-        self.value.1 = (data & 0xff) as u8;
+    fn assert_matches(register: &AddressRegister, fields: &Fields, sequence: &[Access]) {
+        assert_eq!(register.t, fields.t(), "t after {:?}", sequence);
+        assert_eq!(register.v, fields.v, "v after {:?}", sequence);
+        assert_eq!(register.x, fields.fine_x, "x after {:?}", sequence);
+        assert_eq!(register.w, fields.w, "w after {:?}", sequence);
     }
 
-    pub fn get(&self) -> u16{
-        ((self.value.0 as u16) << 8) | (self.value.1 as u16)
+    #[test]
+    fn test_nesdev_wiki_example_sequence() {
+        let mut register = AddressRegister::new();
+        register.t = 0x7fff;
+        register.w = true;
+
+        register.write_control(0x00);
+        assert_eq!(register.t, 0b111_00_11111_11111);
+        register.reset_latch();
+        assert!(!register.w);
+
+        register.write_scroll(0x7d);
+        assert_eq!(register.t, 0b111_00_11111_01111);
+        assert_eq!(register.x, 0b101);
+        assert!(register.w);
+
+        register.write_scroll(0x5e);
+        assert_eq!(register.t, 0b110_00_01011_01111);
+        assert!(!register.w);
+
+        register.update(0x3d);
+        assert_eq!(register.t, 0b011_11_01011_01111);
+        assert!(register.w);
+
+        register.update(0xf0);
+        assert_eq!(register.t, 0b011_11_01111_10000);
+        assert_eq!(register.v, register.t);
+        assert!(!register.w);
     }
 
-    pub fn increment(&mut self, value: u8){
-        let l = self.value.1;
-        self.value.1 = self.value.1.wrapping_add(value);
+    #[test]
+    fn test_single_writes_for_every_value() {
+        for value in 0..=0xffu8 {
+            for &access in &[Access::Control(value), Access::Scroll(value), Access::Address(value)] {
+                for &w in &[false, true] {
+                    let mut register = AddressRegister::new();
+                    register.t = 0x5a5a;
+                    register.w = w;
+                    let mut fields = Fields { w, ..Fields::default() };
+                    fields.set_t(register.t);
+
+                    apply(&mut register, access);
+                    fields.apply(access);
+                    assert_matches(&register, &fields, &[access]);
+                }
+            }
+        }
+    }
 
-        if l > self.value.1{
-            self.value.0 = self.value.0.wrapping_add(1);
+    #[test]
+    fn test_interleaved_write_sequences() {
+        let values = [0x00, 0x07, 0x3f, 0x5e, 0x7d, 0x80, 0xc5, 0xff];
+        let mut accesses = vec![Access::Status];
+        for &value in &values {
+            accesses.push(Access::Control(value));
+            accesses.push(Access::Scroll(value));
+            accesses.push(Access::Address(value));
         }
 
-        if self.get() > 0x3FFF{
-            self.set(self.get() & 0x3FFF);
+        // every sequence of four register accesses
+        let n = accesses.len();
+        for i in 0..n * n * n * n {
+            let sequence = [
+                accesses[i % n],
+                accesses[(i / n) % n],
+                accesses[(i / (n * n)) % n],
+                accesses[i / (n * n * n)],
+            ];
+
+            let mut register = AddressRegister::new();
+            let mut fields = Fields::default();
+            for &access in &sequence {
+                apply(&mut register, access);
+                fields.apply(access);
+            }
+            assert_matches(&register, &fields, &sequence);
         }
     }
 
-    pub fn reset_latch(&mut self){
-        self.hi_ptr = true;
+    #[test]
+    fn test_status_read_resets_shared_toggle() {
+        let mut register = AddressRegister::new();
+
+        // a $2005 write leaves the toggle half way through a $2006 pair
+        register.write_scroll(0x00);
+        register.update(0x05);
+        assert_eq!(register.get(), 0x0005);
+
+        register.reset_latch();
+        register.update(0x23);
+        register.update(0x05);
+        assert_eq!(register.get(), 0x2305);
+    }
+
+    #[test]
+    fn test_scroll_views() {
+        let mut register = AddressRegister::new();
+        register.write_control(0b10);
+        register.write_scroll(0x7d);
+        register.write_scroll(0x5e);
+
+        assert_eq!(register.scroll_x(), 0x7d);
+        assert_eq!(register.scroll_y(), 0x5e);
+        assert_eq!(register.nametable(), 2);
+    }
+
+    #[test]
+    fn test_coarse_x_wraps_into_next_nametable() {
+        let mut register = AddressRegister::new();
+        register.v = 0b000_00_00000_11110;
+        register.increment_coarse_x();
+        assert_eq!(register.v, 0b000_00_00000_11111);
+        register.increment_coarse_x();
+        assert_eq!(register.v, 0b000_01_00000_00000);
+        register.v = 0b000_01_00000_11111;
+        register.increment_coarse_x();
+        assert_eq!(register.v, 0b000_00_00000_00000);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_y_increment() {
+        let mut register = AddressRegister::new();
+        register.v = 0b110_00_00101_00011;
+        register.increment_y();
+        assert_eq!(register.v, 0b111_00_00101_00011);
+        register.increment_y();
+        assert_eq!(register.v, 0b000_00_00110_00011);
+
+        // row 29 is the last one of a nametable
+        register.v = 0b111_01_11101_00000;
+        register.increment_y();
+        assert_eq!(register.v, 0b000_11_00000_00000);
+
+        // rows 30 and 31 wrap without switching nametables
+        register.v = 0b111_00_11111_00000;
+        register.increment_y();
+        assert_eq!(register.v, 0b000_00_00000_00000);
+    }
+
+    #[test]
+    fn test_copies_from_t() {
+        let mut register = AddressRegister::new();
+        register.t = 0b101_10_10101_01010;
+        register.v = 0b010_01_01010_10101;
+        register.copy_horizontal();
+        assert_eq!(register.v, 0b010_00_01010_01010);
+        register.copy_vertical();
+        assert_eq!(register.v, 0b101_10_10101_01010);
+    }
+
+    #[test]
+    fn test_increment_wraps_at_15_bits() {
+        let mut register = AddressRegister::new();
+        register.update(0x3f);
+        register.update(0xff);
+        register.increment(1);
+        assert_eq!(register.get(), 0x0000);
+
+        register.v = 0x7fff;
+        register.increment(32);
+        assert_eq!(register.v, 0x001f);
+    }
+}
@@ -16,6 +16,7 @@ bitflags! {
     // |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
     // +--------- Generate an NMI at the start of the
     //            vertical blanking interval (0: off; 1: on)
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct ControlRegister: u8 {
         const NAMETABLE1              = 0b00000001;
         const NAMETABLE2              = 0b00000010;
@@ -28,6 +29,12 @@ bitflags! {
     }
 }
 
+impl Default for ControlRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ControlRegister{
 
     pub fn new() -> ControlRegister{
@@ -39,8 +46,7 @@ impl ControlRegister{
             0 => 0x2000,
             1 => 0x2400,
             2 => 0x2800,
-            3 => 0x2C00,
-            _ => panic!("Invalid nametable address"),
+            _ => 0x2C00,
         }
     }
 
@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 // bitflags
 bitflags! {
 
@@ -13,6 +15,7 @@ bitflags! {
     // ||+------- Emphasize red
     // |+-------- Emphasize green
     // +--------- Emphasize blue
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct MaskRegister: u8 {
         const GREYSCALE               = 0b00000001;
         const LEFTMOST_8PXL_BACKGROUND  = 0b00000010;
@@ -31,6 +34,12 @@ pub enum Colour{
     Blue
 }
 
+impl Default for MaskRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MaskRegister{
     pub fn new() -> MaskRegister{
         MaskRegister::from_bits_truncate(0)
@@ -1,5 +1,4 @@
 pub mod control;
 pub mod mask;
 pub mod status;
-pub mod address;
-pub mod scroll;
\ No newline at end of file
+pub mod address;
\ No newline at end of file
@@ -21,6 +21,7 @@ bitflags! {
     //            Set at dot 1 of line 241 (the line *after* the post-render
     //            line); cleared after reading $2002 and at dot 1 of the
     //            pre-render line.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct StatusRegister: u8 {
         const NOTUSED          = 0b00000001;
         const NOTUSED2         = 0b00000010;
@@ -33,6 +34,12 @@ bitflags! {
     }
 }
 
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StatusRegister{
     pub fn new() -> StatusRegister{
         StatusRegister::from_bits_truncate(0)
@@ -0,0 +1,231 @@
+use super::registers::control::ControlRegister;
+use super::PPU;
+use crate::render::frame::Frame;
+
+// One of the 64 sprites in OAM, four bytes each
+// from: https://www.nesdev.org/wiki/PPU_OAM
+//
+// | Byte | Contents                                               |
+// |------|--------------------------------------------------------|
+// | 0    | Y of the top row, minus 1                              |
+// | 1    | Tile; for 8x16 sprites bit 0 picks the pattern table   |
+// | 2    | VHP000PP: flips, priority (1 = behind background),     |
+// |      | palette                                                |
+// | 3    | X of the left column                                   |
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    pub index: usize,
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub palette: u8,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub behind_background: bool,
+}
+
+impl Sprite {
+    pub fn from_oam(index: usize, oam: &[u8]) -> Self {
+        let attributes = oam[2];
+        Sprite {
+            index,
+            x: oam[3],
+            y: oam[0],
+            tile: oam[1],
+            palette: attributes & 0b11,
+            flip_horizontal: attributes & 0b0100_0000 != 0,
+            flip_vertical: attributes & 0b1000_0000 != 0,
+            behind_background: attributes & 0b0010_0000 != 0,
+        }
+    }
+
+    // sprites are drawn a scanline below their OAM y
+    pub fn top(&self) -> usize {
+        self.y as usize + 1
+    }
+
+    pub fn contains(&self, x: usize, y: usize, height: usize) -> bool {
+        let left = self.x as usize;
+        (self.top()..self.top() + height).contains(&y) && (left..left + 8).contains(&x)
+    }
+}
+
+impl PPU {
+    pub fn sprite(&self, index: usize) -> Sprite {
+        Sprite::from_oam(index, &self.oam_data[index * 4..index * 4 + 4])
+    }
+
+    // in OAM order, which is also priority order
+    pub fn sprites(&self) -> impl DoubleEndedIterator<Item = Sprite> + '_ {
+        (0..64).map(move |index| self.sprite(index))
+    }
+
+    // 2-bit colour at (column, row) of the sprite as drawn, flips applied
+    pub fn sprite_tile_pixel(&self, sprite: &Sprite, column: usize, row: usize) -> u8 {
+        self.sprite_pattern_pixel(self.control, sprite, column, row)
+    }
+
+    // the same with the sprite size and pattern table of `control`
+    pub fn sprite_pattern_pixel(
+        &self,
+        control: ControlRegister,
+        sprite: &Sprite,
+        column: usize,
+        row: usize,
+    ) -> u8 {
+        let height = control.sprite_size() as usize;
+        let row = if sprite.flip_vertical {
+            height - 1 - row
+        } else {
+            row
+        };
+        let column = if sprite.flip_horizontal {
+            7 - column
+        } else {
+            column
+        };
+
+        // 8x16 sprites pick their bank from bit 0 of the tile index
+        let tile = sprite.tile as usize;
+        let (bank, tile) = if height == 16 {
+            ((tile & 1) * 0x1000, (tile & 0xfe) + row / 8)
+        } else {
+            (control.sprite_pattern_addr() as usize, tile)
+        };
+        self.tile_pixel(bank, tile, column, row % 8)
+    }
+
+    // Sprite evaluation on a visible line looks for the sprites of the next
+    // one and sets the overflow flag when it finds a ninth. Once eight are
+    // found the hardware goes wrong: it also steps through the bytes of each
+    // sprite it passes, so it takes tiles, attributes or X for Y, missing
+    // some overflows and inventing others.
+    // from: https://www.nesdev.org/wiki/PPU_sprite_evaluation#Sprite_overflow_bug
+    pub(super) fn evaluate_sprite_overflow(&mut self) {
+        let line = self.scanline as i32;
+        let height = self.control.sprite_size() as i32;
+        let in_range = |y: u8| (0..height).contains(&(line - y as i32));
+
+        let mut sprite = 0;
+        let mut found = 0;
+        while sprite < 64 && found < 8 {
+            if in_range(self.oam_data[sprite * 4]) {
+                found += 1;
+            }
+            sprite += 1;
+        }
+        if found < 8 {
+            return;
+        }
+
+        let mut byte = 0;
+        while sprite < 64 {
+            if in_range(self.oam_data[sprite * 4 + byte]) {
+                self.status.set_sprite_overflow(true);
+                return;
+            }
+            sprite += 1;
+            if self.sprite_overflow_bug {
+                byte = (byte + 1) % 4;
+            }
+        }
+    }
+
+    // All 64 sprites in an 8x8 grid of 8x16 cells, OAM order left to right
+    // then top to bottom, each in its own palette on the backdrop colour
+    pub fn render_sprites_debug(&self) -> Frame {
+        let mut frame = Frame::with_size(64, 128);
+        let height = self.control.sprite_size() as usize;
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                frame.set_index(x, y, self.palette_table[0]);
+            }
+        }
+        for sprite in self.sprites() {
+            let cell_x = (sprite.index % 8) * 8;
+            let cell_y = (sprite.index / 8) * 16;
+            for row in 0..height {
+                for column in 0..8 {
+                    let value = self.sprite_tile_pixel(&sprite, column, row);
+                    if value != 0 {
+                        let palette = 0x10 + sprite.palette as usize * 4;
+                        let colour = self.palette_table[palette + value as usize];
+                        frame.set_index(cell_x + column, cell_y + row, colour);
+                    }
+                }
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use crate::ppu::registers::status::StatusRegister;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_sprite_list() {
+        // tile 1: the top row is colour 1
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0xff;
+        let mut ppu = PPU::with_chr(chr, Mirroring::VERTICAL);
+        ppu.oam_data[4..8].copy_from_slice(&[20, 1, 0b1010_0011, 30]);
+        ppu.palette_table[0x1d] = 0x16;
+
+        let sprite = ppu.sprites().nth(1).unwrap();
+        assert_eq!(
+            sprite,
+            Sprite {
+                index: 1,
+                x: 30,
+                y: 20,
+                tile: 1,
+                palette: 3,
+                flip_horizontal: false,
+                flip_vertical: true,
+                behind_background: true,
+            }
+        );
+        assert!(sprite.contains(30, 21, 8));
+        assert!(!sprite.contains(30, 20, 8));
+        assert!(!sprite.contains(38, 21, 8));
+
+        // flipped, so the top row comes out at the bottom
+        let frame = ppu.render_sprites_debug();
+        assert_eq!(frame.index(8, 0), 0);
+        assert_eq!(frame.index(8, 7), 0x16);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = PPU::with_chr(vec![0; 0x2000], Mirroring::VERTICAL);
+        ppu.oam_data = [0xff; 0x100];
+        ppu.scanline = 50;
+        // eight sprites on the line: no overflow
+        for sprite in 0..8 {
+            ppu.oam_data[sprite * 4] = 45;
+        }
+        ppu.evaluate_sprite_overflow();
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+
+        // a ninth at sprite 10 is missed, the hardware reads sprite 10's
+        // attributes as its Y instead
+        ppu.oam_data[10 * 4] = 48;
+        ppu.evaluate_sprite_overflow();
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+        ppu.sprite_overflow_bug = false;
+        ppu.evaluate_sprite_overflow();
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+
+        // and attributes that look like a Y on the line make one up
+        ppu.status.set_sprite_overflow(false);
+        ppu.sprite_overflow_bug = true;
+        ppu.oam_data[10 * 4] = 0xff;
+        ppu.oam_data[10 * 4 + 2] = 50;
+        ppu.evaluate_sprite_overflow();
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+}
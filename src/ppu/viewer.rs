@@ -0,0 +1,110 @@
+use super::PPU;
+use crate::render::frame::Frame;
+use crate::render::palette::SYSTEM_PALETTE;
+
+// Debug views of VRAM for frontends, like the PPU viewers of Mesen and FCEUX.
+// They only look at the PPU, so the picture is of the state right now.
+impl PPU {
+    // $0000 on the left and $1000 on the right, 16x16 tiles each, coloured
+    // with background palette 0
+    pub fn render_pattern_tables(&self) -> Frame {
+        let mut frame = Frame::with_size(256, 128);
+        for bank in 0..2 {
+            for tile in 0..256 {
+                let tile_x = bank * 128 + (tile % 16) * 8;
+                let tile_y = (tile / 16) * 8;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let value = self.tile_pixel(bank * 0x1000, tile, x, y);
+                        frame.set_index(tile_x + x, tile_y + y, self.palette_table[value as usize]);
+                    }
+                }
+            }
+        }
+        frame
+    }
+
+    // All four nametables as the 512x480 plane they form, with the part the
+    // screen shows outlined in white
+    pub fn render_nametables(&self) -> Frame {
+        let mut frame = Frame::with_size(512, 480);
+        for y in 0..480 {
+            for x in 0..512 {
+                let (value, palette) = self.nametable_pixel(x, y);
+                let colour = if value == 0 {
+                    self.palette_table[0]
+                } else {
+                    self.palette_table[(palette * 4 + value) as usize]
+                };
+                frame.set_index(x, y, colour);
+            }
+        }
+
+        // the viewport wraps around the edges of the plane
+        let (left, top) = self.scroll_origin();
+        for x in 0..Frame::WIDTH {
+            frame.set_index((left + x) % 512, top, 0x30);
+            frame.set_index((left + x) % 512, (top + Frame::HEIGHT - 1) % 480, 0x30);
+        }
+        for y in 0..Frame::HEIGHT {
+            frame.set_index(left, (top + y) % 480, 0x30);
+            frame.set_index((left + Frame::WIDTH - 1) % 512, (top + y) % 480, 0x30);
+        }
+        frame
+    }
+
+    // RGB of the 32 palette RAM entries: 4 background palettes, then 4 sprite
+    // palettes
+    pub fn palette_colors(&self) -> [[u8; 3]; 32] {
+        let mut colours = [[0; 3]; 32];
+        for (colour, &index) in colours.iter_mut().zip(self.palette_table.iter()) {
+            let (r, g, b) = SYSTEM_PALETTE[(index & 0x3f) as usize];
+            *colour = [r, g, b];
+        }
+        colours
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use crate::ppu::PPUInterface;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_pattern_tables() {
+        // tile 1 of $0000 is solid colour 1, tile 0 of $1000 solid colour 3
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].copy_from_slice(&[0xff; 8]);
+        chr[0x1000..0x1010].copy_from_slice(&[0xff; 16]);
+        let mut ppu = PPU::with_chr(chr, Mirroring::VERTICAL);
+        ppu.palette_table[..4].copy_from_slice(&[0x0f, 0x16, 0x27, 0x30]);
+
+        let frame = ppu.render_pattern_tables();
+        assert_eq!((frame.width, frame.height), (256, 128));
+        assert_eq!(frame.index(0, 0), 0x0f);
+        assert_eq!(frame.index(8, 0), 0x16);
+        assert_eq!(frame.index(15, 7), 0x16);
+        assert_eq!(frame.index(128, 0), 0x30);
+        assert_eq!(frame.index(136, 0), 0x0f);
+        assert_eq!(ppu.palette_colors()[3], [0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_nametables_outline_the_screen() {
+        let mut ppu = PPU::with_chr(vec![0; 0x2000], Mirroring::VERTICAL);
+        ppu.palette_table[0] = 0x0f;
+        // scrolled 16 pixels right and 8 down
+        ppu.write_to_scroll(16);
+        ppu.write_to_scroll(8);
+
+        let frame = ppu.render_nametables();
+        assert_eq!((frame.width, frame.height), (512, 480));
+        assert_eq!(frame.index(0, 0), 0x0f);
+        assert_eq!(frame.index(16, 8), 0x30);
+        assert_eq!(frame.index(271, 100), 0x30);
+        assert_eq!(frame.index(100, 247), 0x30);
+        assert_eq!(frame.index(100, 100), 0x0f);
+    }
+}
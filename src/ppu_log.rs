@@ -0,0 +1,175 @@
+use alloc::collections::VecDeque;
+use core::fmt;
+
+// A log of CPU writes to the PPU's registers, with where the PPU was in the
+// frame at each one, for seeing when a game set up its scroll splits. A
+// split that comes out a few lines or pixels off the screen is usually a
+// $2005 or $2006 write landing too early or late, which is plain in here.
+//
+// The PPU catches up with the CPU after each instruction, so the position
+// is where it was as the writing instruction started, a few dots before
+// the write itself. Frames are counted from the log's start, each one
+// ending as vblank starts like Nes::run_frame's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PpuWrite {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    // CPU cycles since power on
+    pub cycles: usize,
+    // $2000-$2007 with the mirrors folded down, or $4014 for OAM DMA
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl PpuWrite {
+    pub fn register(&self) -> &'static str {
+        match self.addr {
+            0x2000 => "PPUCTRL",
+            0x2001 => "PPUMASK",
+            0x2002 => "PPUSTATUS",
+            0x2003 => "OAMADDR",
+            0x2004 => "OAMDATA",
+            0x2005 => "PPUSCROLL",
+            0x2006 => "PPUADDR",
+            0x2007 => "PPUDATA",
+            _ => "OAMDMA",
+        }
+    }
+}
+
+impl fmt::Display for PpuWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame {} line {:3} dot {:3}: {:<9} ${:04X} = ${:02X}",
+            self.frame,
+            self.scanline,
+            self.dot,
+            self.register(),
+            self.addr,
+            self.value
+        )
+    }
+}
+
+// the newest `capacity` writes
+pub struct PpuWriteLog {
+    writes: VecDeque<PpuWrite>,
+    capacity: usize,
+    frame: u64,
+}
+
+impl PpuWriteLog {
+    pub fn new(capacity: usize) -> Self {
+        PpuWriteLog {
+            writes: VecDeque::with_capacity(capacity.min(0x1000)),
+            capacity: capacity.max(1),
+            frame: 0,
+        }
+    }
+
+    pub fn record(&mut self, addr: u16, value: u8, position: (u16, u16), cycles: usize) {
+        if self.writes.len() == self.capacity {
+            self.writes.pop_front();
+        }
+        self.writes.push_back(PpuWrite {
+            frame: self.frame,
+            scanline: position.0,
+            dot: position.1,
+            cycles,
+            addr,
+            value,
+        });
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    // the frame being logged now
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // oldest first
+    pub fn writes(&self) -> impl DoubleEndedIterator<Item = &PpuWrite> {
+        self.writes.iter()
+    }
+
+    // the writes made during one frame, in the order they were made
+    pub fn frame_writes(&self, frame: u64) -> impl Iterator<Item = &PpuWrite> {
+        self.writes.iter().filter(move |write| write.frame == frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::test::nrom;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_log_keeps_the_newest() {
+        let mut log = PpuWriteLog::new(2);
+        log.record(0x2000, 0x80, (261, 3), 10);
+        log.end_frame();
+        log.record(0x2005, 0x10, (30, 250), 20);
+        log.record(0x2005, 0x00, (30, 274), 28);
+        assert_eq!(log.len(), 2);
+        let scroll: Vec<u8> = log.frame_writes(1).map(|write| write.value).collect();
+        assert_eq!(scroll, [0x10, 0x00]);
+        assert_eq!(log.frame_writes(0).count(), 0);
+        assert_eq!(
+            log.writes().next().unwrap().to_string(),
+            "frame 1 line  30 dot 250: PPUSCROLL $2005 = $10"
+        );
+        log.clear();
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn test_nes_logs_ppu_writes() {
+        // LDA #$80; STA $2000; STA $3FFD (a mirror of $2005); JMP to itself
+        let rom = nrom(
+            &[
+                0xa9, 0x80, 0x8d, 0x00, 0x20, 0x8d, 0xfd, 0x3f, 0x4c, 0x08, 0x80,
+            ],
+            &[],
+        );
+
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.enable_ppu_write_log(64);
+        nes.run_frame();
+        nes.run_frame();
+        let log = nes.ppu_write_log().unwrap();
+        assert_eq!(log.frame(), 2);
+        let writes: Vec<&PpuWrite> = log.writes().collect();
+        assert_eq!(writes.len(), 2);
+        assert_eq!((writes[0].addr, writes[0].value), (0x2000, 0x80));
+        assert_eq!(
+            (writes[1].addr, writes[1].register()),
+            (0x2005, "PPUSCROLL")
+        );
+        assert_eq!(writes[1].frame, 0);
+        // where the second STA starts: after 7 cycles of reset, 2 of LDA and
+        // 4 of the first STA
+        assert_eq!((writes[1].scanline, writes[1].dot), (0, 39));
+        assert!(nes.disable_ppu_write_log().is_some());
+        assert!(nes.ppu_write_log().is_none());
+    }
+}
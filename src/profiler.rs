@@ -0,0 +1,177 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+// Where an instruction ran from: its CPU address, and for code in cartridge
+// ROM its offset into PRG ROM, since the same address runs different code
+// in different banks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Location {
+    pub pc: u16,
+    pub prg_offset: Option<usize>,
+}
+
+impl Location {
+    // 8KB banks, the smallest any supported board switches
+    pub fn bank(&self) -> Option<usize> {
+        self.prg_offset.map(|offset| offset / 0x2000)
+    }
+
+    fn name(&self) -> String {
+        match self.bank() {
+            Some(bank) => format!("bank {:02X}", bank),
+            None => "RAM".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub instructions: u64,
+    // including the interrupt entry, page crossings and DMA it triggered
+    pub cycles: u64,
+}
+
+// Counts every instruction the CPU runs while it's enabled, per location and
+// per opcode. Reports are sorted by the cycles spent.
+#[derive(Default)]
+pub struct Profiler {
+    locations: BTreeMap<Location, Counts>,
+    opcodes: BTreeMap<u8, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn record(&mut self, location: Location, opcode: u8, cycles: usize) {
+        let counts = self.locations.entry(location).or_default();
+        counts.instructions += 1;
+        counts.cycles += cycles as u64;
+        *self.opcodes.entry(opcode).or_default() += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.locations.clear();
+        self.opcodes.clear();
+    }
+
+    pub fn counts(&self, location: Location) -> Counts {
+        self.locations.get(&location).copied().unwrap_or_default()
+    }
+
+    pub fn total(&self) -> Counts {
+        self.locations
+            .values()
+            .fold(Counts::default(), |total, counts| Counts {
+                instructions: total.instructions + counts.instructions,
+                cycles: total.cycles + counts.cycles,
+            })
+    }
+
+    // most cycles first
+    pub fn hot_spots(&self) -> Vec<(Location, Counts)> {
+        let mut hot_spots: Vec<_> = self
+            .locations
+            .iter()
+            .map(|(location, counts)| (*location, *counts))
+            .collect();
+        hot_spots.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        hot_spots
+    }
+
+    // times each opcode ran; opcodes that never ran aren't in it
+    pub fn opcode_counts(&self) -> &BTreeMap<u8, u64> {
+        &self.opcodes
+    }
+
+    // The `limit` hottest locations as a table:
+    //   bank 01 $8123    1234 instr    5678 cycles  12.34%
+    pub fn report(&self, limit: usize) -> String {
+        let total = self.total().cycles.max(1);
+        let mut report = String::new();
+        for (location, counts) in self.hot_spots().into_iter().take(limit) {
+            writeln!(
+                report,
+                "{:<8} ${:04X} {:>10} instr {:>12} cycles {:>6.2}%",
+                location.name(),
+                location.pc,
+                counts.instructions,
+                counts.cycles,
+                counts.cycles as f64 * 100.0 / total as f64
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    // callgrind's file format, for KCachegrind and friends; every bank is a
+    // file and a function, positions are CPU addresses
+    // from: https://valgrind.org/docs/manual/cl-format.html
+    pub fn callgrind(&self) -> String {
+        let mut locations: Vec<_> = self.locations.iter().collect();
+        locations.sort_by_key(|(location, _)| (location.bank(), location.pc));
+
+        let mut output = String::new();
+        output.push_str("# callgrind format\nversion: 1\ncreator: rust-nes-emu\n");
+        output.push_str("positions: instr\nevents: Instructions Cycles\n");
+        let mut current = None;
+        for (location, counts) in locations {
+            if current != Some(location.bank()) {
+                current = Some(location.bank());
+                write!(output, "\nfl={0}\nfn={0}\n", location.name()).unwrap();
+            }
+            writeln!(
+                output,
+                "0x{:04X} {} {}",
+                location.pc, counts.instructions, counts.cycles
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let ram = Location {
+            pc: 0x0600,
+            prg_offset: None,
+        };
+        let rom = Location {
+            pc: 0x8000,
+            prg_offset: Some(0x6000),
+        };
+        let mut profiler = Profiler::new();
+        profiler.record(ram, 0xea, 2);
+        profiler.record(rom, 0xea, 2);
+        profiler.record(rom, 0xad, 4);
+
+        assert_eq!(
+            profiler.counts(rom),
+            Counts {
+                instructions: 2,
+                cycles: 6
+            }
+        );
+        assert_eq!(profiler.total().cycles, 8);
+        assert_eq!(profiler.opcode_counts()[&0xea], 2);
+        assert_eq!(
+            profiler.report(1),
+            "bank 03  $8000          2 instr            6 cycles  75.00%\n"
+        );
+        assert!(profiler
+            .callgrind()
+            .ends_with("\nfl=RAM\nfn=RAM\n0x0600 1 2\n\nfl=bank 03\nfn=bank 03\n0x8000 2 6\n"));
+
+        profiler.clear();
+        assert_eq!(profiler.total().instructions, 0);
+    }
+}
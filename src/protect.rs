@@ -0,0 +1,189 @@
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+// Debugging aid: address ranges on the CPU bus that the game isn't allowed
+// to write. A read-only region drops the write, a trap region lets it
+// through; either way the write is reported so a debugger can stop on it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protection {
+    ReadOnly,
+    Trap,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteViolation {
+    pub addr: u16,
+    pub value: u8,
+    // what the address held before, for RAM
+    pub previous: Option<u8>,
+    // address of the instruction that did the write, None outside the CPU
+    pub pc: Option<u16>,
+    pub protection: Protection,
+}
+
+// RAM and the PPU registers repeat through these areas: first address, last
+// address and the size of one copy
+const MIRRORED: [(u16, u16, u16); 2] = [(0x0000, 0x1fff, 0x0800), (0x2000, 0x3fff, 0x0008)];
+
+// the address in the first copy of RAM or the PPU registers
+fn fold(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x1fff => addr & 0x07ff,
+        0x2000..=0x3fff => addr & 0x2007,
+        _ => addr,
+    }
+}
+
+// `range` with its mirrored parts folded into the first copy; a part that
+// runs past the end of a copy wraps around to its start
+fn fold_range(range: RangeInclusive<u16>) -> Vec<RangeInclusive<u16>> {
+    let (start, end) = range.into_inner();
+    let mut folded = Vec::new();
+    for (first, last, size) in MIRRORED {
+        let (from, to) = (start.max(first), end.min(last));
+        if from > to {
+            continue;
+        }
+        if to - from >= size - 1 {
+            folded.push(first..=first + size - 1);
+            continue;
+        }
+        let (from, to) = (fold(from), fold(to));
+        if from <= to {
+            folded.push(from..=to);
+        } else {
+            folded.push(from..=first + size - 1);
+            folded.push(first..=to);
+        }
+    }
+    if end >= 0x4000 {
+        folded.push(start.max(0x4000)..=end);
+    }
+    folded
+}
+
+#[derive(Default)]
+pub struct WriteProtection {
+    // the ranges of one protect call, folded
+    regions: Vec<(Vec<RangeInclusive<u16>>, Protection)>,
+    violations: Vec<WriteViolation>,
+}
+
+impl WriteProtection {
+    pub fn new() -> Self {
+        WriteProtection::default()
+    }
+
+    // RAM and the PPU registers can be given through any of their mirrors,
+    // and are protected in all of them
+    pub fn protect(&mut self, range: RangeInclusive<u16>, protection: Protection) {
+        let ranges = fold_range(range);
+        if !ranges.is_empty() {
+            self.regions.push((ranges, protection));
+        }
+    }
+
+    // drops every region covering `addr`
+    pub fn unprotect(&mut self, addr: u16) {
+        let addr = fold(addr);
+        self.regions
+            .retain(|(ranges, _)| !ranges.iter().any(|range| range.contains(&addr)));
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+        self.violations.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    // read-only wins when regions overlap
+    pub fn check(&self, addr: u16) -> Option<Protection> {
+        let addr = fold(addr);
+        let mut found = None;
+        for (ranges, protection) in &self.regions {
+            if ranges.iter().any(|range| range.contains(&addr)) {
+                if *protection == Protection::ReadOnly {
+                    return Some(Protection::ReadOnly);
+                }
+                found = Some(*protection);
+            }
+        }
+        found
+    }
+
+    pub fn record(&mut self, addr: u16, value: u8, previous: Option<u8>, protection: Protection) {
+        self.violations.push(WriteViolation {
+            addr,
+            value,
+            previous,
+            pc: None,
+            protection,
+        });
+    }
+
+    // the CPU fills in which instruction it was running once it's done
+    pub fn stamp_pc(&mut self, pc: u16) {
+        for violation in self.violations.iter_mut().filter(|v| v.pc.is_none()) {
+            violation.pc = Some(pc);
+        }
+    }
+
+    pub fn has_violations(&self) -> bool {
+        !self.violations.is_empty()
+    }
+
+    pub fn take_violations(&mut self) -> Vec<WriteViolation> {
+        core::mem::take(&mut self.violations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        let mut protection = WriteProtection::new();
+        protection.protect(0x0300..=0x03ff, Protection::Trap);
+        protection.protect(0x0310..=0x0310, Protection::ReadOnly);
+
+        assert_eq!(protection.check(0x02ff), None);
+        assert_eq!(protection.check(0x0300), Some(Protection::Trap));
+        assert_eq!(protection.check(0x0310), Some(Protection::ReadOnly));
+        assert_eq!(protection.check(0x1b10), Some(Protection::ReadOnly));
+
+        protection.unprotect(0x0310);
+        assert_eq!(protection.check(0x0310), None);
+        assert_eq!(protection.check(0x0311), None);
+    }
+
+    #[test]
+    fn test_regions_through_mirrors() {
+        let mut protection = WriteProtection::new();
+        protection.protect(0x0900..=0x0900, Protection::ReadOnly);
+        assert_eq!(protection.check(0x0100), Some(Protection::ReadOnly));
+        assert_eq!(protection.check(0x1900), Some(Protection::ReadOnly));
+
+        // across the end of a copy of RAM, and on into the PPU registers
+        protection.protect(0x0ffe..=0x2009, Protection::Trap);
+        assert_eq!(protection.check(0x07ff), Some(Protection::Trap));
+        assert_eq!(protection.check(0x0000), Some(Protection::Trap));
+        assert_eq!(protection.check(0x0400), Some(Protection::Trap));
+        assert_eq!(protection.check(0x3ff8), Some(Protection::Trap));
+        protection.unprotect(0x1000);
+        assert_eq!(protection.check(0x0400), None);
+        assert_eq!(protection.check(0x0100), Some(Protection::ReadOnly));
+
+        protection.protect(0x2ffe..=0x3001, Protection::Trap);
+        assert_eq!(protection.check(0x2006), Some(Protection::Trap));
+        assert_eq!(protection.check(0x2001), Some(Protection::Trap));
+        assert_eq!(protection.check(0x2002), None);
+        protection.protect(0x3fff..=0x4001, Protection::Trap);
+        assert_eq!(protection.check(0x2007), Some(Protection::Trap));
+        assert_eq!(protection.check(0x4001), Some(Protection::Trap));
+        assert_eq!(protection.check(0x4002), None);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::nes::Nes;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Narrows down where in RAM a game keeps a number, like lives or score, in
+// the spirit of FCEUX's RAM Search.
+// from: https://fceux.com/web/help/RAMSearch.html
+//
+// Every address in the 2KB of CPU RAM starts out as a candidate. Each filter
+// compares what's there now with a value, or with what was there at the
+// last filter, and drops the addresses that don't pass:
+//
+//   let mut search = RamSearch::new(&nes, View::U8);
+//   // lose a life
+//   search.filter(&nes, Filter::Less(Target::Previous));
+//   // lose another one, or play on without dying
+//   search.filter(&nes, Filter::ChangedBy(-1));
+//   search.filter(&nes, Filter::Equal(Target::Previous));
+//
+// until only a few are left to freeze with a cheat, see
+// SearchResult::cheat_codes.
+const RAM_SIZE: usize = 0x800;
+
+// how the bytes at an address are read as a number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    U8,
+    // little endian, the address and the one after
+    U16,
+    // a byte of two decimal digits, $42 for 42. Bytes that aren't valid BCD
+    // never pass a filter.
+    Bcd,
+}
+
+impl View {
+    fn read(self, memory: &[u8], addr: usize) -> Option<u32> {
+        match self {
+            View::U8 => memory.get(addr).map(|&byte| byte as u32),
+            View::U16 => {
+                let lo = *memory.get(addr)?;
+                let hi = *memory.get(addr + 1)?;
+                Some(u16::from_le_bytes([lo, hi]) as u32)
+            }
+            View::Bcd => {
+                let byte = *memory.get(addr)?;
+                let (tens, ones) = (byte >> 4, byte & 0x0f);
+                if tens > 9 || ones > 9 {
+                    return None;
+                }
+                Some(tens as u32 * 10 + ones as u32)
+            }
+        }
+    }
+
+    // the bytes that make `value` from the address on
+    fn encode(self, value: u32) -> Vec<u8> {
+        match self {
+            View::U8 => vec![value as u8],
+            View::U16 => (value as u16).to_le_bytes().to_vec(),
+            View::Bcd => {
+                let value = value % 100;
+                vec![(((value / 10) << 4) | (value % 10)) as u8]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    // what was there at the last filter, or when the search started
+    Previous,
+    Value(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Equal(Target),
+    NotEqual(Target),
+    Greater(Target),
+    Less(Target),
+    // now minus before, so negative for a drop
+    ChangedBy(i64),
+}
+
+impl Filter {
+    fn passes(self, now: u32, previous: u32) -> bool {
+        let target = |target| match target {
+            Target::Previous => previous,
+            Target::Value(value) => value,
+        };
+        match self {
+            Filter::Equal(other) => now == target(other),
+            Filter::NotEqual(other) => now != target(other),
+            Filter::Greater(other) => now > target(other),
+            Filter::Less(other) => now < target(other),
+            Filter::ChangedBy(delta) => now as i64 - previous as i64 == delta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub address: u16,
+    pub value: u32,
+    pub previous: u32,
+    view: View,
+}
+
+impl SearchResult {
+    // raw cheat codes holding the address at `value`, one per byte the view
+    // reads, for Nes::add_cheat
+    pub fn cheat_codes(&self, value: u32) -> Vec<String> {
+        self.view
+            .encode(value)
+            .iter()
+            .enumerate()
+            .map(|(offset, byte)| format!("{:04X}:{:02X}", self.address as usize + offset, byte))
+            .collect()
+    }
+}
+
+pub struct RamSearch {
+    view: View,
+    // RAM as of the last filter
+    previous: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+fn ram(nes: &Nes) -> Vec<u8> {
+    nes.peek_range(0, RAM_SIZE)
+}
+
+impl RamSearch {
+    pub fn new(nes: &Nes, view: View) -> Self {
+        let mut search = RamSearch {
+            view,
+            previous: Vec::new(),
+            candidates: Vec::new(),
+        };
+        search.reset(nes);
+        search
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    // starts over with every address a candidate again, compared against
+    // RAM as it is now
+    pub fn reset(&mut self, nes: &Nes) {
+        self.previous = ram(nes);
+        let view = self.view;
+        let memory = &self.previous;
+        self.candidates = (0..RAM_SIZE)
+            .filter(|&addr| view.read(memory, addr).is_some())
+            .map(|addr| addr as u16)
+            .collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    // Drops every candidate that doesn't pass, and keeps RAM as it is now
+    // for the next Target::Previous. Returns how many are left.
+    pub fn filter(&mut self, nes: &Nes, filter: Filter) -> usize {
+        let memory = ram(nes);
+        let view = self.view;
+        let previous = &self.previous;
+        self.candidates.retain(|&addr| {
+            let addr = addr as usize;
+            match (view.read(&memory, addr), view.read(previous, addr)) {
+                (Some(now), Some(before)) => filter.passes(now, before),
+                _ => false,
+            }
+        });
+        self.previous = memory;
+        self.candidates.len()
+    }
+
+    // the candidates with what's at them now and at the last filter
+    pub fn results(&self, nes: &Nes) -> Vec<SearchResult> {
+        let memory = ram(nes);
+        self.candidates
+            .iter()
+            .filter_map(|&address| {
+                Some(SearchResult {
+                    address,
+                    value: self.view.read(&memory, address as usize)?,
+                    previous: self.view.read(&self.previous, address as usize)?,
+                    view: self.view,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use crate::rom::test::looping_rom;
+
+    #[test]
+    fn test_narrows_down_a_counter() {
+        let mut nes = Nes::new(&looping_rom()).unwrap();
+        nes.bus_mut().mem_write(0x0042, 3);
+        let mut search = RamSearch::new(&nes, View::U8);
+        assert_eq!(search.len(), RAM_SIZE);
+
+        // a life lost, with something else changing alongside
+        nes.bus_mut().mem_write(0x0042, 2);
+        nes.bus_mut().mem_write(0x0300, 0x10);
+        assert_eq!(search.filter(&nes, Filter::NotEqual(Target::Previous)), 2);
+        nes.bus_mut().mem_write(0x0042, 1);
+        nes.bus_mut().mem_write(0x0300, 0x20);
+        assert_eq!(search.filter(&nes, Filter::ChangedBy(-1)), 1);
+
+        let results = search.results(&nes);
+        assert_eq!(results[0].address, 0x0042);
+        assert_eq!((results[0].value, results[0].previous), (1, 1));
+        assert_eq!(results[0].cheat_codes(9), ["0042:09"]);
+        nes.add_cheat(&results[0].cheat_codes(9)[0]).unwrap();
+        assert_eq!(nes.peek(0x0042), 9);
+
+        search.reset(&nes);
+        assert_eq!(search.filter(&nes, Filter::Greater(Target::Value(0))), 2);
+    }
+
+    #[test]
+    fn test_views() {
+        let mut nes = Nes::new(&looping_rom()).unwrap();
+        // a score of 1234 at $10, both as a word and as BCD digits
+        nes.bus_mut().mem_write(0x0010, 0xd2);
+        nes.bus_mut().mem_write(0x0011, 0x04);
+        nes.bus_mut().mem_write(0x0020, 0x34);
+        nes.bus_mut().mem_write(0x0021, 0x12);
+
+        let mut words = RamSearch::new(&nes, View::U16);
+        // the last address has no byte after it in RAM
+        assert_eq!(words.len(), RAM_SIZE - 1);
+        assert_eq!(words.filter(&nes, Filter::Equal(Target::Value(1234))), 1);
+        assert_eq!(
+            words.results(&nes)[0].cheat_codes(0x1ff),
+            ["0010:FF", "0011:01"]
+        );
+
+        // $D2 isn't BCD, so it isn't a candidate at all
+        let mut bcd = RamSearch::new(&nes, View::Bcd);
+        assert_eq!(bcd.len(), RAM_SIZE - 1);
+        assert_eq!(bcd.filter(&nes, Filter::Equal(Target::Value(34))), 1);
+        assert_eq!(bcd.results(&nes)[0].address, 0x0020);
+        assert_eq!(bcd.results(&nes)[0].cheat_codes(99), ["0020:99"]);
+    }
+}
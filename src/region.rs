@@ -0,0 +1,91 @@
+use crate::rom::Timing;
+
+// Console regions
+// from: https://www.nesdev.org/wiki/Cycle_reference_chart
+//
+// | Region | CPU clock     | PPU dots per CPU cycle | Scanlines | Vblank from | Frame rate |
+// |--------|---------------|------------------------|-----------|-------------|------------|
+// | NTSC   | 1.789773 MHz  | 3                      | 262       | 241         | 60.0988 Hz |
+// | PAL    | 1.662607 MHz  | 3.2                    | 312       | 241         | 50.0070 Hz |
+// | Dendy  | 1.773448 MHz  | 3                      | 312       | 291         | 50.0070 Hz |
+//
+// Only NTSC skips a dot on odd frames. The Dendy's APU keeps the NTSC
+// tables, the PAL APU has its own frame counter, noise and DMC timings.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    // "ntsc", "pal" or "dendy", as frontends take them
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    // multi-region cartridges run as NTSC
+    pub fn from_timing(timing: Timing) -> Self {
+        match timing {
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+
+    pub fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    // PPU dots per CPU cycle as a fraction
+    pub fn ppu_dots_per_cycle(self) -> (u32, u32) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(self) -> u16 {
+        self.scanlines() - 1
+    }
+
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    pub fn pal_apu(self) -> bool {
+        self == Region::Pal
+    }
+}
@@ -0,0 +1,117 @@
+use super::frame::Frame;
+#[cfg(feature = "ntsc")]
+use super::ntsc::{self, NtscFilter};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Post-processing between a rendered frame and the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFilter {
+    // the frame's own RGB, pixel for pixel
+    None,
+    // every line doubled, the second copy darkened like the gaps between
+    // the scanlines of a CRT
+    Crt,
+    // composite video artifacts, see render/ntsc.rs
+    #[cfg(feature = "ntsc")]
+    Ntsc,
+}
+
+impl VideoFilter {
+    // "none", "crt" or "ntsc", as frontends take them
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(VideoFilter::None),
+            "crt" => Some(VideoFilter::Crt),
+            #[cfg(feature = "ntsc")]
+            "ntsc" => Some(VideoFilter::Ntsc),
+            _ => None,
+        }
+    }
+
+    // width and height of the RGB a filter makes of a screen-sized frame
+    pub fn output_size(&self) -> (usize, usize) {
+        match self {
+            VideoFilter::None => (Frame::WIDTH, Frame::HEIGHT),
+            VideoFilter::Crt => (Frame::WIDTH, Frame::HEIGHT * 2),
+            #[cfg(feature = "ntsc")]
+            VideoFilter::Ntsc => (ntsc::OUTPUT_WIDTH, Frame::HEIGHT),
+        }
+    }
+}
+
+// brightness of the darkened lines of the CRT filter, out of 256
+const CRT_GAP_BRIGHTNESS: u16 = 160;
+
+// Applies a VideoFilter frame after frame, keeping its buffers between them
+pub struct Filter {
+    kind: VideoFilter,
+    output: Vec<u8>,
+    #[cfg(feature = "ntsc")]
+    ntsc: Option<NtscFilter>,
+}
+
+impl Filter {
+    pub fn new(kind: VideoFilter) -> Self {
+        let (width, height) = kind.output_size();
+        Filter {
+            kind,
+            output: vec![0; width * height * 3],
+            #[cfg(feature = "ntsc")]
+            ntsc: None,
+        }
+    }
+
+    pub fn kind(&self) -> VideoFilter {
+        self.kind
+    }
+
+    pub fn width(&self) -> usize {
+        self.kind.output_size().0
+    }
+
+    pub fn height(&self) -> usize {
+        self.kind.output_size().1
+    }
+
+    // RGB24, width() x height()
+    pub fn apply<'a>(&'a mut self, frame: &'a Frame) -> &'a [u8] {
+        match self.kind {
+            VideoFilter::None => &frame.data,
+            VideoFilter::Crt => {
+                let row = Frame::WIDTH * 3;
+                for y in 0..Frame::HEIGHT {
+                    let line = &frame.data[y * row..(y + 1) * row];
+                    self.output[y * 2 * row..(y * 2 + 1) * row].copy_from_slice(line);
+                    let gap = &mut self.output[(y * 2 + 1) * row..(y * 2 + 2) * row];
+                    for (dimmed, &value) in gap.iter_mut().zip(line) {
+                        *dimmed = (value as u16 * CRT_GAP_BRIGHTNESS / 256) as u8;
+                    }
+                }
+                &self.output
+            }
+            // its tables take a moment to build, so only when it's used
+            #[cfg(feature = "ntsc")]
+            VideoFilter::Ntsc => self.ntsc.get_or_insert_with(NtscFilter::new).apply(frame),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crt_doubles_lines() {
+        let mut frame = Frame::new();
+        frame.set_index(0, 1, 0x30);
+        let mut filter = Filter::new(VideoFilter::from_name("crt").unwrap());
+        assert_eq!((filter.width(), filter.height()), (256, 480));
+
+        let output = filter.apply(&frame);
+        let row = Frame::WIDTH * 3;
+        assert_eq!(output[2 * row..2 * row + 3], [0xff, 0xff, 0xff]);
+        assert_eq!(output[3 * row..3 * row + 3], [0x9f, 0x9f, 0x9f]);
+        assert_eq!(VideoFilter::from_name("sepia"), None);
+    }
+}
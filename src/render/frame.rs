@@ -0,0 +1,122 @@
+use super::palette::Palette;
+use crate::ppu::registers::mask::MaskRegister;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// A rendered picture, kept both as RGB with greyscale and emphasis applied
+// and as the raw values the PPU put out: a 6-bit palette index per pixel
+// plus the greyscale and emphasis bits of PPUMASK for every scanline.
+// Filters and exports can work from the raw values instead of the baked RGB.
+//
+// Screens are WIDTH x HEIGHT; debug views such as the nametable viewer make
+// frames of other sizes.
+#[derive(Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+    pub indices: Vec<u8>,
+    pub scanline_mask: Vec<u8>,
+    palette: Palette,
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+    // PPUMASK bits that change how a scanline's colours come out
+    pub const MASK_COLOUR_BITS: u8 = 0b1110_0001;
+
+    pub fn new() -> Self {
+        Frame::with_size(Frame::WIDTH, Frame::HEIGHT)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Self {
+        Frame {
+            width,
+            height,
+            data: vec![0; width * height * 3],
+            indices: vec![0; width * height],
+            scanline_mask: vec![0; height],
+            palette: Palette::ntsc(),
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        let base = y * 3 * self.width + x * 3;
+        if base + 2 < self.data.len() {
+            self.data[base] = rgb.0;
+            self.data[base + 1] = rgb.1;
+            self.data[base + 2] = rgb.2;
+        }
+    }
+
+    // the RGB comes out with the greyscale and emphasis bits of the line
+    pub fn set_index(&mut self, x: usize, y: usize, index: u8) {
+        let mask = self.scanline_mask.get(y).copied().unwrap_or(0);
+        self.set_colour(x, y, index, MaskRegister::from_bits_truncate(mask));
+    }
+
+    // the same under `mask`, for PPUMASK changes partway along a line
+    pub fn set_colour(&mut self, x: usize, y: usize, index: u8, mask: MaskRegister) {
+        let index = index & 0x3f;
+        if let Some(pixel) = self.indices.get_mut(y * self.width + x) {
+            *pixel = index;
+        }
+        self.set_pixel(x, y, self.palette.colour(index, mask));
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // switches palettes, recolouring what's already drawn
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        for y in 0..self.height {
+            let mask = MaskRegister::from_bits_truncate(self.scanline_mask[y]);
+            for x in 0..self.width {
+                let colour = self.palette.colour(self.index(x, y), mask);
+                self.set_pixel(x, y, colour);
+            }
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> u8 {
+        self.indices[y * self.width + x]
+    }
+
+    pub fn set_scanline_mask(&mut self, y: usize, mask: u8) {
+        self.scanline_mask[y] = mask & Frame::MASK_COLOUR_BITS;
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::palette::SYSTEM_PALETTE;
+
+    #[test]
+    fn test_index_and_rgb_are_kept_together() {
+        let mut frame = Frame::new();
+        frame.set_index(10, 20, 0x41);
+
+        assert_eq!(frame.index(10, 20), 0x01);
+        let base = (20 * Frame::WIDTH + 10) * 3;
+        let (r, g, b) = SYSTEM_PALETTE[0x01];
+        assert_eq!(frame.data[base..base + 3], [r, g, b]);
+
+        frame.set_scanline_mask(20, 0xff);
+        assert_eq!(frame.scanline_mask[20], 0b1110_0001);
+
+        // greyscale, all three channels dimmed
+        frame.set_index(10, 20, 0x21);
+        assert_eq!(frame.index(10, 20), 0x21);
+        assert_eq!(frame.data[base..base + 3], [0xd0, 0xd0, 0xd0]);
+    }
+}
@@ -0,0 +1,50 @@
+pub mod filter;
+pub mod frame;
+#[cfg(feature = "ntsc")]
+pub mod ntsc;
+pub mod overlay;
+pub mod palette;
+pub mod scaler;
+
+use alloc::vec::Vec;
+use crate::ppu::raster::LineRegisters;
+use crate::ppu::sprite::Sprite;
+use crate::ppu::PPU;
+use frame::Frame;
+
+const SPRITES_PER_LINE: usize = 8;
+
+// Renders the frame a line at a time, each line with the PPUCTRL, PPUMASK
+// and scroll it was latched with (see ppu/raster.rs), greyscale and colour
+// emphasis included. Lines the PPU hasn't reached are drawn from the
+// registers as they are now.
+pub fn render(ppu: &PPU, frame: &mut Frame) {
+    for y in 0..Frame::HEIGHT {
+        let live = [ppu.live_registers(y)];
+        let segments = match ppu.line_registers(y) {
+            [] => &live[..],
+            segments => segments,
+        };
+        render_line(ppu, y, segments, frame);
+    }
+}
+
+fn render_line(ppu: &PPU, y: usize, segments: &[LineRegisters], frame: &mut Frame) {
+    frame.set_scanline_mask(y, segments[0].mask.bits());
+
+    // sprites are picked for the whole line before it's drawn, the first 8
+    // in OAM order, as the PPU's secondary OAM only holds that many
+    // from: https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    let height = segments[0].control.sprite_size() as usize;
+    let sprites: Vec<Sprite> = ppu
+        .sprites()
+        .filter(|sprite| (sprite.top()..sprite.top() + height).contains(&y))
+        .take(SPRITES_PER_LINE)
+        .collect();
+
+    for x in 0..Frame::WIDTH {
+        let registers = LineRegisters::at(segments, x);
+        let colour = ppu.line_pixel_colour(registers, sprites.iter().copied(), x, y);
+        frame.set_colour(x, y, colour, registers.mask);
+    }
+}
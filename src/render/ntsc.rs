@@ -0,0 +1,189 @@
+use super::frame::Frame;
+
+use core::f32::consts::PI;
+
+// Composite video the way the 2C02 makes it: every pixel is 8 samples of a
+// square wave between two voltages, its phase against the 12-sample colour
+// subcarrier picking the hue. Decoding that back into RGB, a few samples at
+// a time, gives the colour fringes at sharp edges and, since every line and
+// every frame starts at a different phase, the crawling dots.
+// from: https://www.nesdev.org/wiki/NTSC_video
+//
+// Output is OUTPUT_WIDTH x 240, wider than the frame so the artifacts fit.
+pub const OUTPUT_WIDTH: usize = 602;
+
+const SAMPLES_PER_PIXEL: usize = 8;
+const LINE_SAMPLES: usize = Frame::WIDTH * SAMPLES_PER_PIXEL;
+// a line is 341 dots of 8 samples, 2728 % 12 = 4 samples of phase
+const LINE_PHASE_STEP: usize = 4;
+// nudges the decoded hues to match the usual palettes, in samples
+const HUE: f32 = 3.9;
+
+// voltages for the 4 brightness levels, low and high halves of the wave,
+// then black and white, relative to sync
+const SIGNAL_LOW: [f32; 4] = [0.228, 0.312, 0.552, 0.880];
+const SIGNAL_HIGH: [f32; 4] = [0.616, 0.840, 1.100, 1.100];
+const BLACK: f32 = 0.312;
+const WHITE: f32 = 1.100;
+// each emphasis bit pulls the wave down for half of the colour cycle
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+pub struct NtscFilter {
+    // signal level of each 9-bit pixel (6-bit colour, emphasis above it) at
+    // each of the 12 phases, black at 0 and white at 1
+    levels: Vec<[f32; 12]>,
+    // the subcarrier, to demodulate I and Q with
+    cos: [f32; 12],
+    sin: [f32; 12],
+    frame_phase: usize,
+    signal: Vec<f32>,
+    output: Vec<u8>,
+}
+
+impl NtscFilter {
+    pub fn new() -> Self {
+        let levels = (0..512)
+            .map(|pixel| {
+                let mut phases = [0.0; 12];
+                for (phase, level) in phases.iter_mut().enumerate() {
+                    *level = (signal(pixel, phase) - BLACK) / (WHITE - BLACK);
+                }
+                phases
+            })
+            .collect();
+        let mut cos = [0.0; 12];
+        let mut sin = [0.0; 12];
+        for phase in 0..12 {
+            let angle = PI * (phase as f32 + HUE) / 6.0;
+            cos[phase] = angle.cos();
+            sin[phase] = angle.sin();
+        }
+        NtscFilter {
+            levels,
+            cos,
+            sin,
+            frame_phase: 0,
+            signal: vec![0.0; LINE_SAMPLES],
+            output: vec![0; OUTPUT_WIDTH * Frame::HEIGHT * 3],
+        }
+    }
+
+    // RGB24, OUTPUT_WIDTH x 240
+    pub fn apply(&mut self, frame: &Frame) -> &[u8] {
+        for y in 0..Frame::HEIGHT {
+            let emphasis = (frame.scanline_mask[y] as usize >> 5) << 6;
+            let greyscale = frame.scanline_mask[y] & 1 != 0;
+            let line_phase = (self.frame_phase + y * LINE_PHASE_STEP) % 12;
+
+            for x in 0..Frame::WIDTH {
+                let mut colour = frame.index(x, y) as usize;
+                if greyscale {
+                    colour &= 0x30;
+                }
+                let levels = &self.levels[emphasis | colour];
+                for sample in 0..SAMPLES_PER_PIXEL {
+                    let at = x * SAMPLES_PER_PIXEL + sample;
+                    self.signal[at] = levels[(line_phase + at) % 12];
+                }
+            }
+
+            for out_x in 0..OUTPUT_WIDTH {
+                let centre = out_x * LINE_SAMPLES / OUTPUT_WIDTH;
+                let (r, g, b) = self.decode(centre, line_phase);
+                let base = (y * OUTPUT_WIDTH + out_x) * 3;
+                self.output[base] = r;
+                self.output[base + 1] = g;
+                self.output[base + 2] = b;
+            }
+        }
+        // the odd frame's missing dot moves the next frame along by 8
+        self.frame_phase = (self.frame_phase + 8) % 12;
+        &self.output
+    }
+
+    // YIQ over the 12 samples around `centre`, then RGB
+    fn decode(&self, centre: usize, line_phase: usize) -> (u8, u8, u8) {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for at in centre.saturating_sub(6)..(centre + 6).min(LINE_SAMPLES) {
+            let level = self.signal[at] / 12.0;
+            let phase = (line_phase + at) % 12;
+            y += level;
+            i += level * self.cos[phase];
+            q += level * self.sin[phase];
+        }
+        let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (
+            to_byte(y + 0.946_882 * i + 0.623_557 * q),
+            to_byte(y - 0.274_788 * i - 0.635_691 * q),
+            to_byte(y - 1.108_545 * i + 1.709_007 * q),
+        )
+    }
+}
+
+impl Default for NtscFilter {
+    fn default() -> Self {
+        NtscFilter::new()
+    }
+}
+
+fn in_colour_phase(colour: usize, phase: usize) -> bool {
+    (colour + phase) % 12 < 6
+}
+
+// voltage of a 9-bit pixel at one phase of the subcarrier
+fn signal(pixel: usize, phase: usize) -> f32 {
+    let colour = pixel & 0x0f;
+    let emphasis = pixel >> 6;
+    // columns $xE and $xF are black whatever the brightness
+    let level = if colour > 13 { 1 } else { (pixel >> 4) & 3 };
+
+    // column 0 is a flat high level, $xD and up a flat low one
+    let mut low = SIGNAL_LOW[level];
+    let mut high = SIGNAL_HIGH[level];
+    if colour == 0 {
+        low = high;
+    }
+    if colour > 12 {
+        high = low;
+    }
+
+    let mut voltage = if in_colour_phase(colour, phase) {
+        high
+    } else {
+        low
+    };
+    let emphasised = (emphasis & 1 != 0 && in_colour_phase(0, phase))
+        || (emphasis & 2 != 0 && in_colour_phase(4, phase))
+        || (emphasis & 4 != 0 && in_colour_phase(8, phase));
+    if colour < 0x0e && emphasised {
+        voltage *= EMPHASIS_ATTENUATION;
+    }
+    voltage
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_greys_and_fringes() {
+        let mut frame = Frame::new();
+        for y in 0..Frame::HEIGHT {
+            for x in 0..Frame::WIDTH {
+                frame.set_index(x, y, if x < 128 { 0x30 } else { 0x0f });
+            }
+        }
+        let mut filter = NtscFilter::new();
+        let output = filter.apply(&frame).to_vec();
+        assert_eq!(output.len(), OUTPUT_WIDTH * Frame::HEIGHT * 3);
+
+        // flat white and black come through untinted
+        assert_eq!(output[30 * 3..30 * 3 + 3], [255, 255, 255]);
+        let black = (10 * OUTPUT_WIDTH + 500) * 3;
+        assert_eq!(output[black..black + 3], [0, 0, 0]);
+
+        // the edge in between is smeared over several output pixels
+        let edge = (10 * OUTPUT_WIDTH + OUTPUT_WIDTH / 2) * 3;
+        assert!(output[edge] > 0 && output[edge] < 255);
+    }
+}
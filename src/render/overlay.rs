@@ -0,0 +1,161 @@
+use super::frame::Frame;
+
+// Text and boxes drawn over a finished frame, for scripts and frontends to
+// show their own information on the picture. Anything off the frame is
+// clipped.
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+// a glyph and the gap after it
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+// 3x5 glyphs, a row to a byte with the leftmost pixel in bit 2. Letters are
+// upper case only; anything missing is drawn as '?'.
+const UNKNOWN: [u8; GLYPH_HEIGHT] = [0b110, 0b001, 0b010, 0b000, 0b010];
+const GLYPHS: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', UNKNOWN),
+    ('(', [0b010, 0b100, 0b100, 0b100, 0b010]),
+    (')', [0b010, 0b001, 0b001, 0b001, 0b010]),
+    ('$', [0b011, 0b110, 0b010, 0b011, 0b110]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+];
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|&&(other, _)| other == c)
+        .map_or(UNKNOWN, |&(_, rows)| rows)
+}
+
+fn plot(frame: &mut Frame, x: isize, y: isize, rgb: (u8, u8, u8)) {
+    if (0..frame.width as isize).contains(&x) && (0..frame.height as isize).contains(&y) {
+        frame.set_pixel(x as usize, y as usize, rgb);
+    }
+}
+
+// width in pixels of `text` drawn with draw_text
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * ADVANCE).saturating_sub(1)
+}
+
+// `text` with its top left corner at x, y, on a black background so it
+// stays readable over the game
+pub fn draw_text(frame: &mut Frame, x: isize, y: isize, text: &str, rgb: (u8, u8, u8)) {
+    let width = text_width(text) as isize;
+    fill_box(
+        frame,
+        x - 1,
+        y - 1,
+        x + width,
+        y + GLYPH_HEIGHT as isize,
+        (0, 0, 0),
+    );
+    for (n, c) in text.chars().enumerate() {
+        let left = x + (n * ADVANCE) as isize;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    plot(frame, left + col as isize, y + row as isize, rgb);
+                }
+            }
+        }
+    }
+}
+
+// the outline of the box between two corners, both included
+pub fn draw_box(frame: &mut Frame, x1: isize, y1: isize, x2: isize, y2: isize, rgb: (u8, u8, u8)) {
+    let (left, right) = (x1.min(x2), x1.max(x2));
+    let (top, bottom) = (y1.min(y2), y1.max(y2));
+    for x in left..=right {
+        plot(frame, x, top, rgb);
+        plot(frame, x, bottom, rgb);
+    }
+    for y in top..=bottom {
+        plot(frame, left, y, rgb);
+        plot(frame, right, y, rgb);
+    }
+}
+
+pub fn fill_box(frame: &mut Frame, x1: isize, y1: isize, x2: isize, y2: isize, rgb: (u8, u8, u8)) {
+    for y in y1.min(y2)..=y1.max(y2) {
+        for x in x1.min(x2)..=x1.max(x2) {
+            plot(frame, x, y, rgb);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rgb(frame: &Frame, x: usize, y: usize) -> (u8, u8, u8) {
+        let at = (y * frame.width + x) * 3;
+        (frame.data[at], frame.data[at + 1], frame.data[at + 2])
+    }
+
+    #[test]
+    fn test_text_and_boxes() {
+        let mut frame = Frame::new();
+        let white = (0xff, 0xff, 0xff);
+        draw_text(&mut frame, 10, 10, "l1", white);
+        assert_eq!(text_width("l1"), 7);
+        // 'L' is its left column and bottom row, '1' starts a pixel in
+        assert_eq!(rgb(&frame, 10, 12), white);
+        assert_eq!(rgb(&frame, 11, 12), (0, 0, 0));
+        assert_eq!(rgb(&frame, 12, 14), white);
+        assert_eq!(rgb(&frame, 15, 10), white);
+        assert_eq!(glyph('~'), glyph('?'));
+
+        // clipped at the edges
+        draw_box(&mut frame, -5, 230, 3, 250, (0xff, 0, 0));
+        assert_eq!(rgb(&frame, 3, 239), (0xff, 0, 0));
+        assert_eq!(rgb(&frame, 0, 239), (0, 0, 0));
+        assert_eq!(rgb(&frame, 0, 230), (0xff, 0, 0));
+    }
+}
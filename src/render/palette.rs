@@ -0,0 +1,176 @@
+use crate::ppu::registers::mask::MaskRegister;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+// 2C02 output colours, indexed by the 6-bit values stored in palette RAM
+#[rustfmt::skip]
+pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
+];
+
+// each emphasis bit dims the other two channels to about this
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+// A .pal file is 64 RGB triples, or 512 with a block of 64 for each
+// combination of the emphasis bits (red in bit 0) as some palette
+// generators write them
+const PAL_FILE_SIZE: usize = 64 * 3;
+const PAL_FILE_EMPHASIS_SIZE: usize = 8 * 64 * 3;
+
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+    WrongSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::WrongSize(len) => write!(
+                f,
+                "Palette file is {} bytes, expected {} or {}",
+                len, PAL_FILE_SIZE, PAL_FILE_EMPHASIS_SIZE
+            ),
+        }
+    }
+}
+
+impl core::error::Error for PaletteError {}
+
+// The RGB every palette index comes out as, for each of the 8 combinations
+// of the emphasis bits. The default is SYSTEM_PALETTE; palettes without
+// emphasis colours of their own get them by dimming the channels each bit
+// doesn't name. The blacks in columns $xE and $xF are never dimmed.
+// from: https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colours: Vec<[(u8, u8, u8); 64]>,
+}
+
+impl Palette {
+    pub fn ntsc() -> Self {
+        Palette::from_colours(&SYSTEM_PALETTE)
+    }
+
+    pub fn from_colours(colours: &[(u8, u8, u8); 64]) -> Self {
+        let colours = (0..8)
+            .map(|emphasis| {
+                let mut emphasised = *colours;
+                for (index, colour) in emphasised.iter_mut().enumerate() {
+                    if index & 0x0e != 0x0e {
+                        *colour = emphasise(*colour, emphasis);
+                    }
+                }
+                emphasised
+            })
+            .collect();
+        Palette { colours }
+    }
+
+    pub fn from_pal_file(bytes: &[u8]) -> Result<Self, PaletteError> {
+        let read = |block: &[u8]| {
+            let mut colours = [(0, 0, 0); 64];
+            for (colour, rgb) in colours.iter_mut().zip(block.chunks_exact(3)) {
+                *colour = (rgb[0], rgb[1], rgb[2]);
+            }
+            colours
+        };
+        match bytes.len() {
+            PAL_FILE_SIZE => Ok(Palette::from_colours(&read(bytes))),
+            PAL_FILE_EMPHASIS_SIZE => Ok(Palette {
+                colours: bytes.chunks_exact(PAL_FILE_SIZE).map(read).collect(),
+            }),
+            len => Err(PaletteError::WrongSize(len)),
+        }
+    }
+
+    // the colour a palette index comes out as under PPUMASK `mask`;
+    // greyscale keeps only the grey column ($x0)
+    pub fn colour(&self, index: u8, mask: MaskRegister) -> (u8, u8, u8) {
+        let index = if mask.is_greyscale() {
+            index & 0x30
+        } else {
+            index & 0x3f
+        };
+        self.colours[(mask.bits() >> 5) as usize][index as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::ntsc()
+    }
+}
+
+// emphasis: the emphasis bits of PPUMASK shifted down, red in bit 0
+fn emphasise((r, g, b): (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+    let dim = |value: u8, own: u8| {
+        if emphasis & !own != 0 {
+            (value as f32 * EMPHASIS_ATTENUATION) as u8
+        } else {
+            value
+        }
+    };
+    (dim(r, 0b001), dim(g, 0b010), dim(b, 0b100))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_greyscale_and_emphasis() {
+        let palette = Palette::ntsc();
+        let colour = |index, mask| palette.colour(index, mask);
+        assert_eq!(colour(0x16, MaskRegister::new()), SYSTEM_PALETTE[0x16]);
+        assert_eq!(colour(0x16, MaskRegister::GREYSCALE), SYSTEM_PALETTE[0x10]);
+
+        assert_eq!(
+            colour(0x30, MaskRegister::EMPHASISE_RED),
+            (0xff, 0xd0, 0xd0)
+        );
+        assert_eq!(
+            colour(
+                0x30,
+                MaskRegister::EMPHASISE_RED | MaskRegister::EMPHASISE_GREEN
+            ),
+            (0xd0, 0xd0, 0xd0)
+        );
+        assert_eq!(
+            colour(0x1f, MaskRegister::EMPHASISE_BLUE),
+            SYSTEM_PALETTE[0x1f]
+        );
+    }
+
+    #[test]
+    fn test_pal_files() {
+        let mut bytes = vec![0; 192];
+        bytes[0x16 * 3..0x16 * 3 + 3].copy_from_slice(&[1, 2, 3]);
+        let palette = Palette::from_pal_file(&bytes).unwrap();
+        assert_eq!(palette.colour(0x16, MaskRegister::new()), (1, 2, 3));
+
+        // with its own emphasis colours
+        let mut bytes = vec![0; 1536];
+        bytes[192 + 0x16 * 3..192 + 0x16 * 3 + 3].copy_from_slice(&[4, 5, 6]);
+        let palette = Palette::from_pal_file(&bytes).unwrap();
+        assert_eq!(palette.colour(0x16, MaskRegister::EMPHASISE_RED), (4, 5, 6));
+
+        assert_eq!(
+            Palette::from_pal_file(&[0; 100]),
+            Err(PaletteError::WrongSize(100))
+        );
+    }
+}
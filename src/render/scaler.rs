@@ -0,0 +1,179 @@
+use alloc::vec::Vec;
+
+// Upscalers for RGB24 pictures (frames, or a filter's output), so frontends
+// can show sharp pixels at any window size instead of leaving it to the GPU
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaler {
+    // every pixel a factor x factor block; with `aspect` the width is also
+    // stretched by 8:7, the shape of the console's pixels on a TV
+    Nearest { factor: usize, aspect: bool },
+    // twice the size, with edges between similar colours smoothed
+    Scale2x,
+}
+
+// hqx's limits on the difference between two colours that count as the same
+const Y_THRESHOLD: i32 = 48;
+const U_THRESHOLD: i32 = 7;
+const V_THRESHOLD: i32 = 6;
+
+impl Scaler {
+    // "nearest" at `factor`, or "scale2x"
+    pub fn from_name(name: &str, factor: usize) -> Option<Self> {
+        match name {
+            "nearest" => Some(Scaler::Nearest {
+                factor,
+                aspect: false,
+            }),
+            "scale2x" => Some(Scaler::Scale2x),
+            _ => None,
+        }
+    }
+
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match *self {
+            Scaler::Nearest { factor, aspect } => {
+                let scaled = width * factor;
+                let scaled = if aspect { (scaled * 8 + 3) / 7 } else { scaled };
+                (scaled, height * factor)
+            }
+            Scaler::Scale2x => (width * 2, height * 2),
+        }
+    }
+
+    // `input` is width x height RGB24; `output` is resized to fit
+    pub fn scale(&self, input: &[u8], width: usize, height: usize, output: &mut Vec<u8>) {
+        let (out_width, out_height) = self.output_size(width, height);
+        output.resize(out_width * out_height * 3, 0);
+        match *self {
+            Scaler::Nearest { .. } => {
+                for out_y in 0..out_height {
+                    let y = out_y * height / out_height;
+                    for out_x in 0..out_width {
+                        let x = out_x * width / out_width;
+                        let from = (y * width + x) * 3;
+                        let to = (out_y * out_width + out_x) * 3;
+                        output[to..to + 3].copy_from_slice(&input[from..from + 3]);
+                    }
+                }
+            }
+            Scaler::Scale2x => scale2x(input, width, height, output),
+        }
+    }
+}
+
+fn pixel(input: &[u8], width: usize, height: usize, x: isize, y: isize) -> [u8; 3] {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    let at = (y * width + x) * 3;
+    [input[at], input[at + 1], input[at + 2]]
+}
+
+fn yuv([r, g, b]: [u8; 3]) -> (i32, i32, i32) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = (r * 299 + g * 587 + b * 114) / 1000;
+    let u = (-r * 169 - g * 331 + b * 500) / 1000 + 128;
+    let v = (r * 500 - g * 419 - b * 81) / 1000 + 128;
+    (y, u, v)
+}
+
+fn similar(a: [u8; 3], b: [u8; 3]) -> bool {
+    let (ay, au, av) = yuv(a);
+    let (by, bu, bv) = yuv(b);
+    (ay - by).abs() <= Y_THRESHOLD
+        && (au - bu).abs() <= U_THRESHOLD
+        && (av - bv).abs() <= V_THRESHOLD
+}
+
+// half the centre, a quarter of each neighbour
+fn blend(centre: [u8; 3], a: [u8; 3], b: [u8; 3]) -> [u8; 3] {
+    let mut mixed = [0; 3];
+    for channel in 0..3 {
+        let sum = centre[channel] as u16 * 2 + a[channel] as u16 + b[channel] as u16;
+        mixed[channel] = (sum / 4) as u8;
+    }
+    mixed
+}
+
+// Scale2x: the corner of a pixel is smoothed where the two neighbours next
+// to it match each other but not the ones across. Colours match when they're
+// within hqx's thresholds in YUV rather than only when equal, and the corner
+// is blended with the neighbours instead of taking their colour.
+// from: https://www.scale2x.it/algorithm
+fn scale2x(input: &[u8], width: usize, height: usize, output: &mut [u8]) {
+    let out_width = width * 2;
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            let at = |dx, dy| pixel(input, width, height, xi + dx, yi + dy);
+            let (b, d, e, f, h) = (at(0, -1), at(-1, 0), at(0, 0), at(1, 0), at(0, 1));
+
+            // top left, top right, bottom left, bottom right
+            let corners = [(d, b, f, h), (b, f, h, d), (h, d, b, f), (f, h, d, b)];
+            for (corner, &(first, second, across_second, across_first)) in
+                corners.iter().enumerate()
+            {
+                let edge = similar(first, second)
+                    && !similar(first, across_first)
+                    && !similar(second, across_second)
+                    && !similar(e, first);
+                let colour = if edge { blend(e, first, second) } else { e };
+                let out_x = x * 2 + corner % 2;
+                let out_y = y * 2 + corner / 2;
+                let to = (out_y * out_width + out_x) * 3;
+                output[to..to + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // white above the diagonal, black below
+    fn diagonal(size: usize) -> Vec<u8> {
+        let mut input = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                input.extend_from_slice(&if x >= y { [0xff; 3] } else { [0; 3] });
+            }
+        }
+        input
+    }
+
+    #[test]
+    fn test_nearest() {
+        let input = diagonal(4);
+        let mut output = Vec::new();
+        let scaler = Scaler::from_name("nearest", 2).unwrap();
+        scaler.scale(&input, 4, 4, &mut output);
+        assert_eq!(output.len(), 8 * 8 * 3);
+        assert_eq!(output[(8 + 1) * 3..(8 + 1) * 3 + 3], [0xff; 3]);
+        assert_eq!(output[(2 * 8 + 1) * 3..(2 * 8 + 1) * 3 + 3], [0; 3]);
+
+        let aspect = Scaler::Nearest {
+            factor: 3,
+            aspect: true,
+        };
+        assert_eq!(aspect.output_size(256, 240), (878, 720));
+    }
+
+    #[test]
+    fn test_scale2x_smooths_edges() {
+        let input = diagonal(4);
+        let mut output = Vec::new();
+        Scaler::Scale2x.scale(&input, 4, 4, &mut output);
+        assert_eq!(output.len(), 8 * 8 * 3);
+
+        // black pixel (0, 1) has white above and to its right: its top right
+        // corner is smoothed, the others stay black
+        let top_right = (2 * 8 + 1) * 3;
+        assert_eq!(output[top_right..top_right + 3], [0x7f; 3]);
+        let top_left = (2 * 8) * 3;
+        assert_eq!(output[top_left..top_left + 3], [0; 3]);
+
+        // flat areas are left alone
+        let flat = (7 * 8) * 3;
+        assert_eq!(output[flat..flat + 3], [0; 3]);
+    }
+}
@@ -0,0 +1,241 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+// History of save states for hold-to-rewind.
+//
+// A state is taken every `interval` frames. Only the newest is kept whole;
+// each older one is kept as its difference from the one after it, XORed
+// and run-length encoded, which is mostly zeros between two nearby frames.
+// Going back one step undoes the newest difference. Once the history is
+// over its memory budget the oldest steps are dropped.
+pub struct Rewind {
+    interval: usize,
+    budget: usize,
+    newest: Option<Vec<u8>>,
+    // deltas[i] turns state i + 1 (or the newest) back into state i
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+    frames_since: usize,
+}
+
+impl Rewind {
+    // a state every `interval` frames, using at most about `budget` bytes
+    pub fn new(interval: usize, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            newest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            frames_since: 0,
+        }
+    }
+
+    // Called once per frame; `save` only runs when a state is due
+    pub fn end_frame<F: FnOnce() -> Vec<u8>>(&mut self, save: F) {
+        self.frames_since += 1;
+        if self.newest.is_some() && self.frames_since < self.interval {
+            return;
+        }
+        self.frames_since = 0;
+
+        let state = save();
+        if let Some(previous) = self.newest.take() {
+            let delta = encode_delta(&previous, &state);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(state);
+
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // The state from about `frames` frames ago, or as far back as the
+    // history goes, with the number of frames it actually went back. The
+    // history carries on from there.
+    pub fn rewind(&mut self, frames: usize) -> Option<(Vec<u8>, usize)> {
+        let mut state = self.newest.take()?;
+        let mut rewound = self.frames_since;
+        while rewound < frames {
+            let delta = match self.deltas.pop_back() {
+                Some(delta) => delta,
+                None => break,
+            };
+            self.delta_bytes -= delta.len();
+            state = apply_delta(&state, &delta);
+            rewound += self.interval;
+        }
+        self.frames_since = 0;
+        self.newest = Some(state.clone());
+        Some((state, rewound))
+    }
+
+    // Drops the last `frames` frames, which are about to be run again
+    // differently, so the states taken from here on follow on from before
+    // them
+    pub fn forget(&mut self, frames: usize) {
+        if self.newest.is_none() {
+            return;
+        }
+        while frames > self.frames_since {
+            let delta = match self.deltas.pop_back() {
+                Some(delta) => delta,
+                None => return self.clear(),
+            };
+            self.delta_bytes -= delta.len();
+            let newest = self.newest.take().unwrap();
+            self.newest = Some(apply_delta(&newest, &delta));
+            self.frames_since += self.interval;
+        }
+        self.frames_since -= frames;
+    }
+
+    // frames of history available
+    pub fn frames(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() * self.interval + self.frames_since,
+            None => 0,
+        }
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len) + self.delta_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.frames_since = 0;
+    }
+}
+
+// `older` XOR `newer`, as the length of `older` followed by pairs of a run
+// of zeros and a run of literal bytes, all lengths u32 little endian
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = older
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ newer.get(i).unwrap_or(&0))
+        .collect();
+
+    let mut delta = (older.len() as u32).to_le_bytes().to_vec();
+    let mut position = 0;
+    while position < xor.len() {
+        let zeros = xor[position..]
+            .iter()
+            .take_while(|&&byte| byte == 0)
+            .count();
+        position += zeros;
+        let literals = xor[position..]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .count();
+        delta.extend_from_slice(&(zeros as u32).to_le_bytes());
+        delta.extend_from_slice(&(literals as u32).to_le_bytes());
+        delta.extend_from_slice(&xor[position..position + literals]);
+        position += literals;
+    }
+    delta
+}
+
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u32 = |at: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&delta[at..at + 4]);
+        u32::from_le_bytes(bytes) as usize
+    };
+    let length = read_u32(0);
+    let mut older: Vec<u8> = (0..length).map(|i| *newer.get(i).unwrap_or(&0)).collect();
+
+    let mut at = 4;
+    let mut position = 0;
+    while at < delta.len() {
+        position += read_u32(at);
+        let literals = read_u32(at + 4);
+        at += 8;
+        for (byte, change) in older[position..position + literals]
+            .iter_mut()
+            .zip(&delta[at..at + literals])
+        {
+            *byte ^= change;
+        }
+        position += literals;
+        at += literals;
+    }
+    older
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    // a 1KB "state" that only differs in its first byte
+    fn state(frame: u8) -> Vec<u8> {
+        let mut state = vec![0x55; 1024];
+        state[0] = frame;
+        state
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let older = vec![1, 2, 3, 0, 0, 9, 9, 7];
+        let newer = vec![1, 2, 4, 0, 1, 9, 9];
+        assert_eq!(apply_delta(&newer, &encode_delta(&older, &newer)), older);
+        assert_eq!(apply_delta(&older, &encode_delta(&newer, &older)), newer);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut rewind = Rewind::new(2, 1 << 20);
+        for frame in 0..10 {
+            rewind.end_frame(|| state(frame));
+        }
+        // states were taken on frames 0, 2, 4, 6 and 8
+        assert_eq!(rewind.frames(), 9);
+        assert!(rewind.memory_used() < 1024 + 4 * 32);
+
+        assert_eq!(rewind.rewind(3), Some((state(6), 3)));
+        assert_eq!(rewind.rewind(2), Some((state(4), 2)));
+        assert_eq!(rewind.rewind(100), Some((state(0), 4)));
+        assert_eq!(rewind.rewind(1), Some((state(0), 0)));
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut rewind = Rewind::new(2, 1 << 20);
+        for frame in 0..10 {
+            rewind.end_frame(|| state(frame));
+        }
+        // frames 7, 8 and 9 again, with a state on 8 as before
+        rewind.forget(3);
+        assert_eq!(rewind.frames(), 6);
+        for frame in 17..20 {
+            rewind.end_frame(|| state(frame));
+        }
+        assert_eq!(rewind.rewind(1), Some((state(18), 1)));
+        assert_eq!(rewind.rewind(2), Some((state(6), 2)));
+
+        rewind.forget(100);
+        assert_eq!(rewind.frames(), 0);
+    }
+
+    #[test]
+    fn test_budget_drops_the_oldest() {
+        let mut rewind = Rewind::new(1, 1024 + 3 * 20);
+        for frame in 0..10 {
+            rewind.end_frame(|| state(frame));
+        }
+        assert!(rewind.memory_used() <= 1024 + 3 * 20);
+        assert_eq!(rewind.rewind(100).unwrap().0, state(7));
+
+        rewind.clear();
+        assert_eq!(rewind.rewind(1), None);
+    }
+}
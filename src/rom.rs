@@ -1,33 +1,162 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
     FOUR_SCREEN,
+    // all four nametables show the same 1KB, picked at runtime by the mapper
+    ONE_SCREEN_LOWER,
+    ONE_SCREEN_UPPER,
+}
+
+// CPU/PPU timing the cartridge was made for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RomError {
+    TooShort(usize),
+    InvalidTag,
+    UnsupportedVersion,
+    NoPrgRom,
+    Truncated { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::TooShort(len) => write!(
+                f,
+                "File is too short ({} bytes) to hold an iNES header",
+                len
+            ),
+            RomError::InvalidTag => write!(f, "File is not in iNES file format"),
+            RomError::UnsupportedVersion => write!(f, "Unknown iNES header version"),
+            RomError::NoPrgRom => write!(f, "Header declares no PRG ROM"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "File is truncated: header declares {} bytes but file has {}",
+                expected, actual
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+        }
+    }
 }
 
+impl core::error::Error for RomError {}
+
 pub struct ROM {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    pub trainer: Option<Vec<u8>>,
+    pub mapper: u16,
+    pub submapper: u8,
     pub screen_mirroring: Mirroring,
+    pub nes2: bool,
+    // the cartridge keeps its PRG RAM powered with a battery
+    pub battery: bool,
+    // RAM sizes in bytes; the nvram sizes are the battery-backed part
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+}
+
+// iNES header:
+// from: https://www.nesdev.org/wiki/INES
+// | Bytes | Description                                                    |
+// |-------|----------------------------------------------------------------|
+// | 0-3   | Constant $4E $45 $53 $1A ("NES" followed by MS-DOS EOF)        |
+// | 4     | Size of PRG ROM in 16 KB units                                 |
+// | 5     | Size of CHR ROM in 8 KB units (0 means the board uses CHR RAM) |
+// | 6     | Flags 6 - Mapper, mirroring, battery, trainer                  |
+// | 7     | Flags 7 - Mapper, VS/Playchoice, NES 2.0                       |
+// | 8-15  | Unused padding in iNES 1.0                                     |
+//
+// NES 2.0 (flags 7 bits 2-3 == 2) reuses bytes 8-15:
+// from: https://www.nesdev.org/wiki/NES_2.0
+// | Bytes | Description                                                    |
+// |-------|----------------------------------------------------------------|
+// | 8     | Mapper bits 8-11 (low nibble), submapper (high nibble)        |
+// | 9     | PRG ROM (low nibble) and CHR ROM (high nibble) size MSBs       |
+// | 10    | PRG RAM (low nibble) and PRG NVRAM (high nibble) shift counts  |
+// | 11    | CHR RAM (low nibble) and CHR NVRAM (high nibble) shift counts  |
+// | 12    | CPU/PPU timing: 0 NTSC, 1 PAL, 2 multiple-region, 3 Dendy      |
+// | 13-15 | Vs. System, extended console type, misc ROMs, default expansion |
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0f {
+        // exponent-multiplier notation: 2^E * (MM * 2 + 1), lsb is EEEEEEMM
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)
+            .unwrap_or(usize::MAX)
+            .saturating_mul(multiplier)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * page_size
+    }
+}
+
+// RAM sizes are stored as shift counts: 64 << n bytes, 0 means none
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+fn timing(value: u8) -> Timing {
+    match value & 0b11 {
+        0 => Timing::Ntsc,
+        1 => Timing::Pal,
+        2 => Timing::MultiRegion,
+        _ => Timing::Dendy,
+    }
 }
 
 impl ROM {
-    pub fn new(raw: &Vec<u8>) -> Result<ROM, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+    pub fn new(raw: &[u8]) -> Result<ROM, String> {
+        ROM::from_bytes(raw).map_err(|err| err.to_string())
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<ROM, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TooShort(raw.len()));
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::InvalidTag);
         }
 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+        let nes2 = match (raw[7] >> 2) & 0b11 {
+            0 => false,
+            2 => true,
+            _ => return Err(RomError::UnsupportedVersion),
+        };
 
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+        let mut mapper = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
+        let mut submapper = 0;
+        if nes2 {
+            mapper |= ((raw[8] & 0b1111) as u16) << 8;
+            submapper = raw[8] >> 4;
         }
 
         let four_screen = raw[6] & 0b1000 != 0;
@@ -38,19 +167,79 @@ impl ROM {
             (false, false) => Mirroring::HORIZONTAL,
         };
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let (prg_rom_size, chr_rom_size) = if nes2 {
+            (
+                nes2_rom_size(raw[4], raw[9] & 0b1111, PRG_ROM_PAGE_SIZE),
+                nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+            )
+        } else {
+            (
+                raw[4] as usize * PRG_ROM_PAGE_SIZE,
+                raw[5] as usize * CHR_ROM_PAGE_SIZE,
+            )
+        };
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let has_trainer = raw[6] & 0b100 != 0;
+
+        let prg_rom_start = HEADER_SIZE + if has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start.saturating_add(prg_rom_size);
+        let expected = chr_rom_start.saturating_add(chr_rom_size);
+        if raw.len() < expected {
+            return Err(RomError::Truncated {
+                expected,
+                actual: raw.len(),
+            });
+        }
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        let trainer = if has_trainer {
+            Some(raw[HEADER_SIZE..prg_rom_start].to_vec())
+        } else {
+            None
+        };
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        // iNES 1.0 can't describe RAM, so assume the usual 8KB of PRG RAM
+        // and 8KB of CHR RAM on boards without CHR ROM
+        let battery = raw[6] & 0b10 != 0;
+        let (prg_ram_size, prg_nvram_size, chr_ram_size, chr_nvram_size, timing) = if nes2 {
+            (
+                nes2_ram_size(raw[10] & 0b1111),
+                nes2_ram_size(raw[10] >> 4),
+                nes2_ram_size(raw[11] & 0b1111),
+                nes2_ram_size(raw[11] >> 4),
+                timing(raw[12]),
+            )
+        } else {
+            let prg_ram_size = 0x2000;
+            let chr_ram_size = if chr_rom_size == 0 { 0x2000 } else { 0 };
+            let ines_timing = if raw[9] & 1 != 0 {
+                Timing::Pal
+            } else {
+                Timing::Ntsc
+            };
+            if battery {
+                (0, prg_ram_size, chr_ram_size, 0, ines_timing)
+            } else {
+                (prg_ram_size, 0, chr_ram_size, 0, ines_timing)
+            }
+        };
 
         Ok(ROM {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..expected].to_vec(),
+            trainer,
+            mapper,
+            submapper,
+            screen_mirroring,
+            nes2,
+            battery,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            timing,
         })
     }
 }
@@ -87,16 +276,137 @@ pub mod test {
     pub fn test_rom() -> ROM {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00,
             ],
             trainer: None,
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         ROM::new(&test_rom).unwrap()
     }
 
+    // The iNES images tests run, built up a piece at a time. Unless told
+    // otherwise: NROM-256 with vertical mirroring, 32KB of PRG ROM full of
+    // NOPs, 8KB of blank CHR ROM, reset at $8000, and NMI and IRQ handlers
+    // at $8100 and $8200 that only RTI.
+    pub struct RomBuilder {
+        mapper: u8,
+        chr: Vec<u8>,
+        code: Vec<(u16, Vec<u8>)>,
+        reset: u16,
+        nmi: (u16, Vec<u8>),
+        irq: (u16, Vec<u8>),
+    }
+
+    impl Default for RomBuilder {
+        fn default() -> Self {
+            RomBuilder::new()
+        }
+    }
+
+    impl RomBuilder {
+        pub fn new() -> Self {
+            RomBuilder {
+                mapper: 0,
+                chr: vec![0; CHR_ROM_PAGE_SIZE],
+                code: Vec::new(),
+                reset: 0x8000,
+                nmi: (0x8100, Vec::new()),
+                irq: (0x8200, Vec::new()),
+            }
+        }
+
+        pub fn mapper(mut self, mapper: u8) -> Self {
+            self.mapper = mapper;
+            self
+        }
+
+        // empty for CHR RAM instead
+        pub fn chr(mut self, chr: Vec<u8>) -> Self {
+            self.chr = chr;
+            self
+        }
+
+        // `code` at `addr`, $8000-$FFFF, where boards that switch banks
+        // have the first and last 16KB at power on
+        pub fn code(mut self, addr: u16, code: &[u8]) -> Self {
+            self.code.push((addr, code.to_vec()));
+            self
+        }
+
+        pub fn reset(mut self, addr: u16) -> Self {
+            self.reset = addr;
+            self
+        }
+
+        // `handler` then RTI at `addr`
+        pub fn nmi(mut self, addr: u16, handler: &[u8]) -> Self {
+            self.nmi = (addr, handler.to_vec());
+            self
+        }
+
+        pub fn irq(mut self, addr: u16, handler: &[u8]) -> Self {
+            self.irq = (addr, handler.to_vec());
+            self
+        }
+
+        // the handlers go in after the code, over it if they share addresses
+        pub fn build(self) -> Vec<u8> {
+            let mut prg = vec![0xea; 2 * PRG_ROM_PAGE_SIZE];
+            let mut put = |addr: u16, bytes: &[u8]| {
+                let at = addr as usize - 0x8000;
+                prg[at..at + bytes.len()].copy_from_slice(bytes);
+            };
+            for (addr, code) in &self.code {
+                put(*addr, code);
+            }
+            for (addr, handler) in [&self.nmi, &self.irq] {
+                put(*addr, handler);
+                put(*addr + handler.len() as u16, &[0x40]);
+            }
+            for (vector, addr) in [
+                (0xfffa, self.nmi.0),
+                (0xfffc, self.reset),
+                (0xfffe, self.irq.0),
+            ] {
+                put(vector, &addr.to_le_bytes());
+            }
+            let mut header = vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 00, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ];
+            header[5] = (self.chr.len() / CHR_ROM_PAGE_SIZE) as u8;
+            header[6] |= self.mapper << 4;
+            header[7] = self.mapper & 0xf0;
+            create_rom(TestRom {
+                header,
+                trainer: None,
+                pgp_rom: prg,
+                chr_rom: self.chr,
+            })
+        }
+    }
+
+    // An NROM-256 image with blank CHR ROM that runs `code` from $8000 and,
+    // when NMIs are on, `nmi` then RTI from $8100
+    pub fn nrom(code: &[u8], nmi: &[u8]) -> Vec<u8> {
+        RomBuilder::new()
+            .code(0x8000, code)
+            .nmi(0x8100, nmi)
+            .build()
+    }
+
+    // An endless loop at $8000: JMP $8000
+    pub fn looping_rom() -> Vec<u8> {
+        nrom(&[0x4c, 0x00, 0x80], &[])
+    }
+
+    // An endless loop at $8005 with NMIs turned on, running `nmi` every
+    // frame: LDA #$80; STA $2000; JMP $8005
+    pub fn nmi_rom(nmi: &[u8]) -> Vec<u8> {
+        nrom(&[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80], nmi)
+    }
+
     #[test]
     fn test() {
         let test_rom = create_rom(TestRom {
@@ -105,12 +415,12 @@ pub mod test {
             ],
             trainer: None,
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         let rom: ROM = ROM::new(&test_rom).unwrap();
 
-        assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
@@ -139,31 +449,114 @@ pub mod test {
             ],
             trainer: Some(vec![0; 512]),
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
 
         let rom: ROM = ROM::new(&test_rom).unwrap();
 
-        assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
     }
 
     #[test]
-    fn test_nes2_is_not_supported() {
+    fn test_nes2_header() {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x02, 0x31, 0x8, 0x51, 00, 0x07, 0x90, 0x01, 00, 00,
+                00,
             ],
             trainer: None,
-            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 2 * CHR_ROM_PAGE_SIZE],
         });
-        let rom = ROM::new(&test_rom);
-        match rom {
-            Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
-        }
+        let rom = ROM::from_bytes(&test_rom).unwrap();
+
+        assert!(rom.nes2);
+        assert_eq!(rom.mapper, 0x103);
+        assert_eq!(rom.submapper, 5);
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), 2 * CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.prg_ram_size, 64 << 7);
+        assert_eq!(rom.prg_nvram_size, 0);
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.chr_nvram_size, 64 << 9);
+        assert_eq!(rom.timing, Timing::Pal);
+    }
+
+    #[test]
+    fn test_nes2_exponent_rom_size() {
+        // 2^14 * (0 * 2 + 1) = 16KB of PRG ROM
+        assert_eq!(
+            nes2_rom_size(14 << 2, 0x0f, PRG_ROM_PAGE_SIZE),
+            PRG_ROM_PAGE_SIZE
+        );
+        // 2^10 * (1 * 2 + 1) = 3KB
+        assert_eq!(
+            nes2_rom_size((10 << 2) | 1, 0x0f, PRG_ROM_PAGE_SIZE),
+            3 * 1024
+        );
+        assert_eq!(
+            nes2_rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE),
+            0x102 * PRG_ROM_PAGE_SIZE
+        );
+    }
+
+    #[test]
+    fn test_trainer_is_kept() {
+        let mut trainer = vec![0; 512];
+        trainer[0] = 0x42;
+        let rom = ROM::from_bytes(&create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0b100, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: Some(trainer),
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        }))
+        .unwrap();
+
+        assert_eq!(rom.trainer.unwrap()[0], 0x42);
+        assert_eq!(rom.prg_rom, vec!(1; PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.prg_ram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn test_malformed_files_are_rejected() {
+        assert_eq!(
+            ROM::from_bytes(&[0x4E, 0x45, 0x53]).err(),
+            Some(RomError::TooShort(3))
+        );
+        assert_eq!(ROM::from_bytes(&[0; 16]).err(), Some(RomError::InvalidTag));
+
+        let no_prg = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x00, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert_eq!(ROM::from_bytes(&no_prg).err(), Some(RomError::NoPrgRom));
+
+        let truncated = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 100],
+        });
+        assert_eq!(
+            ROM::from_bytes(&truncated).err(),
+            Some(RomError::Truncated {
+                expected: 16 + 2 * PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE,
+                actual: 16 + 2 * PRG_ROM_PAGE_SIZE + 100,
+            })
+        );
     }
 }
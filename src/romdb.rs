@@ -0,0 +1,266 @@
+use crate::png::crc32;
+use crate::rom::{Mirroring, Timing, ROM};
+use crate::sha1::sha1;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Known dumps by the CRC32 or SHA-1 of their PRG and CHR ROM, the way
+// NesCartDB and nes20db key them, with what their iNES header should say.
+// Many ROMs in the wild have headers with the wrong mapper or mirroring,
+// which a database entry puts right before the cartridge is made.
+//
+// A database is a text file with a game to a line:
+//
+//   crc32=1A2B3C4D mapper=4 mirroring=vertical battery=yes region=ntsc title=Some Game
+//
+// It needs a crc32 or a sha1 (40 hex digits) and a title, which takes the
+// rest of the line; mapper, mirroring (horizontal, vertical, four-screen),
+// battery (yes, no) and region (ntsc, pal, dendy, multi) are only set if
+// the header gets them wrong. Empty lines and lines starting with # are
+// skipped.
+#[derive(Debug, PartialEq)]
+pub enum RomDbError {
+    // a line that isn't a valid entry, counting from 1
+    InvalidLine(usize),
+}
+
+impl fmt::Display for RomDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomDbError::InvalidLine(line) => {
+                write!(f, "invalid ROM database entry on line {}", line)
+            }
+        }
+    }
+}
+
+impl core::error::Error for RomDbError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameInfo {
+    pub title: String,
+    pub region: Option<Timing>,
+    pub mapper: Option<u16>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+}
+
+impl GameInfo {
+    // Puts the header right; true if anything in it was wrong
+    pub fn apply(&self, rom: &mut ROM) -> bool {
+        let mut fixed = false;
+        if let Some(mapper) = self.mapper.filter(|&mapper| mapper != rom.mapper) {
+            rom.mapper = mapper;
+            rom.submapper = 0;
+            fixed = true;
+        }
+        if let Some(mirroring) = self
+            .mirroring
+            .filter(|&mirroring| mirroring != rom.screen_mirroring)
+        {
+            rom.screen_mirroring = mirroring;
+            fixed = true;
+        }
+        if let Some(battery) = self.battery.filter(|&battery| battery != rom.battery) {
+            // the PRG RAM is the same, only whether it's kept changes
+            let prg_ram = rom.prg_ram_size + rom.prg_nvram_size;
+            rom.battery = battery;
+            (rom.prg_ram_size, rom.prg_nvram_size) =
+                if battery { (0, prg_ram) } else { (prg_ram, 0) };
+            fixed = true;
+        }
+        if let Some(region) = self.region.filter(|&region| region != rom.timing) {
+            rom.timing = region;
+            fixed = true;
+        }
+        fixed
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RomDatabase {
+    games: Vec<GameInfo>,
+    by_crc32: BTreeMap<u32, usize>,
+    by_sha1: BTreeMap<[u8; 20], usize>,
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        RomDatabase::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    // Adds the entries in `text`, see the top of the file; later entries
+    // for a dump replace earlier ones
+    pub fn load(&mut self, text: &str) -> Result<(), RomDbError> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || RomDbError::InvalidLine(number + 1);
+            let (fields, title) = match line.split_once("title=") {
+                Some((fields, title)) if !title.trim().is_empty() => (fields, title.trim()),
+                _ => return Err(invalid()),
+            };
+            let mut game = GameInfo {
+                title: title.to_string(),
+                region: None,
+                mapper: None,
+                mirroring: None,
+                battery: None,
+            };
+            let (mut crc, mut digest) = (None, None);
+            for field in fields.split_whitespace() {
+                let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+                let parsed = match key {
+                    "crc32" => u32::from_str_radix(value, 16)
+                        .ok()
+                        .map(|value| crc = Some(value)),
+                    "sha1" => parse_sha1(value).map(|value| digest = Some(value)),
+                    "mapper" => value.parse().ok().map(|value| game.mapper = Some(value)),
+                    "mirroring" => match value {
+                        "horizontal" => Some(Mirroring::HORIZONTAL),
+                        "vertical" => Some(Mirroring::VERTICAL),
+                        "four-screen" => Some(Mirroring::FOUR_SCREEN),
+                        _ => None,
+                    }
+                    .map(|value| game.mirroring = Some(value)),
+                    "battery" => match value {
+                        "yes" => Some(true),
+                        "no" => Some(false),
+                        _ => None,
+                    }
+                    .map(|value| game.battery = Some(value)),
+                    "region" => match value {
+                        "ntsc" => Some(Timing::Ntsc),
+                        "pal" => Some(Timing::Pal),
+                        "dendy" => Some(Timing::Dendy),
+                        "multi" => Some(Timing::MultiRegion),
+                        _ => None,
+                    }
+                    .map(|value| game.region = Some(value)),
+                    _ => None,
+                };
+                parsed.ok_or_else(invalid)?;
+            }
+            if crc.is_none() && digest.is_none() {
+                return Err(invalid());
+            }
+
+            let index = self.games.len();
+            self.games.push(game);
+            if let Some(crc) = crc {
+                self.by_crc32.insert(crc, index);
+            }
+            if let Some(digest) = digest {
+                self.by_sha1.insert(digest, index);
+            }
+        }
+        Ok(())
+    }
+
+    // The entry for the dump, by SHA-1 if the database has it, else CRC32
+    pub fn lookup(&self, rom: &ROM) -> Option<&GameInfo> {
+        let data = [rom.prg_rom.as_slice(), rom.chr_rom.as_slice()].concat();
+        let index = if self.by_sha1.is_empty() {
+            None
+        } else {
+            self.by_sha1.get(&sha1(&data))
+        };
+        index
+            .or_else(|| self.by_crc32.get(&crc32(&data)))
+            .map(|&index| &self.games[index])
+    }
+
+    // Looks the dump up and puts its header right, returning what the
+    // database knows about it
+    pub fn fix(&self, rom: &mut ROM) -> Option<&GameInfo> {
+        let game = self.lookup(rom)?;
+        game.apply(rom);
+        Some(game)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test;
+    use alloc::format;
+
+    #[test]
+    fn test_fixes_headers() {
+        let mut rom = test::test_rom();
+        let data = [rom.prg_rom.as_slice(), rom.chr_rom.as_slice()].concat();
+        let mut database = RomDatabase::new();
+        database
+            .load(&format!(
+                "# known dumps\n\ncrc32={:08X} mapper=2 mirroring=horizontal battery=yes region=pal title=Test Game (E)\n\
+                 crc32=00000000 title=Something Else\n",
+                crc32(&data)
+            ))
+            .unwrap();
+        assert_eq!(database.len(), 2);
+
+        let game = database.fix(&mut rom).unwrap();
+        assert_eq!(game.title, "Test Game (E)");
+        assert_eq!(rom.mapper, 2);
+        assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        assert!(rom.battery);
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0, 0x2000));
+        assert_eq!(rom.timing, Timing::Pal);
+        // nothing left to put right
+        assert!(!database.lookup(&rom).unwrap().clone().apply(&mut rom));
+
+        // a SHA-1 picks out the dump over a CRC32
+        let mut by_sha1 = RomDatabase::new();
+        let digest: String = sha1(&data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        by_sha1
+            .load(&format!(
+                "crc32={:08x} title=By CRC\nsha1={} title=By SHA-1",
+                crc32(&data),
+                digest
+            ))
+            .unwrap();
+        assert_eq!(by_sha1.lookup(&rom).unwrap().title, "By SHA-1");
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        for (text, line) in [
+            ("title=No Hash", 1),
+            ("\ncrc32=12345678", 2),
+            ("crc32=12345678 mirroring=diagonal title=Game", 1),
+            ("sha1=1234 title=Game", 1),
+        ] {
+            assert_eq!(
+                RomDatabase::new().load(text),
+                Err(RomDbError::InvalidLine(line))
+            );
+        }
+    }
+}
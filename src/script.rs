@@ -0,0 +1,330 @@
+use crate::cpu::Mem;
+use crate::debugger::StopReason;
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::render::overlay;
+
+use rhai::{Dynamic, Engine, FnPtr, ImmutableString, Module, Scope, AST};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Rhai scripts driving the emulator a frame at a time, for bots, trainers
+// and autosplitters in the spirit of FCEUX's Lua scripts.
+// from: https://rhai.rs/book/
+//
+// A script runs once when it's loaded and registers what should happen
+// after every frame:
+//
+//   let deaths = 0;
+//   on_frame(|| {
+//       if memory_read(0x75a) == 0 { deaths += 1; }
+//       gui_text(8, 8, `deaths: ${deaths}`);
+//       joypad_set(1, button::RIGHT | button::B);
+//   });
+//
+// The callbacks see memory as it was when the frame finished, and what they
+// do (memory writes, input, drawing and savestates) happens in order once
+// they return. Input set in one frame's callback is held for the next one.
+//
+//   memory_read(addr), memory_write(addr, value)
+//   joypad_read(player), joypad_set(player, buttons), button::A and so on
+//   gui_text(x, y, text[, colour]), gui_box(x1, y1, x2, y2[, colour]),
+//   with colours as 0xRRGGBB
+//   savestate_save(slot), savestate_load(slot)
+//   frame_count()
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    // the script doesn't parse
+    Syntax(String),
+    // it failed while running, when loaded or in a callback
+    Runtime(String),
+    // savestate_load of a slot nothing was saved in
+    EmptySlot(i64),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Syntax(err) => write!(f, "script error: {}", err),
+            ScriptError::Runtime(err) => write!(f, "script failed: {}", err),
+            ScriptError::EmptySlot(slot) => write!(f, "no savestate in slot {}", slot),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+const WHITE: i64 = 0xff_ffff;
+
+enum Effect {
+    Write(u16, u8),
+    Input(usize, Button),
+    Text(isize, isize, String, (u8, u8, u8)),
+    Box(isize, isize, isize, isize, (u8, u8, u8)),
+    SaveState(i64),
+    LoadState(i64),
+}
+
+// what the registered functions share with the Script
+struct Context {
+    callbacks: Vec<FnPtr>,
+    memory: Vec<u8>,
+    input: [Button; 4],
+    frame: u64,
+    effects: Vec<Effect>,
+}
+
+impl Context {
+    fn new() -> Self {
+        Context {
+            callbacks: Vec::new(),
+            memory: Vec::new(),
+            input: [Button::empty(); 4],
+            frame: 0,
+            effects: Vec::new(),
+        }
+    }
+}
+
+fn rgb(colour: i64) -> (u8, u8, u8) {
+    ((colour >> 16) as u8, (colour >> 8) as u8, colour as u8)
+}
+
+fn player(player: i64) -> usize {
+    (player.clamp(1, 4) - 1) as usize
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    context: Rc<RefCell<Context>>,
+    // input from the last callbacks, for the coming frame
+    injected: [Option<Button>; 4],
+    states: HashMap<i64, Vec<u8>>,
+}
+
+impl Script {
+    // Compiles and runs the top level of the script, which registers its
+    // callbacks. It can already use every function but the ones touching
+    // memory and input, which have nothing to work on until the first frame.
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let context = Rc::new(RefCell::new(Context::new()));
+        let mut engine = Engine::new();
+        register(&mut engine, &context);
+        let ast = engine
+            .compile(source)
+            .map_err(|err| ScriptError::Syntax(err.to_string()))?;
+        engine
+            .run_ast_with_scope(&mut Scope::new(), &ast)
+            .map_err(|err| ScriptError::Runtime(err.to_string()))?;
+        Ok(Script {
+            engine,
+            ast,
+            context,
+            injected: [None; 4],
+            states: HashMap::new(),
+        })
+    }
+
+    // Nes::run_frame with the script's input going in first and its
+    // callbacks running when the frame is finished
+    pub fn run_frame(&mut self, nes: &mut Nes) -> Result<StopReason, ScriptError> {
+        for (player, buttons) in self.injected.iter_mut().enumerate() {
+            if let Some(buttons) = buttons.take() {
+                nes.set_input(player + 1, buttons);
+            }
+        }
+        let reason = nes.run_frame();
+        if reason == StopReason::FrameComplete {
+            self.after_frame(nes)?;
+        }
+        Ok(reason)
+    }
+
+    fn after_frame(&mut self, nes: &mut Nes) -> Result<(), ScriptError> {
+        let callbacks = {
+            let mut context = self.context.borrow_mut();
+            context.memory = nes.peek_range(0, 0x10000);
+            for player in 0..4 {
+                context.input[player] = nes.input(player + 1);
+            }
+            context.frame += 1;
+            context.callbacks.clone()
+        };
+        for callback in callbacks {
+            if let Err(err) = callback.call::<Dynamic>(&self.engine, &self.ast, ()) {
+                return Err(ScriptError::Runtime(err.to_string()));
+            }
+        }
+
+        let effects = std::mem::take(&mut self.context.borrow_mut().effects);
+        for effect in effects {
+            match effect {
+                Effect::Write(addr, value) => nes.bus_mut().mem_write(addr, value),
+                Effect::Input(player, buttons) => self.injected[player] = Some(buttons),
+                Effect::Text(x, y, text, colour) => {
+                    overlay::draw_text(nes.frame_buffer_mut(), x, y, &text, colour)
+                }
+                Effect::Box(x1, y1, x2, y2, colour) => {
+                    overlay::draw_box(nes.frame_buffer_mut(), x1, y1, x2, y2, colour)
+                }
+                Effect::SaveState(slot) => {
+                    self.states.insert(slot, nes.save_state());
+                }
+                Effect::LoadState(slot) => {
+                    let state = self.states.get(&slot).ok_or(ScriptError::EmptySlot(slot))?;
+                    nes.load_state(state)
+                        .map_err(|err| ScriptError::Runtime(err.to_string()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn register(engine: &mut Engine, context: &Rc<RefCell<Context>>) {
+    let mut buttons = Module::new();
+    for (name, button) in [
+        ("A", Button::A),
+        ("B", Button::B),
+        ("SELECT", Button::SELECT),
+        ("START", Button::START),
+        ("UP", Button::UP),
+        ("DOWN", Button::DOWN),
+        ("LEFT", Button::LEFT),
+        ("RIGHT", Button::RIGHT),
+    ] {
+        buttons.set_var(name, button.bits() as i64);
+    }
+    engine.register_static_module("button", buttons.into());
+
+    let shared = context.clone();
+    engine.register_fn("on_frame", move |callback: FnPtr| {
+        shared.borrow_mut().callbacks.push(callback);
+    });
+    let shared = context.clone();
+    engine.register_fn("frame_count", move || shared.borrow().frame as i64);
+
+    // reads of the unmapped snapshot before the first frame give 0
+    let shared = context.clone();
+    engine.register_fn("memory_read", move |addr: i64| {
+        let context = shared.borrow();
+        context
+            .memory
+            .get(addr as u16 as usize)
+            .copied()
+            .unwrap_or(0) as i64
+    });
+    let shared = context.clone();
+    engine.register_fn("memory_write", move |addr: i64, value: i64| {
+        let mut context = shared.borrow_mut();
+        let (addr, value) = (addr as u16, value as u8);
+        // later reads in the same callback see the write
+        if let Some(byte) = context.memory.get_mut(addr as usize) {
+            *byte = value;
+        }
+        context.effects.push(Effect::Write(addr, value));
+    });
+
+    let shared = context.clone();
+    engine.register_fn("joypad_read", move |number: i64| {
+        shared.borrow().input[player(number)].bits() as i64
+    });
+    let shared = context.clone();
+    engine.register_fn("joypad_set", move |number: i64, buttons: i64| {
+        let buttons = Button::from_bits_truncate(buttons as u8);
+        let effect = Effect::Input(player(number), buttons);
+        shared.borrow_mut().effects.push(effect);
+    });
+
+    let shared = context.clone();
+    let text = move |x: i64, y: i64, text: ImmutableString, colour: i64| {
+        let effect = Effect::Text(x as isize, y as isize, text.to_string(), rgb(colour));
+        shared.borrow_mut().effects.push(effect);
+    };
+    let white_text = text.clone();
+    engine.register_fn("gui_text", text);
+    engine.register_fn("gui_text", move |x: i64, y: i64, text: ImmutableString| {
+        white_text(x, y, text, WHITE)
+    });
+    let shared = context.clone();
+    let draw_box = move |x1: i64, y1: i64, x2: i64, y2: i64, colour: i64| {
+        let (x1, y1, x2, y2) = (x1 as isize, y1 as isize, x2 as isize, y2 as isize);
+        let effect = Effect::Box(x1, y1, x2, y2, rgb(colour));
+        shared.borrow_mut().effects.push(effect);
+    };
+    let white_box = draw_box.clone();
+    engine.register_fn("gui_box", draw_box);
+    engine.register_fn("gui_box", move |x1: i64, y1: i64, x2: i64, y2: i64| {
+        white_box(x1, y1, x2, y2, WHITE)
+    });
+
+    let shared = context.clone();
+    engine.register_fn("savestate_save", move |slot: i64| {
+        shared.borrow_mut().effects.push(Effect::SaveState(slot));
+    });
+    let shared = context.clone();
+    engine.register_fn("savestate_load", move |slot: i64| {
+        shared.borrow_mut().effects.push(Effect::LoadState(slot));
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::nmi_rom;
+
+    // counts frames at $10: INC $10
+    const COUNT_FRAMES: [u8; 2] = [0xe6, 0x10];
+
+    #[test]
+    fn test_frame_callbacks() {
+        let mut nes = Nes::new(&nmi_rom(&COUNT_FRAMES)).unwrap();
+        let mut script = Script::new(
+            r#"
+            let seen = [];
+            on_frame(|| {
+                seen.push(memory_read(0x10));
+                memory_write(0x300, seen.len());
+                joypad_set(1, button::START | button::A);
+                gui_text(0, 0, "HI", 0xff0000);
+                if frame_count() == 2 { savestate_save(1); }
+                if frame_count() == 4 { savestate_load(1); }
+            });
+            "#,
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(script.run_frame(&mut nes), Ok(StopReason::FrameComplete));
+        }
+        assert_eq!(nes.peek(0x300), 3);
+        assert_eq!(nes.input(1), Button::START | Button::A);
+        assert_eq!(nes.frame_buffer().data[..3], [0xff, 0, 0]);
+
+        // the fourth frame's callback puts the second frame's state back
+        let counter = nes.peek(0x10);
+        script.run_frame(&mut nes).unwrap();
+        assert_eq!(nes.peek(0x10), counter - 1);
+        assert_eq!(nes.peek(0x300), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            Script::new("on_frame(|| {"),
+            Err(ScriptError::Syntax(_))
+        ));
+        assert!(matches!(
+            Script::new("undefined()"),
+            Err(ScriptError::Runtime(_))
+        ));
+
+        let mut nes = Nes::new(&nmi_rom(&COUNT_FRAMES)).unwrap();
+        let mut script = Script::new("on_frame(|| savestate_load(7));").unwrap();
+        assert_eq!(script.run_frame(&mut nes), Err(ScriptError::EmptySlot(7)));
+    }
+}
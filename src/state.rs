@@ -0,0 +1,330 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Save states
+//
+// A state is a header followed by one section per component:
+// | Bytes | Description                                          |
+// |-------|------------------------------------------------------|
+// | 0-3   | "NESS"                                               |
+// | 4-5   | Format version, little endian                        |
+// | 6-    | Sections: 4-byte tag, 4-byte little endian length,   |
+// |       | then the component's fields in a fixed order         |
+//
+// Sections are looked up by tag, so new components can be added without
+// moving the others. When a component's fields change the version goes up,
+// and `load_state` implementations can check `StateReader::version` to
+// read states written by older versions.
+const STATE_TAG: [u8; 4] = *b"NESS";
+pub const STATE_VERSION: u16 = 6;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    InvalidTag,
+    UnsupportedVersion(u16),
+    Truncated,
+    MissingSection([u8; 4]),
+    // the state was saved with a different cartridge
+    Mismatch,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidTag => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Save state version {} is not supported", version)
+            }
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::MissingSection(tag) => write!(
+                f,
+                "Save state has no {} section",
+                String::from_utf8_lossy(tag)
+            ),
+            StateError::Mismatch => write!(f, "Save state is for a different cartridge"),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+// Components that can be written to and restored from a save state
+pub trait Snapshot {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = STATE_TAG.to_vec();
+        data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        StateWriter { data }
+    }
+
+    pub fn section<F: FnOnce(&mut StateWriter)>(&mut self, tag: &[u8; 4], write: F) {
+        self.data.extend_from_slice(tag);
+        let length_at = self.data.len();
+        self.data.extend_from_slice(&[0; 4]);
+        write(self);
+        let length = (self.data.len() - length_at - 4) as u32;
+        self.data[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_option(&mut self, value: Option<u8>) {
+        self.write_bool(value.is_some());
+        self.write_u8(value.unwrap_or(0));
+    }
+
+    // length prefixed, so it can be checked against the memory it's loaded into
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        if data.len() < 6 || data[0..4] != STATE_TAG {
+            return Err(StateError::InvalidTag);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(StateReader {
+            data,
+            position: 6,
+            version,
+        })
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn section(&self, tag: &[u8; 4]) -> Result<StateReader<'a>, StateError> {
+        let mut position = 6;
+        while position + 8 <= self.data.len() {
+            let length = u32::from_le_bytes([
+                self.data[position + 4],
+                self.data[position + 5],
+                self.data[position + 6],
+                self.data[position + 7],
+            ]) as usize;
+            let start = position + 8;
+            let end = start.checked_add(length).ok_or(StateError::Truncated)?;
+            if end > self.data.len() {
+                return Err(StateError::Truncated);
+            }
+            if &self.data[position..position + 4] == tag {
+                return Ok(StateReader {
+                    data: &self.data[..end],
+                    position: start,
+                    version: self.version,
+                });
+            }
+            position = end;
+        }
+        Err(StateError::MissingSection(*tag))
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() - self.position < count {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_option(&mut self) -> Result<Option<u8>, StateError> {
+        let some = self.read_bool()?;
+        let value = self.read_u8()?;
+        Ok(if some { Some(value) } else { None })
+    }
+
+    // fills `bytes`, which has to be as long as the memory that was saved
+    pub fn read_bytes_into(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        if self.read_u32()? as usize != bytes.len() {
+            return Err(StateError::Mismatch);
+        }
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+}
+
+// The whole machine as plain data for serde, to look at as JSON, compare in
+// tests or keep in whatever format serde writes. Mappers are trait objects,
+// so the cartridge's registers stay in the save state encoding. Like a save
+// state, it only loads with the same cartridge, see CPU::load_machine_state.
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct MachineState {
+    pub cpu: crate::cpu::CpuState,
+    pub bus: crate::bus::BusState,
+    pub ppu: crate::ppu::PpuState,
+    pub apu: crate::apu::ApuState,
+    pub cartridge: Vec<u8>,
+}
+
+// puts memory from a MachineState back, which has to be as long as the
+// memory it was taken from
+#[cfg(feature = "serde")]
+pub fn copy_memory(memory: &mut [u8], saved: &[u8]) -> Result<(), StateError> {
+    if saved.len() != memory.len() {
+        return Err(StateError::Mismatch);
+    }
+    memory.copy_from_slice(saved);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    struct Counter {
+        value: u16,
+        memory: Vec<u8>,
+    }
+
+    impl Snapshot for Counter {
+        fn save_state(&self, state: &mut StateWriter) {
+            state.write_u16(self.value);
+            state.write_bytes(&self.memory);
+        }
+
+        fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+            self.value = state.read_u16()?;
+            state.read_bytes_into(&mut self.memory)
+        }
+    }
+
+    #[test]
+    fn test_sections_round_trip() {
+        let first = Counter {
+            value: 0x1234,
+            memory: vec![1, 2, 3],
+        };
+        let second = Counter {
+            value: 0xabcd,
+            memory: vec![],
+        };
+        let mut writer = StateWriter::new();
+        writer.section(b"ONE ", |state| first.save_state(state));
+        writer.section(b"TWO ", |state| second.save_state(state));
+        let data = writer.finish();
+
+        let reader = StateReader::new(&data).unwrap();
+        let mut counter = Counter {
+            value: 0,
+            memory: vec![],
+        };
+        counter
+            .load_state(&mut reader.section(b"TWO ").unwrap())
+            .unwrap();
+        assert_eq!(counter.value, 0xabcd);
+
+        // memory sizes have to match
+        assert_eq!(
+            counter.load_state(&mut reader.section(b"ONE ").unwrap()),
+            Err(StateError::Mismatch)
+        );
+        counter.memory = vec![0; 3];
+        counter
+            .load_state(&mut reader.section(b"ONE ").unwrap())
+            .unwrap();
+        assert_eq!(counter.memory, vec![1, 2, 3]);
+
+        assert_eq!(
+            reader.section(b"MISS").err(),
+            Some(StateError::MissingSection(*b"MISS"))
+        );
+        assert_eq!(
+            StateReader::new(&data[..data.len() - 1])
+                .unwrap()
+                .section(b"TWO ")
+                .err(),
+            Some(StateError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(
+            StateReader::new(b"NES\x1a\x01\x00").err(),
+            Some(StateError::InvalidTag)
+        );
+        assert_eq!(
+            StateReader::new(b"NESS\xff\x00").err(),
+            Some(StateError::UnsupportedVersion(0xff))
+        );
+        assert_eq!(StateReader::new(b"NESS\x01\x00").unwrap().version(), 1);
+    }
+}
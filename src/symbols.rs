@@ -0,0 +1,263 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// Labels for addresses, from FCEUX name lists or ca65 debug files, for the
+// trace log and the debugger to show instead of raw addresses.
+//
+// Labels in cartridge ROM are kept by their offset into PRG ROM, so with a
+// bank-switching mapper an address gets the label of whatever bank is
+// mapped there when it's looked up. Everything else (RAM, registers, work
+// RAM) is kept by CPU address.
+#[derive(Debug, PartialEq)]
+pub enum SymbolError {
+    // a line that isn't a valid entry, counting from 1
+    InvalidLine(usize),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::InvalidLine(line) => write!(f, "invalid symbol on line {}", line),
+        }
+    }
+}
+
+impl core::error::Error for SymbolError {}
+
+// the size of the banks FCEUX numbers its .nl files by
+const NL_BANK_SIZE: usize = 0x4000;
+const INES_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct Symbols {
+    by_addr: BTreeMap<u16, String>,
+    by_prg_offset: BTreeMap<usize, String>,
+    // every label and one address it's at, for looking them up by name
+    names: BTreeMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_addr.len() + self.by_prg_offset.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the first label at a place wins
+    fn insert(&mut self, name: &str, addr: u16, prg_offset: Option<usize>) {
+        let label = || name.to_string();
+        match prg_offset {
+            Some(offset) => self.by_prg_offset.entry(offset).or_insert_with(label),
+            None => self.by_addr.entry(addr).or_insert_with(label),
+        };
+        self.names.entry(name.to_string()).or_insert(addr);
+    }
+
+    // The label for `addr`, which is mapped to `prg_offset` in PRG ROM if
+    // it's in the cartridge; see BUS::prg_offset
+    pub fn label(&self, addr: u16, prg_offset: Option<usize>) -> Option<&str> {
+        prg_offset
+            .and_then(|offset| self.by_prg_offset.get(&offset))
+            .or_else(|| self.by_addr.get(&addr))
+            .map(String::as_str)
+    }
+
+    // the CPU address of a label, to set breakpoints by name
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names.get(name).copied()
+    }
+
+    // An FCEUX name list: "<rom>.ram.nl" with RAM and register labels, bank
+    // None, or "<rom>.<n>.nl" for the 16KB PRG bank n. A line is
+    // "$addr#name#comment", or "$addr/size#name#comment" for an array, its
+    // size in hex; only its first byte is labelled.
+    // from: https://fceux.com/web/help/NLFilesFormat.html
+    pub fn load_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), SymbolError> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = SymbolError::InvalidLine(number + 1);
+            let mut fields = line.splitn(3, '#');
+            let place = fields.next().and_then(|place| place.strip_prefix('$'));
+            let name = fields.next().unwrap_or_default().trim();
+            let addr = place
+                .map(|place| place.split('/').next().unwrap_or_default())
+                .and_then(|addr| u16::from_str_radix(addr, 16).ok())
+                .ok_or(invalid)?;
+            // FCEUX allows unnamed lines that only carry a comment
+            if name.is_empty() {
+                continue;
+            }
+            let prg_offset = match bank {
+                Some(bank) if addr >= 0x8000 => {
+                    Some(bank * NL_BANK_SIZE + (addr as usize % NL_BANK_SIZE))
+                }
+                _ => None,
+            };
+            self.insert(name, addr, prg_offset);
+        }
+        Ok(())
+    }
+
+    // A ca65/ld65 debug file, from ld65 --dbgfile. Labels in segments that
+    // are written to the ROM image get their PRG ROM offset from the
+    // segment's offset in the file, the rest are kept by address. Imports
+    // and equates (constants, which aren't addresses) are skipped.
+    // from: https://cc65.github.io/doc/debugging.html
+    pub fn load_ca65_dbg(&mut self, text: &str) -> Result<(), SymbolError> {
+        // segment id: start address and offset in the .nes file
+        let mut segments: BTreeMap<u32, (u32, Option<usize>)> = BTreeMap::new();
+        let mut labels = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let invalid = || SymbolError::InvalidLine(number + 1);
+            let (kind, fields) = match line.split_once(char::is_whitespace) {
+                Some((kind, fields)) => (kind, dbg_fields(fields)),
+                None => continue,
+            };
+            let number_field = |key: &str| fields.get(key).and_then(|value| dbg_number(value));
+            match kind {
+                "seg" => {
+                    let id = number_field("id").ok_or_else(invalid)?;
+                    let start = number_field("start").ok_or_else(invalid)?;
+                    let offset = number_field("ooffs").map(|offset| offset as usize);
+                    segments.insert(id, (start, offset));
+                }
+                "sym" if fields.get("type") == Some(&"lab") => {
+                    let name = fields.get("name").ok_or_else(invalid)?.trim_matches('"');
+                    let value = number_field("val").ok_or_else(invalid)?;
+                    labels.push((name.to_string(), value, number_field("seg")));
+                }
+                _ => {}
+            }
+        }
+
+        for (name, value, segment) in labels {
+            let prg_offset = segment
+                .and_then(|segment| segments.get(&segment))
+                .and_then(|&(start, offset)| {
+                    let offset = offset?.checked_sub(INES_HEADER_SIZE)?;
+                    Some(offset + value.checked_sub(start)? as usize)
+                })
+                .filter(|_| value >= 0x8000);
+            self.insert(&name, value as u16, prg_offset);
+        }
+        Ok(())
+    }
+
+    // Whatever symbol files sit next to the ROM: FCEUX's "<rom>.ram.nl" and
+    // "<rom>.<n>.nl", and ld65's "<rom without .nes>.dbg"
+    #[cfg(feature = "std")]
+    pub fn load_next_to(rom_path: &Path) -> io::Result<Self> {
+        let mut symbols = Symbols::new();
+        let invalid = |err: SymbolError| io::Error::new(io::ErrorKind::InvalidData, err);
+        let name_list = |suffix: String| {
+            let mut path = rom_path.as_os_str().to_owned();
+            path.push(suffix);
+            path
+        };
+        if let Some(text) = read_if_exists(Path::new(&name_list(".ram.nl".to_string())))? {
+            symbols.load_nl(&text, None).map_err(invalid)?;
+        }
+        for bank in 0.. {
+            match read_if_exists(Path::new(&name_list(format!(".{:X}.nl", bank))))? {
+                Some(text) => symbols.load_nl(&text, Some(bank)).map_err(invalid)?,
+                None => break,
+            }
+        }
+        if let Some(text) = read_if_exists(&rom_path.with_extension("dbg"))? {
+            symbols.load_ca65_dbg(&text).map_err(invalid)?;
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_if_exists(path: &Path) -> io::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// key=value,key="value",... with no commas inside the quotes that matter
+fn dbg_fields(fields: &str) -> BTreeMap<&str, &str> {
+    fields
+        .trim()
+        .split(',')
+        .filter_map(|field| field.split_once('='))
+        .collect()
+}
+
+fn dbg_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_name_lists() {
+        let mut symbols = Symbols::new();
+        symbols
+            .load_nl(
+                "$0010#PlayerX#\n$0200/100#OamBuffer#sprites\n$2000#PPUCTRL#\n",
+                None,
+            )
+            .unwrap();
+        symbols
+            .load_nl("$8000#Reset#\n$8010#NMI#vblank\n", Some(1))
+            .unwrap();
+
+        assert_eq!(symbols.label(0x0010, None), Some("PlayerX"));
+        assert_eq!(symbols.label(0x0200, None), Some("OamBuffer"));
+        assert_eq!(symbols.label(0x2000, None), Some("PPUCTRL"));
+        // bank 1's labels only where bank 1 is mapped
+        assert_eq!(symbols.label(0x8000, Some(0x4000)), Some("Reset"));
+        assert_eq!(symbols.label(0xc000, Some(0x4000)), Some("Reset"));
+        assert_eq!(symbols.label(0x8000, Some(0x0000)), None);
+        assert_eq!(symbols.label(0x8010, Some(0x4010)), Some("NMI"));
+        assert_eq!(symbols.address("NMI"), Some(0x8010));
+
+        assert_eq!(
+            Symbols::new().load_nl("C000#NoDollar#", None),
+            Err(SymbolError::InvalidLine(1))
+        );
+    }
+
+    #[test]
+    fn test_ca65_debug_file() {
+        let dbg = r#"version	major=2,minor=0
+seg	id=0,name="CODE",start=0x00C000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=16400
+seg	id=1,name="ZEROPAGE",start=0x000000,size=0x0004,addrsize=zeropage,type=rw
+sym	id=0,name="reset",addrsize=absolute,scope=0,def=1,val=0xC004,seg=0,type=lab
+sym	id=1,name="frames",addrsize=zeropage,size=1,scope=0,def=2,val=0x2,seg=1,type=lab
+sym	id=2,name="SPEED",addrsize=zeropage,scope=0,def=3,val=0x3,type=equ
+"#;
+        let mut symbols = Symbols::new();
+        symbols.load_ca65_dbg(dbg).unwrap();
+        assert_eq!(symbols.len(), 2);
+        // 16400 - 16 byte header + 4 into the segment
+        assert_eq!(symbols.label(0xc004, Some(0x4004)), Some("reset"));
+        assert_eq!(symbols.label(0x0002, None), Some("frames"));
+        assert_eq!(symbols.label(0x0003, None), None);
+    }
+}
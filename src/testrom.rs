@@ -0,0 +1,166 @@
+use crate::error::EmulatorError;
+use crate::nes::Nes;
+use crate::ppu::PPU;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// Runs blargg's test ROMs headlessly and reads back their verdict
+// from: https://github.com/christopherpow/nes-test-roms
+//
+// Newer ROMs report through PRG RAM:
+// | Address     | Contents                                               |
+// |-------------|--------------------------------------------------------|
+// | $6000       | $80 while running, $81 when they want a reset pressed  |
+// |             | 100ms or more from now, then the result: 0 is a pass   |
+// | $6001-$6003 | $DE $B0 $61, once $6000 can be trusted                 |
+// | $6004-      | Zero terminated text output                            |
+//
+// Older ones only print the result on screen. Their font has each
+// character at the tile of its ASCII code, so the text can be read straight
+// out of the nametable.
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const RUNNING: u8 = 0x80;
+const RESET_WANTED: u8 = 0x81;
+// frames to hold reset for, a little over 100ms
+const RESET_DELAY_FRAMES: usize = 7;
+
+#[derive(Debug, PartialEq)]
+pub enum TestResult {
+    Passed(String),
+    Failed { code: u8, text: String },
+    // no verdict within the frame limit, with whatever was on screen
+    TimedOut(String),
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        matches!(self, TestResult::Passed(_))
+    }
+}
+
+pub fn run_test_rom(rom_bytes: &[u8], frame_limit: usize) -> Result<TestResult, EmulatorError> {
+    let mut nes = Nes::new(rom_bytes)?;
+    let mut reset_at = None;
+    for frame in 0..frame_limit {
+        nes.run_until_frame();
+
+        if !has_signature(&nes) {
+            continue;
+        }
+        match nes.bus().peek(0x6000) {
+            RUNNING => {}
+            RESET_WANTED => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    nes.soft_reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            0 => return Ok(TestResult::Passed(output_text(&nes))),
+            code => {
+                return Ok(TestResult::Failed {
+                    code,
+                    text: output_text(&nes),
+                })
+            }
+        }
+    }
+
+    // no RAM protocol, go by what's on screen
+    let text = screen_text(&nes.bus().ppu);
+    let lower = text.to_lowercase();
+    Ok(if lower.contains("passed") {
+        TestResult::Passed(text)
+    } else if lower.contains("failed") || lower.contains("error") {
+        TestResult::Failed { code: 1, text }
+    } else {
+        TestResult::TimedOut(text)
+    })
+}
+
+fn has_signature(nes: &Nes) -> bool {
+    (0..3).all(|i| nes.bus().peek(0x6001 + i) == SIGNATURE[i as usize])
+}
+
+fn output_text(nes: &Nes) -> String {
+    let bytes: Vec<u8> = (0x6004..0x8000)
+        .map(|addr| nes.bus().peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// The first nametable as 30 lines of 32 characters, with tiles outside of
+// printable ASCII as spaces and blank lines dropped
+pub fn screen_text(ppu: &PPU) -> String {
+    let lines: Vec<String> = ppu.vram[..960]
+        .chunks(32)
+        .map(|row| {
+            let line: String = row
+                .iter()
+                .map(|&tile| {
+                    if (0x20..0x7f).contains(&tile) {
+                        tile as char
+                    } else {
+                        ' '
+                    }
+                })
+                .collect();
+            line.trim_end().to_string()
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::nrom;
+    use alloc::vec;
+
+    // writes `bytes` from $6000 on, then loops
+    fn reporting(bytes: &[u8]) -> Vec<u8> {
+        let mut program = vec![];
+        for (i, byte) in bytes.iter().enumerate() {
+            // LDA #byte; STA $6000+i
+            program.extend([0xa9, *byte, 0x8d, i as u8, 0x60]);
+        }
+        let end = 0x8000 + program.len() as u16;
+        program.extend([0x4c, end as u8, (end >> 8) as u8]);
+        nrom(&program, &[])
+    }
+
+    #[test]
+    fn test_ram_protocol() {
+        let passed = reporting(&[0x00, 0xde, 0xb0, 0x61, b'o', b'k', 0]);
+        assert_eq!(
+            run_test_rom(&passed, 10).unwrap(),
+            TestResult::Passed("ok".to_string())
+        );
+
+        let failed = reporting(&[0x03, 0xde, 0xb0, 0x61, b'#', b'3', 0]);
+        assert_eq!(
+            run_test_rom(&failed, 10).unwrap(),
+            TestResult::Failed {
+                code: 3,
+                text: "#3".to_string()
+            }
+        );
+
+        let running = reporting(&[0x80, 0xde, 0xb0, 0x61, 0]);
+        assert_eq!(
+            run_test_rom(&running, 10).unwrap(),
+            TestResult::TimedOut(String::new())
+        );
+    }
+
+    #[test]
+    fn test_screen_text() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.vram[32 * 4 + 2..32 * 4 + 8].copy_from_slice(b"Passed");
+        ppu.vram[32 * 6..32 * 6 + 3].copy_from_slice(&[b'O', 0x01, b'K']);
+        assert_eq!(screen_text(&ppu), "  Passed\nO K");
+    }
+}
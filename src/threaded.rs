@@ -0,0 +1,237 @@
+use crate::clock::Clock;
+use crate::error::EmulatorError;
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::render::frame::Frame;
+
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+// frames and sound the frontend hasn't taken yet; past this the emulation
+// thread drops them rather than waiting
+const FRAME_QUEUE: usize = 2;
+const AUDIO_QUEUE: usize = 8;
+
+type Job = Box<dyn FnOnce(&mut Nes) + Send>;
+
+enum Command {
+    Input(usize, Button),
+    Speed(f32),
+    Run(Job),
+    Quit,
+}
+
+// A Nes running on a thread of its own, paced to its frame rate, so that a
+// frontend blocked on vsync or a slow audio device doesn't hold the
+// emulation up (or the other way round). Finished frames and each frame's
+// sound come out of channels; input, save states and anything else go in
+// as commands, run between frames.
+//
+// A Nes can't move between threads, so it's built on the emulation thread
+// by the closure given to `spawn`. Dropping a ThreadedNes stops the thread.
+pub struct ThreadedNes {
+    commands: Sender<Command>,
+    frames: Receiver<Frame>,
+    audio: Option<Receiver<Vec<f32>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadedNes {
+    pub fn spawn<F, E>(build: F) -> Result<ThreadedNes, E>
+    where
+        F: FnOnce() -> Result<Nes, E> + Send + 'static,
+        E: Send + 'static,
+    {
+        let (commands, command_receiver) = mpsc::channel();
+        let (frame_sender, frames) = mpsc::sync_channel(FRAME_QUEUE);
+        let (audio_sender, audio) = mpsc::sync_channel(AUDIO_QUEUE);
+        let (started_sender, started) = mpsc::sync_channel(1);
+        let thread = thread::spawn(move || {
+            let nes = match build() {
+                Ok(nes) => nes,
+                Err(err) => {
+                    let _ = started_sender.send(Err(err));
+                    return;
+                }
+            };
+            let _ = started_sender.send(Ok(()));
+            run(nes, command_receiver, frame_sender, audio_sender);
+        });
+        match started.recv() {
+            Ok(Ok(())) => Ok(ThreadedNes {
+                commands,
+                frames,
+                audio: Some(audio),
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => Err(err),
+            // the closure panicked, so does the caller
+            Err(_) => match thread.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!("the emulation thread ended without starting"),
+            },
+        }
+    }
+
+    // the newest finished frame since the last call, if there is one
+    pub fn latest_frame(&self) -> Option<Frame> {
+        self.frames.try_iter().last()
+    }
+
+    // Hands the sound over to whatever plays it, typically an audio
+    // callback on a thread of its own: one Vec of samples per frame.
+    // There's only the one receiver, later calls get None.
+    pub fn take_audio(&mut self) -> Option<Receiver<Vec<f32>>> {
+        self.audio.take()
+    }
+
+    // for player 1-4, others are ignored rather than panicking on the
+    // emulation thread
+    pub fn set_input(&self, player: usize, buttons: Button) {
+        if !(1..=4).contains(&player) {
+            return;
+        }
+        self.send(Command::Input(player, buttons));
+    }
+
+    // see Clock::set_speed; sound is dropped at any speed but 1x
+    pub fn set_speed(&self, speed: f32) {
+        self.send(Command::Speed(speed));
+    }
+
+    pub fn pause(&self) {
+        self.execute(|nes| nes.pause());
+    }
+
+    pub fn unpause(&self) {
+        self.execute(|nes| nes.unpause());
+    }
+
+    pub fn reset(&self) {
+        self.execute(|nes| nes.reset());
+    }
+
+    // Runs `job` on the emulation thread between frames, for everything
+    // without a method here
+    pub fn execute<F: FnOnce(&mut Nes) + Send + 'static>(&self, job: F) {
+        self.send(Command::Run(Box::new(job)));
+    }
+
+    // Like execute, but waits for the job to run and returns what it did
+    pub fn query<F, T>(&self, job: F) -> Result<T, EmulatorError>
+    where
+        F: FnOnce(&mut Nes) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.execute(move |nes| {
+            let _ = reply.send(job(nes));
+        });
+        result.recv().map_err(|_| EmulatorError::Stopped)
+    }
+
+    pub fn save_state(&self) -> Result<Vec<u8>, EmulatorError> {
+        self.query(|nes| nes.save_state())
+    }
+
+    pub fn load_state(&self, data: Vec<u8>) -> Result<(), EmulatorError> {
+        self.query(move |nes| nes.load_state(&data))?
+    }
+
+    // whether the emulation thread is still going
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    fn send(&self, command: Command) {
+        // a thread that's gone has nothing left to do with it
+        let _ = self.commands.send(command);
+    }
+}
+
+impl Drop for ThreadedNes {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(
+    mut nes: Nes,
+    commands: Receiver<Command>,
+    frames: SyncSender<Frame>,
+    audio: SyncSender<Vec<f32>>,
+) {
+    let mut clock = Clock::new(nes.frame_rate());
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Input(player, buttons)) => nes.set_input(player, buttons),
+                Ok(Command::Speed(speed)) => clock.set_speed(speed),
+                Ok(Command::Run(job)) => job(&mut nes),
+                Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        let frame_count = clock.tick();
+        for _ in 0..frame_count {
+            nes.run_frame();
+        }
+        if frame_count > 0 {
+            // a frontend that fell behind misses frames instead of
+            // slowing the emulation down
+            let _ = frames.try_send(nes.frame_buffer().clone());
+        }
+        let samples = nes.audio_samples();
+        if !clock.audio_muted() && !samples.is_empty() {
+            let _ = audio.try_send(samples);
+        }
+
+        clock.set_frame_rate(nes.frame_rate());
+        clock.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::nmi_rom;
+
+    #[test]
+    fn test_threaded_nes() {
+        let mut nes = ThreadedNes::spawn(|| Nes::new(&nmi_rom(&[]))).unwrap();
+        let audio = nes.take_audio().unwrap();
+        assert!(nes.take_audio().is_none());
+
+        // frames and sound come out at the console's pace
+        let samples = audio.recv().unwrap();
+        assert!(!samples.is_empty());
+        while nes.latest_frame().is_none() {
+            std::thread::yield_now();
+        }
+
+        nes.set_input(1, Button::A);
+        nes.set_input(0, Button::B);
+        let state = nes.save_state().unwrap();
+        assert!(nes.load_state(state).is_ok());
+        assert_eq!(
+            nes.load_state(vec![1, 2, 3]),
+            Err(EmulatorError::InvalidState(
+                crate::state::StateError::InvalidTag
+            ))
+        );
+        assert_eq!(nes.query(|nes| nes.input(1)), Ok(Button::A));
+        assert!(nes.is_running());
+    }
+
+    #[test]
+    fn test_spawn_errors() {
+        let result = ThreadedNes::spawn(|| Nes::new(&[0; 16]));
+        assert!(matches!(result, Err(EmulatorError::InvalidRom(_))));
+    }
+}
@@ -0,0 +1,333 @@
+use crate::cpu::{AddressingMode, CPU};
+use crate::opcodes::{self, OpCode};
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// One line per instruction, formatted like nestest.log so traces can be
+// diffed against it. Logged before the instruction runs:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+// |     |         |                               |                            |       +-- CPU cycles
+// |     |         |                               |                            +---------- scanline, dot
+// |     |         |                               +--------------------------------------- registers
+// |     |         +----------------------------------------------------------------------- disassembly, with
+// |     |                                                                                  the memory it touches
+// |     +--------------------------------------------------------------------------------- instruction bytes
+// +--------------------------------------------------------------------------------------- program counter
+//
+// Unofficial opcodes are marked with a * in front of the mnemonic. With
+// symbols loaded, an address the operand names (a jump or branch target, a
+// pointer, the base of an indexed access) is shown as its label.
+pub type Tracer = Box<dyn FnMut(&str)>;
+
+pub fn trace(cpu: &CPU) -> String {
+    let bus = &cpu.bus;
+    let begin = cpu.program_counter;
+    let code = bus.peek(begin);
+    let opcode = &opcodes::OPCODES[code as usize];
+    if opcode.mnemonic == "???" {
+        return registers(cpu, format!("{:04X}  {:02X}        ???", begin, code));
+    }
+
+    let mut hex_dump = vec![code];
+    let (mem_addr, stored_value) = match opcode.mode {
+        AddressingMode::Immediate | AddressingMode::NoneAddressing => (0, 0),
+        _ => {
+            let addr = operand_address(cpu, &opcode.mode, begin.wrapping_add(1));
+            (addr, bus.peek(addr))
+        }
+    };
+
+    let operand = match opcode.len {
+        1 => match opcode.code {
+            0x0a | 0x4a | 0x2a | 0x6a => "A ".to_string(),
+            _ => String::new(),
+        },
+        2 => {
+            let address = bus.peek(begin.wrapping_add(1));
+            hex_dump.push(address);
+
+            match opcode.mode {
+                AddressingMode::Immediate => format!("#${:02x}", address),
+                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::ZeroPage_X => format!(
+                    "${:02x},X @ {:02x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::ZeroPage_Y => format!(
+                    "${:02x},Y @ {:02x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::Indirect_X => format!(
+                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
+                    address,
+                    address.wrapping_add(cpu.register_x),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressingMode::Indirect_Y => format!(
+                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
+                    address,
+                    mem_addr.wrapping_sub(cpu.register_y as u16),
+                    mem_addr,
+                    stored_value
+                ),
+                // branches: the target, relative to the next instruction
+                _ => {
+                    let target = begin.wrapping_add(2).wrapping_add(address as i8 as u16);
+                    format!("${:04x}", target)
+                }
+            }
+        }
+        3 => {
+            let lo = bus.peek(begin.wrapping_add(1));
+            let hi = bus.peek(begin.wrapping_add(2));
+            hex_dump.push(lo);
+            hex_dump.push(hi);
+            let address = u16::from_le_bytes([lo, hi]);
+
+            match opcode.mode {
+                AddressingMode::NoneAddressing if opcode.code == 0x6c => {
+                    // JMP ($xxFF) reads the high byte from $xx00
+                    let high_addr = (address & 0xff00) | (address.wrapping_add(1) & 0x00ff);
+                    let target = u16::from_le_bytes([bus.peek(address), bus.peek(high_addr)]);
+                    format!("(${:04x}) = {:04x}", address, target)
+                }
+                AddressingMode::NoneAddressing => format!("${:04x}", address),
+                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::Absolute_X => format!(
+                    "${:04x},X @ {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::Absolute_Y => format!(
+                    "${:04x},Y @ {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                _ => String::new(),
+            }
+        }
+        _ => String::new(),
+    };
+
+    let hex_str = hex_dump
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let asm = format!(
+        "{:04x}  {:8} {: >4} {}",
+        begin, hex_str, opcode.mnemonic, operand
+    );
+    let mut asm = asm.trim_end().to_ascii_uppercase();
+    if let Some((addr, written)) = named_address(cpu, opcode, begin) {
+        if let Some(label) = cpu.label(addr) {
+            asm = asm.replacen(&written, label, 1);
+        }
+    }
+    registers(cpu, asm)
+}
+
+// the address the operand of the instruction at `begin` names, and how it's
+// written in the disassembly
+fn named_address(cpu: &CPU, opcode: &OpCode, begin: u16) -> Option<(u16, String)> {
+    let byte = cpu.bus.peek(begin.wrapping_add(1));
+    match (opcode.len, opcode.mode) {
+        (2, AddressingMode::Immediate) => None,
+        // branches, relative to the next instruction
+        (2, AddressingMode::NoneAddressing) => {
+            let target = begin.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            Some((target, format!("${:04X}", target)))
+        }
+        (2, _) => Some((byte as u16, format!("${:02X}", byte))),
+        (3, _) => {
+            let address = u16::from_le_bytes([byte, cpu.bus.peek(begin.wrapping_add(2))]);
+            Some((address, format!("${:04X}", address)))
+        }
+        _ => None,
+    }
+}
+
+fn registers(cpu: &CPU, asm: String) -> String {
+    format!(
+        "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        asm,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status_register.bits(),
+        cpu.stack_pointer,
+        cpu.bus.ppu.scanline,
+        cpu.bus.ppu.dot,
+        cpu.bus.cycles()
+    )
+}
+
+// the operand address the instruction at `pc` will use, read without side
+// effects
+fn operand_address(cpu: &CPU, mode: &AddressingMode, pc: u16) -> u16 {
+    let bus = &cpu.bus;
+    let peek_u16 = |addr: u16| u16::from_le_bytes([bus.peek(addr), bus.peek(addr.wrapping_add(1))]);
+    let zero_page_u16 =
+        |ptr: u8| u16::from_le_bytes([bus.peek(ptr as u16), bus.peek(ptr.wrapping_add(1) as u16)]);
+
+    match mode {
+        AddressingMode::ZeroPage => bus.peek(pc) as u16,
+        AddressingMode::Absolute => peek_u16(pc),
+        AddressingMode::ZeroPage_X => bus.peek(pc).wrapping_add(cpu.register_x) as u16,
+        AddressingMode::ZeroPage_Y => bus.peek(pc).wrapping_add(cpu.register_y) as u16,
+        AddressingMode::Absolute_X => peek_u16(pc).wrapping_add(cpu.register_x as u16),
+        AddressingMode::Absolute_Y => peek_u16(pc).wrapping_add(cpu.register_y as u16),
+        AddressingMode::Indirect_X => zero_page_u16(bus.peek(pc).wrapping_add(cpu.register_x)),
+        AddressingMode::Indirect_Y => {
+            zero_page_u16(bus.peek(pc)).wrapping_add(cpu.register_y as u16)
+        }
+        AddressingMode::Immediate | AddressingMode::NoneAddressing => pc,
+    }
+}
+
+// a tracer that writes each line to a file
+#[cfg(feature = "std")]
+pub fn file_tracer<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+    let mut out = BufWriter::new(File::create(path)?);
+    Ok(Box::new(move |line| {
+        // the trace is a debugging aid, a full disk shouldn't stop the game
+        let _ = writeln!(out, "{}", line);
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::BUS;
+    use crate::cpu::Mem;
+    use crate::rom::test::test_rom;
+    use crate::symbols::Symbols;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_format_trace() {
+        let mut bus = BUS::new(test_rom()).unwrap();
+        bus.mem_write(100, 0xa2);
+        bus.mem_write(101, 0x01);
+        bus.mem_write(102, 0xca);
+        bus.mem_write(103, 0x88);
+        bus.mem_write(104, 0x00);
+
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x64;
+        cpu.register_a = 1;
+        cpu.register_x = 2;
+        cpu.register_y = 3;
+
+        assert_eq!(
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0,  0 CYC:0",
+            trace(&cpu)
+        );
+        cpu.step();
+        assert_eq!(
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0,  6 CYC:2",
+            trace(&cpu)
+        );
+        cpu.step();
+        assert_eq!(
+            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 12 CYC:4",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_tracer_sees_every_instruction() {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new(BUS::new(test_rom()).unwrap());
+        let sink = lines.clone();
+        cpu.set_tracer(move |line| sink.borrow_mut().push(line.to_string()));
+        // INX; INX; BRK
+        cpu.load_and_run(vec![0xe8, 0xe8, 0x00]);
+
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("0601  E8        INX"));
+        assert!(lines[2].contains("X:02"));
+    }
+
+    #[test]
+    fn test_format_mem_access() {
+        let mut bus = BUS::new(test_rom()).unwrap();
+        // ORA ($33), Y
+        bus.mem_write(100, 0x11);
+        bus.mem_write(101, 0x33);
+
+        // data
+        bus.mem_write(0x33, 0x00);
+        bus.mem_write(0x34, 0x04);
+
+        // target cell
+        bus.mem_write(0x400, 0xAA);
+
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x64;
+        cpu.register_y = 0;
+        assert_eq!(
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_unofficial_and_jumps() {
+        let mut bus = BUS::new(test_rom()).unwrap();
+        // *NOP $A9; JMP ($02FF); BNE -4
+        for (i, byte) in [0x04, 0xa9, 0x6c, 0xff, 0x02, 0xd0, 0xfc]
+            .iter()
+            .enumerate()
+        {
+            bus.mem_write(0x0600 + i as u16, *byte);
+        }
+        bus.mem_write(0x02ff, 0x34);
+        bus.mem_write(0x0200, 0x12);
+
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x0600;
+        assert!(trace(&cpu).starts_with("0600  04 A9    *NOP $A9 = 00                    A:00"));
+        cpu.program_counter = 0x0602;
+        assert!(trace(&cpu).starts_with("0602  6C FF 02  JMP ($02FF) = 1234              A:00"));
+        cpu.program_counter = 0x0605;
+        assert!(trace(&cpu).starts_with("0605  D0 FC     BNE $0603                       A:00"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut bus = BUS::new(test_rom()).unwrap();
+        // LDA $10; STA $0300,X; BNE -7
+        for (i, byte) in [0xa5, 0x10, 0x9d, 0x00, 0x03, 0xd0, 0xf9]
+            .iter()
+            .enumerate()
+        {
+            bus.mem_write(0x0600 + i as u16, *byte);
+        }
+        let mut symbols = Symbols::new();
+        symbols
+            .load_nl("$0010#frames#\n$0300#buffer#\n$0600#main#\n", None)
+            .unwrap();
+
+        let mut cpu = CPU::new(bus);
+        cpu.set_symbols(symbols);
+        cpu.program_counter = 0x0600;
+        assert!(trace(&cpu).starts_with("0600  A5 10     LDA frames = 00                 A:00"));
+        cpu.program_counter = 0x0602;
+        assert!(trace(&cpu).starts_with("0602  9D 00 03  STA buffer,X @ 0300 = 00        A:00"));
+        cpu.program_counter = 0x0605;
+        assert!(trace(&cpu).starts_with("0605  D0 F9     BNE main                        A:00"));
+    }
+}
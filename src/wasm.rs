@@ -0,0 +1,103 @@
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::render::frame::Frame;
+
+use wasm_bindgen::prelude::*;
+
+// Browser bindings, built with `wasm-pack build wasm --target web`, see wasm/
+//
+//   const nes = new WasmNes(audioContext.sampleRate);
+//   nes.load_rom(new Uint8Array(await file.arrayBuffer()));
+//   addEventListener("keydown", (e) => nes.key_down(e.code));
+//   addEventListener("keyup", (e) => nes.key_up(e.code));
+//   // once per frame, at nes.frame_rate() frames a second:
+//   nes.run_frame();
+//   const pixels = new ImageData(new Uint8ClampedArray(nes.framebuffer()), 256, 240);
+//   canvas.getContext("2d").putImageData(pixels, 0, 0);
+//
+// Keys are KeyboardEvent.code values, laid out like the SDL frontend's.
+fn button(code: &str) -> Option<Button> {
+    match code {
+        "ArrowDown" => Some(Button::DOWN),
+        "ArrowUp" => Some(Button::UP),
+        "ArrowRight" => Some(Button::RIGHT),
+        "ArrowLeft" => Some(Button::LEFT),
+        "Space" => Some(Button::SELECT),
+        "Enter" => Some(Button::START),
+        "KeyA" => Some(Button::A),
+        "KeyS" => Some(Button::B),
+        _ => None,
+    }
+}
+
+#[wasm_bindgen]
+pub struct WasmNes {
+    nes: Option<Nes>,
+    sample_rate: u32,
+    buttons: Button,
+}
+
+#[wasm_bindgen]
+impl WasmNes {
+    #[wasm_bindgen(constructor)]
+    pub fn init(sample_rate: u32) -> WasmNes {
+        WasmNes {
+            nes: None,
+            sample_rate,
+            buttons: Button::empty(),
+        }
+    }
+
+    pub fn load_rom(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let nes = Nes::with_sample_rate(bytes, self.sample_rate)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.nes = Some(nes);
+        Ok(())
+    }
+
+    // does nothing until a ROM is loaded
+    pub fn run_frame(&mut self) {
+        if let Some(nes) = self.nes.as_mut() {
+            nes.set_input(1, self.buttons);
+            nes.run_frame();
+        }
+    }
+
+    // RGBA, as canvas ImageData wants it
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(Frame::WIDTH * Frame::HEIGHT * 4);
+        if let Some(nes) = self.nes.as_ref() {
+            for rgb in nes.frame_buffer().data.chunks(3) {
+                rgba.extend_from_slice(rgb);
+                rgba.push(0xff);
+            }
+        }
+        rgba
+    }
+
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.nes.as_mut().map_or(vec![], |nes| nes.audio_samples())
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.nes.as_ref().map_or(60.0, |nes| nes.frame_rate())
+    }
+
+    pub fn key_down(&mut self, code: &str) {
+        if let Some(button) = button(code) {
+            self.buttons.insert(button);
+        }
+    }
+
+    pub fn key_up(&mut self, code: &str) {
+        if let Some(button) = button(code) {
+            self.buttons.remove(button);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        if let Some(nes) = self.nes.as_mut() {
+            nes.soft_reset();
+        }
+    }
+}
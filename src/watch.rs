@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Watches the ROM on disk so a frontend can reload it whenever an assembler
+// rewrites it. The modification time is polled, once a frame is plenty, which
+// avoids any platform specific file notification API.
+pub struct RomWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl RomWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        RomWatcher {
+            modified: modified_time(&path),
+            path,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // true once for every change seen since the last call. A file that is
+    // missing (in the middle of being rewritten) doesn't count as a change.
+    pub fn poll(&mut self) -> bool {
+        match modified_time(&self.path) {
+            Some(modified) if Some(modified) != self.modified => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_poll_reports_each_change_once() {
+        let path = std::env::temp_dir().join(format!("rom-watch-{}.nes", std::process::id()));
+        fs::write(&path, b"NES\x1a").unwrap();
+
+        let mut watcher = RomWatcher::new(&path);
+        assert!(!watcher.poll());
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll());
+    }
+}
@@ -0,0 +1,91 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// Collects the mixed output sample by sample and saves it as a mono 16-bit
+// PCM WAV file, see Nes::start_audio_recording
+// from: http://soundfile.sapp.org/doc/WaveFormat/
+pub struct AudioRecorder {
+    sample_rate: u32,
+    samples: Vec<i16>,
+}
+
+impl AudioRecorder {
+    pub fn new(sample_rate: u32) -> Self {
+        AudioRecorder {
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.samples
+            .push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // in seconds
+    pub fn duration(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate as f64
+    }
+
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = self.samples.len() as u32 * 2;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+
+        // PCM, 1 channel, 2 bytes a frame, 16 bits a sample
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_wav())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wav_layout() {
+        let mut recorder = AudioRecorder::new(44_100);
+        recorder.push(0.5);
+        recorder.push(-2.0);
+        assert_eq!(recorder.len(), 2);
+
+        let wav = recorder.to_wav();
+        assert_eq!(wav.len(), 48);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], 40u32.to_le_bytes());
+        assert_eq!(wav[24..28], 44_100u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[44..], [0xff, 0x3f, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::ppu::PPU;
+use crate::render::palette::SYSTEM_PALETTE;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Zapper light gun
+// from: https://www.nesdev.org/wiki/Zapper
+//
+// | Bit | Read from $4016/$4017                    |
+// |-----|------------------------------------------|
+// | 3   | Light sense: 0 while light is detected   |
+// | 4   | Trigger: 1 while pulled                  |
+//
+// The photodiode sees the picture as the beam draws it and stays lit for a
+// while after the beam has passed, so light is only detected in the
+// scanlines just below the point the gun is aimed at, and only when the
+// PPU drew that point bright.
+const LIGHT_SCANLINES: u16 = 20;
+const BRIGHTNESS_THRESHOLD: u32 = 0xc0;
+
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zapper {
+    // screen position the gun points at, None when it's off screen
+    aim: Option<(u8, u8)>,
+    trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper::default()
+    }
+
+    pub fn aim(&mut self, position: Option<(u8, u8)>) {
+        self.aim = position;
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    pub fn read(&self, ppu: &PPU) -> u8 {
+        let dark = !self.light_sensed(ppu) as u8;
+        (dark << 3) | ((self.trigger as u8) << 4)
+    }
+
+    fn light_sensed(&self, ppu: &PPU) -> bool {
+        let (x, y) = match self.aim {
+            Some((x, y)) if (y as usize) < 240 => (x as u16, y as u16),
+            _ => return false,
+        };
+        let passed = ppu.scanline > y || (ppu.scanline == y && ppu.dot > x + 1);
+        if !passed || ppu.scanline >= (y + LIGHT_SCANLINES).min(240) {
+            return false;
+        }
+
+        let colour = ppu.pixel_colour(x as usize, y as usize);
+        let (r, g, b) = SYSTEM_PALETTE[(colour & 0x3f) as usize];
+        (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000 >= BRIGHTNESS_THRESHOLD
+    }
+}
+
+impl Snapshot for Zapper {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.aim.is_some());
+        let (x, y) = self.aim.unwrap_or((0, 0));
+        state.write_u8(x);
+        state.write_u8(y);
+        state.write_bool(self.trigger);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let aimed = state.read_bool()?;
+        let x = state.read_u8()?;
+        let y = state.read_u8()?;
+        self.aim = if aimed { Some((x, y)) } else { None };
+        self.trigger = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a white screen
+    fn white_ppu() -> PPU {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.palette_table[0] = 0x30;
+        ppu
+    }
+
+    #[test]
+    fn test_light_follows_the_beam() {
+        let mut ppu = white_ppu();
+        let mut zapper = Zapper::new();
+        zapper.aim(Some((100, 50)));
+
+        ppu.scanline = 49;
+        assert_eq!(zapper.read(&ppu), 0b0_1000);
+        ppu.scanline = 52;
+        assert_eq!(zapper.read(&ppu), 0b0_0000);
+        ppu.scanline = 50 + LIGHT_SCANLINES;
+        assert_eq!(zapper.read(&ppu), 0b0_1000);
+
+        // black doesn't register
+        ppu.scanline = 52;
+        ppu.palette_table[0] = 0x0f;
+        assert_eq!(zapper.read(&ppu), 0b0_1000);
+
+        zapper.set_trigger(true);
+        zapper.aim(None);
+        assert_eq!(zapper.read(&ppu), 0b1_1000);
+    }
+}
@@ -0,0 +1,271 @@
+// Golden frame hashes for the renderer: small ROMs made here draw fixed
+// scenes (backgrounds, scrolling, sprites, colour emphasis), and the MD5 of
+// chosen frames has to match tests/golden/frame_hashes.txt, with both the
+// accurate and the fast profile. A refactor that changes a single pixel
+// fails it.
+//
+// When a change to the picture is meant to happen, bless the new hashes
+// and check the file in along with the change:
+//
+//   BLESS=1 cargo test --test frame_hashes
+use rust_nes_emu::accuracy::AccuracyConfig;
+use rust_nes_emu::md5::md5;
+use rust_nes_emu::nes::Nes;
+use rust_nes_emu::rom::test::RomBuilder;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// the frames hashed; the scenes are drawn by the end of frame 3
+const FRAMES: [usize; 2] = [4, 10];
+
+// Straight line 6502 code, loaded at $8000
+struct Program {
+    code: Vec<u8>,
+}
+
+impl Program {
+    fn new() -> Self {
+        let mut program = Program { code: vec![] };
+        // the PPU ignores writes for a while after power on
+        program.wait_vblank();
+        program.wait_vblank();
+        program
+    }
+
+    fn wait_vblank(&mut self) {
+        // BIT $2002; BPL back to the BIT
+        self.code.extend([0x2c, 0x02, 0x20, 0x10, 0xfb]);
+    }
+
+    fn store(&mut self, addr: u16, value: u8) {
+        // LDA #value; STA addr
+        let [lo, hi] = addr.to_le_bytes();
+        self.code.extend([0xa9, value, 0x8d, lo, hi]);
+    }
+
+    fn vram(&mut self, addr: u16, data: &[u8]) {
+        let [lo, hi] = addr.to_le_bytes();
+        self.store(0x2006, hi);
+        self.store(0x2006, lo);
+        for &value in data {
+            self.store(0x2007, value);
+        }
+    }
+
+    // sprites go through RAM at $0200 and OAM DMA
+    fn sprites(&mut self, sprites: &[[u8; 4]]) {
+        for (i, sprite) in sprites.iter().enumerate() {
+            for (j, &value) in sprite.iter().enumerate() {
+                self.store(0x0200 + (i * 4 + j) as u16, value);
+            }
+        }
+        // the rest off screen
+        for i in sprites.len()..64 {
+            self.store(0x0200 + i as u16 * 4, 0xff);
+        }
+        self.store(0x4014, 0x02);
+    }
+
+    fn show(&mut self, ctrl: u8, mask: u8, scroll: (u8, u8)) {
+        self.store(0x2000, ctrl);
+        self.store(0x2005, scroll.0);
+        self.store(0x2005, scroll.1);
+        self.store(0x2001, mask);
+    }
+
+    // NROM-256 with 8KB of CHR ROM and vertical mirroring, ending in a loop
+    fn rom(mut self) -> Vec<u8> {
+        let end = 0x8000 + self.code.len() as u16;
+        let [lo, hi] = end.to_le_bytes();
+        // JMP to itself
+        self.code.extend([0x4c, lo, hi]);
+        // NMI and IRQ at an RTI past the code
+        RomBuilder::new()
+            .chr(chr())
+            .code(0x8000, &self.code)
+            .nmi(0xfff0, &[])
+            .irq(0xfff0, &[])
+            .build()
+    }
+}
+
+// 512 tiles of stripes and blocks, each one different, tile 0 blank
+fn chr() -> Vec<u8> {
+    let mut chr = vec![0; 0x2000];
+    for tile in 1..512 {
+        for row in 0..8 {
+            chr[tile * 16 + row] = (0xffu8 >> (tile % 8)).rotate_left((row + tile / 8) as u32);
+            chr[tile * 16 + 8 + row] = if row >= tile % 7 {
+                0x3c << (tile % 3)
+            } else {
+                0
+            };
+        }
+    }
+    chr
+}
+
+fn palettes(program: &mut Program) {
+    let colours: Vec<u8> = (0..32).map(|i| (i * 7 + 3) as u8 % 0x40).collect();
+    program.vram(0x3f00, &colours);
+}
+
+// a nametable and its attributes, different for each `seed`
+fn nametable(program: &mut Program, addr: u16, seed: usize) {
+    let tiles: Vec<u8> = (0..960)
+        .map(|i| match (i + i / 32 * seed) % 7 {
+            0 => (i % 255 + 1) as u8,
+            _ => 0,
+        })
+        .collect();
+    let attributes: Vec<u8> = (0..64).map(|i| (i * 37 + seed) as u8).collect();
+    program.vram(addr, &tiles);
+    program.vram(addr + 960, &attributes);
+}
+
+fn background() -> Vec<u8> {
+    let mut program = Program::new();
+    palettes(&mut program);
+    nametable(&mut program, 0x2000, 3);
+    program.show(0x00, 0x0a, (0, 0));
+    program.rom()
+}
+
+fn scrolled() -> Vec<u8> {
+    let mut program = Program::new();
+    palettes(&mut program);
+    nametable(&mut program, 0x2000, 3);
+    nametable(&mut program, 0x2400, 7);
+    // background tiles from $1000, scrolled into the second nametable
+    program.show(0x10, 0x0a, (100, 37));
+    program.rom()
+}
+
+// every combination of the flip and priority bits, all four palettes,
+// overlapping, at the left and right edges and under the left column mask,
+// and a row of ten where only the first eight show
+fn sprite_list() -> Vec<[u8; 4]> {
+    let mut sprites = vec![];
+    for i in 0..24u8 {
+        let y = 16 + (i / 8) * 40 + i % 3;
+        let x = [0, 4, 60, 64, 128, 131, 244, 250][i as usize % 8];
+        sprites.push([y, i.wrapping_mul(9), (i & 3) | ((i & 0x1c) << 3), x]);
+    }
+    sprites.push([150, 5, 0x00, 120]);
+    sprites.push([154, 6, 0x01, 124]);
+    for i in 0..10u8 {
+        sprites.push([190, 40 + i, i & 3, 20 + i * 20]);
+    }
+    sprites
+}
+
+fn sprites() -> Vec<u8> {
+    let mut program = Program::new();
+    palettes(&mut program);
+    nametable(&mut program, 0x2000, 5);
+    program.sprites(&sprite_list());
+    // sprites from $1000, shown everywhere but the leftmost 8 pixels
+    program.show(0x08, 0x1a, (0, 0));
+    program.rom()
+}
+
+fn tall_sprites() -> Vec<u8> {
+    let mut program = Program::new();
+    palettes(&mut program);
+    nametable(&mut program, 0x2000, 5);
+    program.sprites(&sprite_list());
+    // 8x16 sprites, picking their pattern table by tile number
+    program.show(0x20, 0x1e, (0, 0));
+    program.rom()
+}
+
+fn emphasis() -> Vec<u8> {
+    let mut program = Program::new();
+    palettes(&mut program);
+    nametable(&mut program, 0x2000, 9);
+    program.sprites(&sprite_list());
+    // greyscale with red and blue emphasis
+    program.show(0x00, 0xbf, (3, 200));
+    program.rom()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+type Scene = fn() -> Vec<u8>;
+
+// "scene profile frame" to the hash of that frame's pixels
+fn frame_hashes() -> BTreeMap<String, String> {
+    let scenes: [(&str, Scene); 5] = [
+        ("background", background),
+        ("scrolled", scrolled),
+        ("sprites", sprites),
+        ("tall_sprites", tall_sprites),
+        ("emphasis", emphasis),
+    ];
+    let profiles = [
+        ("accurate", AccuracyConfig::ACCURATE),
+        ("fast", AccuracyConfig::FAST),
+    ];
+    let mut hashes = BTreeMap::new();
+    for (scene, rom) in scenes {
+        for (profile, accuracy) in profiles {
+            let mut nes = Nes::new(&rom()).unwrap();
+            nes.set_accuracy(accuracy);
+            for frame in 1..=FRAMES[FRAMES.len() - 1] {
+                nes.run_frame();
+                if FRAMES.contains(&frame) {
+                    let key = format!("{} {} {}", scene, profile, frame);
+                    hashes.insert(key, hex(&md5(&nes.frame_buffer().data)));
+                }
+            }
+        }
+    }
+    hashes
+}
+
+#[test]
+fn test_frame_hashes() {
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "golden",
+        "frame_hashes.txt",
+    ]
+    .iter()
+    .collect();
+    let hashes = frame_hashes();
+
+    if std::env::var_os("BLESS").is_some() {
+        let golden: String = hashes
+            .iter()
+            .map(|(key, hash)| format!("{} {}\n", key, hash))
+            .collect();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, golden).unwrap();
+        return;
+    }
+
+    let golden = std::fs::read_to_string(&path).unwrap_or_default();
+    let golden: BTreeMap<String, String> = golden
+        .lines()
+        .filter_map(|line| line.rsplit_once(' '))
+        .map(|(key, hash)| (key.to_string(), hash.to_string()))
+        .collect();
+    let mismatches: Vec<&String> = hashes
+        .keys()
+        .filter(|key| golden.get(*key) != hashes.get(*key))
+        .collect();
+    assert!(
+        mismatches.is_empty() && golden.len() == hashes.len(),
+        "frames differ from {}: {:?}; if that's meant to happen, rerun with BLESS=1",
+        path.display(),
+        mismatches
+    );
+}
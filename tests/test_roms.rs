@@ -0,0 +1,66 @@
+// Runs every .nes file under $NES_TEST_ROMS (for instance a checkout of
+// https://github.com/christopherpow/nes-test-roms/tree/master/blargg_*) and
+// reports each one. Without the variable there is nothing to run.
+use rust_nes_emu::testrom::{run_test_rom, TestResult};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// about a minute of emulated time, which the slowest blargg ROMs fit in
+const FRAME_LIMIT: usize = 60 * 60;
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|extension| extension == "nes") {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+fn test_roms() {
+    let dir = match std::env::var_os("NES_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => return,
+    };
+    let mut roms = vec![];
+    find_roms(&dir, &mut roms);
+
+    let mut failures = vec![];
+    for path in roms {
+        let name = path
+            .strip_prefix(&dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let result = fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| run_test_rom(&bytes, FRAME_LIMIT).map_err(|err| err.to_string()));
+        match result {
+            Ok(TestResult::Passed(_)) => println!("{}: passed", name),
+            Ok(TestResult::Failed { code, text }) => {
+                println!("{}: failed with {}\n{}", name, code, text);
+                failures.push(name);
+            }
+            Ok(TestResult::TimedOut(text)) => {
+                println!("{}: timed out\n{}", name, text);
+                failures.push(name);
+            }
+            Err(err) => {
+                println!("{}: {}", name, err);
+                failures.push(name);
+            }
+        }
+    }
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}
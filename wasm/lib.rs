@@ -0,0 +1,3 @@
+// everything is in src/wasm.rs, this only links it into a module browsers
+// can load
+pub use rust_nes_emu::wasm::*;